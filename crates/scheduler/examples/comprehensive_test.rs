@@ -2,8 +2,8 @@ use scheduler::{
     model_downloader::ModelDownloader,
     task_splitter::{TaskSplitter, SplitStrategy},
     task::{MoeTask, TaskPriority},
-    task_executor::TaskExecutor,
-    types::GateWeights,
+    task_executor::{CpuTaskExecutor, Executor, TaskExecutor},
+    types::{DeviceId, GateWeights},
     error::Result,
 };
 use uuid::Uuid;
@@ -26,6 +26,7 @@ fn main() -> Result<()> {
                 hidden_size: 512,
                 intermediate_size: 2048,
                 num_layers: 12,
+                ..Default::default()
             }
         }
     };
@@ -74,6 +75,7 @@ fn main() -> Result<()> {
         // 准备输入数据
         let input_data = prepare_test_input(&model_info);
         let parent_task_id = format!("test_task_{}", Uuid::new_v4());
+        println!("预计拆分为 {} 个任务", strategy.estimate_task_count(&model_info, input_data.len()));
 
         // 执行任务拆分
         match splitter.split_task(&input_data, &parent_task_id, TaskPriority::Normal) {
@@ -135,8 +137,18 @@ fn prepare_test_input(model_info: &scheduler::config::ModelInfo) -> Vec<u8> {
 fn test_task_execution(tasks: &[MoeTask]) -> Result<()> {
     println!("开始测试任务执行...");
     
-    // 创建任务执行器
-    let executor = TaskExecutor::new(0)?;
+    // 创建任务执行器，没有可用GPU时退回到主机执行器
+    let cuda_executor = match TaskExecutor::new(DeviceId(0)) {
+        Ok(executor) => Some(executor),
+        Err(e) => {
+            println!("CUDA不可用（{}），改用CPU执行器", e);
+            None
+        }
+    };
+    let executor: &dyn Executor = match &cuda_executor {
+        Some(executor) => executor,
+        None => &CpuTaskExecutor,
+    };
     
     // 复制任务以便修改
     let mut tasks_copy: Vec<MoeTask> = tasks.iter().cloned().collect();
@@ -156,13 +168,15 @@ fn test_task_execution(tasks: &[MoeTask]) -> Result<()> {
             }
             
             // 获取执行器状态
-            if let Ok((allocated, max)) = executor.get_memory_status() {
-                println!("内存使用: {}/{} 字节 ({:.1}%)", 
-                    allocated, max, (allocated as f32 / max as f32) * 100.0);
-            }
-            
-            if let Ok(loads) = executor.get_load_status() {
-                println!("GPU负载: {:?}", loads);
+            if let Some(executor) = &cuda_executor {
+                if let Ok((allocated, max)) = executor.get_memory_status() {
+                    println!("内存使用: {}/{} 字节 ({:.1}%)", 
+                        allocated, max, (allocated as f32 / max as f32) * 100.0);
+                }
+                
+                if let Ok(loads) = executor.get_load_status() {
+                    println!("GPU负载: {:?}", loads);
+                }
             }
         }
         Err(e) => {
@@ -61,8 +61,12 @@ fn main() -> Result<()> {
 fn format_split_strategy(strategy: &SplitStrategy, model_info: &scheduler::config::ModelInfo) -> String {
     match strategy {
         SplitStrategy::ByExpert => format!("按专家拆分（使用全部{}个专家）", model_info.num_experts),
+        SplitStrategy::ByTopKExpert { top_k } => format!("按门控权重选择{}个专家拆分（共{}个专家）", top_k, model_info.num_experts),
         SplitStrategy::ByLayer => format!("按层拆分（使用全部{}层）", model_info.num_layers),
         SplitStrategy::ByBatch { batch_size } => format!("按批次拆分（批次大小={}）", batch_size),
+        SplitStrategy::ByWindow { window, stride } => format!("滑动窗口拆分（窗口={}，步长={}）", window, stride),
+        SplitStrategy::ByExpertCapacity { capacity_factor } => format!("按专家容量拆分（容量系数={}，共{}个专家）", capacity_factor, model_info.num_experts),
+        SplitStrategy::ByTensorParallel { num_shards } => format!("张量并行拆分（每个专家{}个分片，共{}个专家）", num_shards, model_info.num_experts),
         SplitStrategy::Hybrid { expert_split, layer_split, batch_size, expert_ratio, layer_ratio } => {
             let mut desc = String::from("混合拆分：");
             if *expert_split {
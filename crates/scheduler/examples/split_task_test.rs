@@ -1,4 +1,5 @@
-use scheduler::model_downloader::{ModelDownloader, ModelInfo};
+use scheduler::config::ModelInfo;
+use scheduler::model_downloader::ModelDownloader;
 use scheduler::task_splitter::{SplitStrategy, TaskSplitter};
 use scheduler::task::TaskPriority;
 
@@ -19,6 +20,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 hidden_size: 512,
                 intermediate_size: 2048,
                 num_layers: 12,
+                ..Default::default()
             }
         }
     };
@@ -40,9 +42,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 
     // ---- 3. 创建模拟输入数据 ----
-    // 假设输入数据是一个包含 512 个 f32 浮点数的向量
+    // 假设输入数据是4字节头部（元素个数）加上 512 个 f32 浮点数
     let input_size = model_info.hidden_size;
-    let mock_input_data: Vec<u8> = vec![0; input_size * 4]; // 4 bytes per f32
+    let mut mock_input_data: Vec<u8> = (input_size as u32).to_le_bytes().to_vec();
+    mock_input_data.extend(vec![0; input_size * 4]); // 4 bytes per f32
     println!("创建模拟输入数据，大小: {} 字节", mock_input_data.len());
 
 
@@ -5,13 +5,15 @@
 //! 1. 使用 `tch` 库加载模型和权重。
 //! 2. 准备一份输入数据。
 //! 3. 调用我们自己的 `TaskSplitter` 来拆分任务。
-//! 4. TODO: 获取模型真实的门控权重和路由决策。
-//! 5. TODO: 比较我们拆分出的子任务数据和模型内部的路由结果是否一致。
+//! 4. 获取模型真实的门控权重和路由决策。
+//! 5. 逐专家执行拆分出的子任务并合并，与模型完整的MoE输出比较。
 
 use scheduler::error::Result;
 use scheduler::model_downloader::ModelDownloader;
 use scheduler::model_def::switch_transformer::SwitchTransformersSparseMLP;
-use scheduler::task::TaskPriority;
+use scheduler::reference_executor::{tensor_to_input_bytes, ReferenceExecutor};
+use scheduler::result_merger::ResultMerger;
+use scheduler::task::{LayerStack, TaskPriority};
 use scheduler::task_splitter::{SplitStrategy, TaskSplitter};
 use tch::{nn, Device, Tensor, Kind};
 
@@ -36,6 +38,7 @@ fn main() -> Result<()> {
                 hidden_size: 512,
                 intermediate_size: 2048,
                 num_layers: 12,
+                ..Default::default()
             }
         }
     };
@@ -45,12 +48,21 @@ fn main() -> Result<()> {
     println!("\n正在从 {} 加载 PyTorch 模型...", model_dir);
     let mut vs = nn::VarStore::new(device);
     
-    // 构建模型权重的完整路径
-    // 注意：这需要我们之前修复的 verify_model 逻辑来确定文件名
-    let model_weights_path = format!("{}/model.safetensors", model_dir); 
+    // 权重可能是单个 model.safetensors，也可能是按索引分片的多个文件
+    let weight_files: Vec<_> = downloader
+        .weight_files(model_dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "safetensors"))
+        .collect();
     
-    // 加载权重
-    match vs.load(&model_weights_path) {
+    // 加载权重，分片时每个文件只包含部分参数
+    let load_result = match weight_files.as_slice() {
+        [] => vs.load(format!("{}/model.safetensors", model_dir)),
+        [single] => vs.load(single),
+        shards => shards.iter().try_for_each(|shard| vs.load_partial(shard).map(|_| ())),
+    };
+    match load_result {
         Ok(_) => println!("PyTorch 模型权重加载成功！"),
         Err(e) => {
             println!("无法加载模型权重: {}，跳过PyTorch模型测试", e);
@@ -61,8 +73,7 @@ fn main() -> Result<()> {
     // ---- 3. 实例化我们定义的MoE MLP层 ----
     // 我们以 Encoder 的第0个 block 中的第1个 layer norm 后的 mlp 为例
     // 它的路径是 "encoder.block.0.layer.1.mlp"
-    let mlp_path = vs.root() / "encoder" / "block" / 0 / "layer" / 1 / "mlp";
-    let sparse_mlp = SwitchTransformersSparseMLP::new(mlp_path, &model_info);
+    let sparse_mlp = SwitchTransformersSparseMLP::for_block(&vs, &model_info, LayerStack::Encoder, 0);
     println!("自定义 SparseMLP 实例化成功。");
 
     // ---- 4. 创建输入张量并获取真实的门控权重 ----
@@ -72,8 +83,8 @@ fn main() -> Result<()> {
     let hidden_size = model_info.hidden_size as i64;
     let input_tensor = Tensor::randn(&[batch_size, seq_len, hidden_size], (Kind::Float, device));
     
-    // 执行前向传播，获取 router logits
-    let router_logits = sparse_mlp.forward(&input_tensor);
+    // 获取 router logits
+    let router_logits = sparse_mlp.router_logits(&input_tensor);
     println!("成功获取 Router Logits!");
     router_logits.print();
 
@@ -82,7 +93,7 @@ fn main() -> Result<()> {
     
     // 创建任务拆分器
     let strategy = SplitStrategy::ByExpert;
-    let splitter = match TaskSplitter::new(model_info.clone(), strategy) {
+    let splitter = match TaskSplitter::new(model_info.clone(), strategy.clone()) {
         Ok(s) => s,
         Err(e) => {
             println!("创建任务拆分器失败: {}", e);
@@ -90,14 +101,26 @@ fn main() -> Result<()> {
         }
     };
     
-    // 准备输入数据
-    let input_data = prepare_sample_input(&model_info);
+    // 用输入张量的第一个token作为拆分器的输入，保证模型和拆分器处理的是同一份数据
+    let input_data = tensor_to_input_bytes(&input_tensor.narrow(1, 0, 1).contiguous())?;
     let parent_task_id = "verify_task_001";
     
+    let reference = ReferenceExecutor::new(sparse_mlp, model_info.clone());
+    let merger = ResultMerger::new(model_info.clone());
+
     // 执行任务拆分
     match splitter.split_task(&input_data, parent_task_id, TaskPriority::Normal) {
-        Ok(tasks) => {
+        Ok(mut tasks) => {
             println!("成功拆分为 {} 个任务", tasks.len());
+
+            // 与模型内部的路由结果比较
+            match splitter.compare_with_routing(&tasks, &router_logits) {
+                Ok(diff) => println!(
+                    "路由对比: 命中专家 {:?}，多余的专家任务 {:?}，缺失的专家 {:?}",
+                    diff.matched, diff.extra, diff.missing
+                ),
+                Err(e) => println!("路由对比失败: {}", e),
+            }
             
             // 验证拆分结果
             if let Ok(valid) = splitter.verify_split_results(&tasks, &input_data) {
@@ -108,6 +131,25 @@ fn main() -> Result<()> {
             if let Ok(dependencies) = splitter.get_task_dependencies(&tasks) {
                 println!("依赖关系分析完成，共 {} 个任务", dependencies.len());
             }
+
+            // 逐专家执行并按真实门控权重合并，与完整的MoE输出比较
+            let results = tasks
+                .iter_mut()
+                .map(|task| reference.execute_task(task))
+                .collect::<Result<Vec<_>>>()?;
+            let gate_weights = reference.gate_weights(&input_data)?;
+            let merged = merger.merge_results(&results, Some(gate_weights), &strategy)?;
+            let expected = reference.reference_output(&input_data)?;
+            let max_diff = merged
+                .chunks_exact(4)
+                .zip(expected.chunks_exact(4))
+                .map(|(m, e)| {
+                    let m = f32::from_le_bytes(m.try_into().unwrap());
+                    let e = f32::from_le_bytes(e.try_into().unwrap());
+                    (m - e).abs()
+                })
+                .fold(0.0f32, f32::max);
+            println!("合并结果与参考输出的最大误差: {:e} ({})", max_diff, if max_diff < 1e-5 { "一致" } else { "不一致" });
         }
         Err(e) => {
             println!("任务拆分失败: {}", e);
@@ -116,15 +158,3 @@ fn main() -> Result<()> {
 
     Ok(())
 }
-
-/// 准备示例输入数据
-fn prepare_sample_input(model_info: &scheduler::config::ModelInfo) -> Vec<u8> {
-    let input_size = model_info.hidden_size;
-    let mut input_data = Vec::new();
-    input_data.extend_from_slice(&(input_size as u32).to_le_bytes());
-    for i in 0..input_size {
-        let value = (i % 100) as f32 / 100.0;
-        input_data.extend_from_slice(&value.to_le_bytes());
-    }
-    input_data
-} 
\ No newline at end of file
@@ -0,0 +1,116 @@
+// async_scheduler.rs
+// 异步任务调度器，基于 tokio 的 Notify 唤醒等待任务的工作协程，避免轮询 fetch_next_task。
+use crate::config::SchedulerConfig;
+use crate::scheduler::TaskScheduler;
+use crate::task::MoeTask;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// 异步任务调度器，调度顺序、依赖和并发限制与 TaskScheduler 相同
+pub struct AsyncTaskScheduler {
+    /// 同步调度器，负责实际的排队和选择
+    inner: TaskScheduler,
+    /// 有新任务或名额释放时唤醒等待者
+    notify: Notify,
+    /// 是否已关闭，关闭后 next_task 在没有可执行任务时返回None
+    closed: AtomicBool,
+}
+
+impl AsyncTaskScheduler {
+    /// 创建新的异步调度器实例
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self::from_scheduler(TaskScheduler::new(config))
+    }
+
+    /// 包装已配置好的同步调度器（如设置了调度模式）
+    pub fn from_scheduler(scheduler: TaskScheduler) -> Self {
+        Self {
+            inner: scheduler,
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// 内部的同步调度器
+    pub fn scheduler(&self) -> &TaskScheduler {
+        &self.inner
+    }
+
+    /// 提交一个新任务并唤醒一个等待者
+    pub fn submit_task(&self, task: MoeTask) {
+        self.inner.submit_task(task);
+        self.notify.notify_one();
+    }
+
+    /// 任务执行完成，释放并发名额后唤醒一个等待者
+    pub fn complete_task(&self, task_id: &str) {
+        self.inner.complete_task(task_id);
+        self.notify.notify_one();
+    }
+
+    /// 关闭调度器：不再等待新任务，唤醒所有等待者
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// 等待下一个可执行的任务
+    /// 调度器关闭且没有可执行的任务时返回None
+    pub async fn next_task(&self) -> Option<MoeTask> {
+        loop {
+            // 先注册等待再检查队列，避免检查之后、等待之前的唤醒丢失
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(task) = self.inner.fetch_next_task() {
+                return Some(task);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::TaskPriority;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn test_task(task_id: &str) -> MoeTask {
+        MoeTask::new(task_id, Vec::new(), TaskPriority::Normal)
+    }
+
+    #[tokio::test]
+    async fn test_consumer_awaits_submitted_tasks() {
+        let scheduler = Arc::new(AsyncTaskScheduler::new(SchedulerConfig::default()));
+
+        let consumer = tokio::spawn({
+            let scheduler = Arc::clone(&scheduler);
+            async move {
+                let mut received = Vec::new();
+                while let Some(task) = scheduler.next_task().await {
+                    scheduler.complete_task(&task.task_id);
+                    received.push(task.task_id);
+                }
+                received
+            }
+        });
+
+        // 消费者先进入等待，再逐个提交任务
+        for i in 0..3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            scheduler.submit_task(test_task(&format!("task_{}", i)));
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        scheduler.close();
+
+        let received = consumer.await.unwrap();
+        assert_eq!(received, vec!["task_0", "task_1", "task_2"]);
+        assert_eq!(scheduler.scheduler().in_flight_count(), 0);
+    }
+}
@@ -1,15 +1,86 @@
 // config.rs
 // 调度器全局配置结构体及其默认实现，包含最大并发任务数、批处理大小和可用GPU列表。
+use crate::error::{Error, Result};
+use crate::types::DeviceId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// 层残差缩放系数，可以是所有层统一的标量，也可以是逐层的列表
+/// 例如 DeepNorm 使用的 alpha，在 config.json 中写作 1.5 或 [1.0, 1.5, ...]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LayerResidualScale {
+    /// 所有层使用同一系数
+    Uniform(f32),
+    /// 逐层系数
+    PerLayer(Vec<f32>),
+}
+
+impl LayerResidualScale {
+    /// 获取指定层的缩放系数，逐层列表长度不足时返回None
+    pub fn for_layer(&self, layer_id: usize) -> Option<f32> {
+        match self {
+            LayerResidualScale::Uniform(scale) => Some(*scale),
+            LayerResidualScale::PerLayer(scales) => scales.get(layer_id).copied(),
+        }
+    }
+}
+
+/// 专家前馈网络的激活函数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ActivationKind {
+    /// ReLU，Switch Transformer 的默认激活
+    #[default]
+    Relu,
+    /// 精确（erf）形式的 GELU
+    Gelu,
+    /// tanh 近似形式的 GELU，即 HuggingFace 的 gelu_new
+    NewGelu,
+}
+
+impl ActivationKind {
+    /// 解析 config.json 中 dense_act_fn 的取值，不支持的激活函数返回 ConfigError，避免被当作ReLU执行
+    pub fn from_hf_name(name: &str) -> Result<Self> {
+        match name {
+            "relu" => Ok(ActivationKind::Relu),
+            "gelu" => Ok(ActivationKind::Gelu),
+            "gelu_new" | "gelu_pytorch_tanh" => Ok(ActivationKind::NewGelu),
+            _ => Err(Error::ConfigError(format!("dense_act_fn: 不支持的激活函数 {}", name))),
+        }
+    }
+}
 
 /// 模型信息，包含模型类型、专家数、隐藏层大小等关键参数
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// 默认值的可选字段全部为空，可用 `..Default::default()` 只写出必要字段。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelInfo {
     pub model_type: String,
     pub num_experts: usize,
     pub hidden_size: usize,
     pub intermediate_size: usize,
     pub num_layers: usize,
+    /// 解码器层数，仅编码器-解码器结构的模型（如 Switch Transformer）有
+    #[serde(default)]
+    pub num_decoder_layers: Option<usize>,
+    /// 层残差缩放系数，未配置时按1.0处理
+    #[serde(default)]
+    pub layer_residual_scale: Option<LayerResidualScale>,
+    /// 注意力头数
+    #[serde(default)]
+    pub num_heads: Option<usize>,
+    /// 词表大小
+    #[serde(default)]
+    pub vocab_size: Option<usize>,
+    /// 每个专家单批可处理的最大token数
+    #[serde(default)]
+    pub expert_capacity: Option<usize>,
+    /// 路由器训练时加入的抖动噪声幅度
+    #[serde(default)]
+    pub router_jitter_noise: Option<f32>,
+    /// 专家前馈网络的激活函数，未配置时按ReLU处理
+    #[serde(default)]
+    pub dense_act_fn: Option<ActivationKind>,
 }
 
 /// 用于直接反序列化模型目录中 config.json 的结构体
@@ -23,30 +94,63 @@ pub(crate) struct ModelConfigJson {
     #[serde(rename = "d_ff")]
     intermediate_size: usize,
     num_layers: usize,
+    #[serde(default)]
+    num_decoder_layers: Option<usize>,
+    #[serde(default)]
+    layer_residual_scale: Option<LayerResidualScale>,
+    // 以下字段旧版配置中可能缺失
+    #[serde(default)]
+    num_heads: Option<usize>,
+    #[serde(default)]
+    vocab_size: Option<usize>,
+    #[serde(default)]
+    expert_capacity: Option<usize>,
+    #[serde(default)]
+    router_jitter_noise: Option<f32>,
+    #[serde(default)]
+    dense_act_fn: Option<String>,
+}
+
+impl ModelInfo {
+    /// 编码器与解码器的总层数，未配置解码器层数时等于 num_layers
+    pub fn total_layers(&self) -> usize {
+        self.num_layers + self.num_decoder_layers.unwrap_or(0)
+    }
 }
 
 // 为 ModelConfigJson 实现一个转换方法，使其可以轻松地转为 ModelInfo
-impl From<ModelConfigJson> for ModelInfo {
-    fn from(config_json: ModelConfigJson) -> Self {
-        Self {
+// 激活函数不受支持时转换失败
+impl TryFrom<ModelConfigJson> for ModelInfo {
+    type Error = Error;
+
+    fn try_from(config_json: ModelConfigJson) -> Result<Self> {
+        let dense_act_fn = config_json.dense_act_fn.as_deref().map(ActivationKind::from_hf_name).transpose()?;
+        Ok(Self {
             model_type: config_json.model_type,
             num_experts: config_json.num_experts,
             hidden_size: config_json.hidden_size,
             intermediate_size: config_json.intermediate_size,
             num_layers: config_json.num_layers,
-        }
+            num_decoder_layers: config_json.num_decoder_layers,
+            layer_residual_scale: config_json.layer_residual_scale,
+            num_heads: config_json.num_heads,
+            vocab_size: config_json.vocab_size,
+            expert_capacity: config_json.expert_capacity,
+            router_jitter_noise: config_json.router_jitter_noise,
+            dense_act_fn,
+        })
     }
 }
 
 /// 调度器全局配置，控制任务并发、批大小和可用GPU
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SchedulerConfig {
-    /// 最大并发任务数
+    /// 最大并发任务数，必须大于0（调度器和 `CpuTaskExecutor::execute_tasks_parallel` 的线程数都取该值）
     pub max_concurrent_tasks: usize,
     /// 默认批处理大小
     pub default_batch_size: usize,
-    /// 可用GPU设备ID列表
-    pub gpu_ids: Vec<i32>,
+    /// 可用GPU设备ID列表，配置文件中出现负数时反序列化失败
+    pub gpu_ids: Vec<DeviceId>,
 }
 
 impl Default for SchedulerConfig {
@@ -55,7 +159,146 @@ impl Default for SchedulerConfig {
         Self {
             max_concurrent_tasks: 4,
             default_batch_size: 1,
-            gpu_ids: vec![0],
+            gpu_ids: vec![DeviceId(0)],
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// 从默认配置开始构建，build 时检查参数
+    pub fn builder() -> SchedulerConfigBuilder {
+        SchedulerConfigBuilder::new()
+    }
+
+    /// 检查配置：并发任务数和批大小不能为0，GPU列表不能为空且不能重复
+    pub fn validate(&self) -> Result<()> {
+        if self.max_concurrent_tasks == 0 {
+            return Err(Error::ConfigError("max_concurrent_tasks: 最大并发任务数不能为0".to_string()));
+        }
+        if self.default_batch_size == 0 {
+            return Err(Error::ConfigError("default_batch_size: 默认批处理大小不能为0".to_string()));
+        }
+        if self.gpu_ids.is_empty() {
+            return Err(Error::ConfigError("gpu_ids: GPU列表不能为空".to_string()));
+        }
+        let mut seen = HashSet::new();
+        if let Some(gpu_id) = self.gpu_ids.iter().find(|gpu_id| !seen.insert(**gpu_id)) {
+            return Err(Error::ConfigError(format!("gpu_ids: GPU {} 重复出现", gpu_id)));
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// SchedulerConfig 的构建器，未设置的字段取默认值
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerConfigBuilder {
+    config: SchedulerConfig,
+    // 尚未检查的GPU编号，build 时转换为 DeviceId
+    gpu_ids: Option<Vec<i64>>,
+}
+
+impl SchedulerConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置最大并发任务数
+    pub fn max_concurrent_tasks(mut self, max_concurrent_tasks: usize) -> Self {
+        self.config.max_concurrent_tasks = max_concurrent_tasks;
+        self
+    }
+
+    /// 设置默认批处理大小
+    pub fn default_batch_size(mut self, default_batch_size: usize) -> Self {
+        self.config.default_batch_size = default_batch_size;
+        self
+    }
+
+    /// 设置可用GPU设备ID列表，负数在 build 时被拒绝
+    pub fn gpu_ids(mut self, gpu_ids: impl IntoIterator<Item = i64>) -> Self {
+        self.gpu_ids = Some(gpu_ids.into_iter().collect());
+        self
+    }
+
+    /// 检查参数并生成配置，参数无效时返回 ConfigError
+    pub fn build(mut self) -> Result<SchedulerConfig> {
+        if let Some(gpu_ids) = self.gpu_ids {
+            self.config.gpu_ids = gpu_ids
+                .into_iter()
+                .map(DeviceId::try_from)
+                .collect::<Result<_>>()?;
+        }
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduler_config_builder() {
+        let config = SchedulerConfig::builder()
+            .max_concurrent_tasks(8)
+            .default_batch_size(16)
+            .gpu_ids(vec![1, 0])
+            .build()
+            .unwrap();
+        assert_eq!(config.max_concurrent_tasks, 8);
+        assert_eq!(config.default_batch_size, 16);
+        assert_eq!(config.gpu_ids, vec![DeviceId(1), DeviceId(0)]);
+
+        // 默认配置本身是合法的
+        assert!(SchedulerConfig::builder().build().is_ok());
+        assert!(SchedulerConfig::default().validate().is_ok());
+
+        let rejected = |builder: SchedulerConfigBuilder, field: &str| match builder.build() {
+            Err(Error::ConfigError(msg)) => assert!(msg.starts_with(field), "{}", msg),
+            other => panic!("期望 ConfigError，实际为 {:?}", other),
+        };
+        rejected(SchedulerConfig::builder().gpu_ids(Vec::new()), "gpu_ids");
+        rejected(SchedulerConfig::builder().gpu_ids(vec![0, 1, 0]), "gpu_ids");
+        rejected(SchedulerConfig::builder().gpu_ids(vec![0, -1]), "gpu_ids");
+        rejected(SchedulerConfig::builder().max_concurrent_tasks(0), "max_concurrent_tasks");
+        rejected(SchedulerConfig::builder().default_batch_size(0), "default_batch_size");
+    }
+
+    #[test]
+    fn test_deserialized_negative_gpu_id_is_rejected() {
+        let config: SchedulerConfig = serde_json::from_str(
+            r#"{"max_concurrent_tasks": 4, "default_batch_size": 1, "gpu_ids": [0, 1]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.gpu_ids, vec![DeviceId(0), DeviceId(1)]);
+
+        let parsed = serde_json::from_str::<SchedulerConfig>(
+            r#"{"max_concurrent_tasks": 4, "default_batch_size": 1, "gpu_ids": [0, -1]}"#,
+        );
+        let err = parsed.unwrap_err().to_string();
+        assert!(err.contains("-1"), "{}", err);
+
+        // 序列化后仍是普通整数
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""gpu_ids":[0,1]"#), "{}", json);
+    }
+
+    #[test]
+    fn test_unknown_activation_is_rejected() {
+        let parse = |act: &str| {
+            let config_json: ModelConfigJson = serde_json::from_str(&format!(
+                r#"{{"model_type": "t", "num_experts": 2, "d_model": 4, "d_ff": 8, "num_layers": 2, "dense_act_fn": "{}"}}"#,
+                act
+            ))
+            .unwrap();
+            ModelInfo::try_from(config_json)
+        };
+        assert_eq!(parse("gelu_new").unwrap().dense_act_fn, Some(ActivationKind::NewGelu));
+        for act in ["silu", "gelu_fast"] {
+            match parse(act) {
+                Err(Error::ConfigError(msg)) => assert!(msg.contains(act), "{}", msg),
+                other => panic!("期望 ConfigError，实际为 {:?}", other),
+            }
+        }
+    }
+}
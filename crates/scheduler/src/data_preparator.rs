@@ -2,8 +2,11 @@
 // 数据准备器，负责为专家、层等准备输入数据，包含数据格式转换和辅助信息生成。
 use crate::config::ModelInfo;
 use crate::error::{Error, Result};
+use crate::task_header::{self, LayerConfig, TaskHeader, TaskKind};
+use crate::types::{GateWeights, TokenMetadata, WeightColumnSlice};
 
 
+/// 子任务数据均为 `task_header::encode` 编码的头部后接输入数据，布局见 [`TaskHeader`]
 pub struct DataPreparator {
     pub model_info: ModelInfo,
 }
@@ -13,76 +16,278 @@ impl DataPreparator {
         Self { model_info }
     }
 
-    /// 为专家准备数据
+    /// 为专家准备数据，门控信息为该专家的独热向量
     pub fn prepare_expert_data(&self, input_data: &[u8], expert_id: usize) -> Result<Vec<u8>> {
-        if expert_id >= self.model_info.num_experts {
+        let header = self.expert_header(expert_id, 1.0)?;
+        Ok(Self::encode_with_payload(&header, input_data))
+    }
+
+    /// 为专家准备数据，头部写入路由得到的真实门控权重而不是独热向量
+    /// 布局与 prepare_expert_data 相同，只有该专家的位置非0
+    pub fn prepare_expert_data_with_gates(&self, input_data: &[u8], expert_id: usize, gates: &GateWeights) -> Result<Vec<u8>> {
+        let header = self.expert_header_with_gates(expert_id, gates)?;
+        Ok(Self::encode_with_payload(&header, input_data))
+    }
+
+    /// 为分片专家准备数据，头部额外携带该专家负责的权重列范围
+    pub fn prepare_expert_data_sharded(&self, input_data: &[u8], expert_id: usize, slice: WeightColumnSlice) -> Result<Vec<u8>> {
+        if slice.len == 0 || slice.end() > self.model_info.intermediate_size {
             return Err(Error::InferenceError(format!(
-                "专家ID {} 超出范围 [0, {})", expert_id, self.model_info.num_experts
+                "权重列范围 [{}, {}) 超出中间层大小 {}", slice.start, slice.end(), self.model_info.intermediate_size
             )));
         }
-        let mut expert_data = Vec::new();
-        expert_data.extend_from_slice(&(expert_id as u32).to_le_bytes());
-        let gate_info = self.generate_gate_info(expert_id)?;
-        expert_data.extend_from_slice(&gate_info);
-        expert_data.extend_from_slice(input_data);
-        Ok(expert_data)
+        let mut header = self.expert_header(expert_id, 1.0)?;
+        if let TaskKind::Expert { column_slice, .. } = &mut header.kind {
+            *column_slice = Some(slice);
+        }
+        Ok(Self::encode_with_payload(&header, input_data))
     }
 
-    /// 为层准备数据
-    pub fn prepare_layer_data(&self, input_data: &[u8], layer_id: usize) -> Result<Vec<u8>> {
-        if layer_id >= self.model_info.num_layers {
+    /// 解析分片专家数据，返回专家ID、权重列范围和其后的输入数据
+    pub fn parse_expert_data_sharded<'a>(&self, data: &'a [u8]) -> Result<(usize, WeightColumnSlice, &'a [u8])> {
+        match task_header::decode(data)? {
+            (TaskHeader { kind: TaskKind::Expert { expert_id, column_slice: Some(slice), .. }, .. }, payload) => {
+                Ok((expert_id, slice, payload))
+            }
+            (header, _) => Err(Error::InferenceError(format!(
+                "任务头部不是分片专家头部: {:?}", header.kind
+            ))),
+        }
+    }
+
+    /// 将中间层维度均匀切分为 `num_shards` 段
+    pub fn even_column_slices(&self, num_shards: usize) -> Result<Vec<WeightColumnSlice>> {
+        let intermediate_size = self.model_info.intermediate_size;
+        if num_shards == 0 || !intermediate_size.is_multiple_of(num_shards) {
             return Err(Error::InferenceError(format!(
-                "层ID {} 超出范围 [0, {})", layer_id, self.model_info.num_layers
+                "中间层大小 {} 无法均匀切分为 {} 段", intermediate_size, num_shards
+            )));
+        }
+        let len = intermediate_size / num_shards;
+        Ok((0..num_shards).map(|i| WeightColumnSlice { start: i * len, len }).collect())
+    }
+
+    /// 验证列切片按顺序无缝隙、无重叠地恰好覆盖整个中间层维度
+    pub fn validate_column_slices(&self, slices: &[WeightColumnSlice]) -> Result<()> {
+        let mut next_start = 0;
+        for slice in slices {
+            if slice.len == 0 || slice.start != next_start {
+                return Err(Error::InferenceError(format!(
+                    "权重列范围 [{}, {}) 与期望起始列 {} 不连续", slice.start, slice.end(), next_start
+                )));
+            }
+            next_start = slice.end();
+        }
+        if next_start != self.model_info.intermediate_size {
+            return Err(Error::InferenceError(format!(
+                "权重列范围共覆盖 {} 列，与中间层大小 {} 不一致", next_start, self.model_info.intermediate_size
             )));
         }
-        let mut layer_data = Vec::new();
-        layer_data.extend_from_slice(&(layer_id as u32).to_le_bytes());
-        let layer_config = self.generate_layer_config(layer_id)?;
-        layer_data.extend_from_slice(&layer_config);
-        layer_data.extend_from_slice(input_data);
-        Ok(layer_data)
+        Ok(())
+    }
+
+    /// 为层准备数据
+    /// 层ID为全局编号：编码器层为 [0, num_layers)，解码器层紧随其后
+    pub fn prepare_layer_data(&self, input_data: &[u8], layer_id: usize) -> Result<Vec<u8>> {
+        let header = self.layer_header(layer_id)?;
+        Ok(Self::encode_with_payload(&header, input_data))
+    }
+
+    /// 为专家准备带token元数据的数据，元数据位于头部之后、输入数据之前
+    pub fn prepare_expert_data_with_metadata(&self, input_data: &[u8], expert_id: usize, metadata: &TokenMetadata) -> Result<Vec<u8>> {
+        let mut header = self.expert_header(expert_id, 1.0)?;
+        header.token_metadata = Some(metadata.clone());
+        Ok(Self::encode_with_payload(&header, input_data))
+    }
+
+    /// 为层准备带token元数据的数据，元数据位于头部之后、输入数据之前
+    pub fn prepare_layer_data_with_metadata(&self, input_data: &[u8], layer_id: usize, metadata: &TokenMetadata) -> Result<Vec<u8>> {
+        let mut header = self.layer_header(layer_id)?;
+        header.token_metadata = Some(metadata.clone());
+        Ok(Self::encode_with_payload(&header, input_data))
     }
 
     /// 为层和专家准备数据
     pub fn prepare_layer_expert_data(&self, input_data: &[u8], layer_id: usize, expert_id: usize) -> Result<Vec<u8>> {
+        let header = self.layer_expert_header(layer_id, expert_id)?;
+        Ok(Self::encode_with_payload(&header, input_data))
+    }
+
+    /// 专家任务头部，门控信息中只有该专家的位置为 gate_weight
+    pub fn expert_header(&self, expert_id: usize, gate_weight: f32) -> Result<TaskHeader> {
+        self.check_expert_id(expert_id)?;
+        Ok(TaskHeader::new(TaskKind::Expert {
+            expert_id,
+            gate_weights: self.generate_weighted_gate_info(expert_id, gate_weight),
+            column_slice: None,
+        }))
+    }
+
+    /// 专家任务头部，写入路由得到的该专家的门控权重
+    pub fn expert_header_with_gates(&self, expert_id: usize, gates: &GateWeights) -> Result<TaskHeader> {
+        self.check_expert_id(expert_id)?;
+        if gates.weights.len() != self.model_info.num_experts {
+            return Err(Error::InferenceError(format!(
+                "门控权重数量 {} 与专家数量 {} 不匹配", gates.weights.len(), self.model_info.num_experts
+            )));
+        }
+        self.expert_header(expert_id, gates.weights[expert_id])
+    }
+
+    /// 层任务头部
+    pub fn layer_header(&self, layer_id: usize) -> Result<TaskHeader> {
+        if layer_id >= self.model_info.total_layers() {
+            return Err(Error::InferenceError(format!(
+                "层ID {} 超出范围 [0, {})", layer_id, self.model_info.total_layers()
+            )));
+        }
+        Ok(TaskHeader::new(TaskKind::Layer { layer_id, config: self.generate_layer_config() }))
+    }
+
+    /// 层+专家任务头部
+    pub fn layer_expert_header(&self, layer_id: usize, expert_id: usize) -> Result<TaskHeader> {
         if layer_id >= self.model_info.num_layers {
             return Err(Error::InferenceError(format!(
                 "层ID {} 超出范围 [0, {})", layer_id, self.model_info.num_layers
             )));
         }
+        self.check_expert_id(expert_id)?;
+        Ok(TaskHeader::new(TaskKind::LayerExpert {
+            layer_id,
+            expert_id,
+            gate_weights: self.generate_weighted_gate_info(expert_id, 1.0),
+            config: self.generate_layer_config(),
+        }))
+    }
+
+    /// 编码头部并追加输入数据
+    pub fn encode_with_payload(header: &TaskHeader, input_data: &[u8]) -> Vec<u8> {
+        let mut data = task_header::encode(header);
+        data.extend_from_slice(input_data);
+        data
+    }
+
+    fn check_expert_id(&self, expert_id: usize) -> Result<()> {
         if expert_id >= self.model_info.num_experts {
             return Err(Error::InferenceError(format!(
                 "专家ID {} 超出范围 [0, {})", expert_id, self.model_info.num_experts
             )));
         }
-        let mut layer_expert_data = Vec::new();
-        layer_expert_data.extend_from_slice(&(layer_id as u32).to_le_bytes());
-        layer_expert_data.extend_from_slice(&(expert_id as u32).to_le_bytes());
-        let gate_info = self.generate_gate_info(expert_id)?;
-        layer_expert_data.extend_from_slice(&gate_info);
-        let layer_config = self.generate_layer_config(layer_id)?;
-        layer_expert_data.extend_from_slice(&layer_config);
-        layer_expert_data.extend_from_slice(input_data);
-        Ok(layer_expert_data)
-    }
-
-    /// 生成门控信息
-    fn generate_gate_info(&self, expert_id: usize) -> Result<Vec<u8>> {
-        let mut gate_info = Vec::new();
-        for i in 0..self.model_info.num_experts {
-            let weight: f32 = if i == expert_id { 1.0 } else { 0.0 };
-            gate_info.extend_from_slice(&weight.to_le_bytes());
-        }
-        Ok(gate_info)
+        Ok(())
+    }
+
+    /// 生成门控信息，该专家的位置写入 gate_weight，其余为0
+    fn generate_weighted_gate_info(&self, expert_id: usize, gate_weight: f32) -> Vec<f32> {
+        (0..self.model_info.num_experts)
+            .map(|i| if i == expert_id { gate_weight } else { 0.0 })
+            .collect()
     }
 
     /// 生成层配置信息
-    fn generate_layer_config(&self, layer_id: usize) -> Result<Vec<u8>> {
-        let mut layer_config = Vec::new();
-        layer_config.extend_from_slice(&(layer_id as u32).to_le_bytes());
-        layer_config.extend_from_slice(&(self.model_info.hidden_size as u32).to_le_bytes());
-        layer_config.extend_from_slice(&(self.model_info.intermediate_size as u32).to_le_bytes());
-        layer_config.extend_from_slice(&(self.model_info.num_experts as u32).to_le_bytes());
-        Ok(layer_config)
-    }
-} 
\ No newline at end of file
+    fn generate_layer_config(&self) -> LayerConfig {
+        LayerConfig {
+            hidden_size: self.model_info.hidden_size,
+            intermediate_size: self.model_info.intermediate_size,
+            num_experts: self.model_info.num_experts,
+        }
+    }
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_preparator() -> DataPreparator {
+        DataPreparator::new(ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 512,
+            intermediate_size: 2048,
+            num_layers: 2,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_sharded_expert_column_ranges() {
+        let preparator = test_preparator();
+        let slices = preparator.even_column_slices(4).unwrap();
+        preparator.validate_column_slices(&slices).unwrap();
+
+        let input = vec![7u8; 16];
+        for (expert_id, slice) in slices.iter().enumerate() {
+            let data = preparator.prepare_expert_data_sharded(&input, expert_id, *slice).unwrap();
+            let (parsed_id, parsed_slice, payload) = preparator.parse_expert_data_sharded(&data).unwrap();
+            assert_eq!(parsed_id, expert_id);
+            assert_eq!(parsed_slice, WeightColumnSlice { start: expert_id * 512, len: 512 });
+            assert_eq!(payload, &input[..]);
+        }
+    }
+
+    #[test]
+    fn test_column_slices_must_tile_intermediate_size() {
+        let preparator = test_preparator();
+        let slice = |start, len| WeightColumnSlice { start, len };
+
+        // 有缝隙
+        assert!(preparator.validate_column_slices(&[slice(0, 1024), slice(1536, 512)]).is_err());
+        // 有重叠
+        assert!(preparator.validate_column_slices(&[slice(0, 1024), slice(512, 1536)]).is_err());
+        // 未覆盖完整
+        assert!(preparator.validate_column_slices(&[slice(0, 1024)]).is_err());
+        // 无法均匀切分
+        assert!(preparator.even_column_slices(3).is_err());
+        // 不等长但恰好覆盖
+        assert!(preparator.validate_column_slices(&[slice(0, 1536), slice(1536, 512)]).is_ok());
+    }
+
+    #[test]
+    fn test_expert_data_with_gates_embeds_routing_weight() {
+        let preparator = test_preparator();
+        let gates = GateWeights { weights: vec![0.1, 0.6, 0.2, 0.1], top_k: 1 };
+        let input = vec![3u8; 8];
+
+        let data = preparator.prepare_expert_data_with_gates(&input, 2, &gates).unwrap();
+        // 布局与独热版本一致，只有门控权重的取值不同
+        let one_hot = preparator.prepare_expert_data(&input, 2).unwrap();
+        assert_eq!(data.len(), one_hot.len());
+        let (header, payload) = task_header::decode(&data).unwrap();
+        assert_eq!(header.expert_id(), Some(2));
+        assert_eq!(header.gate_weights().unwrap(), &[0.0, 0.0, 0.2, 0.0]);
+        assert_eq!(payload, &input[..]);
+        let (one_hot_header, _) = task_header::decode(&one_hot).unwrap();
+        assert_eq!(one_hot_header.gate_weights().unwrap(), &[0.0, 0.0, 1.0, 0.0]);
+
+        let wrong_len = GateWeights { weights: vec![1.0], top_k: 1 };
+        assert!(preparator.prepare_expert_data_with_gates(&input, 0, &wrong_len).is_err());
+    }
+
+    #[test]
+    fn test_token_metadata_round_trip() {
+        let preparator = test_preparator();
+        // 2个有效token后跟1个填充token
+        let metadata = TokenMetadata {
+            attention_mask: vec![1, 1, 0],
+            position_ids: [0u32, 1, 2].iter().flat_map(|id| id.to_le_bytes()).collect(),
+        };
+        let input = vec![9u8; 12];
+
+        let expert_data = preparator.prepare_expert_data_with_metadata(&input, 1, &metadata).unwrap();
+        let plain = preparator.prepare_expert_data(&[], 1).unwrap();
+        assert_eq!(expert_data.len(), plain.len() + metadata.encoded_len() + input.len());
+        let (header, payload) = task_header::decode(&expert_data).unwrap();
+        assert_eq!(header.expert_id(), Some(1));
+        assert_eq!(header.token_metadata.as_ref(), Some(&metadata));
+        assert_eq!(payload, &input[..]);
+
+        let layer_data = preparator.prepare_layer_data_with_metadata(&input, 1, &metadata).unwrap();
+        let (header, payload) = task_header::decode(&layer_data).unwrap();
+        assert_eq!(header.layer_id(), Some(1));
+        assert_eq!(header.token_metadata.unwrap().attention_mask, vec![1, 1, 0]);
+        assert_eq!(payload, &input[..]);
+
+        // 截断在元数据中间时报错
+        let truncated = &expert_data[..plain.len() + 5];
+        assert!(task_header::decode(truncated).is_err());
+    }
+}
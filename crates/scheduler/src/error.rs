@@ -20,6 +20,8 @@ pub enum Error {
     Other(String),
     /// 配置错误
     ConfigError(String),
+    /// tch（libtorch）张量运算错误
+    Tch(String),
 }
 
 /// 通用结果类型
@@ -37,9 +39,10 @@ impl From<rustacuda::error::CudaError> for Error {
     }
 }
 
+/// 用于读取配置文件时的JSON解析失败，统一视为配置错误；队列快照等其他JSON数据需自行映射错误
 impl From<serde_json::Error> for Error {
     fn from(e: serde_json::Error) -> Self {
-        Error::Other(format!("JSON error: {}", e))
+        Error::ConfigError(format!("JSON解析失败: {}", e))
     }
 }
 
@@ -49,6 +52,13 @@ impl From<std::ffi::NulError> for Error {
     }
 }
 
+#[cfg(feature = "tch")]
+impl From<tch::TchError> for Error {
+    fn from(e: tch::TchError) -> Self {
+        Error::Tch(e.to_string())
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -59,8 +69,39 @@ impl fmt::Display for Error {
             Error::GpuError(msg) => write!(f, "GPU错误: {}", msg),
             Error::Other(msg) => write!(f, "其他错误: {}", msg),
             Error::ConfigError(msg) => write!(f, "配置错误: {}", msg),
+            Error::Tch(msg) => write!(f, "Tch错误: {}", msg),
         }
     }
 }
 
-impl std::error::Error for Error {}
\ No newline at end of file
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_serde_json_error_is_config_error() {
+        let json_error = serde_json::from_str::<serde_json::Value>("{ not json").unwrap_err();
+        let error: Error = json_error.into();
+        assert!(matches!(error, Error::ConfigError(_)));
+        assert!(error.to_string().starts_with("配置错误"));
+    }
+
+    #[cfg(feature = "tch")]
+    #[test]
+    fn test_from_tch_error_preserves_message() {
+        use tch::{Device, Kind, Tensor};
+
+        let a = Tensor::zeros([2, 3], (Kind::Float, Device::Cpu));
+        let b = Tensor::zeros([4, 5], (Kind::Float, Device::Cpu));
+        let tch_error = a.f_matmul(&b).unwrap_err();
+        let message = tch_error.to_string();
+
+        let error: Error = tch_error.into();
+        match error {
+            Error::Tch(msg) => assert_eq!(msg, message),
+            other => panic!("期望 Tch 错误，实际为 {:?}", other),
+        }
+    }
+}
@@ -1,12 +1,57 @@
 // lib.rs
 // 调度器模块入口，声明并导出各子模块。
+//! MOE推理任务调度器：拆分任务、调度到执行后端并合并结果。
+//!
+//! 常用类型可以直接从crate根导入：
+//!
+//! ```
+//! use scheduler::{GateWeights, MoeTask, ResultMerger, SplitStrategy, TaskSplitter};
+//! use scheduler::config::ModelInfo;
+//! use scheduler::task::TaskPriority;
+//!
+//! let model_info = ModelInfo {
+//!     model_type: "switch_transformers".to_string(),
+//!     num_experts: 4,
+//!     hidden_size: 8,
+//!     intermediate_size: 32,
+//!     num_layers: 2,
+//!     ..Default::default()
+//! };
+//! let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+//! let mut input = 8u32.to_le_bytes().to_vec();
+//! input.extend((0..8).flat_map(|i| (i as f32).to_le_bytes()));
+//!
+//! let tasks: Vec<MoeTask> = splitter.split_task(&input, "request_0", TaskPriority::Normal).unwrap();
+//! assert_eq!(tasks.len(), 4);
+//!
+//! let gates = GateWeights { weights: vec![0.25; 4], top_k: 4 };
+//! let results: Vec<Vec<u8>> = tasks.iter().map(|task| task.effective_input().into_owned()).collect();
+//! let merger = ResultMerger::new(model_info);
+//! assert!(merger.merge_results(&results, Some(gates), &SplitStrategy::ByExpert).is_ok());
+//! ```
+#[cfg(feature = "tokio")]
+pub mod async_scheduler;
 pub mod config;
 pub mod data_preparator;
 pub mod error;
+pub mod metrics;
 pub mod model_downloader;
+#[cfg(feature = "tch")]
+pub mod model_def;
+pub mod pipeline;
+pub mod quantization;
+#[cfg(feature = "tch")]
+pub mod reference_executor;
 pub mod result_merger;
 pub mod scheduler;
 pub mod task;
 pub mod task_executor;
+pub mod task_header;
 pub mod task_splitter;
-pub mod types; 
\ No newline at end of file
+pub mod types;
+pub mod wasi_nn_extension;
+
+pub use result_merger::ResultMerger;
+pub use task::MoeTask;
+pub use task_splitter::{SplitStrategy, TaskSplitter};
+pub use types::GateWeights;
@@ -0,0 +1,81 @@
+// metrics.rs
+// 流水线耗时统计：拆分、逐任务执行（含传输字节数和拷入/计算/拷回各阶段）和合并的累计耗时，可在拆分器与执行器之间共享。
+use crate::task_executor::TaskMetrics;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 某一时刻的统计快照，可直接打印或序列化导出
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MetricsSnapshot {
+    /// 拆分次数
+    pub split_count: usize,
+    /// 拆分累计耗时
+    pub split_time: Duration,
+    /// 在设备上执行的任务数，命中结果缓存的任务不计入
+    pub task_count: usize,
+    /// 任务累计执行耗时（拷入、计算、拷回之和）
+    pub execute_time: Duration,
+    /// 主机到设备拷贝的累计耗时
+    pub h2d_time: Duration,
+    /// 计算的累计耗时
+    pub kernel_time: Duration,
+    /// 设备到主机拷贝的累计耗时
+    pub d2h_time: Duration,
+    /// 主机到设备拷贝的累计字节数
+    pub h2d_bytes: u64,
+    /// 设备到主机拷贝的累计字节数
+    pub d2h_bytes: u64,
+    /// 合并次数
+    pub merge_count: usize,
+    /// 合并累计耗时
+    pub merge_time: Duration,
+}
+
+/// 线程安全的统计收集器
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一次拆分
+    pub fn record_split(&self, elapsed: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.split_count += 1;
+        inner.split_time += elapsed;
+    }
+
+    /// 记录一个任务各阶段的耗时和传输字节数
+    pub fn record_task(&self, timing: &TaskMetrics, h2d_bytes: usize, d2h_bytes: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.task_count += 1;
+        inner.execute_time += timing.total();
+        inner.h2d_time += timing.h2d;
+        inner.kernel_time += timing.kernel;
+        inner.d2h_time += timing.d2h;
+        inner.h2d_bytes += h2d_bytes as u64;
+        inner.d2h_bytes += d2h_bytes as u64;
+    }
+
+    /// 记录一次合并
+    pub fn record_merge(&self, elapsed: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.merge_count += 1;
+        inner.merge_time += elapsed;
+    }
+
+    /// 当前的统计快照
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        *self.inner.lock().unwrap()
+    }
+
+    /// 清零所有统计
+    pub fn reset(&self) {
+        *self.inner.lock().unwrap() = MetricsSnapshot::default();
+    }
+}
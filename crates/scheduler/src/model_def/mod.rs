@@ -0,0 +1,3 @@
+// model_def/mod.rs
+// 基于 tch 的模型结构定义，用于加载真实模型权重，与调度器的拆分/合并结果做对照。
+pub mod switch_transformer;
@@ -0,0 +1,283 @@
+// switch_transformer.rs
+// Switch Transformer 稀疏MLP层的 tch 实现，参数路径与 HuggingFace 的 SwitchTransformersSparseMLP 一致。
+use crate::config::{ActivationKind, ModelInfo};
+use crate::task::LayerStack;
+use tch::nn::{self, Module};
+use tch::{Kind, Tensor};
+
+/// 专家前馈网络的结构配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpertConfig {
+    /// wi 与 wo 之间的激活函数
+    pub activation: ActivationKind,
+    /// wi、wo 是否带偏置
+    pub use_bias: bool,
+}
+
+impl ExpertConfig {
+    /// 按模型配置构造：激活函数取自 dense_act_fn（默认ReLU），Switch Transformer 的 wi/wo 不带偏置
+    pub fn from_model_info(model_info: &ModelInfo) -> Self {
+        Self {
+            activation: model_info.dense_act_fn.unwrap_or_default(),
+            use_bias: false,
+        }
+    }
+}
+
+/// 单个专家的前馈网络：wo(act(wi(x)))
+#[derive(Debug)]
+pub struct SwitchTransformersDenseActDense {
+    wi: nn::Linear,
+    wo: nn::Linear,
+    activation: ActivationKind,
+}
+
+impl SwitchTransformersDenseActDense {
+    /// 按模型配置创建专家
+    pub fn new(path: nn::Path, model_info: &ModelInfo) -> Self {
+        Self::with_config(path, model_info, ExpertConfig::from_model_info(model_info))
+    }
+
+    /// 按指定的激活函数和偏置设置创建专家
+    pub fn with_config(path: nn::Path, model_info: &ModelInfo, expert_config: ExpertConfig) -> Self {
+        let config = nn::LinearConfig { bias: expert_config.use_bias, ..Default::default() };
+        let hidden_size = model_info.hidden_size as i64;
+        let intermediate_size = model_info.intermediate_size as i64;
+        Self {
+            wi: nn::linear(&path / "wi", hidden_size, intermediate_size, config),
+            wo: nn::linear(&path / "wo", intermediate_size, hidden_size, config),
+            activation: expert_config.activation,
+        }
+    }
+
+    /// 激活函数
+    pub fn activation(&self) -> ActivationKind {
+        self.activation
+    }
+}
+
+impl Module for SwitchTransformersDenseActDense {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        let hidden = self.wi.forward(xs);
+        let hidden = match self.activation {
+            ActivationKind::Relu => hidden.relu(),
+            ActivationKind::Gelu => hidden.gelu("none"),
+            ActivationKind::NewGelu => hidden.gelu("tanh"),
+        };
+        self.wo.forward(&hidden)
+    }
+}
+
+/// Top-1 路由器，每个token只路由到概率最大的专家
+#[derive(Debug)]
+pub struct SwitchTransformersTop1Router {
+    classifier: nn::Linear,
+    num_experts: i64,
+}
+
+impl SwitchTransformersTop1Router {
+    pub fn new(path: nn::Path, model_info: &ModelInfo) -> Self {
+        let config = nn::LinearConfig { bias: false, ..Default::default() };
+        let num_experts = model_info.num_experts as i64;
+        Self {
+            classifier: nn::linear(&path / "classifier", model_info.hidden_size as i64, num_experts, config),
+            num_experts,
+        }
+    }
+
+    /// 路由 logits，形状为 [..., num_experts]
+    pub fn router_logits(&self, xs: &Tensor) -> Tensor {
+        self.classifier.forward(xs)
+    }
+
+    /// 稠密门控权重，形状为 [..., num_experts]
+    /// 被选中的专家位置为其路由概率，其余为0
+    pub fn gate(&self, xs: &Tensor) -> Tensor {
+        let probs = self.router_logits(xs).softmax(-1, Kind::Float);
+        let (max_prob, expert_index) = probs.max_dim(-1, true);
+        expert_index.squeeze_dim(-1).one_hot(self.num_experts).to_kind(Kind::Float) * max_prob
+    }
+}
+
+/// 第 `block` 个编码器或解码器块中MoE前馈层的参数路径
+///
+/// 编码器块为 自注意力 → 前馈，MLP 位于 `encoder.block.{block}.layer.1.mlp`；
+/// 解码器块为 自注意力 → 交叉注意力 → 前馈，MLP 位于 `decoder.block.{block}.layer.2.mlp`
+pub fn block_mlp_path<'a>(root: &nn::Path<'a>, stack: LayerStack, block: usize) -> nn::Path<'a> {
+    let (stack_name, mlp_layer) = match stack {
+        LayerStack::Encoder => ("encoder", 1),
+        LayerStack::Decoder => ("decoder", 2),
+    };
+    root / stack_name / "block" / block / "layer" / mlp_layer / "mlp"
+}
+
+/// Switch Transformer 稀疏MLP层：路由器 + 多个专家
+#[derive(Debug)]
+pub struct SwitchTransformersSparseMLP {
+    router: SwitchTransformersTop1Router,
+    experts: Vec<SwitchTransformersDenseActDense>,
+}
+
+impl SwitchTransformersSparseMLP {
+    /// 在 `path` 下创建（或按路径加载）路由器和各专家参数
+    /// 参数路径为 `router.classifier` 和 `experts.expert_{i}.{wi,wo}`
+    pub fn new(path: nn::Path, model_info: &ModelInfo) -> Self {
+        let router = SwitchTransformersTop1Router::new(&path / "router", model_info);
+        let experts_path = &path / "experts";
+        let experts = (0..model_info.num_experts)
+            .map(|i| SwitchTransformersDenseActDense::new(&experts_path / format!("expert_{}", i), model_info))
+            .collect();
+        Self { router, experts }
+    }
+
+    /// 在 `vs` 中为编码器或解码器的第 `block` 个块创建（或按路径加载）MoE前馈层，路径见 block_mlp_path
+    pub fn for_block(vs: &nn::VarStore, model_info: &ModelInfo, stack: LayerStack, block: usize) -> Self {
+        Self::new(block_mlp_path(&vs.root(), stack, block), model_info)
+    }
+
+    /// 专家数量
+    pub fn num_experts(&self) -> usize {
+        self.experts.len()
+    }
+
+    /// 路由器
+    pub fn router(&self) -> &SwitchTransformersTop1Router {
+        &self.router
+    }
+
+    /// 获取指定专家
+    pub fn expert(&self, expert_id: usize) -> Option<&SwitchTransformersDenseActDense> {
+        self.experts.get(expert_id)
+    }
+
+    /// 路由 logits，形状为 [..., num_experts]
+    pub fn router_logits(&self, xs: &Tensor) -> Tensor {
+        self.router.router_logits(xs)
+    }
+
+    /// 完整的MoE前向，同时返回输出隐藏状态和路由 logits
+    ///
+    /// 每个token按路由概率选出top-1专家，只把该token送入被选中的专家（wi → 激活函数（dense_act_fn，默认ReLU） → wo），
+    /// 输出为专家输出乘以其路由概率。输出形状与输入相同，logits 形状为 [..., num_experts]。
+    pub fn forward_with_router_logits(&self, xs: &Tensor) -> (Tensor, Tensor) {
+        let router_logits = self.router.router_logits(xs);
+        let (max_prob, expert_index) = router_logits.softmax(-1, Kind::Float).max_dim(-1, false);
+
+        // 展平为 [num_tokens, hidden_size]，按专家分组分发token
+        let hidden_size = xs.size().last().copied().unwrap_or(0);
+        let tokens = xs.reshape([-1, hidden_size]);
+        let expert_index = expert_index.reshape([-1]);
+        let max_prob = max_prob.reshape([-1, 1]);
+        let mut output = tokens.zeros_like();
+        for (expert_id, expert) in self.experts.iter().enumerate() {
+            let token_ids = expert_index.eq(expert_id as i64).nonzero().squeeze_dim(-1);
+            if token_ids.numel() == 0 {
+                continue;
+            }
+            let expert_output = expert.forward(&tokens.index_select(0, &token_ids)) * max_prob.index_select(0, &token_ids);
+            output = output.index_add(0, &token_ids, &expert_output);
+        }
+        (output.reshape(xs.size()), router_logits)
+    }
+}
+
+impl Module for SwitchTransformersSparseMLP {
+    /// 完整的MoE前向：每个token的输出为其被选中专家的输出乘以该专家的路由概率
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        self.forward_with_router_logits(xs).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Device;
+
+    #[test]
+    fn test_sparse_mlp_forward_dispatches_to_experts() {
+        tch::manual_seed(11);
+        let model_info = ModelInfo {
+            model_type: "switch_transformers".to_string(),
+            num_experts: 2,
+            hidden_size: 8,
+            intermediate_size: 16,
+            num_layers: 1,
+            ..Default::default()
+        };
+        let vs = nn::VarStore::new(Device::Cpu);
+        let mlp = SwitchTransformersSparseMLP::new(vs.root(), &model_info);
+        let xs = Tensor::randn([3, 5, 8], (Kind::Float, Device::Cpu));
+
+        let (output, router_logits) = tch::no_grad(|| mlp.forward_with_router_logits(&xs));
+        assert_eq!(output.size(), xs.size());
+        assert_eq!(router_logits.size(), vec![3, 5, 2]);
+
+        // 与稠密计算（所有专家都算一遍再按门控加权）一致
+        let gate = mlp.router().gate(&xs);
+        let mut dense = xs.zeros_like();
+        for expert_id in 0..mlp.num_experts() {
+            dense += mlp.expert(expert_id).unwrap().forward(&xs) * gate.select(-1, expert_id as i64).unsqueeze(-1);
+        }
+        assert!(output.allclose(&dense, 1e-5, 1e-6, false));
+    }
+
+    #[test]
+    fn test_expert_activation_changes_output() {
+        let mut model_info = ModelInfo {
+            model_type: "switch_transformers".to_string(),
+            num_experts: 2,
+            hidden_size: 8,
+            intermediate_size: 16,
+            num_layers: 1,
+            ..Default::default()
+        };
+        // 相同的随机种子保证两个专家的权重相同，只有激活函数不同
+        let build = |model_info: &ModelInfo| {
+            tch::manual_seed(5);
+            let vs = nn::VarStore::new(Device::Cpu);
+            let expert = SwitchTransformersDenseActDense::new(vs.root() / "expert", model_info);
+            (vs, expert)
+        };
+        let (_relu_vs, relu) = build(&model_info);
+        model_info.dense_act_fn = Some(ActivationKind::Gelu);
+        let (_gelu_vs, gelu) = build(&model_info);
+        assert_eq!(relu.activation(), ActivationKind::Relu);
+        assert_eq!(gelu.activation(), ActivationKind::Gelu);
+
+        let xs = Tensor::randn([4, 8], (Kind::Float, Device::Cpu));
+        let (relu_out, gelu_out) = tch::no_grad(|| (relu.forward(&xs), gelu.forward(&xs)));
+        assert_eq!(relu_out.size(), gelu_out.size());
+        assert!(!relu_out.allclose(&gelu_out, 1e-5, 1e-6, false));
+    }
+
+    #[test]
+    fn test_decoder_block_mlp_path() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformers".to_string(),
+            num_experts: 2,
+            hidden_size: 8,
+            intermediate_size: 16,
+            num_layers: 2,
+            num_decoder_layers: Some(2),
+            ..Default::default()
+        };
+        // 新建的 VarStore 中没有任何权重，参数按路径新建
+        let vs = nn::VarStore::new(Device::Cpu);
+        let mlp = SwitchTransformersSparseMLP::for_block(&vs, &model_info, LayerStack::Decoder, 1);
+        assert_eq!(mlp.num_experts(), 2);
+        let encoder_mlp = SwitchTransformersSparseMLP::for_block(&vs, &model_info, LayerStack::Encoder, 0);
+        assert_eq!(encoder_mlp.num_experts(), 2);
+
+        let variables = vs.variables();
+        for name in [
+            "decoder.block.1.layer.2.mlp.router.classifier.weight",
+            "decoder.block.1.layer.2.mlp.experts.expert_1.wo.weight",
+            "encoder.block.0.layer.1.mlp.router.classifier.weight",
+        ] {
+            assert!(variables.contains_key(name), "缺少参数 {}", name);
+        }
+
+        let xs = Tensor::randn([1, 3, 8], (Kind::Float, Device::Cpu));
+        assert_eq!(tch::no_grad(|| mlp.forward(&xs)).size(), xs.size());
+    }
+}
@@ -1,10 +1,44 @@
 // model_downloader.rs
 // 模型下载器，支持从Hugging Face等平台下载Switch Transformer模型及其配置信息。
 use crate::error::{Error, Result};
+use log::{info, warn};
 use crate::config::ModelInfo; // 导入统一管理的 ModelInfo
-use std::path::Path;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::{Read, Write};
 use std::process::Command;
+use std::time::Duration;
+
+/// Hugging Face 模型仓库信息中的文件清单
+#[derive(Debug, Deserialize)]
+struct RepoInfo {
+    siblings: Vec<RepoSibling>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RepoSibling {
+    rfilename: String,
+    /// 以LFS存储的大文件（如权重）的元数据，请求时带 `blobs=true` 才会返回
+    #[serde(default)]
+    lfs: Option<LfsInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LfsInfo {
+    sha256: String,
+}
+
+/// 分片 safetensors 的索引文件，记录每个参数所在的分片
+#[derive(Debug, Deserialize)]
+struct SafetensorsIndex {
+    weight_map: HashMap<String, String>,
+}
+
+/// 分片 safetensors 权重的索引文件名
+const SAFETENSORS_INDEX_FILE: &str = "model.safetensors.index.json";
 
 /// 模型下载器，支持从Hugging Face下载Switch Transformer模型
 pub struct ModelDownloader {
@@ -12,14 +46,36 @@ pub struct ModelDownloader {
     cache_dir: String,
     /// 是否使用镜像源
     use_mirror: bool,
+    /// 自定义的 Hugging Face 服务地址，设置后优先于镜像源和官方源
+    endpoint: Option<String>,
+    /// 是否使用 hf-hub 直接下载，而不是调用Python脚本
+    native_backend: bool,
+    /// 下载进度回调
+    progress: Option<ProgressCallback>,
+    /// 验证模型时是否校验权重文件的SHA256
+    verify_checksums: bool,
 }
 
+/// 下载进度回调，参数依次为已下载字节数和总字节数
+pub type ProgressCallback = Box<dyn Fn(u64, u64)>;
+
+/// 断点续传下载时每次读取写入的字节数
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 原生下载路径获取的文件
+#[cfg(feature = "hf-hub")]
+const NATIVE_DOWNLOAD_FILES: &[&str] = &["config.json", "tokenizer.json", "model.safetensors"];
+
 impl ModelDownloader {
     /// 创建新的模型下载器
     pub fn new(cache_dir: String) -> Self {
         Self {
             cache_dir,
             use_mirror: false,
+            endpoint: None,
+            native_backend: false,
+            progress: None,
+            verify_checksums: false,
         }
     }
 
@@ -28,20 +84,66 @@ impl ModelDownloader {
         self.use_mirror = use_mirror;
     }
 
+    /// 设置自定义的 Hugging Face 服务地址（如企业内部代理或其他镜像），必须以 http:// 或 https:// 开头
+    ///
+    /// 设置后Python脚本、原生下载和远程仓库查询都使用该地址，覆盖 `use_mirror` 的选择。
+    pub fn set_endpoint(&mut self, url: String) -> Result<()> {
+        let url = url.trim_end_matches('/');
+        let host = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .ok_or_else(|| Error::ConfigError(format!("endpoint: 服务地址 {} 必须以 http:// 或 https:// 开头", url)))?;
+        if host.is_empty() {
+            return Err(Error::ConfigError(format!("endpoint: 服务地址 {} 缺少主机名", url)));
+        }
+        self.endpoint = Some(url.to_string());
+        Ok(())
+    }
+
+    /// 设置是否使用 hf-hub 直接下载模型（需要启用 `hf-hub` 特性）
+    ///
+    /// 仓库只有 `.bin` 权重时仍回退到Python下载。
+    pub fn use_native_backend(&mut self, native_backend: bool) {
+        self.native_backend = native_backend;
+    }
+
+    /// 设置验证模型时是否校验权重文件的SHA256
+    ///
+    /// 启用后每个权重文件旁必须有 `<文件名>.sha256` 校验和文件，下载完成后会尝试
+    /// 从远程仓库元数据生成它们。
+    pub fn verify_checksums(&mut self, verify_checksums: bool) {
+        self.verify_checksums = verify_checksums;
+    }
+
+    /// 设置下载进度回调，原生下载权重文件期间会被周期性调用
+    pub fn on_progress(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
+    }
+
     /// 下载Switch Transformer模型
     pub fn download_switch_transformer(&self, model_name: &str) -> Result<String> {
         let model_dir = format!("{}/{}", self.cache_dir, model_name);
 
         // 检查模型是否已存在且完整，如果是，则跳过下载
         if Path::new(&model_dir).exists() && self.verify_model(&model_dir).is_ok() {
-            println!("模型 '{}' 已存在且文件完整，跳过下载。", model_name);
+            info!("模型 '{}' 已存在且文件完整，跳过下载。", model_name);
             return Ok(model_dir);
         }
         
-        println!("开始下载Switch Transformer模型: {}", model_name);
+        info!("开始下载Switch Transformer模型: {}", model_name);
         
         // 创建缓存目录
         fs::create_dir_all(&model_dir)?;
+
+        if self.native_backend {
+            if self.download_native(model_name, &model_dir)? {
+                self.write_remote_checksums(model_name, &model_dir);
+                self.verify_model(&model_dir)?;
+                info!("Switch Transformer模型下载完成: {}", model_dir);
+                return Ok(model_dir);
+            }
+            warn!("仓库 '{}' 缺少 safetensors 权重或 tokenizer.json，回退到Python下载。", model_name);
+        }
         
         // 使用Python脚本下载模型
         let python_script = self.generate_download_script(model_name, &model_dir)?;
@@ -65,18 +167,160 @@ impl ModelDownloader {
             let error_msg = String::from_utf8_lossy(&output.stderr);
             return Err(Error::ModelLoadError(format!("模型下载失败: {}", error_msg)));
         }
+        // 脚本以半精度重新保存权重，远程校验和不再适用，改为记录实际保存的文件
+        self.write_local_checksums(&model_dir)?;
+        self.verify_model(&model_dir)?;
         
-        println!("Switch Transformer模型下载完成: {}", model_dir);
+        info!("Switch Transformer模型下载完成: {}", model_dir);
         Ok(model_dir)
     }
 
-    /// 生成Python下载脚本
-    fn generate_download_script(&self, model_name: &str, model_dir: &str) -> Result<String> {
-        let mirror_url = if self.use_mirror {
+    /// 使用 hf-hub 下载 config.json、tokenizer.json 和 model.safetensors 到模型目录
+    ///
+    /// 远程仓库缺少其中任一文件时不下载并返回 false，由调用方回退到Python下载。
+    #[cfg(feature = "hf-hub")]
+    fn download_native(&self, model_name: &str, model_dir: &str) -> Result<bool> {
+        let repo = self.hf_repo(model_name)?;
+        let info = repo
+            .info()
+            .map_err(|e| Error::ModelLoadError(format!("获取仓库 {} 信息失败: {}", model_name, e)))?;
+        let all_present = NATIVE_DOWNLOAD_FILES
+            .iter()
+            .all(|file| info.siblings.iter().any(|sibling| sibling.rfilename == *file));
+        if !all_present {
+            return Ok(false);
+        }
+
+        for file in NATIVE_DOWNLOAD_FILES {
+            if *file == "model.safetensors" {
+                // 权重文件较大，直接下载到模型目录以便中断后续传
+                let url = format!("{}/{}/resolve/main/{}", self.endpoint(), model_name, file);
+                self.download_resumable(&url, &Path::new(model_dir).join(file))?;
+            } else {
+                self.fetch_native_file(&repo, file, Path::new(model_dir))?;
+            }
+        }
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "hf-hub"))]
+    fn download_native(&self, _model_name: &str, _model_dir: &str) -> Result<bool> {
+        Err(Error::ConfigError("原生下载需要启用 hf-hub 特性".to_string()))
+    }
+
+    /// 创建指向镜像或官方源的 hf-hub 仓库句柄，下载缓存放在 `cache_dir/.hf-cache`
+    #[cfg(feature = "hf-hub")]
+    fn hf_repo(&self, model_name: &str) -> Result<hf_hub::api::sync::ApiRepo> {
+        let api = hf_hub::api::sync::ApiBuilder::new()
+            .with_endpoint(self.endpoint().to_string())
+            .with_cache_dir(Path::new(&self.cache_dir).join(".hf-cache"))
+            .with_progress(false)
+            .build()
+            .map_err(|e| Error::ModelLoadError(format!("创建 hf-hub 客户端失败: {}", e)))?;
+        Ok(api.model(model_name.to_string()))
+    }
+
+    /// 下载单个文件并复制到模型目录，返回目标路径
+    #[cfg(feature = "hf-hub")]
+    fn fetch_native_file(
+        &self,
+        repo: &hf_hub::api::sync::ApiRepo,
+        file: &str,
+        model_dir: &Path,
+    ) -> Result<std::path::PathBuf> {
+        let cached = repo
+            .get(file)
+            .map_err(|e| Error::ModelLoadError(format!("下载 {} 失败: {}", file, e)))?;
+        let target = model_dir.join(file);
+        fs::copy(&cached, &target)?;
+        info!("已下载 {}", target.display());
+        Ok(target)
+    }
+
+    /// 下载 `url` 到 `target`，本地已有不完整的文件时通过HTTP Range请求只下载剩余部分
+    ///
+    /// 每写入一块数据按本地文件大小调用一次进度回调。
+    pub fn download_resumable(&self, url: &str, target: &Path) -> Result<()> {
+        let total = self.remote_file_size(url)?;
+        let mut local = fs::metadata(target).map(|metadata| metadata.len()).unwrap_or(0);
+        if local > total {
+            // 本地文件比远程文件还大，说明不是同一个文件，重新下载
+            fs::remove_file(target)?;
+            local = 0;
+        } else if local == total {
+            self.report_progress(total, total);
+            return Ok(());
+        } else if local > 0 {
+            info!("续传 {}：已有 {}/{} 字节", target.display(), local, total);
+        }
+
+        let mut request = ureq::get(url);
+        if local > 0 {
+            request = request.set("Range", &format!("bytes={}-", local));
+        }
+        let response = request
+            .call()
+            .map_err(|e| Error::ModelLoadError(format!("下载 {} 失败: {}", url, e)))?;
+        // 服务端不支持Range时返回完整文件，从头写入
+        let (mut file, mut done) = if response.status() == 206 {
+            (fs::OpenOptions::new().append(true).open(target)?, local)
+        } else {
+            (fs::File::create(target)?, 0)
+        };
+        let mut reader = response.into_reader();
+        let mut buffer = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            done += read as u64;
+            self.report_progress(done, total);
+        }
+        file.flush()?;
+
+        if done != total {
+            return Err(Error::ModelLoadError(format!(
+                "下载 {} 不完整：{}/{} 字节", url, done, total
+            )));
+        }
+        self.report_progress(done, total);
+        Ok(())
+    }
+
+    /// 通过HEAD请求获取远程文件大小，跟随重定向并取最后一个响应的 Content-Length
+    fn remote_file_size(&self, url: &str) -> Result<u64> {
+        let response = ureq::head(url)
+            .timeout(Duration::from_secs(30))
+            .call()
+            .map_err(|e| Error::Other(format!("请求 {} 失败: {}", url, e)))?;
+        response
+            .header("Content-Length")
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .ok_or_else(|| Error::ModelLoadError(format!("无法获取 {} 的文件大小", url)))
+    }
+
+    fn report_progress(&self, done: u64, total: u64) {
+        if let Some(callback) = &self.progress {
+            callback(done, total);
+        }
+    }
+
+    /// 服务地址：自定义地址优先，否则为镜像或官方源
+    fn endpoint(&self) -> &str {
+        if let Some(endpoint) = &self.endpoint {
+            endpoint
+        } else if self.use_mirror {
             "https://hf-mirror.com"
         } else {
             "https://huggingface.co"
-        };
+        }
+    }
+
+    /// 生成Python下载脚本
+    fn generate_download_script(&self, model_name: &str, model_dir: &str) -> Result<String> {
+        let mirror_url = self.endpoint();
         
         let script = format!(
             r#"
@@ -149,19 +393,222 @@ if __name__ == "__main__":
             return Err(Error::ModelLoadError("缺少必要文件: tokenizer.json".to_string()));
         }
 
-        // 检查模型权重文件（支持 .bin 和 .safetensors 两种格式）
+        // 检查模型权重文件（支持 .bin、.safetensors 以及带索引的分片 safetensors）
         let has_bin = model_path.join("pytorch_model.bin").exists();
         let has_safetensors = model_path.join("model.safetensors").exists();
+        let has_sharded = model_path.join(SAFETENSORS_INDEX_FILE).exists();
+
+        if has_sharded {
+            let missing: Vec<String> = Self::indexed_shards(model_path)?
+                .into_iter()
+                .filter(|shard| !model_path.join(shard).exists())
+                .collect();
+            if !missing.is_empty() {
+                return Err(Error::ModelLoadError(format!(
+                    "缺少 {} 引用的权重分片: {}", SAFETENSORS_INDEX_FILE, missing.join(", ")
+                )));
+            }
+        }
 
-        if !has_bin && !has_safetensors {
+        if !has_bin && !has_safetensors && !has_sharded {
             return Err(Error::ModelLoadError(
-                "缺少模型权重文件 (pytorch_model.bin 或 model.safetensors)".to_string()
+                "缺少模型权重文件 (pytorch_model.bin、model.safetensors 或 model.safetensors.index.json)".to_string()
             ));
         }
+
+        if self.verify_checksums {
+            for weight_file in Self::existing_weight_files(model_path)? {
+                Self::verify_checksum(&weight_file)?;
+            }
+        }
         
         Ok(true)
     }
 
+    /// 模型目录中的权重文件，分片 safetensors 按索引中的分片名排序返回
+    pub fn weight_files(&self, model_dir: &str) -> Result<Vec<PathBuf>> {
+        Self::existing_weight_files(Path::new(model_dir))
+    }
+
+    fn existing_weight_files(model_path: &Path) -> Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = ["pytorch_model.bin", "model.safetensors"]
+            .iter()
+            .map(|file| model_path.join(file))
+            .filter(|path| path.exists())
+            .collect();
+        if model_path.join(SAFETENSORS_INDEX_FILE).exists() {
+            files.extend(
+                Self::indexed_shards(model_path)?
+                    .into_iter()
+                    .map(|shard| model_path.join(shard))
+                    .filter(|path| path.exists()),
+            );
+        }
+        Ok(files)
+    }
+
+    /// 读取分片索引，返回去重并排序后的分片文件名
+    fn indexed_shards(model_path: &Path) -> Result<Vec<String>> {
+        let index_path = model_path.join(SAFETENSORS_INDEX_FILE);
+        let content = fs::read_to_string(&index_path)
+            .map_err(|e| Error::ModelLoadError(format!("无法读取 {}: {}", index_path.display(), e)))?;
+        let index: SafetensorsIndex = serde_json::from_str(&content)
+            .map_err(|e| Error::ModelLoadError(format!("解析 {} 失败: {}", index_path.display(), e)))?;
+        let mut shards: Vec<String> = index.weight_map.into_values().collect();
+        shards.sort_unstable();
+        shards.dedup();
+        Ok(shards)
+    }
+
+    /// 权重文件对应的校验和文件路径
+    fn checksum_path(weight_file: &Path) -> PathBuf {
+        let mut path = weight_file.as_os_str().to_owned();
+        path.push(".sha256");
+        PathBuf::from(path)
+    }
+
+    /// 计算文件的SHA256，返回小写十六进制字符串
+    fn sha256_hex(path: &Path) -> Result<String> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// 将权重文件的SHA256与校验和文件比对，校验和文件的第一个字段为十六进制摘要
+    fn verify_checksum(weight_file: &Path) -> Result<()> {
+        let checksum_path = Self::checksum_path(weight_file);
+        let expected = fs::read_to_string(&checksum_path)
+            .map_err(|_| Error::ModelLoadError(format!("缺少校验和文件: {}", checksum_path.display())))?;
+        let expected = expected.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+        let actual = Self::sha256_hex(weight_file)?;
+        if actual != expected {
+            return Err(Error::ModelLoadError(format!(
+                "权重文件 {} 校验和不匹配：期望 {}，实际 {}",
+                weight_file.display(), expected, actual
+            )));
+        }
+        Ok(())
+    }
+
+    /// 启用校验时，按远程仓库元数据中的SHA256为缺少校验和文件的权重生成 `.sha256` 文件
+    ///
+    /// 只适用于与远程仓库逐字节相同的文件（原生下载）。
+    /// 获取元数据失败时只打印提示，随后的 verify_model 会因缺少校验和文件而报错。
+    fn write_remote_checksums(&self, model_name: &str, model_dir: &str) {
+        if !self.verify_checksums {
+            return;
+        }
+        let checksums = match self.fetch_remote_checksums(model_name) {
+            Ok(checksums) => checksums,
+            Err(e) => {
+                warn!("无法获取远程校验和 ({})", e);
+                return;
+            }
+        };
+        for weight_file in Self::existing_weight_files(Path::new(model_dir)).unwrap_or_default() {
+            let checksum_path = Self::checksum_path(&weight_file);
+            let name = weight_file.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if let (false, Some(sha256)) = (checksum_path.exists(), checksums.get(name)) {
+                if let Err(e) = fs::write(&checksum_path, format!("{}  {}\n", sha256, name)) {
+                    warn!("写入校验和文件 {} 失败: {}", checksum_path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// 启用校验时，按本地权重文件的实际内容生成 `.sha256` 文件，覆盖已有的校验和文件
+    ///
+    /// 用于Python脚本重新保存的权重，之后可据此发现文件损坏。
+    fn write_local_checksums(&self, model_dir: &str) -> Result<()> {
+        if !self.verify_checksums {
+            return Ok(());
+        }
+        for weight_file in Self::existing_weight_files(Path::new(model_dir))? {
+            let name = weight_file.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let sha256 = Self::sha256_hex(&weight_file)?;
+            fs::write(Self::checksum_path(&weight_file), format!("{}  {}\n", sha256, name))?;
+        }
+        Ok(())
+    }
+
+    /// 按远程仓库的文件清单验证本地模型，返回本地缺失的必要文件
+    ///
+    /// 无法访问网络时回退到仅检查本地的 `verify_model`，本地完整则返回空列表。
+    pub fn verify_against_remote(&self, model_name: &str) -> Result<Vec<String>> {
+        let model_dir = format!("{}/{}", self.cache_dir, model_name);
+        match self.fetch_remote_file_list(model_name) {
+            Ok(remote_files) => Ok(Self::missing_required_files(Path::new(&model_dir), &remote_files)),
+            Err(e) => {
+                warn!("无法获取远程文件清单 ({})，回退到本地验证。", e);
+                self.verify_model(&model_dir)?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// 获取远程仓库的文件清单
+    fn fetch_remote_file_list(&self, model_name: &str) -> Result<Vec<String>> {
+        let repo_info = self.fetch_repo_info(model_name, false)?;
+        Ok(repo_info.siblings.into_iter().map(|sibling| sibling.rfilename).collect())
+    }
+
+    /// 获取远程仓库中LFS文件的SHA256，文件名 -> 十六进制摘要
+    fn fetch_remote_checksums(&self, model_name: &str) -> Result<HashMap<String, String>> {
+        let repo_info = self.fetch_repo_info(model_name, true)?;
+        Ok(repo_info
+            .siblings
+            .into_iter()
+            .filter_map(|sibling| Some((sibling.rfilename, sibling.lfs?.sha256)))
+            .collect())
+    }
+
+    /// 获取远程仓库信息，`blobs` 为true时附带LFS文件元数据
+    fn fetch_repo_info(&self, model_name: &str, blobs: bool) -> Result<RepoInfo> {
+        let mut url = format!("{}/api/models/{}", self.endpoint(), model_name);
+        if blobs {
+            url.push_str("?blobs=true");
+        }
+        let response = ureq::get(&url)
+            .timeout(Duration::from_secs(10))
+            .call()
+            .map_err(|e| Error::Other(format!("请求 {} 失败: {}", url, e)))?;
+        serde_json::from_reader(response.into_reader())
+            .map_err(|e| Error::ModelLoadError(format!("解析远程文件清单失败: {}", e)))
+    }
+
+    /// 根据远程文件清单找出本地缺失的必要文件
+    ///
+    /// 必要文件包括配置、tokenizer 和权重；仓库提供 safetensors 时不再要求 .bin 权重，
+    /// README、Flax/TF 权重等推理用不到的文件不计入。
+    pub fn missing_required_files(model_dir: &Path, remote_files: &[String]) -> Vec<String> {
+        let has_safetensors = remote_files.iter().any(|file| file.ends_with(".safetensors"));
+        remote_files
+            .iter()
+            .filter(|file| Self::is_required_file(file, has_safetensors))
+            .filter(|file| !model_dir.join(file).exists())
+            .cloned()
+            .collect()
+    }
+
+    fn is_required_file(file: &str, has_safetensors: bool) -> bool {
+        let name = file.rsplit('/').next().unwrap_or(file);
+        if name == "config.json"
+            || name.starts_with("tokenizer")
+            || name == "special_tokens_map.json"
+            || name == "spiece.model"
+        {
+            return true;
+        }
+        if name.ends_with(".safetensors") || name == "model.safetensors.index.json" {
+            return true;
+        }
+        // 只有仓库没有 safetensors 权重时才需要 PyTorch .bin 权重
+        !has_safetensors
+            && name.starts_with("pytorch_model")
+            && (name.ends_with(".bin") || name.ends_with(".bin.index.json"))
+    }
+
     /// 获取模型配置信息
     pub fn get_model_info(&self, model_dir: &str) -> Result<ModelInfo> {
         let config_path = Path::new(model_dir).join("config.json");
@@ -174,7 +621,59 @@ if __name__ == "__main__":
             .map_err(|e| Error::ModelLoadError(format!("解析模型配置文件失败: {}", e)))?;
         
         // 将解析后的结构体转换为内部使用的 ModelInfo
-        Ok(config_json.into())
+        config_json.try_into()
+    }
+
+    /// 列出缓存目录中已下载的模型，返回 (模型名称, 模型信息)，按名称排序
+    ///
+    /// 模型名称为相对缓存目录的路径（如 `google/switch-base-8`），最多向下查找两级；
+    /// 未通过 verify_model 或无法解析 config.json 的目录会被跳过，缓存目录不存在时返回空列表。
+    pub fn list_local_models(&self) -> Result<Vec<(String, ModelInfo)>> {
+        let cache_path = Path::new(&self.cache_dir);
+        if !cache_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut candidates = Vec::new();
+        for (name, path) in Self::subdirectories(cache_path)? {
+            if path.join("config.json").exists() {
+                candidates.push((name, path));
+            } else {
+                // 形如 组织/模型 的两级目录
+                for (sub_name, sub_path) in Self::subdirectories(&path)? {
+                    candidates.push((format!("{}/{}", name, sub_name), sub_path));
+                }
+            }
+        }
+
+        let mut models = Vec::new();
+        for (name, path) in candidates {
+            let model_dir = path.to_string_lossy();
+            let info = self.verify_model(&model_dir).and_then(|_| self.get_model_info(&model_dir));
+            match info {
+                Ok(info) => models.push((name, info)),
+                Err(e) => warn!("跳过无效的模型目录 {}: {}", model_dir, e),
+            }
+        }
+        models.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(models)
+    }
+
+    /// 目录下的子目录 (名称, 路径)，跳过以 `.` 开头的隐藏目录（如 hf-hub 的下载缓存）
+    fn subdirectories(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| Error::ModelLoadError(format!("无法读取目录 {}: {}", dir.display(), e)))?;
+        let mut subdirectories = Vec::new();
+        for entry in entries {
+            let entry = entry
+                .map_err(|e| Error::ModelLoadError(format!("无法读取目录 {}: {}", dir.display(), e)))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            if path.is_dir() && !name.starts_with('.') {
+                subdirectories.push((name, path));
+            }
+        }
+        Ok(subdirectories)
     }
 }
 
@@ -195,4 +694,356 @@ pub const SWITCH_TRANSFORMER_MODELS: &[&str] = &[
     "google/switch-xxl-32",           // 32个专家，超大版本
     "google/switch-xxl-64",           // 64个专家，超大版本
     "google/switch-xxl-128",          // 128个专家，超大版本
-]; 
\ No newline at end of file
+]; 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_safetensors_shard_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "tokenizer.json", "model.safetensors.index.json", "model-00001-of-00002.safetensors"] {
+            fs::write(dir.path().join(file), b"{}").unwrap();
+        }
+
+        // 模拟的远程文件清单
+        let remote_files: Vec<String> = [
+            ".gitattributes",
+            "README.md",
+            "config.json",
+            "tokenizer.json",
+            "model.safetensors.index.json",
+            "model-00001-of-00002.safetensors",
+            "model-00002-of-00002.safetensors",
+            "pytorch_model.bin",
+            "flax_model.msgpack",
+        ]
+        .iter()
+        .map(|file| file.to_string())
+        .collect();
+
+        let missing = ModelDownloader::missing_required_files(dir.path(), &remote_files);
+        assert_eq!(missing, vec!["model-00002-of-00002.safetensors".to_string()]);
+    }
+
+    #[test]
+    fn test_bin_weights_required_without_safetensors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("config.json"), b"{}").unwrap();
+
+        let remote_files = vec!["config.json".to_string(), "pytorch_model.bin".to_string()];
+        let missing = ModelDownloader::missing_required_files(dir.path(), &remote_files);
+        assert_eq!(missing, vec!["pytorch_model.bin".to_string()]);
+    }
+
+    #[cfg(feature = "hf-hub")]
+    #[test]
+    fn test_native_download_config_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloader = ModelDownloader::new(dir.path().to_str().unwrap().to_string());
+        let model_dir = dir.path().join("google/switch-base-8");
+        fs::create_dir_all(&model_dir).unwrap();
+
+        // 无法访问网络时跳过
+        let config_path = match downloader
+            .hf_repo("google/switch-base-8")
+            .and_then(|repo| downloader.fetch_native_file(&repo, "config.json", &model_dir))
+        {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        assert_eq!(config_path, model_dir.join("config.json"));
+        let model_info = downloader.get_model_info(model_dir.to_str().unwrap()).unwrap();
+        assert_eq!(model_info.num_experts, 8);
+    }
+
+    /// 在本地启动只支持 HEAD 和带 Range 的 GET 的HTTP服务，返回地址和收到的请求头
+    fn serve_file(data: Vec<u8>, num_requests: usize) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/model.safetensors", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(num_requests) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let start = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Range: bytes="))
+                    .and_then(|range| range.trim().trim_end_matches('-').parse::<usize>().ok())
+                    .unwrap_or(0);
+                let body = &data[start..];
+                let status = if start > 0 { "206 Partial Content" } else { "200 OK" };
+                let mut response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                    status, body.len(), start, data.len() - 1, data.len()
+                )
+                .into_bytes();
+                if head.starts_with("GET") {
+                    response.extend_from_slice(body);
+                }
+                seen.lock().unwrap().push(head);
+                stream.write_all(&response).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_resume_truncated_download() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+        let (url, requests) = serve_file(data.clone(), 2);
+
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "tokenizer.json"] {
+            fs::write(dir.path().join(file), b"{}").unwrap();
+        }
+        let target = dir.path().join("model.safetensors");
+        fs::write(&target, &data[..50_000]).unwrap();
+
+        let progress = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut downloader = ModelDownloader::new(dir.path().to_str().unwrap().to_string());
+        let recorded = std::rc::Rc::clone(&progress);
+        downloader.on_progress(Box::new(move |done, total| recorded.borrow_mut().push((done, total))));
+
+        downloader.download_resumable(&url, &target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), data);
+        assert!(downloader.verify_model(dir.path().to_str().unwrap()).is_ok());
+
+        // 只请求了缺失的部分，进度从已有的字节数开始并以完成结束
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("HEAD"));
+        assert!(requests[1].contains("Range: bytes=50000-"));
+        let progress = progress.borrow();
+        assert!(progress.iter().all(|&(done, total)| done >= 50_000 && total == 200_000));
+        assert_eq!(progress.last(), Some(&(200_000, 200_000)));
+    }
+
+    #[test]
+    fn test_checksum_mismatch_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "tokenizer.json"] {
+            fs::write(dir.path().join(file), b"{}").unwrap();
+        }
+        let weights = dir.path().join("model.safetensors");
+        fs::write(&weights, b"not really safetensors").unwrap();
+        let model_dir = dir.path().to_str().unwrap();
+
+        let mut downloader = ModelDownloader::new(model_dir.to_string());
+        // 默认不校验
+        assert!(downloader.verify_model(model_dir).is_ok());
+
+        downloader.verify_checksums(true);
+        // 缺少校验和文件
+        assert!(matches!(downloader.verify_model(model_dir), Err(Error::ModelLoadError(_))));
+
+        let sidecar = dir.path().join("model.safetensors.sha256");
+        fs::write(&sidecar, format!("{}  model.safetensors\n", "0".repeat(64))).unwrap();
+        match downloader.verify_model(model_dir) {
+            Err(Error::ModelLoadError(message)) => assert!(message.contains("校验和不匹配")),
+            other => panic!("期望校验和不匹配，实际为 {:?}", other),
+        }
+
+        let actual = ModelDownloader::sha256_hex(&weights).unwrap();
+        fs::write(&sidecar, actual.to_uppercase()).unwrap();
+        assert!(downloader.verify_model(model_dir).is_ok());
+    }
+
+    #[test]
+    fn test_local_checksums_replace_stale_remote_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "tokenizer.json"] {
+            fs::write(dir.path().join(file), b"{}").unwrap();
+        }
+        // Python脚本重新保存的权重与远程文件不同，旧的远程校验和不再匹配
+        fs::write(dir.path().join("model.safetensors"), b"re-saved in float16").unwrap();
+        let sidecar = dir.path().join("model.safetensors.sha256");
+        fs::write(&sidecar, format!("{}  model.safetensors\n", "0".repeat(64))).unwrap();
+        let model_dir = dir.path().to_str().unwrap();
+
+        let mut downloader = ModelDownloader::new(model_dir.to_string());
+        downloader.verify_checksums(true);
+        assert!(downloader.verify_model(model_dir).is_err());
+
+        downloader.write_local_checksums(model_dir).unwrap();
+        assert!(downloader.verify_model(model_dir).is_ok());
+    }
+
+    #[test]
+    fn test_sharded_safetensors_missing_shard_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "tokenizer.json"] {
+            fs::write(dir.path().join(file), b"{}").unwrap();
+        }
+        let index = r#"{
+            "metadata": { "total_size": 3 },
+            "weight_map": {
+                "shared.weight": "model-00001-of-00003.safetensors",
+                "encoder.block.0.layer.0.SelfAttention.q.weight": "model-00001-of-00003.safetensors",
+                "encoder.block.1.layer.1.mlp.router.classifier.weight": "model-00002-of-00003.safetensors",
+                "decoder.final_layer_norm.weight": "model-00003-of-00003.safetensors"
+            }
+        }"#;
+        fs::write(dir.path().join(SAFETENSORS_INDEX_FILE), index).unwrap();
+        for shard in ["model-00001-of-00003.safetensors", "model-00003-of-00003.safetensors"] {
+            fs::write(dir.path().join(shard), b"shard").unwrap();
+        }
+        let model_dir = dir.path().to_str().unwrap();
+        let downloader = ModelDownloader::new(model_dir.to_string());
+
+        match downloader.verify_model(model_dir) {
+            Err(Error::ModelLoadError(message)) => {
+                assert!(message.contains("model-00002-of-00003.safetensors"));
+                assert!(!message.contains("model-00001-of-00003.safetensors"));
+            }
+            other => panic!("期望缺少分片的错误，实际为 {:?}", other),
+        }
+
+        fs::write(dir.path().join("model-00002-of-00003.safetensors"), b"shard").unwrap();
+        assert!(downloader.verify_model(model_dir).is_ok());
+        let weight_files = downloader.weight_files(model_dir).unwrap();
+        assert_eq!(weight_files.len(), 3);
+        assert!(weight_files[0].ends_with("model-00001-of-00003.safetensors"));
+    }
+
+    #[test]
+    fn test_get_model_info_switch_base_8_config() {
+        // google/switch-base-8 的 config.json
+        let config = r#"{
+            "_name_or_path": "google/switch-base-8",
+            "architectures": ["SwitchTransformersForConditionalGeneration"],
+            "batch_prioritized_routing": false,
+            "d_ff": 3072,
+            "d_kv": 64,
+            "d_model": 768,
+            "decoder_sparse_step": 2,
+            "decoder_start_token_id": 0,
+            "dense_act_fn": "relu",
+            "dropout_rate": 0.1,
+            "encoder_sparse_step": 2,
+            "eos_token_id": 1,
+            "expert_capacity": 64,
+            "initializer_factor": 1.0,
+            "is_encoder_decoder": true,
+            "is_gated_act": false,
+            "layer_norm_epsilon": 1e-06,
+            "model_type": "switch_transformers",
+            "num_decoder_layers": 12,
+            "num_experts": 8,
+            "num_heads": 12,
+            "num_layers": 12,
+            "num_selected_experts": 1,
+            "num_sparse_decoder_layers": 6,
+            "num_sparse_encoder_layers": 6,
+            "pad_token_id": 0,
+            "relative_attention_max_distance": 128,
+            "relative_attention_num_buckets": 32,
+            "router_aux_loss_coef": 0.001,
+            "router_bias": false,
+            "router_dtype": "float32",
+            "router_ignore_padding_tokens": false,
+            "router_jitter_noise": 0.01,
+            "router_type": "tokens_masked",
+            "router_z_loss_coef": 0.001,
+            "torch_dtype": "float32",
+            "transformers_version": "4.26.0.dev0",
+            "use_cache": true,
+            "vocab_size": 32128
+        }"#;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("config.json"), config).unwrap();
+        let model_dir = dir.path().to_str().unwrap();
+        let downloader = ModelDownloader::new(model_dir.to_string());
+
+        let info = downloader.get_model_info(model_dir).unwrap();
+        assert_eq!(info.model_type, "switch_transformers");
+        assert_eq!(info.num_experts, 8);
+        assert_eq!(info.hidden_size, 768);
+        assert_eq!(info.intermediate_size, 3072);
+        assert_eq!(info.num_layers, 12);
+        assert_eq!(info.num_decoder_layers, Some(12));
+        assert_eq!(info.num_heads, Some(12));
+        assert_eq!(info.vocab_size, Some(32128));
+        assert_eq!(info.expert_capacity, Some(64));
+        assert_eq!(info.router_jitter_noise, Some(0.01));
+        assert_eq!(info.dense_act_fn, Some(crate::config::ActivationKind::Relu));
+
+        // 旧版配置缺少新增字段时仍能解析
+        fs::write(
+            dir.path().join("config.json"),
+            r#"{"model_type": "switch_transformers", "num_experts": 8, "d_model": 768, "d_ff": 3072, "num_layers": 12}"#,
+        ).unwrap();
+        let info = downloader.get_model_info(model_dir).unwrap();
+        assert!(info.num_decoder_layers.is_none());
+        assert!(info.num_heads.is_none());
+        assert!(info.vocab_size.is_none());
+        assert!(info.expert_capacity.is_none());
+        assert!(info.router_jitter_noise.is_none());
+        assert!(info.dense_act_fn.is_none());
+    }
+
+    #[test]
+    fn test_list_local_models_skips_invalid() {
+        let cache = tempfile::tempdir().unwrap();
+        let config = |num_experts: usize| format!(
+            r#"{{"model_type": "switch_transformers", "num_experts": {}, "d_model": 768, "d_ff": 3072, "num_layers": 12}}"#,
+            num_experts
+        );
+        // 两个完整的模型
+        for (name, num_experts) in [("google/switch-base-8", 8), ("google/switch-base-16", 16)] {
+            let dir = cache.path().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("config.json"), config(num_experts)).unwrap();
+            fs::write(dir.join("tokenizer.json"), b"{}").unwrap();
+            fs::write(dir.join("model.safetensors"), b"weights").unwrap();
+        }
+        // 缺少权重文件的模型
+        let broken = cache.path().join("google/switch-base-32");
+        fs::create_dir_all(&broken).unwrap();
+        fs::write(broken.join("config.json"), config(32)).unwrap();
+        fs::write(broken.join("tokenizer.json"), b"{}").unwrap();
+
+        let downloader = ModelDownloader::new(cache.path().to_str().unwrap().to_string());
+        let models = downloader.list_local_models().unwrap();
+        let names: Vec<&str> = models.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["google/switch-base-16", "google/switch-base-8"]);
+        assert_eq!(models[0].1.num_experts, 16);
+        assert_eq!(models[1].1.num_experts, 8);
+
+        let missing = ModelDownloader::new(cache.path().join("missing").to_str().unwrap().to_string());
+        assert!(missing.list_local_models().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_custom_endpoint_in_download_script() {
+        let mut downloader = ModelDownloader::new("downloads".to_string());
+        downloader.use_mirror(true);
+        let script = downloader.generate_download_script("google/switch-base-8", "downloads/google/switch-base-8").unwrap();
+        assert!(script.contains("os.environ['HF_ENDPOINT'] = 'https://hf-mirror.com'"));
+
+        downloader.set_endpoint("https://hf.proxy.example.com/".to_string()).unwrap();
+        let script = downloader.generate_download_script("google/switch-base-8", "downloads/google/switch-base-8").unwrap();
+        assert!(script.contains("os.environ['HF_ENDPOINT'] = 'https://hf.proxy.example.com'"));
+        assert!(!script.contains("hf-mirror.com"));
+
+        for invalid in ["ftp://hf.example.com", "hf.example.com", "https://"] {
+            assert!(matches!(downloader.set_endpoint(invalid.to_string()), Err(Error::ConfigError(_))), "{}", invalid);
+        }
+        // 无效地址不会覆盖已设置的地址
+        assert_eq!(downloader.endpoint(), "https://hf.proxy.example.com");
+    }
+}
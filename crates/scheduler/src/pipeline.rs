@@ -0,0 +1,625 @@
+// pipeline.rs
+// 推理流水线，根据一份配置文件组装模型信息、任务拆分器、调度器和执行器。
+use crate::config::{ModelInfo, SchedulerConfig};
+use crate::error::{Error, Result};
+use crate::scheduler::TaskScheduler;
+use crate::task::{MoeTask, TaskPriority};
+use crate::task_executor::{CpuTaskExecutor, Executor, TaskExecutor, DEFAULT_MEMORY_FRACTION};
+use crate::task_splitter::{SplitStrategy, TaskSplitter};
+use crate::types::{DeviceId, GateWeights};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// 流水线使用的计算设备，配置文件中写作 "cpu" 或 "cuda:<id>"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PipelineDevice {
+    /// 仅在主机上运行，不创建CUDA执行器
+    Cpu,
+    /// 指定ID的GPU
+    Cuda(DeviceId),
+}
+
+impl FromStr for PipelineDevice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        if s == "cpu" {
+            return Ok(PipelineDevice::Cpu);
+        }
+        if s == "cuda" {
+            return Ok(PipelineDevice::Cuda(DeviceId(0)));
+        }
+        match s.strip_prefix("cuda:").map(|id| id.parse::<u32>()) {
+            Some(Ok(id)) => Ok(PipelineDevice::Cuda(DeviceId(id))),
+            _ => Err(Error::ConfigError(format!("无法识别的设备: {}", s))),
+        }
+    }
+}
+
+impl TryFrom<String> for PipelineDevice {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<PipelineDevice> for String {
+    fn from(device: PipelineDevice) -> Self {
+        device.to_string()
+    }
+}
+
+impl fmt::Display for PipelineDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineDevice::Cpu => write!(f, "cpu"),
+            PipelineDevice::Cuda(id) => write!(f, "cuda:{}", id),
+        }
+    }
+}
+
+fn default_memory_fraction() -> f32 {
+    DEFAULT_MEMORY_FRACTION
+}
+
+fn default_max_concurrent_tasks() -> usize {
+    4
+}
+
+/// 流水线配置，可从JSON或TOML文件加载
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    /// 模型目录（包含 config.json）
+    pub model_dir: String,
+    /// 拆分策略
+    pub strategy: SplitStrategy,
+    /// 计算设备
+    pub device: PipelineDevice,
+    /// 执行器内存池可使用的显存比例
+    #[serde(default = "default_memory_fraction")]
+    pub memory_fraction: f32,
+    /// 最大并发任务数
+    #[serde(default = "default_max_concurrent_tasks")]
+    pub max_concurrent_tasks: usize,
+}
+
+impl PipelineConfig {
+    /// 从配置文件加载配置，扩展名为 `.toml` 时按TOML解析，否则按JSON解析
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::ConfigError(format!("读取配置文件 {} 失败: {}", path.display(), e)))?;
+        let is_toml = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+        let parsed = if is_toml {
+            toml::from_str(&contents).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&contents).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| Error::ConfigError(format!("解析配置文件 {} 失败: {}", path.display(), e)))
+    }
+
+    /// 验证与模型无关的配置项
+    pub fn validate(&self) -> Result<()> {
+        if !(self.memory_fraction > 0.0 && self.memory_fraction <= 1.0) {
+            return Err(Error::ConfigError(format!(
+                "显存使用比例 {} 必须在 (0.0, 1.0] 范围内", self.memory_fraction
+            )));
+        }
+        self.scheduler_config().validate()
+    }
+
+    /// 由配置生成调度器配置
+    ///
+    /// CPU设备用不到GPU列表，保留默认值，使生成的配置总能通过 `SchedulerConfig::validate`。
+    pub fn scheduler_config(&self) -> SchedulerConfig {
+        let mut config = SchedulerConfig {
+            max_concurrent_tasks: self.max_concurrent_tasks,
+            ..SchedulerConfig::default()
+        };
+        if let PipelineDevice::Cuda(id) = self.device {
+            config.gpu_ids = vec![id];
+        }
+        config
+    }
+}
+
+/// 推理流水线，持有按配置组装好的各组件：拆分、调度执行、合并共用一套流程，执行后端可替换
+pub struct Pipeline {
+    /// 流水线配置
+    pub config: PipelineConfig,
+    /// 任务拆分器
+    pub splitter: TaskSplitter,
+    /// 任务调度器
+    pub scheduler: TaskScheduler,
+    /// 执行后端，CPU设备为 CpuTaskExecutor，CUDA设备为 TaskExecutor
+    pub executor: Arc<dyn Executor>,
+    /// 下一个请求的编号，用于生成父任务ID，保证不同调用之间子任务ID不重复
+    next_request: AtomicU64,
+}
+
+impl Pipeline {
+    /// 由配置组装流水线：加载模型信息、创建拆分器、调度器，并按设备创建执行器
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn new(config: PipelineConfig) -> Result<Self> {
+        config.validate()?;
+        let executor: Arc<dyn Executor> = match config.device {
+            PipelineDevice::Cpu => Arc::new(CpuTaskExecutor::new()),
+            PipelineDevice::Cuda(id) => Arc::new(TaskExecutor::with_memory_fraction(id, config.memory_fraction)?),
+        };
+        Self::with_executor(config, executor)
+    }
+
+    /// 使用给定的执行后端组装流水线，配置中的设备只用于生成调度器配置，不再创建执行器
+    pub fn with_executor(config: PipelineConfig, executor: Arc<dyn Executor>) -> Result<Self> {
+        config.validate()?;
+
+        // 拆分器负责加载 config.json 并验证策略与模型是否匹配
+        let splitter = TaskSplitter::new_from_model_dir(&config.model_dir, config.strategy.clone())?;
+        let scheduler = TaskScheduler::new(config.scheduler_config());
+
+        Ok(Self {
+            config,
+            splitter,
+            scheduler,
+            executor,
+            next_request: AtomicU64::new(0),
+        })
+    }
+
+    /// 从JSON或TOML配置文件组装流水线
+    pub fn from_config_file(path: &Path) -> Result<Self> {
+        let config = PipelineConfig::from_file(path)?;
+        Self::new(config)
+    }
+
+    /// 模型信息
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.splitter.model_info
+    }
+
+    /// 拆分策略
+    pub fn strategy(&self) -> &SplitStrategy {
+        &self.splitter.strategy
+    }
+
+    /// 计算设备
+    pub fn device(&self) -> PipelineDevice {
+        self.config.device
+    }
+
+    /// 对单个输入执行推理：拆分、调度执行、合并
+    pub fn infer(&self, input: &[u8]) -> Result<Vec<u8>> {
+        self.infer_with_gates(input, None)
+    }
+
+    /// 按门控权重对单个输入执行推理，任一子任务失败时返回第一个错误
+    ///
+    /// 提供门控权重时按权重拆分（`ByTopKExpert` 只为选中的专家建任务），合并时也使用同一组权重。
+    pub fn infer_with_gates(&self, input: &[u8], gates: Option<GateWeights>) -> Result<Vec<u8>> {
+        let parent_task_id = self.next_parent_task_id();
+        let tasks = match &gates {
+            Some(gates) => self.splitter.split_task_with_gates(input, &parent_task_id, TaskPriority::Normal, gates)?,
+            None => self.splitter.split_task(input, &parent_task_id, TaskPriority::Normal)?,
+        };
+        let mut outputs = self.run_requests(vec![tasks], gates)?;
+        Ok(outputs.remove(0))
+    }
+
+    /// 批量推理，按输入顺序返回各请求的输出
+    ///
+    /// 所有请求的子任务交错提交到调度器。执行器可在线程间共享时（如 CpuTaskExecutor）
+    /// 最多由 `max_concurrent_tasks` 个线程并发执行；否则（如只有一个流的CUDA执行器）
+    /// 子任务按调度顺序在当前线程上依次执行。
+    pub fn infer_batch(&self, inputs: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+        let mut per_request_tasks = Vec::with_capacity(inputs.len());
+        let mut stream_id_base = 0;
+        for input in inputs {
+            let tasks = self.splitter.split_task_with_stream_base(
+                input,
+                &self.next_parent_task_id(),
+                TaskPriority::Normal,
+                stream_id_base,
+            )?;
+            stream_id_base += tasks.len();
+            per_request_tasks.push(tasks);
+        }
+        self.run_requests(per_request_tasks, None)
+    }
+
+    fn next_parent_task_id(&self) -> String {
+        format!("request_{}", self.next_request.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// 调度执行各请求的子任务，再按请求合并结果
+    fn run_requests(&self, per_request_tasks: Vec<Vec<MoeTask>>, gates: Option<GateWeights>) -> Result<Vec<Vec<u8>>> {
+        // 记录子任务属于哪个请求的第几个位置
+        let mut slots = HashMap::new();
+        let mut results: Vec<Vec<Option<MoeTask>>> = Vec::with_capacity(per_request_tasks.len());
+        for (request_id, tasks) in per_request_tasks.iter().enumerate() {
+            for (position, task) in tasks.iter().enumerate() {
+                slots.insert(task.task_id.clone(), (request_id, position));
+            }
+            results.push(vec![None; tasks.len()]);
+        }
+
+        if let Err(e) = self.submit_interleaved(per_request_tasks) {
+            for task_id in slots.keys() {
+                self.scheduler.cancel(task_id);
+            }
+            return Err(e);
+        }
+
+        let results = Mutex::new(results);
+        self.run_scheduled_tasks(&slots, &results)?;
+
+        let results = results
+            .into_inner()
+            .map_err(|_| Error::Other("结果锁已损坏".to_string()))?;
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(request_id, request_tasks)| {
+                let request_tasks = request_tasks
+                    .into_iter()
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| Error::InferenceError(format!("请求 {} 有子任务未完成", request_id)))?;
+                self.splitter.merge_task_results(&request_tasks, gates.clone())
+            })
+            .collect()
+    }
+
+    /// 轮流从各请求取子任务提交，使不同请求的子任务交错执行
+    ///
+    /// 子任务带着拆分策略给出的依赖提交，例如按层拆分时后一层要等前一层完成才会被取出。
+    fn submit_interleaved(&self, per_request_tasks: Vec<Vec<MoeTask>>) -> Result<()> {
+        let mut dependencies = HashMap::new();
+        for tasks in &per_request_tasks {
+            dependencies.extend(self.splitter.get_task_dependencies(tasks)?);
+        }
+        let mut queues: Vec<_> = per_request_tasks.into_iter().map(|tasks| tasks.into_iter()).collect();
+        loop {
+            let mut submitted = false;
+            for queue in queues.iter_mut() {
+                if let Some(task) = queue.next() {
+                    let deps = dependencies.remove(&task.task_id).unwrap_or_default();
+                    self.scheduler.submit_with_deps(task, deps)?;
+                    submitted = true;
+                }
+            }
+            if !submitted {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// 执行调度器中的全部任务，结果按 `slots` 写回对应请求的位置
+    ///
+    /// 任一任务失败时释放其并发名额，并取消本次调用中其余的任务，调度器中不留下本次调用的任务。
+    fn run_scheduled_tasks(
+        &self,
+        slots: &HashMap<String, (usize, usize)>,
+        results: &Mutex<Vec<Vec<Option<MoeTask>>>>,
+    ) -> Result<()> {
+        let store = |mut task: MoeTask, result: Vec<u8>| -> Result<()> {
+            let &(request_id, position) = slots.get(&task.task_id).ok_or_else(|| {
+                Error::InferenceError(format!("任务 {} 不属于本次推理", task.task_id))
+            })?;
+            let mut results = results
+                .lock()
+                .map_err(|_| Error::Other("结果锁已损坏".to_string()))?;
+            // 保留完整的子任务，合并时按其头部和有效长度去除批次填充
+            task.result = Some(result);
+            results[request_id][position] = Some(task);
+            Ok(())
+        };
+        // 依次执行取出的任务；工作线程只借用调度器，不借用整个流水线（执行器未必能跨线程共享）
+        let scheduler = &self.scheduler;
+        let drain = |executor: &dyn Executor| -> Result<()> {
+            while let Some(mut task) = scheduler.fetch_next_task() {
+                let task_id = task.task_id.clone();
+                let outcome = executor.execute_task(&mut task).and_then(|result| store(task, result));
+                finish_scheduled_task(scheduler, &task_id, outcome, slots)?;
+            }
+            Ok(())
+        };
+
+        let Some(executor) = self.executor.as_shared() else {
+            return drain(self.executor.as_ref());
+        };
+        let drain = &drain;
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.config.max_concurrent_tasks)
+                .map(|_| scope.spawn(move || drain(executor)))
+                .collect();
+            workers.into_iter().try_for_each(|worker| {
+                worker
+                    .join()
+                    .map_err(|_| Error::Other("工作线程异常退出".to_string()))?
+            })
+        })
+    }
+}
+
+/// 结束一个已取出的任务：成功时标记完成；失败时释放其并发名额，
+/// 并取消 `slots` 中本次调用的其余任务（仍在队列中的被移出，正在执行的置位取消标记）
+fn finish_scheduled_task(
+    scheduler: &TaskScheduler,
+    task_id: &str,
+    outcome: Result<()>,
+    slots: &HashMap<String, (usize, usize)>,
+) -> Result<()> {
+    match outcome {
+        Ok(()) => {
+            scheduler.complete_task(task_id);
+            Ok(())
+        }
+        Err(e) => {
+            scheduler.release_task(task_id);
+            for task_id in slots.keys() {
+                scheduler.cancel(task_id);
+            }
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_model_dir(dir: &Path) {
+        let config_json = r#"{
+            "model_type": "switch_transformers",
+            "num_experts": 8,
+            "d_model": 64,
+            "d_ff": 256,
+            "num_layers": 2
+        }"#;
+        fs::write(dir.join("config.json"), config_json).unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_from_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_model_dir(dir.path());
+
+        let pipeline_config = format!(
+            r#"{{
+                "model_dir": {:?},
+                "strategy": {{ "ByBatch": {{ "batch_size": 128 }} }},
+                "device": "cpu",
+                "max_concurrent_tasks": 2
+            }}"#,
+            dir.path().to_str().unwrap()
+        );
+        let config_path = dir.path().join("pipeline.json");
+        fs::write(&config_path, pipeline_config).unwrap();
+
+        let pipeline = Pipeline::from_config_file(&config_path).unwrap();
+        assert!(matches!(pipeline.strategy(), SplitStrategy::ByBatch { batch_size: 128 }));
+        assert_eq!(pipeline.device(), PipelineDevice::Cpu);
+        assert_eq!(pipeline.model_info().num_experts, 8);
+        assert_eq!(pipeline.scheduler.config.max_concurrent_tasks, 2);
+        assert!(pipeline.scheduler.config.validate().is_ok());
+        assert!((pipeline.config.memory_fraction - 0.8).abs() < f32::EPSILON);
+        assert!(pipeline.executor.as_shared().is_some());
+    }
+
+    #[test]
+    fn test_pipeline_from_toml_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_model_dir(dir.path());
+
+        let pipeline_config = format!(
+            r#"
+                model_dir = {:?}
+                device = "cpu"
+                memory_fraction = 0.5
+                strategy = {{ ByBatch = {{ batch_size = 64 }} }}
+            "#,
+            dir.path().to_str().unwrap()
+        );
+        let config_path = dir.path().join("pipeline.toml");
+        fs::write(&config_path, pipeline_config).unwrap();
+
+        let pipeline = Pipeline::from_config_file(&config_path).unwrap();
+        assert!(matches!(pipeline.strategy(), SplitStrategy::ByBatch { batch_size: 64 }));
+        assert_eq!(pipeline.device(), PipelineDevice::Cpu);
+        assert!((pipeline.config.memory_fraction - 0.5).abs() < f32::EPSILON);
+        assert_eq!(pipeline.scheduler.config.max_concurrent_tasks, 4);
+
+        fs::write(&config_path, "model_dir = ").unwrap();
+        assert!(matches!(Pipeline::from_config_file(&config_path), Err(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_pipeline_config_rejects_invalid_values() {
+        assert_eq!("cuda:1".parse::<PipelineDevice>().unwrap(), PipelineDevice::Cuda(DeviceId(1)));
+        assert!("tpu".parse::<PipelineDevice>().is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        write_model_dir(dir.path());
+        let config = PipelineConfig {
+            model_dir: dir.path().to_str().unwrap().to_string(),
+            strategy: SplitStrategy::ByExpert,
+            device: PipelineDevice::Cpu,
+            memory_fraction: 1.5,
+            max_concurrent_tasks: 4,
+        };
+        assert!(matches!(Pipeline::new(config), Err(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_infer_batch_cpu_returns_outputs_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        write_model_dir(dir.path());
+        let config = PipelineConfig {
+            model_dir: dir.path().to_str().unwrap().to_string(),
+            strategy: SplitStrategy::ByBatch { batch_size: 4 },
+            device: PipelineDevice::Cpu,
+            memory_fraction: 0.8,
+            max_concurrent_tasks: 3,
+        };
+        let pipeline = Pipeline::new(config).unwrap();
+
+        // 第i个请求包含 i+1 个token，每个token 64 个 f32
+        let inputs: Vec<Vec<u8>> = (0..5)
+            .map(|request_id| {
+                let num_elements = 64 * (request_id + 1);
+                let mut input = (num_elements as u32).to_le_bytes().to_vec();
+                input.extend((0..num_elements).flat_map(|i| ((request_id * 1000 + i) as f32).to_le_bytes()));
+                input
+            })
+            .collect();
+
+        let outputs = pipeline.infer_batch(&inputs).unwrap();
+        assert_eq!(outputs.len(), 5);
+        for (output, input) in outputs.iter().zip(inputs.iter()) {
+            assert_eq!(output.len(), input.len());
+            assert_eq!(output, input);
+        }
+        assert!(pipeline.scheduler.fetch_next_task().is_none());
+
+        assert_eq!(pipeline.infer(&inputs[2]).unwrap(), inputs[2]);
+    }
+
+    #[test]
+    fn test_layer_tasks_submitted_with_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        write_model_dir(dir.path());
+        let config = PipelineConfig {
+            model_dir: dir.path().to_str().unwrap().to_string(),
+            strategy: SplitStrategy::ByLayer,
+            device: PipelineDevice::Cpu,
+            memory_fraction: 0.8,
+            max_concurrent_tasks: 4,
+        };
+        let pipeline = Pipeline::new(config).unwrap();
+        let mut input = 64u32.to_le_bytes().to_vec();
+        input.extend((0..64).flat_map(|i| (i as f32).to_le_bytes()));
+        let per_request_tasks: Vec<Vec<MoeTask>> = ["a", "b"]
+            .iter()
+            .map(|parent| pipeline.splitter.split_task(&input, parent, TaskPriority::Normal).unwrap())
+            .collect();
+        let layer_ids: Vec<Vec<String>> = per_request_tasks
+            .iter()
+            .map(|tasks| tasks.iter().map(|task| task.task_id.clone()).collect())
+            .collect();
+        pipeline.submit_interleaved(per_request_tasks).unwrap();
+
+        // 并发名额充足，但每个请求只有第0层可以取出，完成后才轮到下一层
+        let mut first_round: Vec<String> = std::iter::from_fn(|| pipeline.scheduler.fetch_next_task())
+            .map(|task| task.task_id)
+            .collect();
+        first_round.sort();
+        assert_eq!(first_round, vec![layer_ids[0][0].clone(), layer_ids[1][0].clone()]);
+        for task_id in &first_round {
+            pipeline.scheduler.complete_task(task_id);
+        }
+        let next = pipeline.scheduler.fetch_next_task().unwrap();
+        assert!(next.task_id == layer_ids[0][1] || next.task_id == layer_ids[1][1]);
+    }
+
+    #[test]
+    fn test_failed_infer_leaves_no_tasks_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        write_model_dir(dir.path());
+        let config = PipelineConfig {
+            model_dir: dir.path().to_str().unwrap().to_string(),
+            strategy: SplitStrategy::ByBatch { batch_size: 4 },
+            device: PipelineDevice::Cpu,
+            memory_fraction: 0.8,
+            max_concurrent_tasks: 1,
+        };
+        let pipeline = Pipeline::new(config).unwrap();
+        let mut input = 256u32.to_le_bytes().to_vec();
+        input.extend((0..256).flat_map(|i| (i as f32).to_le_bytes()));
+
+        // 调度器中混入一个不属于本次调用的任务，它先被取出，结果无处写回而失败
+        let stray = pipeline.splitter.split_task(&input, "stray", TaskPriority::High).unwrap().remove(0);
+        pipeline.scheduler.submit_task(stray);
+        assert!(matches!(pipeline.infer(&input), Err(Error::InferenceError(_))));
+
+        // 失败后名额已释放，本次调用的其余任务已移出队列
+        assert_eq!(pipeline.scheduler.in_flight_count(), 0);
+        assert!(pipeline.scheduler.fetch_next_task().is_none());
+
+        // 之后的调用使用新的父任务ID，正常完成
+        assert_eq!(pipeline.infer(&input).unwrap(), input);
+        assert_eq!(pipeline.scheduler.in_flight_count(), 0);
+    }
+
+    fn pipeline_with_executor(dir: &Path, strategy: SplitStrategy, executor: Arc<dyn Executor>) -> Pipeline {
+        write_model_dir(dir);
+        let config = PipelineConfig {
+            model_dir: dir.to_str().unwrap().to_string(),
+            strategy,
+            device: PipelineDevice::Cpu,
+            memory_fraction: 0.8,
+            max_concurrent_tasks: 2,
+        };
+        Pipeline::with_executor(config, executor).unwrap()
+    }
+
+    fn moe_input() -> Vec<u8> {
+        let mut input = (64u32).to_le_bytes().to_vec();
+        input.extend((0..64).flat_map(|i| (i as f32 * 0.5).to_le_bytes()));
+        input
+    }
+
+    #[test]
+    fn test_infer_with_gates_by_expert_and_by_layer() {
+        let input = moe_input();
+        let gates = GateWeights { weights: vec![0.05, 0.1, 0.15, 0.2, 0.1, 0.1, 0.15, 0.15], top_k: 8 };
+
+        for (strategy, gates) in [
+            (SplitStrategy::ByExpert, Some(gates.clone())),
+            (SplitStrategy::ByLayer, None),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let pipeline = pipeline_with_executor(dir.path(), strategy, Arc::new(CpuTaskExecutor::new()));
+
+            // CPU执行器原样返回子任务输入，期望值即为直接合并拆分结果
+            let tasks = match &gates {
+                Some(gates) => pipeline.splitter.split_task_with_gates(&input, "expected", TaskPriority::Normal, gates),
+                None => pipeline.splitter.split_task(&input, "expected", TaskPriority::Normal),
+            }
+            .unwrap();
+            let inputs: Vec<Vec<u8>> = tasks.iter().map(|task| task.effective_input().into_owned()).collect();
+            let expected = pipeline.splitter.merge_results(&inputs, gates.clone()).unwrap();
+
+            let output = pipeline.infer_with_gates(&input, gates).unwrap();
+            assert!(!output.is_empty());
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_returns_first_error() {
+        struct FailingExecutor;
+        impl Executor for FailingExecutor {
+            fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+                Err(Error::InferenceError(format!("任务 {} 执行失败", task.task_id)))
+            }
+        }
+
+        // 不可共享的执行器在当前线程上依次执行，按层拆分时第0层最先被取出
+        let dir = tempfile::tempdir().unwrap();
+        let pipeline = pipeline_with_executor(dir.path(), SplitStrategy::ByLayer, Arc::new(FailingExecutor));
+        match pipeline.infer(&moe_input()) {
+            Err(Error::InferenceError(message)) => assert!(message.contains("layer_0")),
+            other => panic!("期望推理错误，实际为 {:?}", other),
+        }
+        assert_eq!(pipeline.scheduler.in_flight_count(), 0);
+        assert!(pipeline.scheduler.fetch_next_task().is_none());
+    }
+}
@@ -0,0 +1,208 @@
+// quantization.rs
+// 按张量对称量化：执行器在拷贝到GPU前把任务负载中的f32张量量化为 int8/int4，拷回后再反量化，缩放因子记录在任务头部。
+use crate::error::{Error, Result};
+use crate::task_header::{self, TaskHeader};
+use crate::types::{ELEMENT_SIZE, INPUT_HEADER_SIZE};
+
+/// 支持的量化位宽
+pub const SUPPORTED_BITS: [u8; 2] = [8, 4];
+
+/// 量化参数，每个张量共用一个缩放因子：`value ≈ q * scale`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantParams {
+    pub bits: u8,
+    pub scale: f32,
+}
+
+/// 检查量化位宽是否受支持
+pub fn check_bits(bits: u8) -> Result<()> {
+    if SUPPORTED_BITS.contains(&bits) {
+        Ok(())
+    } else {
+        Err(Error::InferenceError(format!(
+            "不支持的量化位宽 {}，支持的位宽为 {:?}", bits, SUPPORTED_BITS
+        )))
+    }
+}
+
+/// 位宽对应的最大量化值，int8 为127，int4 为7
+fn max_level(bits: u8) -> f32 {
+    ((1i32 << (bits - 1)) - 1) as f32
+}
+
+/// 量化数据的字节数，int4 每个字节存放两个值
+pub fn quantized_len(num_elements: usize, bits: u8) -> usize {
+    if bits == 4 { num_elements.div_ceil(2) } else { num_elements }
+}
+
+/// 将f32张量量化为定点数据，int4 时低4位存放偶数下标的值
+pub fn quantize(values: &[f32], bits: u8) -> Result<(Vec<u8>, QuantParams)> {
+    check_bits(bits)?;
+    let max_abs = values.iter().fold(0.0f32, |max, value| max.max(value.abs()));
+    if !max_abs.is_finite() {
+        return Err(Error::InferenceError("张量包含非有限值，无法量化".to_string()));
+    }
+    let max_level = max_level(bits);
+    // 全零张量的缩放因子取1，避免除零
+    let scale = if max_abs > 0.0 { max_abs / max_level } else { 1.0 };
+    let levels = values
+        .iter()
+        .map(|value| (value / scale).round().clamp(-max_level, max_level) as i8);
+
+    let data = if bits == 4 {
+        let levels: Vec<i8> = levels.collect();
+        levels
+            .chunks(2)
+            .map(|pair| {
+                let low = pair[0] as u8 & 0x0F;
+                let high = pair.get(1).map_or(0, |level| *level as u8 & 0x0F);
+                low | (high << 4)
+            })
+            .collect()
+    } else {
+        levels.map(|level| level as u8).collect()
+    };
+    Ok((data, QuantParams { bits, scale }))
+}
+
+/// 将定点数据还原为 `num_elements` 个f32
+pub fn dequantize(data: &[u8], num_elements: usize, params: QuantParams) -> Result<Vec<f32>> {
+    check_bits(params.bits)?;
+    let expected = quantized_len(num_elements, params.bits);
+    if data.len() != expected {
+        return Err(Error::InferenceError(format!(
+            "{} 位量化数据大小 {} 与 {} 个元素需要的 {} 字节不匹配",
+            params.bits, data.len(), num_elements, expected
+        )));
+    }
+
+    let values = if params.bits == 4 {
+        data.iter()
+            // 左移后再算术右移，对4位补码做符号扩展
+            .flat_map(|byte| [((byte << 4) as i8) >> 4, (*byte as i8) >> 4])
+            .take(num_elements)
+            .map(|level| level as f32 * params.scale)
+            .collect()
+    } else {
+        data.iter().map(|byte| *byte as i8 as f32 * params.scale).collect()
+    };
+    Ok(values)
+}
+
+/// 量化任务输入：负载 `[u32 元素个数][f32 ...]` 替换为 `[u32 元素个数][定点数据]`，量化参数写入头部
+///
+/// 没有任务头部或负载不是完整f32张量的输入（例如按字节拆分的批次任务）原样返回。
+pub fn quantize_task_input(input: &[u8], bits: u8) -> Result<Vec<u8>> {
+    check_bits(bits)?;
+    let (mut header, payload) = match task_header::decode(input) {
+        Ok(decoded) => decoded,
+        Err(_) => return Ok(input.to_vec()),
+    };
+    let values = match decode_f32_payload(payload) {
+        Some(values) => values,
+        None => return Ok(input.to_vec()),
+    };
+
+    let (data, params) = quantize(&values, bits)?;
+    header.quantization = Some(params);
+    Ok(encode_task(&header, values.len(), &data))
+}
+
+/// 反量化执行结果，还原为量化前的布局；头部不带量化参数的结果原样返回
+pub fn dequantize_task_output(output: &[u8]) -> Result<Vec<u8>> {
+    let (mut header, payload) = match task_header::decode(output) {
+        Ok(decoded) => decoded,
+        Err(_) => return Ok(output.to_vec()),
+    };
+    let params = match header.quantization.take() {
+        Some(params) => params,
+        None => return Ok(output.to_vec()),
+    };
+    if payload.len() < INPUT_HEADER_SIZE {
+        return Err(Error::InferenceError("量化结果缺少元素个数".to_string()));
+    }
+    let num_elements = u32::from_le_bytes(payload[..INPUT_HEADER_SIZE].try_into().unwrap()) as usize;
+    let values = dequantize(&payload[INPUT_HEADER_SIZE..], num_elements, params)?;
+    let body: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+    Ok(encode_task(&header, num_elements, &body))
+}
+
+/// 解析 `[u32 元素个数][f32 ...]` 负载，大小不一致时返回None
+fn decode_f32_payload(payload: &[u8]) -> Option<Vec<f32>> {
+    let count = payload.get(..INPUT_HEADER_SIZE)?;
+    let num_elements = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+    let body = &payload[INPUT_HEADER_SIZE..];
+    if body.len() != num_elements.checked_mul(ELEMENT_SIZE)? {
+        return None;
+    }
+    Some(body.chunks_exact(ELEMENT_SIZE).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap())).collect())
+}
+
+fn encode_task(header: &TaskHeader, num_elements: usize, body: &[u8]) -> Vec<u8> {
+    let mut bytes = task_header::encode(header);
+    bytes.extend_from_slice(&(num_elements as u32).to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_header::TaskKind;
+
+    fn sample_values() -> Vec<f32> {
+        (0..257).map(|i| (i as f32 * 0.173).sin() * 3.5).collect()
+    }
+
+    #[test]
+    fn test_int8_round_trip_within_error_bound() {
+        let values = sample_values();
+        let (data, params) = quantize(&values, 8).unwrap();
+        assert_eq!(data.len(), values.len());
+        let restored = dequantize(&data, values.len(), params).unwrap();
+
+        // 舍入误差不超过半个量化步长
+        let bound = params.scale / 2.0 + f32::EPSILON;
+        for (original, restored) in values.iter().zip(&restored) {
+            assert!((original - restored).abs() <= bound, "{} 还原为 {}", original, restored);
+        }
+    }
+
+    #[test]
+    fn test_int4_packs_two_values_per_byte() {
+        let values = sample_values();
+        let (data, params) = quantize(&values, 4).unwrap();
+        assert_eq!(data.len(), values.len().div_ceil(2));
+        let restored = dequantize(&data, values.len(), params).unwrap();
+        assert_eq!(restored.len(), values.len());
+        let bound = params.scale / 2.0 + f32::EPSILON;
+        assert!(values.iter().zip(&restored).all(|(o, r)| (o - r).abs() <= bound));
+        assert!(matches!(quantize(&values, 2), Err(Error::InferenceError(_))));
+    }
+
+    #[test]
+    fn test_task_input_quantization_round_trip() {
+        let values = sample_values();
+        let header = TaskHeader::new(TaskKind::Expert { expert_id: 2, gate_weights: vec![0.5, 0.5], column_slice: None });
+        let mut input = task_header::encode(&header);
+        input.extend_from_slice(&(values.len() as u32).to_le_bytes());
+        input.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+
+        let quantized = quantize_task_input(&input, 8).unwrap();
+        assert!(quantized.len() < input.len());
+        let (quantized_header, _) = task_header::decode(&quantized).unwrap();
+        assert_eq!(quantized_header.quantization.map(|params| params.bits), Some(8));
+
+        let restored = dequantize_task_output(&quantized).unwrap();
+        assert_eq!(restored.len(), input.len());
+        let (restored_header, payload) = task_header::decode(&restored).unwrap();
+        assert_eq!(restored_header, header);
+        let restored_values = decode_f32_payload(payload).unwrap();
+        let bound = quantized_header.quantization.unwrap().scale / 2.0 + f32::EPSILON;
+        assert!(values.iter().zip(&restored_values).all(|(o, r)| (o - r).abs() <= bound));
+
+        // 没有任务头部的原始字节不做量化
+        let raw = vec![1u8, 2, 3, 4, 5];
+        assert_eq!(quantize_task_input(&raw, 8).unwrap(), raw);
+    }
+}
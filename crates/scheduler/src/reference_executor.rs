@@ -0,0 +1,224 @@
+// reference_executor.rs
+// 基于 tch 的参考执行器，用真实的 SwitchTransformersSparseMLP 计算完整MoE输出，作为“拆分→逐专家执行→合并”的对照基准。
+use crate::config::ModelInfo;
+use crate::error::{Error, Result};
+use crate::model_def::switch_transformer::SwitchTransformersSparseMLP;
+use crate::task::{MoeTask, TaskStatus};
+use crate::task_header;
+use crate::types::*;
+use tch::nn::Module;
+use tch::{Kind, Tensor};
+
+/// 参考执行器，持有一个已加载的稀疏MLP层
+pub struct ReferenceExecutor {
+    mlp: SwitchTransformersSparseMLP,
+    model_info: ModelInfo,
+}
+
+impl ReferenceExecutor {
+    pub fn new(mlp: SwitchTransformersSparseMLP, model_info: ModelInfo) -> Self {
+        Self { mlp, model_info }
+    }
+
+    /// 稀疏MLP层
+    pub fn mlp(&self) -> &SwitchTransformersSparseMLP {
+        &self.mlp
+    }
+
+    /// 计算完整的MoE输出（真值）
+    /// 输入为 [u32 元素个数][f32 ...] 字节流，返回f32字节流
+    pub fn reference_output(&self, input_data: &[u8]) -> Result<Vec<u8>> {
+        let xs = self.decode_input(input_data)?;
+        tch::no_grad(|| encode_output(&self.mlp.forward(&xs)))
+    }
+
+    /// 计算单个token输入的门控权重，可直接交给 ResultMerger 合并专家结果
+    pub fn gate_weights(&self, input_data: &[u8]) -> Result<GateWeights> {
+        let xs = self.decode_input(input_data)?;
+        if xs.size()[0] != 1 {
+            return Err(Error::InferenceError(format!(
+                "门控权重只支持单个token的输入，实际为 {} 个token", xs.size()[0]
+            )));
+        }
+        let gate = tch::no_grad(|| self.mlp.router().gate(&xs).flatten(0, -1));
+        let weights = Vec::<f32>::try_from(&gate)?;
+        Ok(GateWeights { weights, top_k: 1 })
+    }
+
+    /// 执行按专家拆分出的子任务，只用任务对应的专家计算
+    /// 任务输入为 task_header 编码的专家头部后接原始输入
+    pub fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+        task.status = TaskStatus::Running;
+        let input = task.effective_input();
+        let (header, payload) = task_header::decode(&input)?;
+        let expert_id = header.expert_id().ok_or_else(|| {
+            Error::InferenceError(format!("任务 {} 不是专家任务: {:?}", task.task_id, header.kind))
+        })?;
+        let expert = self.mlp.expert(expert_id).ok_or_else(|| {
+            Error::InferenceError(format!("专家ID {} 超出范围 [0, {})", expert_id, self.mlp.num_experts()))
+        })?;
+        let xs = self.decode_input(payload)?;
+        let result = tch::no_grad(|| encode_output(&expert.forward(&xs)))?;
+
+        task.status = TaskStatus::Completed;
+        task.result = Some(result.clone());
+        Ok(result)
+    }
+
+    /// 将输入字节流解码为 [num_tokens, hidden_size] 的张量
+    fn decode_input(&self, input_data: &[u8]) -> Result<Tensor> {
+        if input_data.len() < INPUT_HEADER_SIZE {
+            return Err(Error::InferenceError("输入数据缺少头部".to_string()));
+        }
+        let num_elements = u32::from_le_bytes(input_data[..INPUT_HEADER_SIZE].try_into().unwrap()) as usize;
+        let body = &input_data[INPUT_HEADER_SIZE..];
+        let hidden_size = self.model_info.hidden_size;
+        if body.len() != num_elements * ELEMENT_SIZE || num_elements == 0 || !num_elements.is_multiple_of(hidden_size) {
+            return Err(Error::InferenceError(format!(
+                "输入元素个数 {} 与数据大小 {} 或隐藏层大小 {} 不匹配",
+                num_elements, body.len(), hidden_size
+            )));
+        }
+        let values: Vec<f32> = body
+            .chunks_exact(ELEMENT_SIZE)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Ok(Tensor::from_slice(&values).reshape([(num_elements / hidden_size) as i64, hidden_size as i64]))
+    }
+}
+
+/// 将连续存储的f32张量按拆分器的输入布局编码为 [u32 元素个数][f32 ...] 小端字节流，
+/// 使同一份数据既能送入模型也能交给 TaskSplitter；非连续或非f32的张量返回 InferenceError
+pub fn tensor_to_input_bytes(t: &Tensor) -> Result<Vec<u8>> {
+    if t.kind() != Kind::Float {
+        return Err(Error::InferenceError(format!("只支持f32张量，实际类型为 {:?}", t.kind())));
+    }
+    if !t.is_contiguous() {
+        return Err(Error::InferenceError(format!("张量 {:?} 不是连续存储的，请先调用 contiguous()", t.size())));
+    }
+    let num_elements = u32::try_from(t.numel())
+        .map_err(|_| Error::InferenceError(format!("张量元素个数 {} 超出 u32 范围", t.numel())))?;
+    let values = Vec::<f32>::try_from(&t.flatten(0, -1))?;
+    let mut bytes = Vec::with_capacity(INPUT_HEADER_SIZE + values.len() * ELEMENT_SIZE);
+    bytes.extend_from_slice(&num_elements.to_le_bytes());
+    bytes.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    Ok(bytes)
+}
+
+/// tensor_to_input_bytes 的逆变换：按给定形状还原f32张量，元素个数与头部或数据大小不符时返回 InferenceError
+pub fn input_bytes_to_tensor(input_data: &[u8], shape: &[i64]) -> Result<Tensor> {
+    if input_data.len() < INPUT_HEADER_SIZE {
+        return Err(Error::InferenceError("输入数据缺少头部".to_string()));
+    }
+    let num_elements = u32::from_le_bytes(input_data[..INPUT_HEADER_SIZE].try_into().unwrap()) as usize;
+    let body = &input_data[INPUT_HEADER_SIZE..];
+    let shape_elements: i64 = shape.iter().product();
+    if body.len() != num_elements * ELEMENT_SIZE || shape_elements != num_elements as i64 {
+        return Err(Error::InferenceError(format!(
+            "输入元素个数 {} 与数据大小 {} 或形状 {:?} 不匹配",
+            num_elements, body.len(), shape
+        )));
+    }
+    let values: Vec<f32> = body
+        .chunks_exact(ELEMENT_SIZE)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    Ok(Tensor::from_slice(&values).reshape(shape))
+}
+
+/// 将张量编码为f32字节流
+fn encode_output(output: &Tensor) -> Result<Vec<u8>> {
+    let values = Vec::<f32>::try_from(&output.flatten(0, -1))?;
+    Ok(values.iter().flat_map(|v| v.to_le_bytes()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result_merger::ResultMerger;
+    use crate::task::TaskPriority;
+    use crate::task_splitter::{SplitStrategy, TaskSplitter};
+    use tch::{nn, Device};
+
+    fn test_model_info() -> ModelInfo {
+        ModelInfo {
+            model_type: "switch_transformers".to_string(),
+            num_experts: 4,
+            hidden_size: 8,
+            intermediate_size: 16,
+            num_layers: 1,
+            ..Default::default()
+        }
+    }
+
+    fn token_input(values: &[f32]) -> Vec<u8> {
+        let mut input = (values.len() as u32).to_le_bytes().to_vec();
+        input.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+        input
+    }
+
+    fn to_f32s(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_split_execute_merge_matches_reference() {
+        tch::manual_seed(7);
+        let model_info = test_model_info();
+        let vs = nn::VarStore::new(Device::Cpu);
+        let mlp = SwitchTransformersSparseMLP::new(vs.root() / "mlp", &model_info);
+        let executor = ReferenceExecutor::new(mlp, model_info.clone());
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+        let merger = ResultMerger::new(model_info.clone());
+
+        for token in 0..3 {
+            let values: Vec<f32> = (0..model_info.hidden_size)
+                .map(|i| ((i + token * 5) as f32 * 0.37).sin())
+                .collect();
+            let input = token_input(&values);
+
+            let expected = to_f32s(&executor.reference_output(&input).unwrap());
+
+            let mut tasks = splitter.split_task(&input, "reference", TaskPriority::Normal).unwrap();
+            let results: Vec<Vec<u8>> = tasks
+                .iter_mut()
+                .map(|task| executor.execute_task(task).unwrap())
+                .collect();
+            let gate_weights = executor.gate_weights(&input).unwrap();
+            let merged = merger.merge_results(&results, Some(gate_weights), &SplitStrategy::ByExpert).unwrap();
+            let merged = to_f32s(&merged);
+
+            assert_eq!(merged.len(), expected.len());
+            for (m, e) in merged.iter().zip(expected.iter()) {
+                assert!((m - e).abs() < 1e-5, "合并结果 {} 与参考输出 {} 不一致", m, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tensor_input_bytes_round_trip() {
+        tch::manual_seed(11);
+        let tensor = Tensor::randn([1, 4, 8], (Kind::Float, Device::Cpu));
+        let bytes = tensor_to_input_bytes(&tensor).unwrap();
+        assert_eq!(bytes.len(), INPUT_HEADER_SIZE + 32 * ELEMENT_SIZE);
+        assert_eq!(u32::from_le_bytes(bytes[..INPUT_HEADER_SIZE].try_into().unwrap()), 32);
+        assert_eq!(to_f32s(&bytes[INPUT_HEADER_SIZE..]), Vec::<f32>::try_from(&tensor.flatten(0, -1)).unwrap());
+
+        let restored = input_bytes_to_tensor(&bytes, &[1, 4, 8]).unwrap();
+        assert_eq!(restored.size(), vec![1, 4, 8]);
+        assert!(restored.equal(&tensor));
+        assert!(matches!(input_bytes_to_tensor(&bytes, &[1, 4, 9]), Err(Error::InferenceError(_))));
+        assert!(matches!(input_bytes_to_tensor(&bytes[..bytes.len() - 1], &[1, 4, 8]), Err(Error::InferenceError(_))));
+
+        // 拆分器能直接使用转换得到的字节
+        let mut model_info = test_model_info();
+        model_info.hidden_size = 32;
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        assert_eq!(splitter.split_task(&bytes, "tensor", TaskPriority::Normal).unwrap().len(), 4);
+
+        let transposed = tensor.transpose(1, 2);
+        assert!(matches!(tensor_to_input_bytes(&transposed), Err(Error::InferenceError(_))));
+        assert!(tensor_to_input_bytes(&transposed.contiguous()).is_ok());
+        assert!(matches!(tensor_to_input_bytes(&tensor.to_kind(Kind::Double)), Err(Error::InferenceError(_))));
+    }
+}
@@ -2,19 +2,176 @@
 // 结果合并器，负责合并各子任务（如专家、层、批次等）的推理结果。
 use crate::config::ModelInfo;
 use crate::error::{Error, Result};
+use log::warn;
+use crate::task::MoeTask;
+use crate::task_header;
 use crate::types::*;
-use crate::task_splitter::SplitStrategy;
+use crate::task_splitter::{ratio_count, SplitStrategy};
+use half::{bf16, f16};
+use std::collections::HashSet;
+use std::path::Path;
  
+/// 专家结果的合并方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeMode {
+    /// 按门控权重加权求和
+    #[default]
+    WeightedSum,
+    /// 加权求和后除以参与合并的权重之和，适用于 top-k 截断后权重和不为1的情况
+    WeightedMean,
+}
+
+/// merge_results_checked 默认允许的输出范数与最大专家结果范数之比
+pub const DEFAULT_MAX_NORM_RATIO: f32 = 10.0;
+
+/// 按 dtype 把一个元素的小端字节转换为f32
+fn decode_as(dtype: DType, bytes: &[u8]) -> f32 {
+    match dtype {
+        DType::F32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+        DType::F16 => f16::from_le_bytes(bytes.try_into().unwrap()).to_f32(),
+        DType::BF16 => bf16::from_le_bytes(bytes.try_into().unwrap()).to_f32(),
+    }
+}
+
+/// 按 dtype 把f32转换为小端字节追加到 out
+fn encode_as(dtype: DType, value: f32, out: &mut Vec<u8>) {
+    match dtype {
+        DType::F32 => out.extend_from_slice(&value.to_le_bytes()),
+        DType::F16 => out.extend_from_slice(&f16::from_f32(value).to_le_bytes()),
+        DType::BF16 => out.extend_from_slice(&bf16::from_f32(value).to_le_bytes()),
+    }
+}
+
+/// 增量合并专家结果：每个结果到达时立即按门控权重累加到唯一的输出缓冲区，
+/// 不必先收齐所有专家的结果。
+///
+/// 按专家ID升序 push 时与 ResultMerger 的批量合并逐字节相同（批量合并即按此顺序调用本结构）；
+/// 乱序 push 时只有浮点累加顺序不同带来的舍入误差。
+#[derive(Debug)]
+pub struct StreamingMerger {
+    dtype: DType,
+    merge_mode: MergeMode,
+    // f16/bf16 也按f32累加，finalize 时再转换回原类型
+    accumulated: Vec<f32>,
+    // 第一个结果的字节数，之后的结果必须与之一致
+    result_size: Option<usize>,
+    active_weight_sum: f32,
+    pushed: HashSet<usize>,
+}
+
+impl StreamingMerger {
+    pub fn new(dtype: DType, merge_mode: MergeMode) -> Self {
+        Self { dtype, merge_mode, accumulated: Vec::new(), result_size: None, active_weight_sum: 0.0, pushed: HashSet::new() }
+    }
+
+    /// 累加一个专家的结果，权重不为正的结果只检查大小不参与累加
+    pub fn push(&mut self, expert_id: usize, weight: f32, result: &[u8]) -> Result<()> {
+        let element_size = self.dtype.size();
+        match self.result_size {
+            Some(result_size) if result.len() != result_size => {
+                return Err(Error::InferenceError(format!(
+                    "专家 {} 的结果大小 {} 与其他专家不一致 {}",
+                    expert_id, result.len(), result_size
+                )));
+            }
+            Some(_) => {}
+            None => {
+                if !result.len().is_multiple_of(element_size) {
+                    return Err(Error::InferenceError(format!(
+                        "专家结果大小 {} 不是 {:?} 元素大小 {} 的整数倍", result.len(), self.dtype, element_size
+                    )));
+                }
+                self.result_size = Some(result.len());
+                self.accumulated = vec![0.0f32; result.len() / element_size];
+            }
+        }
+        if !self.pushed.insert(expert_id) {
+            return Err(Error::InferenceError(format!("专家 {} 的结果已经合并过", expert_id)));
+        }
+
+        if weight > 0.0 {
+            for (acc, result_chunk) in self.accumulated.iter_mut().zip(result.chunks_exact(element_size)) {
+                *acc += decode_as(self.dtype, result_chunk) * weight;
+            }
+            self.active_weight_sum += weight;
+        }
+        Ok(())
+    }
+
+    /// 已累加的专家结果个数
+    pub fn len(&self) -> usize {
+        self.pushed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pushed.is_empty()
+    }
+
+    /// 结束合并，返回按 dtype 编码的合并结果
+    pub fn finalize(mut self) -> Result<Vec<u8>> {
+        let result_size = self.result_size.ok_or_else(|| Error::InferenceError("没有专家结果可合并".to_string()))?;
+
+        // 加权平均：除以参与合并的权重之和；权重全为0时累加结果也全为0，直接保留
+        if self.merge_mode == MergeMode::WeightedMean && self.active_weight_sum > 0.0 {
+            for acc in self.accumulated.iter_mut() {
+                *acc /= self.active_weight_sum;
+            }
+        }
+
+        let mut merged_result = Vec::with_capacity(result_size);
+        for value in self.accumulated {
+            encode_as(self.dtype, value, &mut merged_result);
+        }
+        Ok(merged_result)
+    }
+}
+
 /// 结果合并器，负责合并各子任务（如专家、层、批次等）的推理结果。
 pub struct ResultMerger {
     pub model_info: ModelInfo,
+    /// 子任务输出的元素类型，默认为f32
+    pub dtype: DType,
+    /// 专家结果的合并方式，默认为加权求和
+    pub merge_mode: MergeMode,
+    /// merge_results_checked 允许的输出范数与最大专家结果范数之比
+    pub max_norm_ratio: f32,
 }
 
 /// 结果合并器实现
 impl ResultMerger {
     // 创建结果合并器
     pub fn new(model_info: ModelInfo) -> Self {
-        Self { model_info }
+        Self { model_info, dtype: DType::F32, merge_mode: MergeMode::default(), max_norm_ratio: DEFAULT_MAX_NORM_RATIO }
+    }
+
+    /// 设置 merge_results_checked 允许的输出范数与最大专家结果范数之比
+    pub fn set_max_norm_ratio(&mut self, max_norm_ratio: f32) {
+        self.max_norm_ratio = max_norm_ratio;
+    }
+
+    /// 设置专家结果的合并方式
+    pub fn set_merge_mode(&mut self, merge_mode: MergeMode) {
+        self.merge_mode = merge_mode;
+    }
+
+    /// 设置子任务输出的元素类型
+    pub fn set_dtype(&mut self, dtype: DType) {
+        self.dtype = dtype;
+    }
+
+    /// 读取 GateWeights::save 保存的门控权重，用于以固定路由复现合并结果；
+    /// 权重个数与模型专家数不一致时返回 ConfigError
+    pub fn load_gate_weights(&self, path: &Path) -> Result<GateWeights> {
+        let gate_weights = GateWeights::load(path)?;
+        if gate_weights.weights.len() != self.model_info.num_experts {
+            return Err(Error::ConfigError(format!(
+                "门控权重文件 {} 有 {} 个权重，与专家数 {} 不一致",
+                path.display(),
+                gate_weights.weights.len(),
+                self.model_info.num_experts
+            )));
+        }
+        Ok(gate_weights)
     }
 
     /// 合并多个子任务的结果
@@ -29,19 +186,214 @@ impl ResultMerger {
                 // 如果是按专家拆分，必须有门控权重才能进行有意义的合并
                 // 在模拟场景下，如果权重为 None，我们可以采取一种简化的合并策略，例如拼接
                 if gate_weights.is_none() {
-                    println!("警告：缺少门控权重，将使用简单的拼接策略合并专家结果。");
+                    warn!("缺少门控权重，将使用简单的拼接策略合并专家结果。");
                     return self.concatenate_results(results);
                 }
                 self.merge_expert_results(results, gate_weights.unwrap())
             },
+            SplitStrategy::ByTopKExpert { top_k } => match gate_weights {
+                // 只有被选中专家的结果，按同样的规则选出对应的门控权重
+                Some(gate_weights) => {
+                    let selected = GateWeights {
+                        weights: gate_weights
+                            .top_k_experts(*top_k)
+                            .into_iter()
+                            .map(|expert_id| gate_weights.weights[expert_id])
+                            .collect(),
+                        top_k: *top_k,
+                    };
+                    self.merge_expert_results(results, selected)
+                }
+                None => {
+                    warn!("缺少门控权重，将使用简单的拼接策略合并专家结果。");
+                    self.concatenate_results(results)
+                }
+            },
             SplitStrategy::ByLayer => self.merge_layer_results(results),
+            SplitStrategy::ByExpertCapacity { .. } => Err(Error::InferenceError(
+                "按专家容量拆分的结果需要任务头部中的token路由，请使用 merge_task_results".to_string(),
+            )),
             SplitStrategy::ByBatch { .. } => self.merge_batch_results(results),
+            SplitStrategy::ByWindow { window, stride } => self.merge_window_results(results, *window, *stride),
+            SplitStrategy::ByTensorParallel { num_shards } => {
+                let expert_results = self.merge_tensor_parallel_results(results, *num_shards)?;
+                match gate_weights {
+                    Some(gate_weights) => self.merge_expert_results(&expert_results, gate_weights),
+                    None => {
+                        warn!("缺少门控权重，将使用简单的拼接策略合并专家结果。");
+                        self.concatenate_results(&expert_results)
+                    }
+                }
+            }
             SplitStrategy::Hybrid { expert_split, layer_split, expert_ratio, layer_ratio, .. } => {
                 self.merge_hybrid_results(results, gate_weights, *expert_split, *layer_split, *expert_ratio, *layer_ratio)
             }
         }
     }
 
+    /// 合并已完成子任务的结果，按各任务的 valid_len 去除批次填充后再合并
+    /// 按批次拆分时按任务ID中的批次ID排序，任务可以按任意完成顺序传入
+    pub fn merge_task_results(
+        &self,
+        tasks: &[MoeTask],
+        gate_weights: Option<GateWeights>,
+        strategy: &SplitStrategy,
+    ) -> Result<Vec<u8>> {
+        if let SplitStrategy::ByExpertCapacity { .. } = strategy {
+            return self.merge_capacity_results(tasks);
+        }
+        if let SplitStrategy::ByBatch { .. } | SplitStrategy::ByWindow { .. } = strategy {
+            let indexed = tasks
+                .iter()
+                .map(|task| {
+                    let batch_id = task.parsed_task_id()?.batch.ok_or_else(|| {
+                        Error::InferenceError(format!("子任务 {} 缺少批次ID", task.task_id))
+                    })?;
+                    let result = task.result.as_deref().ok_or_else(|| {
+                        Error::InferenceError(format!("子任务 {} 没有结果", task.task_id))
+                    })?;
+                    Ok((batch_id, self.remove_padding(task, result)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if let SplitStrategy::ByWindow { window, stride } = strategy {
+                let ordered = Self::order_by_batch_id(&indexed)?;
+                return self.merge_window_results(&ordered, *window, *stride);
+            }
+            return self.merge_indexed_batch_results(&indexed);
+        }
+
+        let results = tasks
+            .iter()
+            .map(|task| {
+                let result = task.result.as_deref().ok_or_else(|| {
+                    Error::InferenceError(format!("子任务 {} 没有结果", task.task_id))
+                })?;
+                Ok(self.remove_padding(task, result))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.merge_results(&results, gate_weights, strategy)
+    }
+
+    /// 合并按专家容量拆分的结果：各专家输出按头部中的token路由放回原位置，被丢弃的token输出为0
+    ///
+    /// 专家结果末尾的 保留token数 × hidden_size 个f32 依次为各保留token的输出。
+    fn merge_capacity_results(&self, tasks: &[MoeTask]) -> Result<Vec<u8>> {
+        let row_size = self.model_info.hidden_size * ELEMENT_SIZE;
+        let mut merged: Option<Vec<u8>> = None;
+        for task in tasks {
+            let (header, _) = task_header::decode(&task.input_data)?;
+            let routing = header.token_routing.ok_or_else(|| {
+                Error::InferenceError(format!("子任务 {} 的头部缺少token路由", task.task_id))
+            })?;
+            let result = task.result.as_deref().ok_or_else(|| {
+                Error::InferenceError(format!("子任务 {} 没有结果", task.task_id))
+            })?;
+            let rows_len = routing.kept.len() * row_size;
+            if result.len() < rows_len {
+                return Err(Error::InferenceError(format!(
+                    "子任务 {} 的结果大小 {} 小于 {} 个token的输出", task.task_id, result.len(), routing.kept.len()
+                )));
+            }
+
+            let output = merged.get_or_insert_with(|| vec![0u8; routing.num_tokens * row_size]);
+            if output.len() != routing.num_tokens * row_size {
+                return Err(Error::InferenceError(format!(
+                    "子任务 {} 的token总数 {} 与其他任务不一致", task.task_id, routing.num_tokens
+                )));
+            }
+            let rows = &result[result.len() - rows_len..];
+            for (row, &token) in rows.chunks_exact(row_size).zip(&routing.kept) {
+                let target = output.get_mut(token * row_size..(token + 1) * row_size).ok_or_else(|| {
+                    Error::InferenceError(format!("token下标 {} 超出范围 [0, {})", token, routing.num_tokens))
+                })?;
+                target.copy_from_slice(row);
+            }
+        }
+        merged.ok_or_else(|| Error::InferenceError("没有可合并的专家结果".to_string()))
+    }
+
+    /// 合并各专家处理token数不同的结果：按头部token路由中的 `kept` 把每个专家的输出行放回原位置后再按门控权重加权
+    ///
+    /// 专家结果末尾的 保留token数 × hidden_size 个元素依次为各保留token的输出，元素类型为 dtype。
+    /// 加权平均时每个token除以覆盖该token的专家权重之和；没有专家覆盖的token输出为0。
+    pub fn merge_ragged_expert_results(&self, tasks: &[MoeTask], gate_weights: GateWeights) -> Result<Vec<u8>> {
+        let hidden_size = self.model_info.hidden_size;
+        let row_size = hidden_size * self.dtype.size();
+        let mut num_tokens = None;
+        let mut accumulated = Vec::new();
+        let mut token_weights = Vec::new();
+        let mut merged_experts = HashSet::new();
+        for task in tasks {
+            let (header, _) = task_header::decode(&task.input_data)?;
+            let expert_id = header.expert_id().ok_or_else(|| {
+                Error::InferenceError(format!("子任务 {} 不是专家任务", task.task_id))
+            })?;
+            let routing = header.token_routing.ok_or_else(|| {
+                Error::InferenceError(format!("子任务 {} 的头部缺少token路由", task.task_id))
+            })?;
+            let weight = *gate_weights.weights.get(expert_id).ok_or_else(|| {
+                Error::InferenceError(format!(
+                    "专家 {} 超出门控权重数量 {}", expert_id, gate_weights.weights.len()
+                ))
+            })?;
+            if !merged_experts.insert(expert_id) {
+                return Err(Error::InferenceError(format!("专家 {} 的结果已经合并过", expert_id)));
+            }
+            let result = task.result.as_deref().ok_or_else(|| {
+                Error::InferenceError(format!("子任务 {} 没有结果", task.task_id))
+            })?;
+            let rows_len = routing.kept.len() * row_size;
+            if result.len() < rows_len {
+                return Err(Error::InferenceError(format!(
+                    "子任务 {} 的结果大小 {} 小于 {} 个token的输出", task.task_id, result.len(), routing.kept.len()
+                )));
+            }
+
+            match num_tokens {
+                Some(num_tokens) if num_tokens != routing.num_tokens => {
+                    return Err(Error::InferenceError(format!(
+                        "子任务 {} 的token总数 {} 与其他任务不一致", task.task_id, routing.num_tokens
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    num_tokens = Some(routing.num_tokens);
+                    accumulated = vec![0.0f32; routing.num_tokens * hidden_size];
+                    token_weights = vec![0.0f32; routing.num_tokens];
+                }
+            }
+            if weight <= 0.0 {
+                continue;
+            }
+            let rows = &result[result.len() - rows_len..];
+            for (row, &token) in rows.chunks_exact(row_size).zip(&routing.kept) {
+                let target = accumulated.get_mut(token * hidden_size..(token + 1) * hidden_size).ok_or_else(|| {
+                    Error::InferenceError(format!("token下标 {} 超出范围 [0, {})", token, routing.num_tokens))
+                })?;
+                for (acc, element) in target.iter_mut().zip(row.chunks_exact(self.dtype.size())) {
+                    *acc += decode_as(self.dtype, element) * weight;
+                }
+                token_weights[token] += weight;
+            }
+        }
+        if num_tokens.is_none() {
+            return Err(Error::InferenceError("没有可合并的专家结果".to_string()));
+        }
+
+        if self.merge_mode == MergeMode::WeightedMean {
+            for (token_row, &weight_sum) in accumulated.chunks_exact_mut(hidden_size.max(1)).zip(&token_weights) {
+                if weight_sum > 0.0 {
+                    token_row.iter_mut().for_each(|acc| *acc /= weight_sum);
+                }
+            }
+        }
+        let mut merged = Vec::with_capacity(accumulated.len() * self.dtype.size());
+        for value in accumulated {
+            encode_as(self.dtype, value, &mut merged);
+        }
+        Ok(merged)
+    }
+
     /// 将所有结果简单地拼接在一起
     fn concatenate_results(&self, results: &[Vec<u8>]) -> Result<Vec<u8>> {
         Ok(results.concat())
@@ -60,33 +412,156 @@ impl ResultMerger {
                 gate_weights.weights.len()
             )));
         }
-        
-        // 检查所有结果的大小是否一致
-        let result_size = results[0].len();
+
+        // 按专家ID顺序逐个累加，与增量合并共用同一套累加逻辑
+        let mut streaming = self.streaming_merger();
+        for (expert_id, (result, weight)) in results.iter().zip(gate_weights.weights.iter()).enumerate() {
+            streaming.push(expert_id, *weight, result)?;
+        }
+        streaming.finalize()
+    }
+
+    /// 创建增量合并器，沿用当前的 dtype 和合并方式
+    pub fn streaming_merger(&self) -> StreamingMerger {
+        StreamingMerger::new(self.dtype, self.merge_mode)
+    }
+
+    /// 合并部分专家失败时的结果，`None` 表示该专家的任务失败
+    ///
+    /// 失败专家的门控权重被丢弃，剩余专家的权重重新归一化为和为1后再合并；
+    /// 没有成功的专家，或成功专家的权重之和为0时返回错误。
+    pub fn merge_expert_results_partial(&self, results: &[Option<Vec<u8>>], gate_weights: GateWeights) -> Result<Vec<u8>> {
+        if results.len() != gate_weights.weights.len() {
+            return Err(Error::InferenceError(format!(
+                "专家结果数量 {} 与门控权重数量 {} 不匹配",
+                results.len(),
+                gate_weights.weights.len()
+            )));
+        }
+
+        let (succeeded, failed): (Vec<_>, Vec<_>) = results
+            .iter()
+            .zip(gate_weights.weights.iter())
+            .enumerate()
+            .partition(|(_, (result, _))| result.is_some());
+        if !failed.is_empty() {
+            let failed_ids: Vec<usize> = failed.iter().map(|(expert_id, _)| *expert_id).collect();
+            warn!("专家 {:?} 没有结果，按剩余 {} 个专家重新归一化门控权重", failed_ids, succeeded.len());
+        }
+
+        let weight_sum: f32 = succeeded.iter().map(|(_, (_, weight))| **weight).sum();
+        if succeeded.is_empty() || weight_sum <= 0.0 {
+            return Err(Error::InferenceError("没有门控权重为正的专家结果可合并".to_string()));
+        }
+        let (results, weights): (Vec<Vec<u8>>, Vec<f32>) = succeeded
+            .into_iter()
+            .filter_map(|(_, (result, weight))| result.clone().map(|result| (result, weight / weight_sum)))
+            .unzip();
+        let top_k = gate_weights.top_k.min(weights.len());
+        self.merge_expert_results(&results, GateWeights { weights, top_k })
+    }
+
+    /// 合并专家结果并检查输出幅值是否合理
+    ///
+    /// 专家结果和输出都不能包含 NaN 或 Inf，输出的L2范数不能超过专家结果最大范数的 max_norm_ratio 倍，
+    /// 专家结果不全为0时输出也不能全为0（例如门控权重全为0）。用于尽早发现权重未归一化、dtype 或布局错误。
+    pub fn merge_results_checked(&self, results: &[Vec<u8>], gate_weights: GateWeights) -> Result<Vec<u8>> {
+        let merged = self.merge_expert_results(results, gate_weights)?;
+
+        let mut max_input_norm = 0.0f32;
+        for (expert_id, result) in results.iter().enumerate() {
+            let norm = self.l2_norm(result);
+            if !norm.is_finite() {
+                return Err(Error::InferenceError(format!("专家 {} 的结果包含 NaN 或 Inf", expert_id)));
+            }
+            max_input_norm = max_input_norm.max(norm);
+        }
+
+        let output_norm = self.l2_norm(&merged);
+        if !output_norm.is_finite() {
+            return Err(Error::InferenceError("合并结果包含 NaN 或 Inf".to_string()));
+        }
+        if output_norm > max_input_norm * self.max_norm_ratio {
+            return Err(Error::InferenceError(format!(
+                "合并结果的L2范数 {} 超过专家结果最大范数 {} 的 {} 倍，门控权重可能未归一化",
+                output_norm, max_input_norm, self.max_norm_ratio
+            )));
+        }
+        if output_norm == 0.0 && max_input_norm > 0.0 {
+            return Err(Error::InferenceError(format!(
+                "专家结果的最大范数为 {}，合并结果却全为0，门控权重可能全为0", max_input_norm
+            )));
+        }
+        Ok(merged)
+    }
+
+    /// 按 dtype 解码后计算L2范数，平方和用f64累加避免溢出
+    fn l2_norm(&self, bytes: &[u8]) -> f32 {
+        bytes
+            .chunks_exact(self.dtype.size())
+            .map(|chunk| (self.decode_element(chunk) as f64).powi(2))
+            .sum::<f64>()
+            .sqrt() as f32
+    }
+
+    /// 按 dtype 把一个元素的小端字节转换为f32
+    fn decode_element(&self, bytes: &[u8]) -> f32 {
+        decode_as(self.dtype, bytes)
+    }
+
+    /// 按 dtype 把f32转换为小端字节追加到 out
+    fn encode_element(&self, value: f32, out: &mut Vec<u8>) {
+        encode_as(self.dtype, value, out)
+    }
+
+    /// 合并int8量化的专家结果
+    /// 每个专家的输出先按各自的反量化比例还原为f32，再按门控权重加权求和，返回f32字节流
+    pub fn merge_expert_results_quant(&self, results: &[Vec<u8>], gate_weights: GateWeights, scales: &[f32]) -> Result<Vec<u8>> {
+        if results.is_empty() {
+            return Err(Error::InferenceError("没有专家结果可合并".to_string()));
+        }
+
+        if scales.len() != results.len() {
+            return Err(Error::InferenceError(format!(
+                "专家结果数量 {} 与反量化比例数量 {} 不匹配",
+                results.len(),
+                scales.len()
+            )));
+        }
+
+        if results.len() != gate_weights.weights.len() {
+            return Err(Error::InferenceError(format!(
+                "专家结果数量 {} 与门控权重数量 {} 不匹配",
+                results.len(),
+                gate_weights.weights.len()
+            )));
+        }
+
+        // 检查所有结果的大小是否一致（每个元素1字节）
+        let num_elements = results[0].len();
         for (i, result) in results.iter().enumerate() {
-            if result.len() != result_size {
+            if result.len() != num_elements {
                 return Err(Error::InferenceError(format!(
-                    "专家 {} 的结果大小 {} 与其他专家不一致 {}", 
-                    i, result.len(), result_size
+                    "专家 {} 的结果大小 {} 与其他专家不一致 {}",
+                    i, result.len(), num_elements
                 )));
             }
         }
-        
-        // 按门控权重合并结果
-        let mut merged_result = vec![0u8; result_size];
-        
-        for (i, (result, weight)) in results.iter().zip(gate_weights.weights.iter()).enumerate() {
+
+        // 反量化后按门控权重累加
+        let mut accumulated = vec![0.0f32; num_elements];
+        for ((result, weight), scale) in results.iter().zip(gate_weights.weights.iter()).zip(scales.iter()) {
             if *weight > 0.0 {
-                // 将结果按权重累加
-                for (merged_chunk, result_chunk) in merged_result.chunks_exact_mut(4).zip(result.chunks_exact(4)) {
-                    let current_val = f32::from_le_bytes(merged_chunk.try_into().unwrap());
-                    let expert_val = f32::from_le_bytes(result_chunk.try_into().unwrap());
-                    let weighted_sum = current_val + expert_val * weight;
-                    merged_chunk.copy_from_slice(&weighted_sum.to_le_bytes());
+                for (acc, &byte) in accumulated.iter_mut().zip(result.iter()) {
+                    *acc += (byte as i8) as f32 * scale * weight;
                 }
             }
         }
-        
+
+        let mut merged_result = Vec::with_capacity(num_elements * 4);
+        for value in accumulated {
+            merged_result.extend_from_slice(&value.to_le_bytes());
+        }
         Ok(merged_result)
     }
 
@@ -94,43 +569,129 @@ impl ResultMerger {
         if results.is_empty() {
             return Err(Error::InferenceError("没有层结果可合并".to_string()));
         }
-        let mut merged_result = vec![0u8; results[0].len()];
-        let mut is_first = true;
+        // 先检查所有层结果，避免按元素切分时丢掉末尾不足一个元素的字节
+        let element_size = self.dtype.size();
+        let result_size = results[0].len();
+        for (layer_id, result) in results.iter().enumerate() {
+            if !result.len().is_multiple_of(element_size) {
+                return Err(Error::InferenceError(format!(
+                    "第 {} 层结果大小 {} 不是 {:?} 元素大小 {} 的整数倍",
+                    layer_id, result.len(), self.dtype, element_size
+                )));
+            }
+            if result.len() != result_size {
+                return Err(Error::InferenceError(format!(
+                    "第 {} 层输出大小 {} 与残差大小 {}（第 0 层输出大小）不匹配",
+                    layer_id, result.len(), result_size
+                )));
+            }
+        }
 
-        for result in results {
-            if is_first {
-                merged_result.copy_from_slice(result);
-                is_first = false;
-            } else {
-                if merged_result.len() != result.len() {
-                    return Err(Error::InferenceError("层输出大小与残差大小不匹配".to_string()));
-                }
-                for (merged_chunk, result_chunk) in merged_result.chunks_exact_mut(4).zip(result.chunks_exact(4)) {
-                    let residual_val = f32::from_le_bytes(merged_chunk.try_into().unwrap());
-                    let current_val = f32::from_le_bytes(result_chunk.try_into().unwrap());
-                    let sum = residual_val + current_val;
-                    merged_chunk.copy_from_slice(&sum.to_le_bytes());
-                }
+        // 残差累加：各层输出按缩放系数累加到同一缓冲区
+        let mut accumulated = vec![0.0f32; result_size / element_size];
+        for (layer_id, result) in results.iter().enumerate() {
+            let scale = self.layer_residual_scale(layer_id)?;
+            for (acc, result_chunk) in accumulated.iter_mut().zip(result.chunks_exact(element_size)) {
+                *acc += self.decode_element(result_chunk) * scale;
             }
         }
+
+        let mut merged_result = Vec::with_capacity(result_size);
+        for value in accumulated {
+            self.encode_element(value, &mut merged_result);
+        }
         Ok(merged_result)
     }
 
+    /// 获取指定层输出的残差缩放系数，未配置时为1.0
+    fn layer_residual_scale(&self, layer_id: usize) -> Result<f32> {
+        match &self.model_info.layer_residual_scale {
+            None => Ok(1.0),
+            Some(scale) => scale.for_layer(layer_id).ok_or_else(|| {
+                Error::InferenceError(format!("缺少第 {} 层的残差缩放系数", layer_id))
+            }),
+        }
+    }
+
+    /// 按批次ID合并批次结果，结果可以按任意顺序传入
+    /// 批次ID必须恰好是 0..n 且不重复
+    pub fn merge_indexed_batch_results(&self, results: &[(usize, Vec<u8>)]) -> Result<Vec<u8>> {
+        let ordered = Self::order_by_batch_id(results)?;
+        self.merge_batch_results(&ordered)
+    }
+
+    /// 按批次ID排序结果，批次ID必须从0开始连续且不重复
+    fn order_by_batch_id(results: &[(usize, Vec<u8>)]) -> Result<Vec<Vec<u8>>> {
+        let mut ordered: Vec<&(usize, Vec<u8>)> = results.iter().collect();
+        ordered.sort_by_key(|(batch_id, _)| *batch_id);
+        for (position, (batch_id, _)) in ordered.iter().enumerate() {
+            if *batch_id != position {
+                return Err(Error::InferenceError(format!(
+                    "批次ID不连续或重复：第 {} 个批次的ID为 {}", position, batch_id
+                )));
+            }
+        }
+        Ok(ordered.into_iter().map(|(_, result)| result.clone()).collect())
+    }
+
+    /// 合并滑动窗口结果：第一个窗口完整保留，之后每个窗口去掉与前一个窗口重叠的 window - stride 字节
+    /// 结果需按窗口顺序排列，且已去除填充（见 merge_task_results），否则末尾会保留最后一个窗口的填充
+    fn merge_window_results(&self, results: &[Vec<u8>], window: usize, stride: usize) -> Result<Vec<u8>> {
+        if results.is_empty() {
+            return Err(Error::InferenceError("没有窗口结果可合并".to_string()));
+        }
+        let overlap = window.saturating_sub(stride);
+        let mut merged = results[0].clone();
+        for result in &results[1..] {
+            merged.extend_from_slice(&result[overlap.min(result.len())..]);
+        }
+        Ok(merged)
+    }
+
     // 合并批次结果 直接拼接
+    // 结果需已去除填充（见 merge_task_results），否则末尾会保留最后一个批次的填充
     fn merge_batch_results(&self, results: &[Vec<u8>]) -> Result<Vec<u8>> {
         if results.is_empty() {
             return Err(Error::InferenceError("没有批次结果可合并".to_string()));
         }
-        let mut merged_result = Vec::new();
-        for (batch_id, result) in results.iter().enumerate() {
-            let actual_result = if batch_id == results.len() - 1 {
-                self.remove_padding(result)?
-            } else {
-                result.clone()
-            };
-            merged_result.extend_from_slice(&actual_result);
+        Ok(results.concat())
+    }
+
+    /// 合并张量并行的分片结果，返回每个专家一个结果
+    /// 每个分片的输出是 [token数, 分片列数] 的 dtype 矩阵，同一专家的分片沿特征维度（列）拼接为 [token数, 中间层大小]
+    fn merge_tensor_parallel_results(&self, results: &[Vec<u8>], num_shards: usize) -> Result<Vec<Vec<u8>>> {
+        let expected = self.model_info.num_experts * num_shards;
+        if num_shards == 0 || results.len() != expected {
+            return Err(Error::InferenceError(format!(
+                "张量并行结果数量 {} 与期望数量 {} 不匹配", results.len(), expected
+            )));
         }
-        Ok(merged_result)
+        let row_size = self.model_info.intermediate_size / num_shards * self.dtype.size();
+        let shard_size = results[0].len();
+        if row_size == 0 || !shard_size.is_multiple_of(row_size) {
+            return Err(Error::InferenceError(format!(
+                "分片结果大小 {} 不是分片行大小 {} 的整数倍", shard_size, row_size
+            )));
+        }
+        if let Some(i) = results.iter().position(|result| result.len() != shard_size) {
+            return Err(Error::InferenceError(format!(
+                "分片 {} 的结果大小 {} 与其他分片不一致 {}", i, results[i].len(), shard_size
+            )));
+        }
+
+        let num_tokens = shard_size / row_size;
+        Ok(results
+            .chunks(num_shards)
+            .map(|shards| {
+                let mut merged = Vec::with_capacity(shard_size * num_shards);
+                for token in 0..num_tokens {
+                    for shard in shards {
+                        merged.extend_from_slice(&shard[token * row_size..(token + 1) * row_size]);
+                    }
+                }
+                merged
+            })
+            .collect())
     }
 
     // 合并混合策略结果
@@ -149,8 +710,8 @@ impl ResultMerger {
 
         if expert_split && layer_split {
             // 先按层合并专家结果，再合并层结果
-            let num_experts_to_use = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
-            let num_layers_to_use = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
+            let num_experts_to_use = ratio_count(self.model_info.num_experts, expert_ratio);
+            let num_layers_to_use = ratio_count(self.model_info.num_layers, layer_ratio);
             
             if results.len() != num_layers_to_use * num_experts_to_use {
                 return Err(Error::InferenceError(format!(
@@ -186,14 +747,9 @@ impl ResultMerger {
             self.merge_layer_results(&layer_results)
         } else if expert_split {
             // 只按专家拆分
-            let num_experts_to_use = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
-            if results.len() != num_experts_to_use {
-                return Err(Error::InferenceError(format!(
-                    "专家拆分结果数量 {} 与期望数量 {} 不匹配", 
-                    results.len(), 
-                    num_experts_to_use
-                )));
-            }
+            let num_experts_to_use = ratio_count(self.model_info.num_experts, expert_ratio);
+            // 拆分器会把每个专家任务再按批次拆分，先把各专家的批次结果拼回去
+            let results = self.merge_batched_groups(results, num_experts_to_use)?;
             
             let expert_gate_weights = if let Some(ref weights) = gate_weights {
                 GateWeights {
@@ -207,27 +763,424 @@ impl ResultMerger {
                 }
             };
             
-            self.merge_expert_results(results, expert_gate_weights)
+            self.merge_expert_results(&results, expert_gate_weights)
         } else if layer_split {
             // 只按层拆分
-            let num_layers_to_use = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
-            if results.len() != num_layers_to_use {
-                return Err(Error::InferenceError(format!(
-                    "层拆分结果数量 {} 与期望数量 {} 不匹配", 
-                    results.len(), 
-                    num_layers_to_use
-                )));
-            }
-            
-            self.merge_layer_results(results)
+            let num_layers_to_use = ratio_count(self.model_info.num_layers, layer_ratio);
+            let results = self.merge_batched_groups(results, num_layers_to_use)?;
+            self.merge_layer_results(&results)
         } else {
             // 只按批次拆分
             self.merge_batch_results(results)
         }
     }
 
-    // 移除填充
-    fn remove_padding(&self, result: &[u8]) -> Result<Vec<u8>> {
-        Ok(result.to_vec())
+    /// 将按组连续排列的批次结果合并为每组一个结果
+    /// 结果数量等于组数时原样返回，否则必须是组数的整数倍
+    fn merge_batched_groups(&self, results: &[Vec<u8>], num_groups: usize) -> Result<Vec<Vec<u8>>> {
+        if results.len() == num_groups {
+            return Ok(results.to_vec());
+        }
+        if num_groups == 0 || !results.len().is_multiple_of(num_groups) {
+            return Err(Error::InferenceError(format!(
+                "混合策略结果数量 {} 不是分组数量 {} 的整数倍",
+                results.len(),
+                num_groups
+            )));
+        }
+        let batches_per_group = results.len() / num_groups;
+        results
+            .chunks(batches_per_group)
+            .map(|group| self.merge_batch_results(group))
+            .collect()
+    }
+
+    // 移除填充 截断到任务的有效长度
+    fn remove_padding(&self, task: &MoeTask, result: &[u8]) -> Vec<u8> {
+        task.valid_slice(result).to_vec()
+    }
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayerResidualScale;
+
+    fn test_merger() -> ResultMerger {
+        ResultMerger::new(ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 2,
+            hidden_size: 4,
+            intermediate_size: 16,
+            num_layers: 2,
+            ..Default::default()
+        })
+    }
+
+    fn to_f32s(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_expert_results_quant() {
+        let merger = test_merger();
+        let expert_0: Vec<i8> = vec![10, -20, 30, 127];
+        let expert_1: Vec<i8> = vec![-5, 15, 0, -128];
+        let results = vec![
+            expert_0.iter().map(|&v| v as u8).collect::<Vec<u8>>(),
+            expert_1.iter().map(|&v| v as u8).collect::<Vec<u8>>(),
+        ];
+        let gate_weights = GateWeights {
+            weights: vec![0.6, 0.4],
+            top_k: 2,
+        };
+        let scales = [0.1, 0.2];
+
+        let merged = merger.merge_expert_results_quant(&results, gate_weights, &scales).unwrap();
+        let merged = to_f32s(&merged);
+
+        assert_eq!(merged.len(), 4);
+        for i in 0..4 {
+            let expected = expert_0[i] as f32 * 0.1 * 0.6 + expert_1[i] as f32 * 0.2 * 0.4;
+            assert!((merged[i] - expected).abs() < 1e-5, "元素 {}: {} != {}", i, merged[i], expected);
+        }
+    }
+
+    #[test]
+    fn test_merge_expert_results_quant_scale_mismatch() {
+        let merger = test_merger();
+        let results = vec![vec![1u8; 4], vec![2u8; 4]];
+        let gate_weights = GateWeights {
+            weights: vec![0.5, 0.5],
+            top_k: 2,
+        };
+        assert!(merger.merge_expert_results_quant(&results, gate_weights, &[0.1]).is_err());
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_merge_layer_results_with_residual_scale() {
+        let results = vec![f32_bytes(&[1.0, 2.0]), f32_bytes(&[3.0, 4.0]), f32_bytes(&[5.0, 6.0])];
+
+        let default_merged = to_f32s(&test_merger().merge_results(&results, None, &SplitStrategy::ByLayer).unwrap());
+        assert_eq!(default_merged, vec![9.0, 12.0]);
+
+        let mut deepnorm = test_merger();
+        deepnorm.model_info.layer_residual_scale = Some(LayerResidualScale::Uniform(1.5));
+        let scaled = to_f32s(&deepnorm.merge_results(&results, None, &SplitStrategy::ByLayer).unwrap());
+        for (scaled, default) in scaled.iter().zip(default_merged.iter()) {
+            assert!((scaled - default * 1.5).abs() < 1e-5);
+        }
+
+        let mut per_layer = test_merger();
+        per_layer.model_info.layer_residual_scale = Some(LayerResidualScale::PerLayer(vec![1.0, 0.5, 2.0]));
+        let merged = to_f32s(&per_layer.merge_results(&results, None, &SplitStrategy::ByLayer).unwrap());
+        assert_eq!(merged, vec![1.0 + 1.5 + 10.0, 2.0 + 2.0 + 12.0]);
+
+        // 逐层系数不足时报错
+        per_layer.model_info.layer_residual_scale = Some(LayerResidualScale::PerLayer(vec![1.0]));
+        assert!(per_layer.merge_results(&results, None, &SplitStrategy::ByLayer).is_err());
+    }
+
+    #[test]
+    fn test_layer_residual_scale_from_config_json() {
+        let scalar: crate::config::ModelConfigJson = serde_json::from_str(
+            r#"{"model_type": "t", "num_experts": 2, "d_model": 4, "d_ff": 8, "num_layers": 2, "layer_residual_scale": 1.5}"#,
+        ).unwrap();
+        let info = ModelInfo::try_from(scalar).unwrap();
+        assert_eq!(info.layer_residual_scale, Some(LayerResidualScale::Uniform(1.5)));
+
+        let vector: crate::config::ModelConfigJson = serde_json::from_str(
+            r#"{"model_type": "t", "num_experts": 2, "d_model": 4, "d_ff": 8, "num_layers": 2, "layer_residual_scale": [1.0, 2.0]}"#,
+        ).unwrap();
+        let info = ModelInfo::try_from(vector).unwrap();
+        assert_eq!(info.layer_residual_scale, Some(LayerResidualScale::PerLayer(vec![1.0, 2.0])));
+
+        let absent: crate::config::ModelConfigJson = serde_json::from_str(
+            r#"{"model_type": "t", "num_experts": 2, "d_model": 4, "d_ff": 8, "num_layers": 2}"#,
+        ).unwrap();
+        assert!(ModelInfo::try_from(absent).unwrap().layer_residual_scale.is_none());
+    }
+
+    #[test]
+    fn test_merge_tensor_parallel_concatenates_features() {
+        let merger = test_merger();
+        let num_shards = 4;
+        // 2个专家 x 4个分片，每个分片输出 2个token x 4列，元素值编码为 专家*100 + token*10 + 列
+        let results: Vec<Vec<u8>> = (0..2)
+            .flat_map(|expert| (0..num_shards).map(move |shard| (expert, shard)))
+            .map(|(expert, shard)| {
+                (0..2)
+                    .flat_map(|token| (0..4).map(move |col| (expert * 100 + token * 10 + shard * 4 + col) as f32))
+                    .flat_map(f32::to_le_bytes)
+                    .collect()
+            })
+            .collect();
+
+        let strategy = SplitStrategy::ByTensorParallel { num_shards };
+        let merged = merger.merge_results(&results, None, &strategy).unwrap();
+        let values = to_f32s(&merged);
+        assert_eq!(values.len(), 2 * 2 * 16);
+        // 每个专家的输出为 [2个token, 16列]，列按分片顺序拼接
+        for (i, value) in values.iter().enumerate() {
+            let (expert, token, col) = (i / 32, i % 32 / 16, i % 16);
+            assert_eq!(*value, (expert * 100 + token * 10 + col) as f32);
+        }
+
+        let gate_weights = GateWeights { weights: vec![1.0, 0.0], top_k: 1 };
+        let weighted = merger.merge_results(&results, Some(gate_weights), &strategy).unwrap();
+        assert_eq!(to_f32s(&weighted), values[..32].to_vec());
+
+        assert!(merger.merge_results(&results[1..], None, &strategy).is_err());
+    }
+
+    #[test]
+    fn test_merge_expert_results_half_precision() {
+        let values: Vec<Vec<f32>> = vec![
+            (0..16).map(|i| i as f32 * 0.37 - 2.5).collect(),
+            (0..16).map(|i| 1.25 - i as f32 * 0.11).collect(),
+        ];
+        let gate_weights = GateWeights { weights: vec![0.7, 0.3], top_k: 2 };
+
+        let mut merger = test_merger();
+        let f32_results: Vec<Vec<u8>> = values.iter().map(|v| v.iter().flat_map(|x| x.to_le_bytes()).collect()).collect();
+        let reference = to_f32s(&merger.merge_results(&f32_results, Some(gate_weights.clone()), &SplitStrategy::ByExpert).unwrap());
+
+        // (dtype, 编码函数, 解码函数, 容差)
+        type Codec = (DType, fn(f32) -> [u8; 2], fn([u8; 2]) -> f32, f32);
+        let codecs: [Codec; 2] = [
+            (DType::F16, |x| f16::from_f32(x).to_le_bytes(), |b| f16::from_le_bytes(b).to_f32(), 1e-2),
+            (DType::BF16, |x| bf16::from_f32(x).to_le_bytes(), |b| bf16::from_le_bytes(b).to_f32(), 5e-2),
+        ];
+        for (dtype, encode, decode, tolerance) in codecs {
+            merger.set_dtype(dtype);
+            let results: Vec<Vec<u8>> = values.iter().map(|v| v.iter().flat_map(|x| encode(*x)).collect()).collect();
+            let merged = merger.merge_results(&results, Some(gate_weights.clone()), &SplitStrategy::ByExpert).unwrap();
+            assert_eq!(merged.len(), 16 * 2);
+            for (chunk, expected) in merged.chunks_exact(2).zip(reference.iter()) {
+                let value = decode(chunk.try_into().unwrap());
+                assert!((value - expected).abs() <= tolerance, "{:?}: {} vs {}", dtype, value, expected);
+            }
+            // 奇数字节数无法按2字节元素解析
+            let odd = vec![vec![0u8; 3], vec![0u8; 3]];
+            assert!(merger.merge_results(&odd, Some(gate_weights.clone()), &SplitStrategy::ByExpert).is_err());
+        }
+    }
+
+    #[test]
+    fn test_merge_expert_results_weighted_sum_per_dtype() {
+        let gate_weights = GateWeights { weights: vec![0.75, 0.25], top_k: 2 };
+        let experts = [[1.0f32, -2.0, 4.0], [5.0, 2.0, -8.0]];
+        let expected = [2.0f32, -1.0, 1.0];
+
+        let mut merger = test_merger();
+        let results: Vec<Vec<u8>> = experts.iter().map(|v| f32_bytes(v)).collect();
+        let merged = merger.merge_results(&results, Some(gate_weights.clone()), &SplitStrategy::ByExpert).unwrap();
+        assert_eq!(to_f32s(&merged), expected);
+
+        // 同样的f16缓冲区按f32解析会得到错误的结果，按f16解析时加权和精确
+        let results: Vec<Vec<u8>> = experts
+            .iter()
+            .map(|v| v.iter().flat_map(|x| f16::from_f32(*x).to_le_bytes()).collect())
+            .collect();
+        assert!(merger.merge_results(&results, Some(gate_weights.clone()), &SplitStrategy::ByExpert).is_err());
+        merger.set_dtype(DType::F16);
+        let merged = merger.merge_results(&results, Some(gate_weights), &SplitStrategy::ByExpert).unwrap();
+        let merged: Vec<f32> = merged.chunks_exact(2).map(|c| f16::from_le_bytes(c.try_into().unwrap()).to_f32()).collect();
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_streaming_merge_matches_batch_merge() {
+        let num_experts = 16;
+        let results: Vec<Vec<u8>> = (0..num_experts)
+            .map(|expert_id| f32_bytes(&(0..64).map(|i| ((expert_id * 64 + i) as f32 * 0.013).sin()).collect::<Vec<_>>()))
+            .collect();
+        let logits: Vec<f32> = (0..num_experts).map(|i| (i as f32 * 0.7).cos()).collect();
+        let gate_weights = GateWeights::from_logits(&logits, 4);
+
+        let mut merger = test_merger();
+        merger.model_info.num_experts = num_experts;
+        for merge_mode in [MergeMode::WeightedSum, MergeMode::WeightedMean] {
+            merger.set_merge_mode(merge_mode);
+            let batch = merger.merge_results(&results, Some(gate_weights.clone()), &SplitStrategy::ByExpert).unwrap();
+
+            let mut streaming = merger.streaming_merger();
+            for (expert_id, result) in results.iter().enumerate() {
+                streaming.push(expert_id, gate_weights.weights[expert_id], result).unwrap();
+            }
+            assert_eq!(streaming.len(), num_experts);
+            assert_eq!(streaming.finalize().unwrap(), batch);
+        }
+
+        let mut streaming = merger.streaming_merger();
+        assert!(matches!(merger.streaming_merger().finalize(), Err(Error::InferenceError(_))));
+        streaming.push(0, 1.0, &results[0]).unwrap();
+        assert!(streaming.push(0, 1.0, &results[0]).is_err());
+        assert!(streaming.push(1, 1.0, &results[1][..8]).is_err());
+    }
+
+    #[test]
+    fn test_merge_layer_results_rejects_partial_elements() {
+        let merger = test_merger();
+        let full: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let partial: Vec<u8> = full[..6].to_vec();
+
+        for results in [vec![full.clone(), partial.clone()], vec![partial.clone(), partial.clone()]] {
+            match merger.merge_results(&results, None, &SplitStrategy::ByLayer) {
+                Err(Error::InferenceError(msg)) => assert!(msg.contains('6'), "{}", msg),
+                other => panic!("期望 InferenceError，实际为 {:?}", other),
+            }
+        }
+
+        // 元素完整但大小不一致时，错误中给出层ID、实际大小和期望大小
+        let longer: Vec<u8> = [1.0f32, 2.0, 3.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+        match merger.merge_results(&[full.clone(), full.clone(), longer], None, &SplitStrategy::ByLayer) {
+            Err(Error::InferenceError(msg)) => assert!(msg.contains("第 2 层输出大小 12 与残差大小 8"), "{}", msg),
+            other => panic!("期望 InferenceError，实际为 {:?}", other),
+        }
+
+        let merged = merger.merge_results(&[full.clone(), full], None, &SplitStrategy::ByLayer).unwrap();
+        assert_eq!(to_f32s(&merged), vec![2.0, 4.0]);
+    }
+
+    #[test]
+    fn test_merge_mode_weighted_mean() {
+        let mut merger = test_merger();
+        let results: Vec<Vec<u8>> = [[1.0f32, 2.0], [3.0, 6.0]]
+            .iter()
+            .map(|v| v.iter().flat_map(|x| x.to_le_bytes()).collect())
+            .collect();
+        let gate_weights = GateWeights { weights: vec![0.4, 0.4], top_k: 2 };
+
+        assert_eq!(merger.merge_mode, MergeMode::WeightedSum);
+        let sum = to_f32s(&merger.merge_results(&results, Some(gate_weights.clone()), &SplitStrategy::ByExpert).unwrap());
+        assert!((sum[0] - 1.6).abs() < 1e-6 && (sum[1] - 3.2).abs() < 1e-6);
+
+        merger.set_merge_mode(MergeMode::WeightedMean);
+        let mean = to_f32s(&merger.merge_results(&results, Some(gate_weights), &SplitStrategy::ByExpert).unwrap());
+        assert!((mean[0] - 2.0).abs() < 1e-6 && (mean[1] - 4.0).abs() < 1e-6);
+
+        // 权重全为0时不会除以0
+        let zero_weights = GateWeights { weights: vec![0.0, 0.0], top_k: 2 };
+        let zeros = to_f32s(&merger.merge_results(&results, Some(zero_weights), &SplitStrategy::ByExpert).unwrap());
+        assert_eq!(zeros, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_merge_with_gate_weights_loaded_from_file() {
+        let merger = test_merger();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gates.bin");
+        GateWeights { weights: vec![0.25, 0.75], top_k: 2 }.save(&path).unwrap();
+
+        let gate_weights = merger.load_gate_weights(&path).unwrap();
+        let results = vec![f32_bytes(&[4.0, 8.0]), f32_bytes(&[8.0, 4.0])];
+        let merged = to_f32s(&merger.merge_results(&results, Some(gate_weights), &SplitStrategy::ByExpert).unwrap());
+        assert_eq!(merged, vec![7.0, 5.0]);
+
+        // 权重个数与专家数不一致时拒绝
+        GateWeights { weights: vec![0.5; 3], top_k: 2 }.save(&path).unwrap();
+        assert!(matches!(merger.load_gate_weights(&path), Err(Error::ConfigError(_))));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_merge_partial_renormalizes_over_successful_experts() {
+        let merger = test_merger();
+        let results = vec![
+            Some(f32_bytes(&[1.0, 10.0])),
+            None,
+            Some(f32_bytes(&[3.0, 30.0])),
+        ];
+        let gate_weights = GateWeights { weights: vec![0.2, 0.5, 0.3], top_k: 3 };
+
+        // 专家0和2的权重 0.2、0.3 归一化为 0.4、0.6
+        let merged = to_f32s(&merger.merge_expert_results_partial(&results, gate_weights).unwrap());
+        assert!((merged[0] - (0.4 * 1.0 + 0.6 * 3.0)).abs() < 1e-6);
+        assert!((merged[1] - (0.4 * 10.0 + 0.6 * 30.0)).abs() < 1e-5);
+
+        let all_failed = vec![None, None, None];
+        let gate_weights = GateWeights { weights: vec![0.2, 0.5, 0.3], top_k: 3 };
+        assert!(matches!(merger.merge_expert_results_partial(&all_failed, gate_weights), Err(Error::InferenceError(_))));
+    }
+
+    #[test]
+    fn test_merge_results_checked_rejects_implausible_output() {
+        let mut merger = test_merger();
+        let results = vec![f32_bytes(&[3.0, 4.0]), f32_bytes(&[0.0, 5.0])];
+        let gate_weights = GateWeights { weights: vec![0.5, 0.5], top_k: 2 };
+        let merged = to_f32s(&merger.merge_results_checked(&results, gate_weights).unwrap());
+        assert_eq!(merged, vec![1.5, 4.5]);
+
+        // 专家结果中混入 NaN
+        let with_nan = vec![f32_bytes(&[3.0, f32::NAN]), f32_bytes(&[0.0, 5.0])];
+        let gate_weights = GateWeights { weights: vec![0.5, 0.5], top_k: 2 };
+        assert!(matches!(merger.merge_results_checked(&with_nan, gate_weights), Err(Error::InferenceError(_))));
+
+        // 门控权重全为0，输出全为0
+        let zero_weights = GateWeights { weights: vec![0.0, 0.0], top_k: 2 };
+        assert!(matches!(merger.merge_results_checked(&results, zero_weights), Err(Error::InferenceError(_))));
+
+        // 未归一化的权重使输出超过10倍；放宽比例后通过
+        let unnormalized = GateWeights { weights: vec![8.0, 8.0], top_k: 2 };
+        assert!(matches!(merger.merge_results_checked(&results, unnormalized.clone()), Err(Error::InferenceError(_))));
+        merger.set_max_norm_ratio(20.0);
+        assert!(merger.merge_results_checked(&results, unnormalized).is_ok());
+    }
+
+    /// 带token路由头部的专家任务，结果为 kept 中各token的输出行
+    fn routed_expert_task(expert_id: usize, num_tokens: usize, kept: Vec<usize>, rows: &[f32]) -> MoeTask {
+        let mut header = task_header::TaskHeader::new(task_header::TaskKind::Expert {
+            expert_id,
+            gate_weights: Vec::new(),
+            column_slice: None,
+        });
+        header.token_routing = Some(TokenRouting { num_tokens, kept, dropped: Vec::new() });
+        MoeTask {
+            status: crate::task::TaskStatus::Completed,
+            result: Some(f32_bytes(rows)),
+            ..MoeTask::new(format!("ragged/expert_{}", expert_id), task_header::encode(&header), crate::task::TaskPriority::Normal)
+        }
+    }
+
+    #[test]
+    fn test_merge_ragged_expert_results_disjoint_tokens() {
+        let merger = test_merger();
+        // 6个token，每个token 4个元素，专家0处理前2个token，专家1处理后4个
+        let sequence: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let tasks = vec![
+            routed_expert_task(1, 6, vec![2, 3, 4, 5], &sequence[8..]),
+            routed_expert_task(0, 6, vec![0, 1], &sequence[..8]),
+        ];
+        let gate_weights = GateWeights { weights: vec![1.0, 1.0], top_k: 2 };
+        let merged = merger.merge_ragged_expert_results(&tasks, gate_weights).unwrap();
+        assert_eq!(to_f32s(&merged), sequence);
+
+        // 权重作用在各自负责的token上
+        let gate_weights = GateWeights { weights: vec![0.5, 2.0], top_k: 2 };
+        let merged = to_f32s(&merger.merge_ragged_expert_results(&tasks, gate_weights).unwrap());
+        assert_eq!(merged[..8], sequence[..8].iter().map(|v| v * 0.5).collect::<Vec<_>>()[..]);
+        assert_eq!(merged[8..], sequence[8..].iter().map(|v| v * 2.0).collect::<Vec<_>>()[..]);
+
+        // 加权平均时每个token只除以覆盖它的专家权重
+        let mut merger = test_merger();
+        merger.set_merge_mode(MergeMode::WeightedMean);
+        let gate_weights = GateWeights { weights: vec![0.5, 2.0], top_k: 2 };
+        let merged = merger.merge_ragged_expert_results(&tasks, gate_weights).unwrap();
+        assert_eq!(to_f32s(&merged), sequence);
+
+        // token总数不一致时报错
+        let mismatched = vec![
+            routed_expert_task(0, 6, vec![0, 1], &sequence[..8]),
+            routed_expert_task(1, 5, vec![2, 3, 4], &sequence[8..20]),
+        ];
+        let gate_weights = GateWeights { weights: vec![1.0, 1.0], top_k: 2 };
+        assert!(merger.merge_ragged_expert_results(&mismatched, gate_weights).is_err());
+    }
+}
@@ -1,16 +1,123 @@
 // scheduler.rs
 // 任务调度器，支持任务队列的提交、获取等基本调度操作。
-use crate::task::MoeTask;
+use crate::task::{MoeTask, TaskPriority, TaskStatus};
 use crate::config::SchedulerConfig;
-use std::collections::VecDeque;
+use crate::error::{Error, Result};
+use crate::task_executor::Executor;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-/// 简单的任务调度器，支持任务队列的提交与获取
+/// 调度模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedulingMode {
+    /// 按优先级从高到低，同优先级按提交顺序
+    #[default]
+    Priority,
+    /// 先进先出，忽略优先级
+    Fifo,
+    /// 最早截止时间优先（EDF），无截止时间的任务排在后面，再按优先级和提交顺序
+    EarliestDeadline,
+}
+
+/// 队列中的任务，记录提交序号用于同优先级内的先进先出
+#[derive(Debug, Clone)]
+pub struct QueuedTask {
+    /// 提交序号，越小越早提交
+    pub seq: u64,
+    /// 任务
+    pub task: MoeTask,
+    /// 入队时间，用于优先级老化
+    pub enqueued_at: Instant,
+}
+
+impl QueuedTask {
+    /// 堆排序键：优先级越高越大，同优先级提交越早越大
+    fn key(&self) -> (TaskPriority, Reverse<u64>) {
+        (self.task.priority, Reverse(self.seq))
+    }
+
+    /// 老化后的有效优先级：在原优先级上每等待一个 aging_interval 提升一级
+    /// 未设置老化间隔（或间隔为0）时即为原优先级
+    pub fn effective_priority(&self, now: Instant, aging_interval: Option<Duration>) -> u64 {
+        let boost = match aging_interval {
+            Some(interval) if !interval.is_zero() => {
+                (now.saturating_duration_since(self.enqueued_at).as_nanos() / interval.as_nanos()) as u64
+            }
+            _ => 0,
+        };
+        self.task.priority as u64 + boost
+    }
+}
+
+impl PartialEq for QueuedTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for QueuedTask {}
+
+impl PartialOrd for QueuedTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedTask {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// 队列快照中的一个任务及其尚未完成的依赖和剩余截止时间
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotEntry {
+    task: MoeTask,
+    /// 快照时尚未完成的依赖任务ID
+    #[serde(default)]
+    deps: Vec<String>,
+    /// 快照时距截止时间的剩余毫秒数，已过期的为0
+    #[serde(default)]
+    deadline_in_ms: Option<u64>,
+}
+
+/// 父任务的子任务结果收集状态
+#[derive(Debug, Default)]
+struct JoinState {
+    /// 父任务ID -> 按子任务顺序排列的结果
+    parents: HashMap<String, Vec<Option<Vec<u8>>>>,
+    /// 子任务ID -> (父任务ID, 在父任务中的位置)
+    slots: HashMap<String, (String, usize)>,
+}
+
+/// 任务调度器，默认按优先级取任务，同优先级内先进先出
 pub struct TaskScheduler {
     /// 调度器配置
     pub config: SchedulerConfig,
-    /// 任务队列，线程安全
-    pub queue: Arc<Mutex<VecDeque<MoeTask>>>,
+    /// 任务队列（按优先级排序的堆），线程安全
+    pub queue: Arc<Mutex<BinaryHeap<QueuedTask>>>,
+    /// 下一个提交序号
+    next_seq: AtomicU64,
+    /// 调度模式
+    mode: SchedulingMode,
+    /// 是否丢弃已超过截止时间的任务
+    drop_expired: bool,
+    /// 优先级老化间隔，设置后任务每等待一个间隔有效优先级提升一级
+    aging_interval: Option<Duration>,
+    /// 因超过截止时间而被丢弃的任务
+    expired: Arc<Mutex<Vec<MoeTask>>>,
+    /// 通过 submit_with_deps 提交的任务ID到其依赖任务ID的映射
+    dependencies: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    /// 已完成的任务ID
+    completed: Arc<Mutex<HashSet<String>>>,
+    /// 已取出但尚未完成的任务ID及其取消标记
+    running: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    /// 父任务的子任务结果收集状态
+    joins: Arc<Mutex<JoinState>>,
 }
 
 impl TaskScheduler {
@@ -18,19 +125,752 @@ impl TaskScheduler {
     pub fn new(config: SchedulerConfig) -> Self {
         Self {
             config,
-            queue: Arc::new(Mutex::new(VecDeque::new())),
+            queue: Arc::new(Mutex::new(BinaryHeap::new())),
+            next_seq: AtomicU64::new(0),
+            mode: SchedulingMode::default(),
+            drop_expired: false,
+            aging_interval: None,
+            expired: Arc::new(Mutex::new(Vec::new())),
+            dependencies: Arc::new(Mutex::new(HashMap::new())),
+            completed: Arc::new(Mutex::new(HashSet::new())),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            joins: Arc::new(Mutex::new(JoinState::default())),
         }
     }
 
+    /// 设置调度模式
+    pub fn set_mode(&mut self, mode: SchedulingMode) {
+        self.mode = mode;
+    }
+
+    /// 获取调度模式
+    pub fn mode(&self) -> SchedulingMode {
+        self.mode
+    }
+
+    /// 设置是否丢弃已超过截止时间的任务
+    /// 启用后取任务时会把过期任务标记为 Failed("deadline exceeded") 并移出队列
+    pub fn set_drop_expired(&mut self, drop_expired: bool) {
+        self.drop_expired = drop_expired;
+    }
+
+    /// 设置优先级老化间隔，避免大量高优先级任务使低优先级任务一直得不到执行
+    /// 启用后按优先级调度时，取任务时按等待时间重新计算有效优先级，有效优先级相同时先提交的优先；传入None关闭老化
+    pub fn set_aging_interval(&mut self, aging_interval: Option<Duration>) {
+        self.aging_interval = aging_interval;
+    }
+
     /// 提交一个新任务到队列
     pub fn submit_task(&self, task: MoeTask) {
         let mut queue = self.queue.lock().unwrap();
-        queue.push_back(task);
+        let seq = self.next_seq.fetch_add(1, AtomicOrdering::Relaxed);
+        queue.push(QueuedTask { seq, task, enqueued_at: Instant::now() });
+    }
+
+    /// 提交一个带依赖的任务，只有依赖的任务都通过 mark_completed 标记完成后才会被取出
+    /// 依赖关系形成环时返回错误，任务不会入队
+    pub fn submit_with_deps(&self, task: MoeTask, deps: Vec<String>) -> Result<()> {
+        {
+            let mut dependencies = self.dependencies.lock().unwrap();
+            if let Some(path) = Self::find_cycle(&dependencies, &task.task_id, &deps) {
+                return Err(Error::Other(format!("任务依赖存在环: {}", path.join(" -> "))));
+            }
+            dependencies.insert(task.task_id.clone(), deps);
+        }
+        self.submit_task(task);
+        Ok(())
+    }
+
+    /// 标记任务已完成，依赖它的任务随后可以被取出
+    pub fn mark_completed(&self, task_id: &str) {
+        let mut completed = self.completed.lock().unwrap();
+        completed.insert(task_id.to_string());
+    }
+
+    /// 任务执行完成：释放并发名额，并标记为已完成以解除依赖它的任务
+    pub fn complete_task(&self, task_id: &str) {
+        self.running.lock().unwrap().remove(task_id);
+        self.mark_completed(task_id);
+    }
+
+    /// 任务执行失败：只释放并发名额，依赖它的任务不会被解除
+    pub fn release_task(&self, task_id: &str) {
+        self.running.lock().unwrap().remove(task_id);
+    }
+
+    /// 已取出但尚未调用 complete_task 的任务数
+    pub fn in_flight_count(&self) -> usize {
+        self.running.lock().unwrap().len()
+    }
+
+    /// 登记父任务及其子任务，之后可通过 try_take_completed 按子任务顺序取回全部结果
+    pub fn register_parent(&self, parent_id: &str, child_ids: Vec<String>) -> Result<()> {
+        let mut joins = self.joins.lock().unwrap();
+        if joins.parents.contains_key(parent_id) {
+            return Err(Error::Other(format!("父任务 {} 已经登记", parent_id)));
+        }
+        let mut seen = HashSet::new();
+        for child_id in &child_ids {
+            if joins.slots.contains_key(child_id) || !seen.insert(child_id) {
+                return Err(Error::Other(format!("子任务 {} 重复登记", child_id)));
+            }
+        }
+        for (position, child_id) in child_ids.iter().enumerate() {
+            joins.slots.insert(child_id.clone(), (parent_id.to_string(), position));
+        }
+        joins.parents.insert(parent_id.to_string(), vec![None; child_ids.len()]);
+        Ok(())
+    }
+
+    /// 提交子任务的结果，同时按 complete_task 释放名额并标记完成
+    pub fn submit_result(&self, task_id: &str, result: Vec<u8>) -> Result<()> {
+        {
+            let mut joins = self.joins.lock().unwrap();
+            let (parent_id, position) = joins
+                .slots
+                .get(task_id)
+                .cloned()
+                .ok_or_else(|| Error::Other(format!("任务 {} 不属于任何已登记的父任务", task_id)))?;
+            if let Some(results) = joins.parents.get_mut(&parent_id) {
+                results[position] = Some(result);
+            }
+        }
+        self.complete_task(task_id);
+        Ok(())
+    }
+
+    /// 任务是否是某个已登记父任务的子任务
+    fn has_parent(&self, task_id: &str) -> bool {
+        self.joins.lock().unwrap().slots.contains_key(task_id)
+    }
+
+    /// 父任务的所有子任务都已提交结果时，按子任务登记顺序取回结果并清除登记
+    /// 尚有子任务未完成或父任务未登记时返回None
+    pub fn try_take_completed(&self, parent_id: &str) -> Option<Vec<Vec<u8>>> {
+        let mut joins = self.joins.lock().unwrap();
+        if !joins.parents.get(parent_id)?.iter().all(Option::is_some) {
+            return None;
+        }
+        let results: Vec<Vec<u8>> = joins.parents.remove(parent_id)?.into_iter().flatten().collect();
+        joins.slots.retain(|_, (parent, _)| parent != parent_id);
+        Some(results)
     }
 
-    /// 获取下一个待执行任务（FIFO）
+    /// 获取下一个待执行任务，跳过依赖尚未全部完成的任务
+    /// 已有 max_concurrent_tasks 个任务在执行（为0时不限制），或队列不为空但没有可执行的任务时返回None
     pub fn fetch_next_task(&self) -> Option<MoeTask> {
         let mut queue = self.queue.lock().unwrap();
-        queue.pop_front()
+        if self.drop_expired {
+            self.drop_expired_tasks(&mut queue);
+        }
+        let mut next = self.select_next_task(&mut queue)?;
+        let cancel_flag = next.cancel_flag.get_or_insert_with(|| Arc::new(AtomicBool::new(false)));
+        self.running.lock().unwrap().insert(next.task_id.clone(), Arc::clone(cancel_flag));
+        Some(next)
+    }
+
+    /// 取消任务及其登记的所有子任务（递归）
+    /// 仍在队列中的任务被移出并标记为 Failed("cancelled") 后返回；已取出的任务只置位取消标记，
+    /// 由执行器在拷回结果前检查并提前返回
+    pub fn cancel(&self, task_id: &str) -> Vec<MoeTask> {
+        // 收集任务自身及其递归登记的子任务
+        let mut to_cancel = vec![task_id.to_string()];
+        {
+            let joins = self.joins.lock().unwrap();
+            let mut index = 0;
+            while index < to_cancel.len() {
+                let parent_id = to_cancel[index].clone();
+                let mut children: Vec<(usize, String)> = joins
+                    .slots
+                    .iter()
+                    .filter(|(_, (parent, _))| *parent == parent_id)
+                    .map(|(child_id, (_, position))| (*position, child_id.clone()))
+                    .collect();
+                children.sort();
+                to_cancel.extend(children.into_iter().map(|(_, child_id)| child_id));
+                index += 1;
+            }
+        }
+        let to_cancel: HashSet<String> = to_cancel.into_iter().collect();
+
+        let mut queue = self.queue.lock().unwrap();
+        let (mut cancelled, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut *queue)
+            .into_vec()
+            .into_iter()
+            .partition(|queued| to_cancel.contains(&queued.task.task_id));
+        *queue = BinaryHeap::from(kept);
+        drop(queue);
+
+        let running = self.running.lock().unwrap();
+        for task_id in &to_cancel {
+            if let Some(flag) = running.get(task_id) {
+                flag.store(true, AtomicOrdering::SeqCst);
+            }
+        }
+
+        cancelled.sort_by_key(|queued| queued.seq);
+        cancelled
+            .into_iter()
+            .map(|queued| {
+                let mut task = queued.task;
+                task.status = TaskStatus::Failed("cancelled".to_string());
+                task
+            })
+            .collect()
+    }
+
+    /// 按调度模式从队列中取出下一个可执行的任务
+    fn select_next_task(&self, queue: &mut BinaryHeap<QueuedTask>) -> Option<MoeTask> {
+        let max_concurrent_tasks = self.config.max_concurrent_tasks;
+        if max_concurrent_tasks > 0 && self.in_flight_count() >= max_concurrent_tasks {
+            return None;
+        }
+        let dependencies = self.dependencies.lock().unwrap();
+        let completed = self.completed.lock().unwrap();
+        let is_ready = |queued: &QueuedTask| {
+            dependencies
+                .get(&queued.task.task_id)
+                .is_none_or(|deps| deps.iter().all(|dep| completed.contains(dep)))
+        };
+        match self.mode {
+            SchedulingMode::Priority if self.aging_interval.is_some() => {
+                let now = Instant::now();
+                Self::remove_min_by_key(queue, is_ready, |queued| {
+                    (Reverse(queued.effective_priority(now, self.aging_interval)), queued.seq)
+                })
+            }
+            SchedulingMode::Priority => {
+                // 依次弹出优先级最高的任务，依赖未完成的先放一边，取到后再放回
+                let mut blocked = Vec::new();
+                let mut next = None;
+                while let Some(queued) = queue.pop() {
+                    if is_ready(&queued) {
+                        next = Some(queued.task);
+                        break;
+                    }
+                    blocked.push(queued);
+                }
+                queue.extend(blocked);
+                next
+            }
+            SchedulingMode::Fifo => Self::remove_min_by_key(queue, is_ready, |queued| queued.seq),
+            SchedulingMode::EarliestDeadline => {
+                // 有截止时间的排在前面并按截止时间升序，其次按优先级降序，最后按提交顺序
+                Self::remove_min_by_key(queue, is_ready, |queued| {
+                    let task = &queued.task;
+                    (task.deadline.is_none(), task.deadline, Reverse(task.priority), queued.seq)
+                })
+            }
+        }
+    }
+
+    /// 将待执行队列按提交顺序序列化为JSON，用于崩溃恢复
+    ///
+    /// 每个任务附带尚未完成的依赖和距截止时间的剩余时间；已完成的依赖不再记录，
+    /// 取消标记不参与序列化。
+    pub fn dump_queue(&self) -> Result<String> {
+        let queue = self.queue.lock().unwrap();
+        let dependencies = self.dependencies.lock().unwrap();
+        let completed = self.completed.lock().unwrap();
+        let now = Instant::now();
+        let mut queued: Vec<&QueuedTask> = queue.iter().collect();
+        queued.sort_by_key(|queued| queued.seq);
+        let entries: Vec<SnapshotEntry> = queued
+            .into_iter()
+            .map(|queued| SnapshotEntry {
+                task: queued.task.clone(),
+                deps: dependencies
+                    .get(&queued.task.task_id)
+                    .map(|deps| deps.iter().filter(|dep| !completed.contains(*dep)).cloned().collect())
+                    .unwrap_or_default(),
+                deadline_in_ms: queued
+                    .task
+                    .deadline
+                    .map(|deadline| deadline.saturating_duration_since(now).as_millis() as u64),
+            })
+            .collect();
+        serde_json::to_string(&entries).map_err(|e| Error::Other(format!("序列化任务队列失败: {}", e)))
+    }
+
+    /// 从 dump_queue 生成的JSON恢复任务，按原提交顺序通过 submit_with_deps 追加到队列末尾
+    ///
+    /// 截止时间按快照时的剩余时间从现在起重新计算。
+    pub fn load_queue(&self, json: &str) -> Result<()> {
+        let entries: Vec<SnapshotEntry> =
+            serde_json::from_str(json).map_err(|e| Error::Other(format!("队列快照无效: {}", e)))?;
+        let now = Instant::now();
+        for SnapshotEntry { mut task, deps, deadline_in_ms } in entries {
+            task.deadline = deadline_in_ms.map(|remaining| now + Duration::from_millis(remaining));
+            self.submit_with_deps(task, deps)?;
+        }
+        Ok(())
+    }
+
+    /// 取出因超过截止时间而被丢弃的任务
+    pub fn take_expired_tasks(&self) -> Vec<MoeTask> {
+        let mut expired = self.expired.lock().unwrap();
+        std::mem::take(&mut *expired)
+    }
+
+    /// 在可执行的任务中按给定的键取出最小的任务，用于不按优先级排序的调度模式
+    fn remove_min_by_key<K: Ord>(
+        queue: &mut BinaryHeap<QueuedTask>,
+        is_ready: impl Fn(&QueuedTask) -> bool,
+        key: impl Fn(&QueuedTask) -> K,
+    ) -> Option<MoeTask> {
+        let mut tasks = std::mem::take(queue).into_vec();
+        let index = tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, queued)| is_ready(queued))
+            .min_by_key(|(_, queued)| key(queued))
+            .map(|(index, _)| index);
+        let task = index.map(|index| tasks.swap_remove(index).task);
+        *queue = BinaryHeap::from(tasks);
+        task
+    }
+
+    /// 检查加入 task_id -> deps 后依赖图是否有环，有环时返回环上的任务ID路径
+    fn find_cycle(dependencies: &HashMap<String, Vec<String>>, task_id: &str, deps: &[String]) -> Option<Vec<String>> {
+        // 从每个依赖出发深度优先搜索，能回到 task_id 即有环
+        let mut visited = HashSet::new();
+        let mut stack: Vec<Vec<String>> = deps
+            .iter()
+            .map(|dep| vec![task_id.to_string(), dep.clone()])
+            .collect();
+        while let Some(path) = stack.pop() {
+            let current = path.last().unwrap();
+            if current == task_id {
+                return Some(path);
+            }
+            if !visited.insert(current.clone()) {
+                continue;
+            }
+            for next in dependencies.get(current).into_iter().flatten() {
+                let mut next_path = path.clone();
+                next_path.push(next.clone());
+                stack.push(next_path);
+            }
+        }
+        None
+    }
+
+    /// 将已超过截止时间的任务移出队列
+    fn drop_expired_tasks(&self, queue: &mut BinaryHeap<QueuedTask>) {
+        let now = Instant::now();
+        let mut expired = self.expired.lock().unwrap();
+        let (mut dropped, kept): (Vec<_>, Vec<_>) = std::mem::take(queue)
+            .into_vec()
+            .into_iter()
+            .partition(|queued| queued.task.deadline.is_some_and(|deadline| deadline <= now));
+        *queue = BinaryHeap::from(kept);
+        // 按提交顺序记录过期任务
+        dropped.sort_by_key(|queued| queued.seq);
+        for queued in dropped {
+            let mut task = queued.task;
+            task.status = TaskStatus::Failed("deadline exceeded".to_string());
+            expired.push(task);
+        }
+    }
+}
+
+/// 任务运行器，从调度器取出任务交给执行后端执行
+///
+/// 执行后端可以是CUDA执行器、主机执行器或测试中的模拟实现。
+pub struct TaskRunner {
+    scheduler: Arc<TaskScheduler>,
+    executor: Arc<dyn Executor>,
+}
+
+impl TaskRunner {
+    pub fn new(scheduler: Arc<TaskScheduler>, executor: Arc<dyn Executor>) -> Self {
+        Self { scheduler, executor }
+    }
+
+    /// 所驱动的调度器
+    pub fn scheduler(&self) -> &Arc<TaskScheduler> {
+        &self.scheduler
+    }
+
+    /// 依次取出并执行任务，直到没有可执行的任务为止，按执行顺序返回已完成的任务
+    ///
+    /// 登记了父任务的子任务结果通过 submit_result 提交，其余任务直接标记完成。
+    /// 任务失败时释放其并发名额并返回错误，依赖它的任务留在队列中。
+    pub fn run_until_idle(&self) -> Result<Vec<MoeTask>> {
+        let mut finished = Vec::new();
+        while let Some(mut task) = self.scheduler.fetch_next_task() {
+            let result = match self.executor.execute_task(&mut task) {
+                Ok(result) => result,
+                Err(e) => {
+                    task.mark_failed(&e);
+                    self.scheduler.release_task(&task.task_id);
+                    return Err(e);
+                }
+            };
+            if self.scheduler.has_parent(&task.task_id) {
+                self.scheduler.submit_result(&task.task_id, result)?;
+            } else {
+                self.scheduler.complete_task(&task.task_id);
+            }
+            finished.push(task);
+        }
+        Ok(finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn test_task(task_id: &str, priority: TaskPriority, deadline: Option<Instant>) -> MoeTask {
+        MoeTask {
+            deadline,
+            ..MoeTask::new(task_id, Vec::new(), priority)
+        }
+    }
+
+    /// 依次取出并完成所有可执行的任务
+    fn drain(scheduler: &TaskScheduler) -> Vec<String> {
+        std::iter::from_fn(|| {
+            let task = scheduler.fetch_next_task()?;
+            scheduler.complete_task(&task.task_id);
+            Some(task.task_id)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_earliest_deadline_first_order() {
+        let mut scheduler = TaskScheduler::new(SchedulerConfig::default());
+        scheduler.set_mode(SchedulingMode::EarliestDeadline);
+        let now = Instant::now();
+
+        scheduler.submit_task(test_task("no_deadline_low", TaskPriority::Low, None));
+        scheduler.submit_task(test_task("late", TaskPriority::Normal, Some(now + Duration::from_secs(30))));
+        scheduler.submit_task(test_task("no_deadline_high", TaskPriority::High, None));
+        scheduler.submit_task(test_task("early", TaskPriority::Low, Some(now + Duration::from_secs(10))));
+        scheduler.submit_task(test_task("middle", TaskPriority::Normal, Some(now + Duration::from_secs(20))));
+        scheduler.submit_task(test_task("no_deadline_high_2", TaskPriority::High, None));
+
+        assert_eq!(
+            drain(&scheduler),
+            vec!["early", "middle", "late", "no_deadline_high", "no_deadline_high_2", "no_deadline_low"]
+        );
+    }
+
+    #[test]
+    fn test_expired_task_dropped() {
+        let mut scheduler = TaskScheduler::new(SchedulerConfig::default());
+        scheduler.set_mode(SchedulingMode::EarliestDeadline);
+        scheduler.set_drop_expired(true);
+        let now = Instant::now();
+
+        scheduler.submit_task(test_task("expired", TaskPriority::Critical, Some(now - Duration::from_millis(1))));
+        scheduler.submit_task(test_task("pending", TaskPriority::Normal, Some(now + Duration::from_secs(10))));
+
+        assert_eq!(drain(&scheduler), vec!["pending"]);
+        let expired = scheduler.take_expired_tasks();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].task_id, "expired");
+        assert!(matches!(&expired[0].status, TaskStatus::Failed(reason) if reason == "deadline exceeded"));
+    }
+
+    #[test]
+    fn test_fifo_mode_ignores_priority() {
+        let mut scheduler = TaskScheduler::new(SchedulerConfig::default());
+        scheduler.set_mode(SchedulingMode::Fifo);
+        let now = Instant::now();
+        scheduler.submit_task(test_task("first", TaskPriority::Low, Some(now + Duration::from_secs(10))));
+        scheduler.submit_task(test_task("second", TaskPriority::High, Some(now + Duration::from_secs(1))));
+        assert_eq!(drain(&scheduler), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_priority_is_default() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        assert_eq!(scheduler.mode(), SchedulingMode::Priority);
+        scheduler.submit_task(test_task("low", TaskPriority::Low, None));
+        scheduler.submit_task(test_task("critical", TaskPriority::Critical, None));
+        scheduler.submit_task(test_task("normal", TaskPriority::Normal, None));
+        assert_eq!(drain(&scheduler), vec!["critical", "normal", "low"]);
+
+        // 同优先级内先进先出
+        for i in 0..5 {
+            scheduler.submit_task(test_task(&format!("normal_{}", i), TaskPriority::Normal, None));
+        }
+        scheduler.submit_task(test_task("high", TaskPriority::High, None));
+        assert_eq!(drain(&scheduler), vec!["high", "normal_0", "normal_1", "normal_2", "normal_3", "normal_4"]);
+    }
+
+    #[test]
+    fn test_priority_aging_prevents_starvation() {
+        let mut scheduler = TaskScheduler::new(SchedulerConfig::default());
+        scheduler.submit_task(test_task("low", TaskPriority::Low, None));
+        std::thread::sleep(Duration::from_millis(30));
+        scheduler.submit_task(test_task("normal", TaskPriority::Normal, None));
+
+        // 未启用老化时按原优先级取任务
+        assert_eq!(scheduler.fetch_next_task().unwrap().task_id, "normal");
+        scheduler.complete_task("normal");
+        assert_eq!(scheduler.fetch_next_task().unwrap().task_id, "low");
+        scheduler.complete_task("low");
+
+        // 低优先级任务等待超过两个老化间隔后排在新提交的普通任务之前
+        scheduler.set_aging_interval(Some(Duration::from_millis(10)));
+        scheduler.submit_task(test_task("low", TaskPriority::Low, None));
+        std::thread::sleep(Duration::from_millis(30));
+        scheduler.submit_task(test_task("normal", TaskPriority::Normal, None));
+        assert_eq!(scheduler.fetch_next_task().unwrap().task_id, "low");
+        assert_eq!(scheduler.fetch_next_task().unwrap().task_id, "normal");
+    }
+
+    #[test]
+    fn test_dump_and_load_queue_preserves_fetch_order() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        scheduler.submit_task(test_task("normal_0", TaskPriority::Normal, None));
+        let mut high = test_task("high", TaskPriority::High, None);
+        high.input_data = vec![1, 2, 3];
+        scheduler.submit_task(high);
+        scheduler.submit_task(test_task("normal_1", TaskPriority::Normal, None));
+
+        let json = scheduler.dump_queue().unwrap();
+        scheduler.queue.lock().unwrap().clear();
+        assert!(scheduler.fetch_next_task().is_none());
+
+        scheduler.load_queue(&json).unwrap();
+        let first = scheduler.fetch_next_task().unwrap();
+        assert_eq!(first.task_id, "high");
+        assert_eq!(first.input_data, vec![1, 2, 3]);
+        scheduler.complete_task(&first.task_id);
+        assert_eq!(drain(&scheduler), vec!["normal_0", "normal_1"]);
+
+        match scheduler.load_queue("{ not json") {
+            Err(Error::Other(message)) => assert!(message.contains("队列快照无效")),
+            other => panic!("期望队列快照无效的错误，实际为 {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dump_and_load_queue_keeps_dependencies_and_deadline() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        let deadline = Instant::now() + Duration::from_secs(60);
+        scheduler.submit_with_deps(test_task("layer_0", TaskPriority::Low, None), Vec::new()).unwrap();
+        scheduler
+            .submit_with_deps(test_task("layer_1", TaskPriority::High, Some(deadline)), vec!["layer_0".to_string()])
+            .unwrap();
+        scheduler
+            .submit_with_deps(test_task("layer_2", TaskPriority::Critical, None), vec!["layer_1".to_string()])
+            .unwrap();
+        // 第0层在快照前已完成，恢复后不应再等待它
+        let first = scheduler.fetch_next_task().unwrap();
+        assert_eq!(first.task_id, "layer_0");
+        scheduler.complete_task(&first.task_id);
+
+        let json = scheduler.dump_queue().unwrap();
+        let restored = TaskScheduler::new(SchedulerConfig::default());
+        restored.load_queue(&json).unwrap();
+
+        let layer_1 = restored.fetch_next_task().unwrap();
+        assert_eq!(layer_1.task_id, "layer_1");
+        let restored_deadline = layer_1.deadline.expect("截止时间应随快照恢复");
+        assert!(restored_deadline <= deadline + Duration::from_secs(1));
+        assert!(restored_deadline > Instant::now() + Duration::from_secs(50));
+        // 第2层优先级更高，但第1层完成前取不到
+        assert!(restored.fetch_next_task().is_none());
+        restored.complete_task(&layer_1.task_id);
+        let layer_2 = restored.fetch_next_task().unwrap();
+        assert_eq!(layer_2.task_id, "layer_2");
+        assert!(layer_2.deadline.is_none());
+    }
+
+    #[test]
+    fn test_dependencies_enforce_layer_order() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        // 倒序提交3层链式依赖，后面的层优先级更高，仍必须等前一层完成
+        scheduler
+            .submit_with_deps(test_task("layer_2", TaskPriority::Critical, None), vec!["layer_1".to_string()])
+            .unwrap();
+        scheduler
+            .submit_with_deps(test_task("layer_1", TaskPriority::High, None), vec!["layer_0".to_string()])
+            .unwrap();
+        scheduler.submit_with_deps(test_task("layer_0", TaskPriority::Low, None), Vec::new()).unwrap();
+
+        let mut order = Vec::new();
+        while let Some(task) = scheduler.fetch_next_task() {
+            // 当前层未完成前取不到下一层
+            assert!(scheduler.fetch_next_task().is_none());
+            scheduler.complete_task(&task.task_id);
+            order.push(task.task_id);
+        }
+        assert_eq!(order, vec!["layer_0", "layer_1", "layer_2"]);
+    }
+
+    #[test]
+    fn test_dependency_cycle_rejected() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        assert!(scheduler
+            .submit_with_deps(test_task("self", TaskPriority::Normal, None), vec!["self".to_string()])
+            .is_err());
+
+        scheduler.submit_with_deps(test_task("a", TaskPriority::Normal, None), vec!["b".to_string()]).unwrap();
+        scheduler.submit_with_deps(test_task("b", TaskPriority::Normal, None), vec!["c".to_string()]).unwrap();
+        match scheduler.submit_with_deps(test_task("c", TaskPriority::Normal, None), vec!["a".to_string()]) {
+            Err(Error::Other(msg)) => assert!(msg.contains("c -> a -> b -> c"), "{}", msg),
+            other => panic!("期望依赖环错误，实际为 {:?}", other),
+        }
+        // 被拒绝的任务不会入队，c 只能作为外部依赖标记完成
+        scheduler.mark_completed("c");
+        assert_eq!(drain(&scheduler), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_max_concurrent_tasks_limits_in_flight() {
+        let config = SchedulerConfig { max_concurrent_tasks: 2, ..SchedulerConfig::default() };
+        let scheduler = TaskScheduler::new(config);
+        for i in 0..3 {
+            scheduler.submit_task(test_task(&format!("task_{}", i), TaskPriority::Normal, None));
+        }
+
+        let first = scheduler.fetch_next_task().unwrap();
+        let second = scheduler.fetch_next_task().unwrap();
+        assert_eq!(scheduler.in_flight_count(), 2);
+        assert!(scheduler.fetch_next_task().is_none());
+
+        scheduler.complete_task(&first.task_id);
+        assert_eq!(scheduler.in_flight_count(), 1);
+        let third = scheduler.fetch_next_task().unwrap();
+        assert_eq!(third.task_id, "task_2");
+        assert_eq!(scheduler.in_flight_count(), 2);
+
+        scheduler.complete_task(&second.task_id);
+        scheduler.complete_task(&third.task_id);
+        assert_eq!(scheduler.in_flight_count(), 0);
+        assert!(scheduler.fetch_next_task().is_none());
+    }
+
+    #[test]
+    fn test_collect_child_results_in_order() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        let child_ids: Vec<String> = (0..3).map(|i| format!("parent/expert_{}", i)).collect();
+        scheduler.register_parent("parent", child_ids.clone()).unwrap();
+        assert!(scheduler.register_parent("parent", Vec::new()).is_err());
+        for child_id in &child_ids {
+            scheduler.submit_task(test_task(child_id, TaskPriority::Normal, None));
+        }
+
+        // 子任务乱序完成
+        let tasks: Vec<MoeTask> = std::iter::from_fn(|| scheduler.fetch_next_task()).collect();
+        assert_eq!(tasks.len(), 3);
+        for position in [2, 0] {
+            scheduler.submit_result(&tasks[position].task_id, vec![position as u8; 2]).unwrap();
+            assert!(scheduler.try_take_completed("parent").is_none());
+        }
+        scheduler.submit_result(&tasks[1].task_id, vec![1; 2]).unwrap();
+        assert_eq!(scheduler.in_flight_count(), 0);
+
+        assert_eq!(scheduler.try_take_completed("parent").unwrap(), vec![vec![0; 2], vec![1; 2], vec![2; 2]]);
+        // 取走后登记被清除
+        assert!(scheduler.try_take_completed("parent").is_none());
+        assert!(scheduler.submit_result(&child_ids[0], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_cancel_pending_task_never_fetched() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        scheduler.submit_task(test_task("keep", TaskPriority::Normal, None));
+        scheduler.submit_task(test_task("drop", TaskPriority::Critical, None));
+
+        let cancelled = scheduler.cancel("drop");
+        assert_eq!(cancelled.len(), 1);
+        assert!(matches!(&cancelled[0].status, TaskStatus::Failed(reason) if reason == "cancelled"));
+        assert_eq!(drain(&scheduler), vec!["keep"]);
+        assert!(scheduler.cancel("drop").is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_cancel_parent_cascades_to_children() {
+        let scheduler = TaskScheduler::new(SchedulerConfig::default());
+        let child_ids: Vec<String> = (0..3).map(|i| format!("parent/layer_{}", i)).collect();
+        scheduler.register_parent("parent", child_ids.clone()).unwrap();
+        for child_id in &child_ids {
+            scheduler.submit_task(test_task(child_id, TaskPriority::Normal, None));
+        }
+        scheduler.submit_task(test_task("other", TaskPriority::Normal, None));
+
+        // 第一个子任务已在执行，只能置位取消标记
+        let running = scheduler.fetch_next_task().unwrap();
+        assert!(!running.is_cancelled());
+
+        let cancelled: Vec<String> = scheduler.cancel("parent").into_iter().map(|task| task.task_id).collect();
+        assert_eq!(cancelled, vec!["parent/layer_1", "parent/layer_2"]);
+        assert!(running.is_cancelled());
+        assert_eq!(drain(&scheduler), vec!["other"]);
+    }
+
+    /// 测试用执行后端：记录执行顺序，把输入反转作为结果，指定的任务返回错误
+    struct MockExecutor {
+        executed: Mutex<Vec<String>>,
+        fail_task: Option<String>,
+    }
+
+    impl MockExecutor {
+        fn new(fail_task: Option<&str>) -> Self {
+            Self {
+                executed: Mutex::new(Vec::new()),
+                fail_task: fail_task.map(str::to_string),
+            }
+        }
+    }
+
+    impl Executor for MockExecutor {
+        fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+            self.executed.lock().unwrap().push(task.task_id.clone());
+            if self.fail_task.as_deref() == Some(task.task_id.as_str()) {
+                return Err(Error::InferenceError(format!("任务 {} 模拟失败", task.task_id)));
+            }
+            let mut result = task.input_data.clone();
+            result.reverse();
+            task.status = TaskStatus::Completed;
+            task.result = Some(result.clone());
+            Ok(result)
+        }
+    }
+
+    #[test]
+    fn test_runner_drives_scheduler_with_mock_executor() {
+        let scheduler = Arc::new(TaskScheduler::new(SchedulerConfig::default()));
+        let mut child_a = test_task("p/a", TaskPriority::Normal, None);
+        child_a.input_data = vec![1, 2];
+        let mut child_b = test_task("p/b", TaskPriority::High, None);
+        child_b.input_data = vec![3, 4];
+        scheduler.register_parent("p", vec!["p/a".to_string(), "p/b".to_string()]).unwrap();
+        scheduler.submit_with_deps(test_task("after", TaskPriority::Critical, None), vec!["p/a".to_string()]).unwrap();
+        scheduler.submit_task(child_a);
+        scheduler.submit_task(child_b);
+
+        let executor = Arc::new(MockExecutor::new(None));
+        let runner = TaskRunner::new(Arc::clone(&scheduler), executor.clone());
+        let finished = runner.run_until_idle().unwrap();
+
+        assert_eq!(*executor.executed.lock().unwrap(), vec!["p/b", "p/a", "after"]);
+        assert_eq!(finished.len(), 3);
+        assert!(finished.iter().all(|task| matches!(task.status, TaskStatus::Completed)));
+        assert_eq!(scheduler.try_take_completed("p"), Some(vec![vec![2, 1], vec![4, 3]]));
+        assert_eq!(scheduler.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn test_runner_stops_on_failure_and_keeps_dependents() {
+        let scheduler = Arc::new(TaskScheduler::new(SchedulerConfig::default()));
+        scheduler.submit_task(test_task("bad", TaskPriority::High, None));
+        scheduler.submit_with_deps(test_task("dependent", TaskPriority::Normal, None), vec!["bad".to_string()]).unwrap();
+
+        let runner = TaskRunner::new(Arc::clone(&scheduler), Arc::new(MockExecutor::new(Some("bad"))));
+        assert!(matches!(runner.run_until_idle(), Err(Error::InferenceError(_))));
+        // 失败任务的并发名额已释放，依赖它的任务仍在队列中
+        assert_eq!(scheduler.in_flight_count(), 0);
+        assert!(runner.scheduler().fetch_next_task().is_none());
+        assert_eq!(scheduler.queue.lock().unwrap().len(), 1);
+    }
+}
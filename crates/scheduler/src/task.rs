@@ -1,6 +1,14 @@
 // task.rs
 // 定义MOE任务结构体、任务状态枚举、任务优先级等。
+use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 /// 任务状态枚举，描述任务的生命周期
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +32,167 @@ pub enum TaskPriority {
     Critical = 3,
 }
 
+/// 层所属的堆栈：编码器或解码器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LayerStack {
+    Encoder,
+    Decoder,
+}
+
+/// 结构化的子任务ID，由父任务ID和所在的层、专家、张量分片、批次组成
+///
+/// 字符串形式为 `父任务ID[/layer_N][/decoder_layer_N][/expert_N][/shard_N][/batch_N]`，父任务ID中的 `%` 和 `/`
+/// 分别转义为 `%25` 和 `%2F`，因此对任意父任务ID都能无歧义地解析回来。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskId {
+    /// 父任务ID（拆分时传入的原始任务ID）
+    pub parent: String,
+    /// 层ID（编码器层）
+    pub layer: Option<usize>,
+    /// 解码器层ID
+    pub decoder_layer: Option<usize>,
+    /// 专家ID
+    pub expert: Option<usize>,
+    /// 张量并行分片ID
+    pub shard: Option<usize>,
+    /// 批次ID
+    pub batch: Option<usize>,
+}
+
+impl TaskId {
+    /// 以父任务ID创建，不含层、专家、批次
+    pub fn new(parent: &str) -> Self {
+        Self {
+            parent: parent.to_string(),
+            layer: None,
+            decoder_layer: None,
+            expert: None,
+            shard: None,
+            batch: None,
+        }
+    }
+
+    /// 设置层ID
+    pub fn with_layer(mut self, layer_id: usize) -> Self {
+        self.layer = Some(layer_id);
+        self
+    }
+
+    /// 设置解码器层ID
+    pub fn with_decoder_layer(mut self, layer_id: usize) -> Self {
+        self.decoder_layer = Some(layer_id);
+        self
+    }
+
+    /// 任务所在层属于编码器还是解码器，不含层ID时为None
+    pub fn layer_stack(&self) -> Option<LayerStack> {
+        match (self.layer, self.decoder_layer) {
+            (_, Some(_)) => Some(LayerStack::Decoder),
+            (Some(_), None) => Some(LayerStack::Encoder),
+            (None, None) => None,
+        }
+    }
+
+    /// 设置专家ID
+    pub fn with_expert(mut self, expert_id: usize) -> Self {
+        self.expert = Some(expert_id);
+        self
+    }
+
+    /// 设置张量并行分片ID
+    pub fn with_shard(mut self, shard_id: usize) -> Self {
+        self.shard = Some(shard_id);
+        self
+    }
+
+    /// 设置批次ID
+    pub fn with_batch(mut self, batch_id: usize) -> Self {
+        self.batch = Some(batch_id);
+        self
+    }
+
+    /// 各组成部分的名称与取值，按字符串形式中的顺序排列
+    fn components(&self) -> [(&'static str, Option<usize>); 5] {
+        [
+            ("layer", self.layer),
+            ("decoder_layer", self.decoder_layer),
+            ("expert", self.expert),
+            ("shard", self.shard),
+            ("batch", self.batch),
+        ]
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.parent.chars() {
+            match c {
+                '%' => f.write_str("%25")?,
+                '/' => f.write_str("%2F")?,
+                _ => write!(f, "{}", c)?,
+            }
+        }
+        for (name, value) in self.components() {
+            if let Some(value) = value {
+                write!(f, "/{}_{}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TaskId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Other(format!("无效的任务ID: {}", s));
+        let mut segments = s.split('/');
+        let escaped_parent = segments.next().ok_or_else(invalid)?;
+
+        let mut parent = String::with_capacity(escaped_parent.len());
+        let mut rest = escaped_parent;
+        while let Some(index) = rest.find('%') {
+            parent.push_str(&rest[..index]);
+            let escape = rest.get(index..index + 3).ok_or_else(invalid)?;
+            parent.push(match escape {
+                "%25" => '%',
+                "%2F" => '/',
+                _ => return Err(invalid()),
+            });
+            rest = &rest[index + 3..];
+        }
+        parent.push_str(rest);
+
+        let mut id = TaskId::new(&parent);
+        // 组成部分必须按 layer、decoder_layer、expert、shard、batch 的顺序出现，且每种最多一次
+        let mut next_component = 0;
+        for segment in segments {
+            let (name, value) = segment.rsplit_once('_').ok_or_else(invalid)?;
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let value = value.parse::<usize>().map_err(|_| invalid())?;
+            let position = id
+                .components()
+                .iter()
+                .position(|(component, _)| *component == name)
+                .ok_or_else(invalid)?;
+            if position < next_component {
+                return Err(invalid());
+            }
+            match position {
+                0 => id.layer = Some(value),
+                1 => id.decoder_layer = Some(value),
+                2 => id.expert = Some(value),
+                3 => id.shard = Some(value),
+                _ => id.batch = Some(value),
+            }
+            next_component = position + 1;
+        }
+        Ok(id)
+    }
+}
+
 /// MOE任务结构体，包含任务ID、输入数据、状态和结果
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MoeTask {
@@ -41,4 +210,187 @@ pub struct MoeTask {
     pub stream_id: Option<usize>,
     /// 父任务ID（用于子任务）
     pub parent_task_id: Option<String>,
-}
\ No newline at end of file
+    /// 多个子任务共享的输入主体，任务的完整输入为 input_data（头部）后接该主体
+    #[serde(default)]
+    pub shared_input: Option<Arc<[u8]>>,
+    /// 截止时间，按截止时间调度时使用；Instant 无法序列化，反序列化后为None
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+    /// input_data 中有效数据的字节数，按批次拆分时最后一个批次填充前的真实长度；None表示全部有效
+    #[serde(default)]
+    pub valid_len: Option<usize>,
+    /// 取消标记，调度器取出任务时设置；执行器在拷回结果前检查，被置位时提前返回
+    #[serde(skip)]
+    pub cancel_flag: Option<Arc<AtomicBool>>,
+}
+
+impl MoeTask {
+    /// 创建处于Pending状态的任务，其余字段（流、父任务、共享输入、截止时间等）为空，
+    /// 需要时用结构体更新语法覆盖：`MoeTask { stream_id: Some(0), ..MoeTask::new(id, data, priority) }`
+    pub fn new(task_id: impl Into<String>, input_data: Vec<u8>, priority: TaskPriority) -> Self {
+        Self {
+            task_id: task_id.into(),
+            input_data,
+            status: TaskStatus::Pending,
+            result: None,
+            priority,
+            stream_id: None,
+            parent_task_id: None,
+            shared_input: None,
+            deadline: None,
+            valid_len: None,
+            cancel_flag: None,
+        }
+    }
+
+    /// 任务的完整输入：input_data 后接共享主体（如有）
+    pub fn effective_input(&self) -> Cow<'_, [u8]> {
+        match &self.shared_input {
+            Some(body) => {
+                let mut input = Vec::with_capacity(self.input_data.len() + body.len());
+                input.extend_from_slice(&self.input_data);
+                input.extend_from_slice(body);
+                Cow::Owned(input)
+            }
+            None => Cow::Borrowed(&self.input_data),
+        }
+    }
+
+    /// 完整输入的字节数
+    pub fn input_len(&self) -> usize {
+        self.input_data.len() + self.shared_input.as_ref().map_or(0, |body| body.len())
+    }
+
+    /// 解析结构化的任务ID，只对拆分器生成的子任务有意义
+    pub fn parsed_task_id(&self) -> Result<TaskId> {
+        self.task_id.parse()
+    }
+
+    /// 任务是否已被取消
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_flag.as_ref().is_some_and(|flag| flag.load(Ordering::SeqCst))
+    }
+
+    /// 按错误将任务标记为失败，执行器已给出的失败原因（如 cancelled、timeout）保持不变
+    pub fn mark_failed(&mut self, error: &Error) {
+        if !matches!(self.status, TaskStatus::Failed(_)) {
+            self.status = TaskStatus::Failed(error.to_string());
+        }
+    }
+
+    /// 截掉 data 末尾的批次填充，只保留前 valid_len 个字节
+    pub fn valid_slice<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        match self.valid_len {
+            Some(valid_len) if valid_len < data.len() => &data[..valid_len],
+            _ => data,
+        }
+    }
+
+    /// 以 bincode 二进制格式序列化任务，字节数组按原样写入，比JSON紧凑得多
+    /// 截止时间和取消标记与JSON一样不参与序列化
+    #[cfg(feature = "bincode")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|e| Error::Other(format!("任务 {} 序列化失败: {}", self.task_id, e)))
+    }
+
+    /// 从 to_bytes 生成的二进制数据还原任务
+    #[cfg(feature = "bincode")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<MoeTask> {
+        bincode::deserialize(bytes).map_err(|e| Error::Other(format!("任务反序列化失败: {}", e)))
+    }
+
+    /// 任务输入的去重键
+    pub fn payload_key(&self) -> PayloadKey {
+        PayloadKey {
+            header: self.input_data.clone(),
+            body: self.shared_input.clone(),
+        }
+    }
+}
+
+/// 任务输入的去重键，由头部和共享主体组成
+/// 头部按内容比较；共享主体只按指针比较和哈希，指向同一份内存时才视为相同，
+/// 因此不随主体大小增长。键持有主体的引用，存活期间指针不会被其他分配复用
+#[derive(Debug, Clone)]
+pub struct PayloadKey {
+    header: Vec<u8>,
+    body: Option<Arc<[u8]>>,
+}
+
+impl PartialEq for PayloadKey {
+    fn eq(&self, other: &Self) -> bool {
+        let same_body = match (&self.body, &other.body) {
+            (Some(body), Some(other_body)) => Arc::ptr_eq(body, other_body),
+            (None, None) => true,
+            _ => false,
+        };
+        same_body && self.header == other.header
+    }
+}
+
+impl Eq for PayloadKey {}
+
+impl Hash for PayloadKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.header.hash(state);
+        self.body.as_ref().map(|body| body.as_ptr()).hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_id_display_and_parse() {
+        let id = TaskId::new("req_3").with_layer(3).with_expert(2);
+        assert_eq!(id.to_string(), "req_3/layer_3/expert_2");
+        assert_eq!("req_3/layer_3/expert_2".parse::<TaskId>().unwrap(), id);
+        // 父任务ID本身像子任务ID时也不会混淆
+        let tricky = TaskId::new("a/layer_3%2F").with_expert(1);
+        assert_eq!(tricky.to_string(), "a%2Flayer_3%252F/expert_1");
+        assert_eq!(tricky.to_string().parse::<TaskId>().unwrap(), tricky);
+    }
+
+    #[test]
+    fn test_task_id_rejects_malformed() {
+        let malformed_ids = [
+            "p/expert_1/layer_2",
+            "p/expert_1/expert_2",
+            "p/shard_1/expert_2",
+            "p/layer_",
+            "p/layer_x",
+            "p/part_1",
+            "p%2",
+            "p%41",
+        ];
+        for malformed in malformed_ids {
+            assert!(malformed.parse::<TaskId>().is_err(), "{}", malformed);
+        }
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_much_smaller_than_json() {
+        let input_data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let task = MoeTask {
+            stream_id: Some(3),
+            parent_task_id: Some("parent".to_string()),
+            valid_len: Some(1000),
+            ..MoeTask::new("large", input_data.clone(), TaskPriority::High)
+        };
+
+        let bytes = task.to_bytes().unwrap();
+        let json = serde_json::to_vec(&task).unwrap();
+        // 二进制格式只比原始数据多出少量字段开销，JSON则把每个字节写成十进制数字
+        assert!(bytes.len() < input_data.len() + 128, "bincode 大小 {}", bytes.len());
+        assert!(json.len() > input_data.len() * 3, "JSON 大小 {}", json.len());
+
+        let restored = MoeTask::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.task_id, "large");
+        assert_eq!(restored.input_data, input_data);
+        assert_eq!(restored.priority, TaskPriority::High);
+        assert_eq!(restored.valid_len, Some(1000));
+        assert!(MoeTask::from_bytes(&bytes[..16]).is_err());
+    }
+}
@@ -1,40 +1,573 @@
 // task_executor.rs
 // 任务执行器，负责实际执行单个MoE子任务，例如调用CUDA核函数进行专家计算。
+use crate::config::{ModelInfo, SchedulerConfig};
 use crate::error::{Error, Result};
-use crate::task::{MoeTask, TaskStatus};
+use log::{debug, info, warn};
+use crate::metrics::Metrics;
+use crate::quantization;
+use crate::task::{MoeTask, PayloadKey, TaskStatus};
+use crate::types::{DeviceId, ExpertGpuMapping, ELEMENT_SIZE};
+use rayon::prelude::*;
 use rustacuda::prelude::*;
-use rustacuda::memory::{DeviceBuffer, CopyDestination};
+use rustacuda::memory::{DeviceBuffer, LockedBuffer, AsyncCopyDestination};
+use rustacuda::event::{Event, EventFlags};
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use rustacuda::context::{ContextStack, CurrentContext, UnownedContext};
+
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// 单个任务各阶段的耗时
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TaskMetrics {
+    /// 主机到设备拷贝耗时
+    pub h2d: Duration,
+    /// 计算（核函数）耗时
+    pub kernel: Duration,
+    /// 设备到主机拷贝耗时
+    pub d2h: Duration,
+    /// 是否由CUDA事件在设备端计时，为false时表示回退到了CPU挂钟时间
+    pub device_timed: bool,
+}
+
+impl TaskMetrics {
+    /// 拷入、计算、拷回三个阶段的耗时之和
+    pub fn total(&self) -> Duration {
+        self.h2d + self.kernel + self.d2h
+    }
+}
+
+/// 执行器保留的逐任务计时条数上限，超出后淘汰最早记录的任务
+const MAX_TASK_METRICS: usize = 4096;
+
+/// 最近完成任务的计时，按记录先后淘汰，长时间运行时不会无限增长
+#[derive(Debug)]
+struct RecentTaskMetrics {
+    entries: HashMap<String, TaskMetrics>,
+    order: VecDeque<String>, // 任务ID按记录先后排列，队首最早
+    capacity: usize,
+}
+
+impl RecentTaskMetrics {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    fn insert(&mut self, task_id: String, metrics: TaskMetrics) {
+        // 同一任务重复执行时只更新计时，不重复登记
+        if self.entries.insert(task_id.clone(), metrics).is_none() {
+            self.order.push_back(task_id);
+        }
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn get(&self, task_id: &str) -> Option<TaskMetrics> {
+        self.entries.get(task_id).copied()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// 内存池占用情况，用于诊断仍有余量却分配失败的原因
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// 已借出、正在使用的字节数
+    pub in_use_bytes: usize,
+    /// 已归还、缓存在池中的空闲字节数
+    pub cached_idle_bytes: usize,
+    /// 缓存的空闲缓冲区个数
+    pub num_cached_buffers: usize,
+    /// 最大的空闲缓冲区字节数
+    pub largest_idle_block: usize,
+}
+
+/// 结果中出现 NaN/Inf 时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// 替换为0并记录替换个数
+    #[default]
+    Zero,
+    /// 返回 InferenceError
+    Error,
+}
+
+/// 按f32扫描结果缓冲区中的 NaN/Inf，末尾不足一个元素的字节不参与扫描；
+/// 返回发现的非有限值个数，按 Zero 处理时这些值被替换为0
+fn sanitize_non_finite(task_id: &str, output: &mut [u8], policy: NonFinitePolicy) -> Result<usize> {
+    let mut count = 0;
+    for chunk in output.chunks_exact_mut(ELEMENT_SIZE) {
+        if f32::from_le_bytes((&*chunk).try_into().unwrap()).is_finite() {
+            continue;
+        }
+        count += 1;
+        if policy == NonFinitePolicy::Zero {
+            chunk.copy_from_slice(&0.0f32.to_le_bytes());
+        }
+    }
+    if count > 0 {
+        match policy {
+            NonFinitePolicy::Zero => warn!("任务 {} 的结果中有 {} 个 NaN/Inf，已替换为0", task_id, count),
+            NonFinitePolicy::Error => {
+                return Err(Error::InferenceError(format!("任务 {} 的结果中有 {} 个 NaN/Inf", task_id, count)));
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// 重试的初始退避时间，之后每次翻倍
+const RETRY_BASE_BACKOFF: Duration = Duration::from_millis(10);
+
+/// 是否为可重试的瞬时CUDA错误（如显存不足、资源暂时不够）
+fn is_transient_error(error: &Error) -> bool {
+    use rustacuda::error::CudaError;
+    matches!(
+        error,
+        Error::CudaError(
+            CudaError::OutOfMemory | CudaError::LaunchOutOfResources | CudaError::LaunchTimeout | CudaError::NotReady
+        )
+    )
+}
+
+/// 逐个执行任务，瞬时错误按指数退避重试最多 max_retries 次，失败的任务不影响后续任务
+/// 返回每个任务各自的执行结果，失败的任务状态被标记为 Failed
+fn execute_with_retries<F>(
+    tasks: &mut [MoeTask],
+    max_retries: usize,
+    base_backoff: Duration,
+    mut execute: F,
+) -> Vec<Result<Vec<u8>>>
+where
+    F: FnMut(&mut MoeTask) -> Result<Vec<u8>>,
+{
+    tasks
+        .iter_mut()
+        .map(|task| {
+            let mut backoff = base_backoff;
+            let mut attempt = 0;
+            loop {
+                match execute(task) {
+                    Ok(result) => return Ok(result),
+                    Err(e) if attempt < max_retries && is_transient_error(&e) => {
+                        attempt += 1;
+                        warn!(
+                            "[Executor] 任务 {} 遇到瞬时错误 {}，{:?} 后第 {} 次重试",
+                            task.task_id, e, backoff, attempt
+                        );
+                        std::thread::sleep(backoff);
+                        backoff *= 2;
+                    }
+                    Err(e) => {
+                        task.mark_failed(&e);
+                        return Err(e);
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// 接入真实核函数之前用于基准测试的模拟计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComputeSim {
+    /// 固定延迟，与任务大小无关
+    Fixed(Duration),
+    /// 延迟与输入大小成正比：base + per_kib × 输入KiB数
+    SizeScaled { base: Duration, per_kib: Duration },
+    /// 在主机上按专家维度做一次真实的矩阵乘 [token数, hidden_size] × [hidden_size, intermediate_size]，
+    /// token数由输入大小推算，至少为1
+    ExpertGemm { hidden_size: usize, intermediate_size: usize },
+}
+
+impl Default for ComputeSim {
+    /// 默认按输入大小缩放：固定开销 100µs，每KiB 10µs
+    fn default() -> Self {
+        ComputeSim::SizeScaled { base: Duration::from_micros(100), per_kib: Duration::from_micros(10) }
+    }
+}
+
+impl ComputeSim {
+    /// 延迟类模拟对 input_len 字节输入的延迟，ExpertGemm 的耗时取决于主机算力，返回None
+    pub fn latency(&self, input_len: usize) -> Option<Duration> {
+        match *self {
+            ComputeSim::Fixed(delay) => Some(delay),
+            ComputeSim::SizeScaled { base, per_kib } => Some(base + per_kib.mul_f64(input_len as f64 / 1024.0)),
+            ComputeSim::ExpertGemm { .. } => None,
+        }
+    }
+
+    /// 对 input_len 字节的输入执行一次模拟计算
+    pub fn run(&self, input_len: usize) {
+        self.run_until(input_len, None);
+    }
+
+    /// 执行一次模拟计算，到 deadline 仍未算完时提前停止并返回false；
+    /// ExpertGemm 无法中途打断，算完后再检查是否超过 deadline
+    pub fn run_until(&self, input_len: usize, deadline: Option<Instant>) -> bool {
+        match *self {
+            ComputeSim::ExpertGemm { hidden_size, intermediate_size } => {
+                let num_tokens = (input_len / (hidden_size * ELEMENT_SIZE).max(1)).max(1);
+                let lhs: Vec<f32> = (0..num_tokens * hidden_size).map(|i| (i % 7) as f32 * 0.1).collect();
+                let rhs: Vec<f32> = (0..hidden_size * intermediate_size).map(|i| (i % 5) as f32 * 0.1).collect();
+                let mut out = vec![0.0f32; num_tokens * intermediate_size];
+                for (row, out_row) in lhs.chunks_exact(hidden_size).zip(out.chunks_exact_mut(intermediate_size)) {
+                    for (&a, rhs_row) in row.iter().zip(rhs.chunks_exact(intermediate_size)) {
+                        for (o, &b) in out_row.iter_mut().zip(rhs_row) {
+                            *o += a * b;
+                        }
+                    }
+                }
+                std::hint::black_box(out);
+                deadline.is_none_or(|deadline| Instant::now() < deadline)
+            }
+            _ => {
+                let delay = self.latency(input_len).unwrap_or_default();
+                match deadline {
+                    Some(deadline) if Instant::now() + delay >= deadline => {
+                        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                        false
+                    }
+                    _ => {
+                        std::thread::sleep(delay);
+                        true
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 阶段计时器，在流上记录CUDA事件，事件不可用时回退到CPU挂钟时间
+struct PhaseTimer {
+    events: Option<Vec<Event>>,
+    instants: Vec<Instant>,
+}
+
+impl PhaseTimer {
+    fn start(stream: &Stream) -> Self {
+        let mut timer = Self {
+            events: Some(Vec::new()),
+            instants: Vec::new(),
+        };
+        timer.mark(stream);
+        timer
+    }
+
+    /// 在流上记录一个阶段边界
+    fn mark(&mut self, stream: &Stream) {
+        self.instants.push(Instant::now());
+        if let Some(events) = &mut self.events {
+            match Event::new(EventFlags::DEFAULT).and_then(|event| event.record(stream).map(|_| event)) {
+                Ok(event) => events.push(event),
+                Err(_) => self.events = None,
+            }
+        }
+    }
+
+    /// 计算相邻边界之间的耗时，必须在流同步之后调用
+    fn finish(&self) -> TaskMetrics {
+        let device_durations = self.events.as_ref().and_then(|events| {
+            events
+                .windows(2)
+                .map(|pair| {
+                    pair[1]
+                        .elapsed_time_f32(&pair[0])
+                        .map(|ms| Duration::from_secs_f32(ms.max(0.0) / 1000.0))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .ok()
+        });
+        let (durations, device_timed) = match device_durations {
+            Some(durations) => (durations, true),
+            None => (self.instants.windows(2).map(|pair| pair[1] - pair[0]).collect(), false),
+        };
+        TaskMetrics {
+            h2d: durations.first().copied().unwrap_or_default(),
+            kernel: durations.get(1).copied().unwrap_or_default(),
+            d2h: durations.get(2).copied().unwrap_or_default(),
+            device_timed,
+        }
+    }
+}
+
+/// 设备往返使用的主机缓冲区
+enum HostBuffer {
+    /// 普通可分页内存
+    Pageable(Vec<u8>),
+    /// 从内存池借出的页锁定内存，输入先暂存于此，结果也拷回到这里
+    Pinned(LockedBuffer<u8>),
+}
+
+/// 已在流上发出、尚未同步的一次设备往返
+struct InFlight {
+    device_buffer: DeviceBuffer<u8>,
+    // 拷入设备的字节数
+    h2d_bytes: usize,
+    host_buffer: HostBuffer,
+    timer: PhaseTimer,
+    // 超过该时间点仍未完成的任务按超时处理
+    deadline: Option<Instant>,
+}
+
+/// 一次设备往返的执行设置
+struct LaunchConfig<'a> {
+    // 是否经由内存池中的页锁定主机缓冲区传输
+    pinned: bool,
+    compute_sim: &'a ComputeSim,
+    // 计算到该时间点仍未完成时放弃任务
+    deadline: Option<Instant>,
+}
+
+/// 在给定流上异步发出一次设备往返：从内存池取缓冲区、拷入输入、计算、拷回结果，不等待完成
+///
+/// 任务在计算期间被取消或计算超过 deadline 时不再发出结果拷回，
+/// 同步流（等待已发出的输入拷贝，CUDA 无法撤回已入队的拷贝）并归还缓冲区后返回 None；
+/// 拷贝发出失败时同样先同步并归还缓冲区，再返回错误。
+fn launch_on_device(
+    memory_pool: &Mutex<MemoryPool>,
+    stream: &Stream,
+    task: &MoeTask,
+    input: &[u8],
+    gpu_id: DeviceId,
+    config: &LaunchConfig,
+) -> Result<Option<InFlight>> {
+    // 从内存池获取缓冲区
+    let (mut device_buffer, mut host_buffer) = {
+        let mut pool = memory_pool.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        let device_buffer = pool.get_buffer(input.len())?;
+        let host_buffer = if config.pinned {
+            let mut staging = match pool.get_pinned_buffer(input.len()) {
+                Ok(staging) => staging,
+                Err(e) => {
+                    pool.return_buffer(device_buffer);
+                    return Err(e);
+                }
+            };
+            staging.copy_from_slice(input);
+            HostBuffer::Pinned(staging)
+        } else {
+            HostBuffer::Pageable(vec![0u8; input.len()])
+        };
+        (device_buffer, host_buffer)
+    };
+
+    let mut timer = PhaseTimer::start(stream);
+
+    // 1. 将输入数据的切片从CPU内存拷贝到GPU设备内存
+    let copied = match &host_buffer {
+        HostBuffer::Pinned(staging) => unsafe { device_buffer.async_copy_from(staging.as_slice(), stream) },
+        HostBuffer::Pageable(_) => unsafe { device_buffer.async_copy_from(input, stream) },
+    };
+    if let Err(e) = copied {
+        reclaim_buffers(memory_pool, stream, device_buffer, host_buffer)?;
+        return Err(Error::CudaError(e));
+    }
+    timer.mark(stream);
+    debug!("[Executor] 已将 {} 字节数据拷贝到 GPU {}。", input.len(), gpu_id);
+
+    // --- 此处未来将插入真实的CUDA核函数调用 ---
+    let computed = config.compute_sim.run_until(input.len(), config.deadline);
+    timer.mark(stream);
+
+    if task.is_cancelled() || !computed {
+        reclaim_buffers(memory_pool, stream, device_buffer, host_buffer)?;
+        return Ok(None);
+    }
+
+    // 2. 将结果从GPU设备内存拷贝回CPU内存，主机缓冲区在同步前必须保持有效
+    let copied = match &mut host_buffer {
+        HostBuffer::Pinned(staging) => unsafe { device_buffer.async_copy_to(staging.as_mut_slice(), stream) },
+        HostBuffer::Pageable(host_result) => unsafe { device_buffer.async_copy_to(host_result, stream) },
+    };
+    if let Err(e) = copied {
+        reclaim_buffers(memory_pool, stream, device_buffer, host_buffer)?;
+        return Err(Error::CudaError(e));
+    }
+    timer.mark(stream);
+
+    Ok(Some(InFlight { device_buffer, h2d_bytes: input.len(), host_buffer, timer, deadline: config.deadline }))
+}
+
+/// 同步流（等待已入队的拷贝结束后缓冲区才能复用或释放）并将缓冲区归还给内存池，不取回结果
+fn reclaim_buffers(
+    memory_pool: &Mutex<MemoryPool>,
+    stream: &Stream,
+    device_buffer: DeviceBuffer<u8>,
+    host_buffer: HostBuffer,
+) -> Result<()> {
+    stream.synchronize()
+        .map_err(Error::CudaError)?;
+    let mut pool = memory_pool.lock()
+        .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+    pool.return_buffer(device_buffer);
+    if let HostBuffer::Pinned(staging) = host_buffer {
+        pool.return_pinned_buffer(staging);
+    }
+    Ok(())
+}
+
+/// 同步流，取回结果和各阶段耗时并将缓冲区归还给内存池
+fn finish_on_device(
+    memory_pool: &Mutex<MemoryPool>,
+    stream: &Stream,
+    in_flight: InFlight,
+) -> Result<(Vec<u8>, TaskMetrics)> {
+    stream.synchronize()
+        .map_err(Error::CudaError)?;
+    let InFlight { device_buffer, host_buffer, timer, .. } = in_flight;
+
+    let mut pool = memory_pool.lock()
+        .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+    pool.return_buffer(device_buffer);
+    let host_result = match host_buffer {
+        HostBuffer::Pageable(host_result) => host_result,
+        HostBuffer::Pinned(staging) => {
+            let host_result = staging.to_vec();
+            pool.return_pinned_buffer(staging);
+            host_result
+        }
+    };
+    debug!("[Executor] 已将 {} 字节结果传回 CPU。", host_result.len());
+
+    Ok((host_result, timer.finish()))
+}
+
+/// 内存池默认使用的显存比例
+pub const DEFAULT_MEMORY_FRACTION: f32 = 0.8;
+
+/// 检查显存使用比例
+fn check_memory_fraction(fraction: f32) -> Result<()> {
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(Error::ConfigError(format!(
+            "显存使用比例 {} 必须在 (0.0, 1.0] 范围内", fraction
+        )));
+    }
+    Ok(())
+}
+
+/// 按显存使用比例计算内存池上限（MB）
+fn pool_memory_mb(total_memory: usize, fraction: f32) -> usize {
+    ((total_memory / 1024 / 1024) as f64 * fraction as f64) as usize
+}
+
+/// 获取指定ID的设备，ID超出当前设备数量时返回可读的 GpuError
+fn get_device(device_id: DeviceId) -> Result<Device> {
+    let count = Device::num_devices()
+        .map_err(Error::CudaError)?;
+    if device_id.0 >= count {
+        return Err(Error::GpuError(format!(
+            "请求的设备 {} 不存在，当前只有 {} 个GPU", device_id, count
+        )));
+    }
+    Device::get_device(device_id.0)
+        .map_err(Error::CudaError)
+}
+
+/// 单个专家权重（wi 与 wo 两个 f32 矩阵）占用的显存，按MB向上取整
+fn expert_memory_mb(model_info: &ModelInfo) -> u64 {
+    let bytes = 2 * model_info.hidden_size as u64 * model_info.intermediate_size as u64 * ELEMENT_SIZE as u64;
+    bytes.div_ceil(1024 * 1024)
+}
+
+/// 把专家依次放到已分配显存最少的GPU上（相同时取列表中靠前的GPU），使各GPU的显存占用尽量均衡
+fn plan_expert_placement(model_info: &ModelInfo, gpu_ids: &[DeviceId]) -> Vec<ExpertGpuMapping> {
+    if gpu_ids.is_empty() {
+        return Vec::new();
+    }
+    let memory_required = expert_memory_mb(model_info);
+    let mut assigned = vec![0u64; gpu_ids.len()];
+    (0..model_info.num_experts)
+        .map(|expert_id| {
+            let (index, _) = assigned
+                .iter()
+                .enumerate()
+                .min_by_key(|(index, memory)| (**memory, *index))
+                .unwrap();
+            assigned[index] += memory_required;
+            ExpertGpuMapping { expert_id, gpu_id: gpu_ids[index], memory_required }
+        })
+        .collect()
+}
+
+/// 已开始执行的任务
+enum Started {
+    /// 命中结果缓存
+    Cached(Vec<u8>),
+    /// 已在流上发出，等待同步
+    Launched(InFlight),
+}
+
+/// 在给定流上完成一次设备往返，任务被取消时返回 None
+fn run_on_device(
+    memory_pool: &Mutex<MemoryPool>,
+    stream: &Stream,
+    task: &MoeTask,
+    input: &[u8],
+    gpu_id: DeviceId,
+) -> Result<Option<(Vec<u8>, TaskMetrics)>> {
+    let config = LaunchConfig { pinned: false, compute_sim: &ComputeSim::default(), deadline: None };
+    match launch_on_device(memory_pool, stream, task, input, gpu_id, &config)? {
+        Some(in_flight) => finish_on_device(memory_pool, stream, in_flight).map(Some),
+        None => Ok(None),
+    }
+}
 
 /// 内存池管理
 #[derive(Debug)]
 struct MemoryPool {
     available_buffers: HashMap<usize, Vec<DeviceBuffer<u8>>>,
+    return_order: VecDeque<usize>, // 空闲缓冲区按归还先后排列的大小，队首最久未用
+    pinned_buffers: HashMap<usize, Vec<LockedBuffer<u8>>>, // 空闲的页锁定主机缓冲区，不计入显存
     total_allocated: usize,
     max_memory: usize,
+    last_activity: Instant, // 最近一次借出或归还缓冲区的时间
 }
 
 impl MemoryPool {
     fn new(max_memory_mb: usize) -> Self {
         Self {
             available_buffers: HashMap::new(),
+            return_order: VecDeque::new(),
+            pinned_buffers: HashMap::new(),
             total_allocated: 0,
             max_memory: max_memory_mb * 1024 * 1024, // 转换为字节
+            last_activity: Instant::now(),
         }
     }
 
     fn get_buffer(&mut self, size: usize) -> Result<DeviceBuffer<u8>> {
+        self.last_activity = Instant::now();
+
         // 检查是否有合适大小的可用缓冲区
         if let Some(buffers) = self.available_buffers.get_mut(&size) {
             if let Some(buffer) = buffers.pop() {
+                self.forget_returned(size);
                 return Ok(buffer);
             }
         }
 
-        // 检查内存限制
-        if self.total_allocated + size > self.max_memory {
+        // 检查内存限制，超出时先淘汰最久未用的空闲缓冲区，加法溢出同样视为超出限制
+        if !self.fits(size) && !self.evict_until_fits(size) {
             return Err(Error::CudaError(rustacuda::error::CudaError::InvalidValue));
         }
 
@@ -46,58 +579,268 @@ impl MemoryPool {
     }
 
     fn return_buffer(&mut self, buffer: DeviceBuffer<u8>) {
+        self.last_activity = Instant::now();
         let size = buffer.len();
         self.available_buffers.entry(size).or_insert_with(Vec::new).push(buffer);
+        self.return_order.push_back(size);
     }
+
+    /// 借出 `size` 字节的页锁定主机缓冲区，优先复用已归还的缓冲区
+    fn get_pinned_buffer(&mut self, size: usize) -> Result<LockedBuffer<u8>> {
+        if let Some(buffer) = self.pinned_buffers.get_mut(&size).and_then(|buffers| buffers.pop()) {
+            return Ok(buffer);
+        }
+        unsafe { LockedBuffer::uninitialized(size) }.map_err(Error::CudaError)
+    }
+
+    fn return_pinned_buffer(&mut self, buffer: LockedBuffer<u8>) {
+        self.pinned_buffers.entry(buffer.len()).or_default().push(buffer);
+    }
+
+    /// 再分配 `size` 字节后是否仍在内存上限内
+    fn fits(&self, size: usize) -> bool {
+        matches!(self.total_allocated.checked_add(size), Some(total) if total <= self.max_memory)
+    }
+
+    /// 按归还顺序释放最久未用的空闲缓冲区，直到能容纳 `size` 字节；空闲缓冲区耗尽仍放不下时返回 false
+    fn evict_until_fits(&mut self, size: usize) -> bool {
+        while !self.fits(size) {
+            let evicted = match self.return_order.pop_front() {
+                Some(evicted) => evicted,
+                None => return false,
+            };
+            if let Some(buffers) = self.available_buffers.get_mut(&evicted) {
+                if !buffers.is_empty() {
+                    drop(buffers.remove(0));
+                    self.total_allocated -= evicted;
+                }
+                if buffers.is_empty() {
+                    self.available_buffers.remove(&evicted);
+                }
+            }
+        }
+        true
+    }
+
+    /// 从归还顺序中移除最近一次归还的 `size` 大小记录（对应缓冲区已被借出或释放）
+    fn forget_returned(&mut self, size: usize) {
+        if let Some(pos) = self.return_order.iter().rposition(|&s| s == size) {
+            self.return_order.remove(pos);
+        }
+    }
+
+    /// 空闲（已归还、未借出）缓冲区的总字节数
+    fn idle_bytes(&self) -> usize {
+        self.available_buffers
+            .iter()
+            .map(|(size, buffers)| size * buffers.len())
+            .sum()
+    }
+
+    /// 统计借出与空闲缓冲区的占用情况
+    fn stats(&self) -> MemoryStats {
+        let cached_idle_bytes = self.idle_bytes();
+        MemoryStats {
+            in_use_bytes: self.total_allocated - cached_idle_bytes,
+            cached_idle_bytes,
+            num_cached_buffers: self.available_buffers.values().map(Vec::len).sum(),
+            largest_idle_block: self
+                .available_buffers
+                .iter()
+                .filter(|(_, buffers)| !buffers.is_empty())
+                .map(|(&size, _)| size)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// 释放空闲缓冲区，直到保留的空闲字节数不超过 `keep_bytes`，返回释放的字节数
+    /// 优先释放较大的缓冲区，借出中的缓冲区不受影响
+    fn trim(&mut self, keep_bytes: usize) -> usize {
+        let mut idle = self.idle_bytes();
+        let mut sizes: Vec<usize> = self.available_buffers.keys().copied().collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut freed = 0;
+        for size in sizes {
+            let buffers = match self.available_buffers.get_mut(&size) {
+                Some(buffers) => buffers,
+                None => continue,
+            };
+            let before = buffers.len();
+            while idle > keep_bytes {
+                match buffers.pop() {
+                    Some(buffer) => {
+                        drop(buffer);
+                        idle -= size;
+                        freed += size;
+                    }
+                    None => break,
+                }
+            }
+            let dropped = before - buffers.len();
+            if buffers.is_empty() {
+                self.available_buffers.remove(&size);
+            }
+            for _ in 0..dropped {
+                self.forget_returned(size);
+            }
+            if idle <= keep_bytes {
+                break;
+            }
+        }
+        self.total_allocated -= freed;
+        freed
+    }
+}
+
+/// 子任务结果缓存，完整输入相同的任务只执行一次
+#[derive(Debug, Default)]
+pub struct ResultCache {
+    entries: HashMap<PayloadKey, Vec<u8>>,
+    hits: usize,
 }
 
+impl ResultCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 查找与任务输入相同的已缓存结果
+    pub fn get(&mut self, task: &MoeTask) -> Option<Vec<u8>> {
+        let result = self.entries.get(&task.payload_key()).cloned();
+        if result.is_some() {
+            self.hits += 1;
+        }
+        result
+    }
+
+    /// 缓存任务结果
+    pub fn insert(&mut self, task: &MoeTask, result: Vec<u8>) {
+        self.entries.insert(task.payload_key(), result);
+    }
+
+    /// 命中次数
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// 缓存条目数
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+    }
+}
+
+/// 后台空闲裁剪线程的句柄
+struct IdleTrimmer {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+/// 未登记显存容量的GPU按此容量折算负载
+const DEFAULT_GPU_CAPACITY: usize = 1024 * 1024 * 1024;
+
 /// 负载均衡器
+///
+/// 每个任务按输入字节数占目标GPU总显存的比例计入负载，释放时减去同样的量。
 #[derive(Debug)]
 struct LoadBalancer {
-    gpu_loads: HashMap<usize, f32>, // GPU ID -> 当前负载 (0.0-1.0)
-    task_distribution: HashMap<String, usize>, // 任务ID -> GPU ID
+    gpu_loads: HashMap<DeviceId, f32>, // GPU ID -> 当前负载 (0.0-1.0)
+    gpu_capacities: HashMap<DeviceId, usize>, // GPU ID -> 总显存字节数
+    task_distribution: HashMap<String, DeviceId>, // 任务ID -> GPU ID
+    task_loads: HashMap<String, (DeviceId, f32)>, // 任务ID -> (GPU ID, 计入的负载)
 }
 
 impl LoadBalancer {
     fn new() -> Self {
         Self {
             gpu_loads: HashMap::new(),
+            gpu_capacities: HashMap::new(),
             task_distribution: HashMap::new(),
+            task_loads: HashMap::new(),
         }
     }
 
-    fn select_gpu(&mut self, available_gpus: &[usize]) -> Result<usize> {
-        if available_gpus.is_empty() {
-            return Err(Error::CudaError(rustacuda::error::CudaError::InvalidValue));
-        }
+    /// 登记GPU的总显存，用于折算任务负载
+    fn register_gpu(&mut self, gpu_id: DeviceId, total_memory: usize) {
+        self.gpu_capacities.insert(gpu_id, total_memory);
+    }
 
-        // 找到负载最低的GPU
-        let mut best_gpu = available_gpus[0];
-        let mut min_load = self.gpu_loads.get(&best_gpu).unwrap_or(&0.0);
+    /// `task_bytes` 字节的任务在该GPU上对应的负载
+    fn task_load(&self, gpu_id: DeviceId, task_bytes: usize) -> f32 {
+        let capacity = self
+            .gpu_capacities
+            .get(&gpu_id)
+            .copied()
+            .filter(|&capacity| capacity > 0)
+            .unwrap_or(DEFAULT_GPU_CAPACITY);
+        (task_bytes as f64 / capacity as f64) as f32
+    }
 
-        for &gpu_id in available_gpus {
-            let load = self.gpu_loads.get(&gpu_id).unwrap_or(&0.0);
-            if load < min_load {
-                best_gpu = gpu_id;
-                min_load = load;
-            }
-        }
+    /// 选出放入 `task_bytes` 字节任务后负载最低的GPU，负载相同时选ID最小的GPU，与传入顺序无关
+    fn select_gpu(&self, available_gpus: &[DeviceId], task_bytes: usize) -> Result<DeviceId> {
+        let load_after = |gpu_id: DeviceId| {
+            self.gpu_loads.get(&gpu_id).copied().unwrap_or(0.0) + self.task_load(gpu_id, task_bytes)
+        };
 
-        // 更新负载
-        let current_load = self.gpu_loads.get(&best_gpu).unwrap_or(&0.0);
-        self.gpu_loads.insert(best_gpu, current_load + 0.1); // 增加负载
+        available_gpus
+            .iter()
+            .map(|&gpu_id| (load_after(gpu_id), gpu_id))
+            .min_by(|(load_a, gpu_a), (load_b, gpu_b)| load_a.total_cmp(load_b).then(gpu_a.cmp(gpu_b)))
+            .map(|(_, gpu_id)| gpu_id)
+            .ok_or(Error::CudaError(rustacuda::error::CudaError::InvalidValue))
+    }
 
-        Ok(best_gpu)
+    /// 将任务分配到GPU并计入其负载
+    fn assign_task(&mut self, task_id: &str, gpu_id: DeviceId, task_bytes: usize) {
+        let load = self.task_load(gpu_id, task_bytes);
+        *self.gpu_loads.entry(gpu_id).or_insert(0.0) += load;
+        self.task_distribution.insert(task_id.to_string(), gpu_id);
+        self.task_loads.insert(task_id.to_string(), (gpu_id, load));
     }
 
-    fn release_gpu(&mut self, gpu_id: usize) {
-        if let Some(load) = self.gpu_loads.get_mut(&gpu_id) {
-            *load = (*load - 0.1).max(0.0);
+    /// 释放任务计入的负载
+    fn release_gpu(&mut self, task_id: &str) {
+        if let Some((gpu_id, added)) = self.task_loads.remove(task_id) {
+            if let Some(load) = self.gpu_loads.get_mut(&gpu_id) {
+                *load = (*load - added).max(0.0);
+            }
         }
     }
+}
 
-    fn assign_task(&mut self, task_id: &str, gpu_id: usize) {
-        self.task_distribution.insert(task_id.to_string(), gpu_id);
+/// 任务执行后端，CUDA执行器和主机执行器都实现该接口，拆分与合并逻辑可以不依赖具体硬件
+pub trait Executor {
+    /// 执行单个任务，返回结果数据
+    fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>>;
+
+    /// 依次执行一批任务，遇到第一个失败的任务时将其标记为失败并返回错误
+    fn execute_tasks(&self, tasks: &mut [MoeTask]) -> Result<Vec<Vec<u8>>> {
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks.iter_mut() {
+            match self.execute_task(task) {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    task.mark_failed(&e);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// 可在线程间共享时返回自身，流水线据此决定是否用多个工作线程并发执行；默认不可共享
+    fn as_shared(&self) -> Option<&(dyn Executor + Sync)> {
+        None
     }
 }
 
@@ -108,21 +851,55 @@ pub struct TaskExecutor {
     _context: Context,
     memory_pool: Arc<Mutex<MemoryPool>>,
     load_balancer: Arc<Mutex<LoadBalancer>>,
-    device_id: usize,
+    device_id: DeviceId,
+    // 用于异步拷贝和事件计时的默认流，未指定 stream_id 的任务使用
+    stream: Stream,
+    // stream_id -> 该编号任务专用的流，首次使用时创建
+    streams: Mutex<HashMap<usize, Stream>>,
+    task_metrics: Arc<Mutex<RecentTaskMetrics>>,
+    idle_trimmer: Mutex<Option<IdleTrimmer>>,
+    // 结果缓存，为None时不启用
+    result_cache: Mutex<Option<ResultCache>>,
+    // 单个任务允许的最大输入字节数
+    max_task_bytes: usize,
+    // 是否经由页锁定主机内存传输
+    pinned_memory: bool,
+    // 量化位宽，为None时按f32传输
+    quantization_bits: Option<u8>,
+    // 结果中 NaN/Inf 的处理方式，为None时不检查
+    output_sanitization: Option<NonFinitePolicy>,
+    // 代替核函数的模拟计算
+    compute_sim: ComputeSim,
+    // 单个任务的超时时间，为None时不限制
+    task_timeout: Option<Duration>,
+    // 累计耗时与传输字节数
+    metrics: Arc<Metrics>,
+    // 已发出、尚未同步的任务数，归零时通知 tasks_idle
+    active_tasks: Mutex<usize>,
+    tasks_idle: Condvar,
+    // 调用 shutdown 后不再接受新任务
+    shutting_down: AtomicBool,
 }
 
 impl TaskExecutor {
     /// 创建一个新的 TaskExecutor
     ///
     /// 这会初始化 Rustacuda 并设置当前的 CUDA 上下文。
-    pub fn new(device_id: usize) -> Result<Self> {
+    pub fn new(device_id: DeviceId) -> Result<Self> {
+        // 默认使用80%的显存
+        Self::with_memory_fraction(device_id, DEFAULT_MEMORY_FRACTION)
+    }
+
+    /// 创建一个新的 TaskExecutor，内存池最多使用 `fraction` 比例的显存
+    pub fn with_memory_fraction(device_id: DeviceId, fraction: f32) -> Result<Self> {
+        check_memory_fraction(fraction)?;
+
         // 初始化CUDA驱动API
         rustacuda::init(CudaFlags::empty())
             .map_err(Error::CudaError)?;
 
         // 获取指定ID的设备
-        let device = Device::get_device(device_id as u32)
-            .map_err(Error::CudaError)?;
+        let device = get_device(device_id)?;
 
         // 为该设备创建上下文
         let context = Context::create_and_push(ContextFlags::MAP_HOST | ContextFlags::SCHED_AUTO, device)
@@ -131,95 +908,370 @@ impl TaskExecutor {
         // 获取设备内存信息
         let total_memory = device.total_memory()
             .map_err(Error::CudaError)?;
-        let max_memory_mb = (total_memory / 1024 / 1024 * 80) / 100; // 使用80%的显存
+        let max_memory_mb = pool_memory_mb(total_memory, fraction);
+
+        let memory_pool = MemoryPool::new(max_memory_mb);
+        // 默认单个任务不能超过整个内存池
+        let max_task_bytes = memory_pool.max_memory;
+        let memory_pool = Arc::new(Mutex::new(memory_pool));
+        let mut load_balancer = LoadBalancer::new();
+        load_balancer.register_gpu(device_id, total_memory);
+        let load_balancer = Arc::new(Mutex::new(load_balancer));
 
-        let memory_pool = Arc::new(Mutex::new(MemoryPool::new(max_memory_mb as usize)));
-        let load_balancer = Arc::new(Mutex::new(LoadBalancer::new()));
+        let stream = Stream::new(StreamFlags::NON_BLOCKING, None)
+            .map_err(Error::CudaError)?;
 
         Ok(Self { 
             _context: context,
             memory_pool,
             load_balancer,
             device_id,
+            stream,
+            streams: Mutex::new(HashMap::new()),
+            task_metrics: Arc::new(Mutex::new(RecentTaskMetrics::new(MAX_TASK_METRICS))),
+            idle_trimmer: Mutex::new(None),
+            result_cache: Mutex::new(None),
+            max_task_bytes,
+            pinned_memory: false,
+            quantization_bits: None,
+            output_sanitization: None,
+            compute_sim: ComputeSim::default(),
+            task_timeout: None,
+            metrics: Arc::new(Metrics::new()),
+            active_tasks: Mutex::new(0),
+            tasks_idle: Condvar::new(),
+            shutting_down: AtomicBool::new(false),
         })
     }
 
-    /// 执行一个任务，将数据拷贝到GPU再拷贝回来
-    ///
-    /// 这是真实计算的第一步，用于验证数据通路。
-    pub fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
-        println!("  [Executor] 开始执行任务: {}", task.task_id);
-
-        // 更新任务状态
-        task.status = TaskStatus::Running;
+    /// 启用或关闭页锁定主机内存：启用后输入和结果经由内存池复用的页锁定缓冲区传输，
+    /// 大专家的拷贝带宽更高
+    pub fn with_pinned_memory(mut self, enabled: bool) -> Self {
+        self.pinned_memory = enabled;
+        self
+    }
 
-        // 选择GPU进行负载均衡
-        let gpu_id = {
-            let mut balancer = self.load_balancer.lock()
-                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
-            let selected_gpu = balancer.select_gpu(&[self.device_id])?;
-            balancer.assign_task(&task.task_id, selected_gpu);
-            selected_gpu
-        };
+    /// 是否经由页锁定主机内存传输
+    pub fn pinned_memory(&self) -> bool {
+        self.pinned_memory
+    }
 
-        // 从内存池获取缓冲区
-        let mut device_buffer = {
-            let mut pool = self.memory_pool.lock()
-                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
-            pool.get_buffer(task.input_data.len())?
-        };
+    /// 设置单个任务允许的最大输入字节数，超过的任务在分配显存前被拒绝
+    pub fn set_max_task_bytes(&mut self, max_task_bytes: usize) {
+        self.max_task_bytes = max_task_bytes;
+    }
 
-        // 1. 将输入数据的切片从CPU内存拷贝到GPU设备内存
-        device_buffer.copy_from(&task.input_data)
-            .map_err(|e| Error::CudaError(e))?;
-        println!("  [Executor] 已将 {} 字节数据拷贝到 GPU {}。", task.input_data.len(), gpu_id);
-        
-        // --- 此处未来将插入真实的CUDA核函数调用 ---
-        // 模拟计算延迟
-        std::thread::sleep(std::time::Duration::from_millis(10));
-        
-        // 2. 将结果从GPU设备内存拷贝回CPU内存
-        let mut host_result = vec![0u8; task.input_data.len()];
-        device_buffer.copy_to(&mut host_result)
-            .map_err(|e| Error::CudaError(e))?;
-        println!("  [Executor] 已将 {} 字节结果传回 CPU。", host_result.len());
+    /// 获取单个任务允许的最大输入字节数
+    pub fn max_task_bytes(&self) -> usize {
+        self.max_task_bytes
+    }
 
-        // 将缓冲区归还给内存池
-        {
-            let mut pool = self.memory_pool.lock()
-                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
-            pool.return_buffer(device_buffer);
+    /// 设置量化位宽：启用后带任务头部的输入在拷贝到GPU前量化为 int8/int4，结果拷回后反量化；
+    /// 传入None关闭量化，不支持的位宽返回 InferenceError
+    pub fn set_quantization_bits(&mut self, bits: Option<u8>) -> Result<()> {
+        if let Some(bits) = bits {
+            quantization::check_bits(bits)?;
         }
+        self.quantization_bits = bits;
+        Ok(())
+    }
 
-        // 释放GPU负载
-        {
-            let mut balancer = self.load_balancer.lock()
-                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
-            balancer.release_gpu(gpu_id);
-        }
+    /// 获取量化位宽，未启用量化时为None
+    pub fn quantization_bits(&self) -> Option<u8> {
+        self.quantization_bits
+    }
 
-        // 更新任务状态和结果
-        task.status = TaskStatus::Completed;
-        task.result = Some(host_result.clone());
+    /// 启用或关闭结果检查：启用后拷回的结果按f32扫描，NaN/Inf 按 set_non_finite_policy 设置的方式处理
+    /// （默认替换为0），避免其沿残差链传播
+    pub fn sanitize_output(&mut self, enabled: bool) {
+        self.output_sanitization = enabled.then(|| self.output_sanitization.unwrap_or_default());
+    }
 
-        Ok(host_result)
+    /// 设置结果中 NaN/Inf 的处理方式，同时启用结果检查
+    pub fn set_non_finite_policy(&mut self, policy: NonFinitePolicy) {
+        self.output_sanitization = Some(policy);
     }
 
-    /// 批量执行任务
-    pub fn execute_tasks(&self, tasks: &mut [MoeTask]) -> Result<Vec<Vec<u8>>> {
-        let mut results = Vec::new();
-        
-        for task in tasks.iter_mut() {
-            match self.execute_task(task) {
-                Ok(result) => results.push(result),
-                Err(e) => {
-                    task.status = TaskStatus::Failed(e.to_string());
-                    return Err(e);
-                }
-            }
+    /// 结果中 NaN/Inf 的处理方式，未启用结果检查时为None
+    pub fn output_sanitization(&self) -> Option<NonFinitePolicy> {
+        self.output_sanitization
+    }
+
+    /// 设置代替核函数的模拟计算
+    pub fn set_compute_sim(&mut self, compute_sim: ComputeSim) {
+        self.compute_sim = compute_sim;
+    }
+
+    /// 当前使用的模拟计算
+    pub fn compute_sim(&self) -> ComputeSim {
+        self.compute_sim
+    }
+
+    /// 设置单个任务的超时时间：超时的任务被标记为 Failed("timeout") 并返回 GpuError，传入None不限制
+    pub fn set_task_timeout(&mut self, timeout: Option<Duration>) {
+        self.task_timeout = timeout;
+    }
+
+    /// 单个任务的超时时间，未设置时为None
+    pub fn task_timeout(&self) -> Option<Duration> {
+        self.task_timeout
+    }
+
+    /// 累计的执行耗时与传输字节数
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// 替换耗时统计收集器，传入拆分器使用的收集器即可汇总整条流水线
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// 在 stream_id 对应的流上执行 f，流不存在时创建；未指定 stream_id 的任务使用默认流
+    fn with_stream<R>(&self, stream_id: Option<usize>, f: impl FnOnce(&Stream) -> Result<R>) -> Result<R> {
+        let stream_id = match stream_id {
+            Some(stream_id) => stream_id,
+            None => return f(&self.stream),
+        };
+        let mut streams = self.streams.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        let stream = match streams.entry(stream_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                Stream::new(StreamFlags::NON_BLOCKING, None).map_err(Error::CudaError)?,
+            ),
+        };
+        f(stream)
+    }
+
+    /// 已为任务创建的流数量（不含默认流）
+    pub fn stream_count(&self) -> Result<usize> {
+        let streams = self.streams.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        Ok(streams.len())
+    }
+
+    /// 开始执行任务：命中缓存时直接返回结果，否则在任务的流上异步发出拷贝和计算
+    fn begin_task(&self, task: &mut MoeTask) -> Result<Started> {
+        debug!("[Executor] 开始执行任务: {}", task.task_id);
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(Self::shut_down_error(&task.task_id));
         }
-        
-        Ok(results)
+
+        // 在分配任何显存之前拒绝过大的任务
+        let input_len = task.input_len();
+        if input_len > self.max_task_bytes {
+            return Err(Error::ConfigError(format!(
+                "任务 {} 的输入大小 {} 字节超过单任务上限 {} 字节",
+                task.task_id, input_len, self.max_task_bytes
+            )));
+        }
+
+        // 完整输入相同的任务直接复用缓存结果
+        {
+            let mut cache = self.result_cache.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            if let Some(result) = cache.as_mut().and_then(|cache| cache.get(task)) {
+                debug!("[Executor] 任务 {} 命中结果缓存", task.task_id);
+                task.status = TaskStatus::Completed;
+                task.result = Some(result.clone());
+                return Ok(Started::Cached(result));
+            }
+        }
+
+        // 更新任务状态
+        task.status = TaskStatus::Running;
+        let deadline = self.task_timeout.map(|timeout| Instant::now() + timeout);
+        let input = match self.quantization_bits {
+            Some(bits) => Cow::Owned(quantization::quantize_task_input(&task.effective_input(), bits)?),
+            None => task.effective_input(),
+        };
+
+        // 选择GPU进行负载均衡
+        let gpu_id = {
+            let mut balancer = self.load_balancer.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            let selected_gpu = balancer.select_gpu(&[self.device_id], input.len())?;
+            balancer.assign_task(&task.task_id, selected_gpu, input.len());
+            selected_gpu
+        };
+
+        if let Err(e) = self.enter_task(&task.task_id) {
+            self.release_task(&task.task_id)?;
+            return Err(e);
+        }
+        let config = LaunchConfig { pinned: self.pinned_memory, compute_sim: &self.compute_sim, deadline };
+        let launched = self.with_stream(task.stream_id, |stream| {
+            launch_on_device(&self.memory_pool, stream, task, &input, gpu_id, &config)
+        });
+        if !matches!(launched, Ok(Some(_))) {
+            self.exit_task();
+        }
+        match launched {
+            Ok(Some(in_flight)) => Ok(Started::Launched(in_flight)),
+            Ok(None) => {
+                // 任务在执行期间被取消或超时时不再拷回结果，释放负载后提前返回
+                self.release_task(&task.task_id)?;
+                if !task.is_cancelled() {
+                    return Err(self.timed_out(task));
+                }
+                debug!("[Executor] 任务 {} 已取消，跳过结果拷回", task.task_id);
+                task.status = TaskStatus::Failed("cancelled".to_string());
+                Err(Error::InferenceError(format!("任务 {} 已取消", task.task_id)))
+            }
+            Err(e) => {
+                self.release_task(&task.task_id)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// 同步任务所在的流，记录耗时、写入缓存并完成任务
+    fn finish_task(&self, task: &mut MoeTask, in_flight: InFlight) -> Result<Vec<u8>> {
+        let h2d_bytes = in_flight.h2d_bytes;
+        let deadline = in_flight.deadline;
+        let finished = self.with_stream(task.stream_id, |stream| {
+            finish_on_device(&self.memory_pool, stream, in_flight)
+        });
+        self.exit_task();
+        // 释放GPU负载
+        self.release_task(&task.task_id)?;
+        let (host_result, task_metrics) = finished?;
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(self.timed_out(task));
+        }
+        self.metrics.record_task(&task_metrics, h2d_bytes, host_result.len());
+        let mut host_result = match self.quantization_bits {
+            Some(_) => quantization::dequantize_task_output(&host_result)?,
+            None => host_result,
+        };
+        if let Some(policy) = self.output_sanitization {
+            sanitize_non_finite(&task.task_id, &mut host_result, policy)?;
+        }
+
+        // 记录各阶段耗时
+        {
+            let mut metrics = self.task_metrics.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            metrics.insert(task.task_id.clone(), task_metrics);
+        }
+
+        {
+            let mut cache = self.result_cache.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            if let Some(cache) = cache.as_mut() {
+                cache.insert(task, host_result.clone());
+            }
+        }
+
+        // 更新任务状态和结果
+        task.status = TaskStatus::Completed;
+        task.result = Some(host_result.clone());
+
+        Ok(host_result)
+    }
+
+    /// 登记一个即将发出到设备的任务，执行器已关闭时拒绝
+    fn enter_task(&self, task_id: &str) -> Result<()> {
+        let mut active = self.active_tasks.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        // 在持有计数锁时检查，保证 shutdown 开始等待后不会再有新任务发出
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(Self::shut_down_error(task_id));
+        }
+        *active += 1;
+        Ok(())
+    }
+
+    /// 任务已同步或未能发出，计数归零时唤醒等待中的 shutdown
+    fn exit_task(&self) {
+        if let Ok(mut active) = self.active_tasks.lock() {
+            *active = active.saturating_sub(1);
+            if *active == 0 {
+                self.tasks_idle.notify_all();
+            }
+        }
+    }
+
+    /// 将超时的任务标记为 Failed("timeout")，返回对应的 GpuError
+    fn timed_out(&self, task: &mut MoeTask) -> Error {
+        let timeout = self.task_timeout.unwrap_or_default();
+        warn!("[Executor] 任务 {} 超过 {:?} 仍未完成，已放弃", task.task_id, timeout);
+        task.status = TaskStatus::Failed("timeout".to_string());
+        Error::GpuError(format!("任务 {} 执行超时 ({:?})", task.task_id, timeout))
+    }
+
+    fn shut_down_error(task_id: &str) -> Error {
+        Error::InferenceError(format!("执行器已关闭，拒绝任务 {}", task_id))
+    }
+
+    /// 释放任务占用的GPU负载
+    fn release_task(&self, task_id: &str) -> Result<()> {
+        let mut balancer = self.load_balancer.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        balancer.release_gpu(task_id);
+        Ok(())
+    }
+
+    /// 执行一个任务，将数据拷贝到GPU再拷贝回来
+    ///
+    /// 这是真实计算的第一步，用于验证数据通路。
+    pub fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+        match self.begin_task(task)? {
+            Started::Cached(result) => Ok(result),
+            Started::Launched(in_flight) => self.finish_task(task, in_flight),
+        }
+    }
+
+    /// 批量执行任务
+    ///
+    /// 先在各任务 stream_id 对应的流上发出全部拷贝和计算，再逐个同步收集结果，
+    /// 不同流上的传输因此可以相互重叠。
+    pub fn execute_tasks(&self, tasks: &mut [MoeTask]) -> Result<Vec<Vec<u8>>> {
+        let mut started = Vec::with_capacity(tasks.len());
+        for index in 0..tasks.len() {
+            match self.begin_task(&mut tasks[index]) {
+                Ok(task_started) => started.push(task_started),
+                Err(e) => {
+                    tasks[index].mark_failed(&e);
+                    // 已发出的任务仍需同步，以便归还缓冲区和负载
+                    for (task, task_started) in tasks.iter_mut().zip(started) {
+                        if let Started::Launched(in_flight) = task_started {
+                            let _ = self.finish_task(task, in_flight);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        let mut first_error = None;
+        for (task, task_started) in tasks.iter_mut().zip(started) {
+            let result = match task_started {
+                Started::Cached(result) => Ok(result),
+                Started::Launched(in_flight) => self.finish_task(task, in_flight),
+            };
+            match result {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    task.mark_failed(&e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(results),
+        }
+    }
+
+    /// 批量执行任务，不因单个任务失败而中止
+    /// 显存不足等瞬时CUDA错误按指数退避重试最多 max_retries 次，其他错误不重试；
+    /// 返回与 tasks 一一对应的执行结果
+    pub fn execute_tasks_resilient(&self, tasks: &mut [MoeTask], max_retries: usize) -> Vec<Result<Vec<u8>>> {
+        execute_with_retries(tasks, max_retries, RETRY_BASE_BACKOFF, |task| self.execute_task(task))
     }
 
     /// 获取内存池状态
@@ -229,13 +1281,142 @@ impl TaskExecutor {
         Ok((pool.total_allocated, pool.max_memory))
     }
 
+    /// 获取内存池的详细占用情况：使用中与缓存空闲的字节数、空闲缓冲区个数和最大空闲块
+    pub fn get_memory_stats(&self) -> Result<MemoryStats> {
+        let pool = self.memory_pool.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        Ok(pool.stats())
+    }
+
     /// 获取负载均衡状态
-    pub fn get_load_status(&self) -> Result<HashMap<usize, f32>> {
+    pub fn get_load_status(&self) -> Result<HashMap<DeviceId, f32>> {
         let balancer = self.load_balancer.lock()
             .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
         Ok(balancer.gpu_loads.clone())
     }
 
+    /// 启用或关闭结果缓存，关闭时丢弃已缓存的结果
+    pub fn enable_result_cache(&self, enabled: bool) -> Result<()> {
+        let mut cache = self.result_cache.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        *cache = if enabled { Some(ResultCache::new()) } else { None };
+        Ok(())
+    }
+
+    /// 获取结果缓存命中次数，未启用缓存时返回0
+    pub fn result_cache_hits(&self) -> Result<usize> {
+        let cache = self.result_cache.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        Ok(cache.as_ref().map_or(0, |cache| cache.hits()))
+    }
+
+    /// 获取任务各阶段耗时，任务未执行或计时已被淘汰（只保留最近 4096 个任务）时返回None
+    pub fn get_task_metrics(&self, task_id: &str) -> Result<Option<TaskMetrics>> {
+        let metrics = self.task_metrics.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        Ok(metrics.get(task_id))
+    }
+
+    /// 释放内存池中缓存的空闲缓冲区，直到保留的空闲字节数不超过 `keep_bytes`
+    ///
+    /// 正在使用的缓冲区不受影响，返回实际释放的字节数。
+    pub fn trim_idle(&self, keep_bytes: usize) -> Result<usize> {
+        let mut pool = self.memory_pool.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        let freed = pool.trim(keep_bytes);
+        if freed > 0 {
+            debug!("[Executor] 释放空闲显存 {} 字节", freed);
+        }
+        Ok(freed)
+    }
+
+    /// 启动后台线程，在内存池空闲超过 `idle_period` 后自动裁剪到 `keep_bytes`
+    ///
+    /// 重复调用会先停止已有的裁剪线程。
+    pub fn start_idle_trimmer(&self, idle_period: Duration, keep_bytes: usize) -> Result<()> {
+        self.stop_idle_trimmer()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let pool = Arc::clone(&self.memory_pool);
+        // 释放显存需要在当前线程绑定同一个CUDA上下文
+        let context: UnownedContext = self._context.get_unowned();
+        let check_interval = std::cmp::min(idle_period, Duration::from_millis(100));
+
+        let handle = std::thread::spawn(move || {
+            if CurrentContext::set_current(&context).is_err() {
+                return;
+            }
+            while !thread_stop.load(Ordering::Relaxed) {
+                std::thread::sleep(check_interval);
+                if let Ok(mut pool) = pool.lock() {
+                    if pool.last_activity.elapsed() >= idle_period {
+                        pool.trim(keep_bytes);
+                    }
+                }
+            }
+        });
+
+        let mut trimmer = self.idle_trimmer.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        *trimmer = Some(IdleTrimmer { stop, handle });
+        Ok(())
+    }
+
+    /// 停止后台空闲裁剪线程
+    pub fn stop_idle_trimmer(&self) -> Result<()> {
+        let trimmer = self.idle_trimmer.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?
+            .take();
+        if let Some(trimmer) = trimmer {
+            trimmer.stop.store(true, Ordering::Relaxed);
+            let _ = trimmer.handle.join();
+        }
+        Ok(())
+    }
+
+    /// 关闭执行器：不再接受新任务，等待已发出的任务同步完成，同步所有流后再释放内存池
+    ///
+    /// 关闭后提交的任务返回 InferenceError，重复调用是安全的。`Drop` 时会自动调用。
+    pub fn shutdown(&self) -> Result<()> {
+        {
+            let mut active = self.active_tasks.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            self.shutting_down.store(true, Ordering::SeqCst);
+            while *active > 0 {
+                debug!("[Executor] 等待 {} 个进行中的任务完成", *active);
+                active = self.tasks_idle.wait(active)
+                    .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            }
+        }
+
+        self.stream.synchronize()
+            .map_err(Error::CudaError)?;
+        {
+            let streams = self.streams.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            for stream in streams.values() {
+                stream.synchronize()
+                    .map_err(Error::CudaError)?;
+            }
+        }
+
+        self.stop_idle_trimmer()?;
+        self.cleanup()
+    }
+
+    /// 规划专家到GPU的放置，按 intermediate_size 估算每个专家的显存并在 `gpu_ids` 之间均衡分配
+    ///
+    /// 单卡执行器只使用自己的设备，规划结果交给 `MultiGpuExecutor::set_expert_placement` 使用。
+    pub fn plan_expert_placement(&self, model_info: &ModelInfo, gpu_ids: &[DeviceId]) -> Vec<ExpertGpuMapping> {
+        plan_expert_placement(model_info, gpu_ids)
+    }
+
+    /// 是否已调用 shutdown
+    pub fn is_shut_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
     /// 清理资源
     pub fn cleanup(&self) -> Result<()> {
         // 清理内存池
@@ -243,6 +1424,8 @@ impl TaskExecutor {
             let mut pool = self.memory_pool.lock()
                 .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
             pool.available_buffers.clear();
+            pool.return_order.clear();
+            pool.pinned_buffers.clear();
             pool.total_allocated = 0;
         }
 
@@ -252,16 +1435,1111 @@ impl TaskExecutor {
                 .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
             balancer.gpu_loads.clear();
             balancer.task_distribution.clear();
+            balancer.task_loads.clear();
+        }
+
+        // 清理结果缓存
+        {
+            let mut cache = self.result_cache.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            if let Some(cache) = cache.as_mut() {
+                cache.clear();
+            }
+        }
+
+        // 清理任务计时
+        {
+            let mut metrics = self.task_metrics.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            metrics.clear();
         }
 
-        println!("  [Executor] 资源清理完成");
+        info!("[Executor] 资源清理完成");
         Ok(())
     }
 }
 
 impl Drop for TaskExecutor {
     fn drop(&mut self) {
-        // 自动清理资源
-        let _ = self.cleanup();
+        // 等待进行中的任务后再释放资源
+        let _ = self.shutdown();
+    }
+} 
+
+impl Executor for TaskExecutor {
+    fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+        TaskExecutor::execute_task(self, task)
+    }
+
+    fn execute_tasks(&self, tasks: &mut [MoeTask]) -> Result<Vec<Vec<u8>>> {
+        TaskExecutor::execute_tasks(self, tasks)
+    }
+}
+
+/// 主机执行器，不需要CUDA，与CUDA执行器一样把输入原样作为结果返回
+///
+/// 用于没有GPU的机器和测试中验证拆分、调度与合并流程。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuTaskExecutor;
+
+impl CpuTaskExecutor {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// 在线程池中并行执行一批互不依赖的任务，结果按任务在输入中的顺序返回
+    ///
+    /// 线程池大小取 `config.max_concurrent_tasks`，与调度器一致必须大于0，配置未通过 `validate` 时返回配置错误。
+    /// `dependencies` 为 `TaskSplitter::get_task_dependencies` 的结果，其中存在依赖边时退回串行执行。
+    /// 任一任务失败时将其标记为失败，并返回按任务顺序的第一个错误。
+    pub fn execute_tasks_parallel(
+        &self,
+        tasks: &mut [MoeTask],
+        dependencies: &HashMap<String, Vec<String>>,
+        config: &SchedulerConfig,
+    ) -> Result<Vec<Vec<u8>>> {
+        config.validate()?;
+        if dependencies.values().any(|deps| !deps.is_empty()) {
+            debug!("任务之间存在依赖，串行执行 {} 个任务", tasks.len());
+            return self.execute_tasks(tasks);
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.max_concurrent_tasks)
+            .build()
+            .map_err(|e| Error::Other(format!("创建线程池失败: {}", e)))?;
+        let results: Vec<Result<Vec<u8>>> = pool.install(|| {
+            tasks
+                .par_iter_mut()
+                .map(|task| {
+                    self.execute_task(task).inspect_err(|e| task.mark_failed(e))
+                })
+                .collect()
+        });
+        results.into_iter().collect()
+    }
+}
+
+impl Executor for CpuTaskExecutor {
+    fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+        if task.is_cancelled() {
+            task.status = TaskStatus::Failed("cancelled".to_string());
+            return Err(Error::InferenceError(format!("任务 {} 已取消", task.task_id)));
+        }
+        task.status = TaskStatus::Running;
+        let result = task.effective_input().into_owned();
+        task.status = TaskStatus::Completed;
+        task.result = Some(result.clone());
+        Ok(result)
+    }
+
+    fn as_shared(&self) -> Option<&(dyn Executor + Sync)> {
+        Some(self)
+    }
+}
+
+/// 单个GPU的执行资源
+///
+/// 字段按声明顺序析构，上下文必须最后释放。
+struct GpuDevice {
+    gpu_id: DeviceId,
+    memory_pool: Mutex<MemoryPool>,
+    stream: Stream,
+    context: Context,
+}
+
+impl Drop for GpuDevice {
+    fn drop(&mut self) {
+        // 缓冲区和流需要在所属上下文中释放，上下文销毁时会自动出栈
+        let _ = ContextStack::push(&self.context);
+        if let Ok(mut pool) = self.memory_pool.lock() {
+            pool.available_buffers.clear();
+            pool.return_order.clear();
+            pool.pinned_buffers.clear();
+            pool.total_allocated = 0;
+        }
     }
-} 
\ No newline at end of file
+}
+
+/// 多GPU任务执行器，每个设备拥有独立的上下文和内存池，任务按负载分配到最空闲的GPU
+pub struct MultiGpuExecutor {
+    devices: Vec<GpuDevice>,
+    load_balancer: Mutex<LoadBalancer>,
+    // 专家ID -> 放置的GPU，专家任务固定在该GPU上执行
+    expert_placement: HashMap<usize, DeviceId>,
+}
+
+impl MultiGpuExecutor {
+    /// 为每个设备ID创建上下文、内存池（使用80%显存）和流
+    pub fn new(gpu_ids: &[DeviceId]) -> Result<Self> {
+        if gpu_ids.is_empty() {
+            return Err(Error::ConfigError("GPU设备列表不能为空".to_string()));
+        }
+
+        // 初始化CUDA驱动API
+        rustacuda::init(CudaFlags::empty())
+            .map_err(Error::CudaError)?;
+
+        let mut devices: Vec<GpuDevice> = Vec::with_capacity(gpu_ids.len());
+        let mut load_balancer = LoadBalancer::new();
+        for &gpu_id in gpu_ids {
+            if devices.iter().any(|device| device.gpu_id == gpu_id) {
+                return Err(Error::ConfigError(format!("GPU设备 {} 重复", gpu_id)));
+            }
+
+            let device = get_device(gpu_id)?;
+            let context = Context::create_and_push(ContextFlags::MAP_HOST | ContextFlags::SCHED_AUTO, device)
+                .map_err(Error::CudaError)?;
+            let total_memory = device.total_memory()
+                .map_err(Error::CudaError)?;
+            let max_memory_mb = pool_memory_mb(total_memory, DEFAULT_MEMORY_FRACTION);
+            load_balancer.register_gpu(gpu_id, total_memory);
+            // 流属于当前上下文，创建完成后把上下文弹出，执行任务时再按需压栈
+            let stream = Stream::new(StreamFlags::NON_BLOCKING, None)
+                .map_err(Error::CudaError)?;
+            ContextStack::pop()
+                .map_err(Error::CudaError)?;
+
+            devices.push(GpuDevice {
+                gpu_id,
+                memory_pool: Mutex::new(MemoryPool::new(max_memory_mb)),
+                stream,
+                context,
+            });
+        }
+
+        Ok(Self {
+            devices,
+            load_balancer: Mutex::new(load_balancer),
+            expert_placement: HashMap::new(),
+        })
+    }
+
+    /// 使用调度器配置中的 `gpu_ids` 创建执行器
+    pub fn from_config(config: &SchedulerConfig) -> Result<Self> {
+        Self::new(&config.gpu_ids)
+    }
+
+    /// 管理的GPU设备ID
+    pub fn device_ids(&self) -> Vec<DeviceId> {
+        self.devices.iter().map(|device| device.gpu_id).collect()
+    }
+
+    /// 在本执行器的所有GPU之间规划专家放置
+    pub fn plan_expert_placement(&self, model_info: &ModelInfo) -> Vec<ExpertGpuMapping> {
+        plan_expert_placement(model_info, &self.device_ids())
+    }
+
+    /// 设置专家放置，之后该专家的任务都分配到映射的GPU上；映射中的GPU必须属于本执行器
+    pub fn set_expert_placement(&mut self, mappings: &[ExpertGpuMapping]) -> Result<()> {
+        let device_ids = self.device_ids();
+        let mut placement = HashMap::with_capacity(mappings.len());
+        for mapping in mappings {
+            if !device_ids.contains(&mapping.gpu_id) {
+                return Err(Error::ConfigError(format!(
+                    "专家 {} 映射到的GPU设备 {} 不在执行器中", mapping.expert_id, mapping.gpu_id
+                )));
+            }
+            placement.insert(mapping.expert_id, mapping.gpu_id);
+        }
+        self.expert_placement = placement;
+        Ok(())
+    }
+
+    /// 为每个任务选择GPU并占用其负载，返回与 tasks 一一对应的GPU ID
+    ///
+    /// 已设置放置的专家任务使用映射的GPU，其他任务选择负载最低的GPU。
+    /// 占用的负载在对应任务经 `execute_on` 执行后释放。
+    pub fn dispatch(&self, tasks: &[MoeTask]) -> Result<Vec<DeviceId>> {
+        let gpu_ids = self.device_ids();
+        let mut balancer = self.load_balancer.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        tasks
+            .iter()
+            .map(|task| {
+                let task_bytes = task.input_len();
+                let placed = task
+                    .parsed_task_id()
+                    .ok()
+                    .and_then(|task_id| task_id.expert)
+                    .and_then(|expert_id| self.expert_placement.get(&expert_id).copied());
+                let gpu_id = match placed {
+                    Some(gpu_id) => gpu_id,
+                    None => balancer.select_gpu(&gpu_ids, task_bytes)?,
+                };
+                balancer.assign_task(&task.task_id, gpu_id, task_bytes);
+                Ok(gpu_id)
+            })
+            .collect()
+    }
+
+    /// 在指定GPU上执行任务，压入该GPU的上下文后完成拷贝与计算，结束后释放负载
+    pub fn execute_on(&self, gpu_id: DeviceId, task: &mut MoeTask) -> Result<Vec<u8>> {
+        let device = self
+            .devices
+            .iter()
+            .find(|device| device.gpu_id == gpu_id)
+            .ok_or_else(|| Error::ConfigError(format!("GPU设备 {} 不在执行器中", gpu_id)))?;
+
+        debug!("[Executor] 在 GPU {} 上执行任务: {}", gpu_id, task.task_id);
+        task.status = TaskStatus::Running;
+        let input = task.effective_input();
+
+        ContextStack::push(&device.context)
+            .map_err(Error::CudaError)?;
+        let outcome = run_on_device(&device.memory_pool, &device.stream, task, &input, gpu_id);
+        let popped = ContextStack::pop();
+
+        {
+            let mut balancer = self.load_balancer.lock()
+                .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+            balancer.release_gpu(&task.task_id);
+        }
+
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                task.mark_failed(&e);
+                return Err(e);
+            }
+        };
+        popped.map_err(Error::CudaError)?;
+
+        match outcome {
+            Some((host_result, _)) => {
+                task.status = TaskStatus::Completed;
+                task.result = Some(host_result.clone());
+                Ok(host_result)
+            }
+            None => {
+                debug!("[Executor] 任务 {} 已取消，跳过结果拷回", task.task_id);
+                task.status = TaskStatus::Failed("cancelled".to_string());
+                Err(Error::InferenceError(format!("任务 {} 已取消", task.task_id)))
+            }
+        }
+    }
+
+    /// 将单个任务分配到负载最低的GPU上执行
+    pub fn execute_task(&self, task: &mut MoeTask) -> Result<Vec<u8>> {
+        let gpu_id = self.dispatch(std::slice::from_ref(task))?[0];
+        self.execute_on(gpu_id, task)
+    }
+
+    /// 先为整批任务分配GPU再依次执行，使负载在各GPU间均匀分布
+    ///
+    /// 任一任务失败时释放其余任务占用的负载并返回错误。
+    pub fn execute_tasks(&self, tasks: &mut [MoeTask]) -> Result<Vec<Vec<u8>>> {
+        let gpu_ids = self.dispatch(tasks)?;
+        let task_ids: Vec<String> = tasks.iter().map(|task| task.task_id.clone()).collect();
+        let mut results = Vec::with_capacity(tasks.len());
+        for (index, (task, &gpu_id)) in tasks.iter_mut().zip(&gpu_ids).enumerate() {
+            match self.execute_on(gpu_id, task) {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    if let Ok(mut balancer) = self.load_balancer.lock() {
+                        for pending in &task_ids[index + 1..] {
+                            balancer.release_gpu(pending);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// 获取各GPU的负载
+    pub fn get_load_status(&self) -> Result<HashMap<DeviceId, f32>> {
+        let balancer = self.load_balancer.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        Ok(balancer.gpu_loads.clone())
+    }
+
+    /// 获取指定GPU内存池的已分配字节数和上限
+    pub fn get_memory_status(&self, gpu_id: DeviceId) -> Result<(usize, usize)> {
+        let device = self
+            .devices
+            .iter()
+            .find(|device| device.gpu_id == gpu_id)
+            .ok_or_else(|| Error::ConfigError(format!("GPU设备 {} 不在执行器中", gpu_id)))?;
+        let pool = device.memory_pool.lock()
+            .map_err(|_| Error::CudaError(rustacuda::error::CudaError::InvalidValue))?;
+        Ok((pool.total_allocated, pool.max_memory))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::TaskPriority;
+    use crate::task_header::{self, TaskHeader, TaskKind};
+
+    fn test_task(task_id: &str, size: usize) -> MoeTask {
+        MoeTask {
+            stream_id: Some(0),
+            ..MoeTask::new(task_id, (0..size).map(|i| (i % 251) as u8).collect(), TaskPriority::Normal)
+        }
+    }
+
+    #[test]
+    fn test_recent_task_metrics_evicts_oldest() {
+        let timing = |ms| TaskMetrics { kernel: Duration::from_millis(ms), ..TaskMetrics::default() };
+        let mut metrics = RecentTaskMetrics::new(2);
+        metrics.insert("a".to_string(), timing(1));
+        metrics.insert("b".to_string(), timing(2));
+        // 重复记录只更新计时，不占用额外名额
+        metrics.insert("a".to_string(), timing(3));
+        assert_eq!(metrics.entries.len(), 2);
+        assert_eq!(metrics.get("a"), Some(timing(3)));
+
+        metrics.insert("c".to_string(), timing(4));
+        assert_eq!(metrics.get("a"), None);
+        assert_eq!(metrics.get("b"), Some(timing(2)));
+        assert_eq!(metrics.get("c"), Some(timing(4)));
+        assert_eq!(metrics.order.len(), 2);
+    }
+
+    #[test]
+    fn test_phase_timer_wall_clock_fallback() {
+        // CUDA事件不可用时按各阶段边界的挂钟时间计时
+        let start = Instant::now();
+        let gaps = [Duration::from_millis(2), Duration::from_millis(5), Duration::from_millis(3)];
+        let mut instants = vec![start];
+        for gap in gaps {
+            instants.push(*instants.last().unwrap() + gap);
+        }
+        let timer = PhaseTimer { events: None, instants };
+
+        let timing = timer.finish();
+        assert!(!timing.device_timed);
+        assert_eq!([timing.h2d, timing.kernel, timing.d2h], gaps);
+        assert_eq!(timing.total(), timer.instants[3] - start);
+    }
+
+    #[test]
+    fn test_event_timed_kernel_within_wall_clock() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut task = test_task("timed", 4096);
+
+        let wall_start = Instant::now();
+        let result = executor.execute_task(&mut task).unwrap();
+        let wall = wall_start.elapsed();
+        assert_eq!(result, task.input_data);
+
+        let metrics = executor.get_task_metrics("timed").unwrap().unwrap();
+        assert!(metrics.kernel > Duration::ZERO);
+        assert!(metrics.kernel < wall);
+        assert!(metrics.total() <= wall);
+    }
+
+    #[test]
+    fn test_tasks_on_different_streams_are_independent() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut first = test_task("stream_a", 128);
+        first.stream_id = Some(0);
+        let mut second = test_task("stream_b", 96);
+        second.stream_id = Some(1);
+        second.input_data.iter_mut().for_each(|byte| *byte = byte.wrapping_add(7));
+        let expected = vec![first.input_data.clone(), second.input_data.clone()];
+
+        let mut tasks = vec![first, second];
+        let results = executor.execute_tasks(&mut tasks).unwrap();
+        assert_eq!(results, expected);
+        assert_eq!(executor.stream_count().unwrap(), 2);
+        for (task, expected) in tasks.iter().zip(&expected) {
+            assert!(matches!(task.status, TaskStatus::Completed));
+            assert_eq!(task.result.as_ref(), Some(expected));
+        }
+        assert!(executor.get_load_status().unwrap().values().all(|load| *load == 0.0));
+    }
+
+    #[test]
+    fn test_pinned_memory_large_round_trip() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor.with_pinned_memory(true),
+            Err(_) => return,
+        };
+        assert!(executor.pinned_memory());
+        const SIZE: usize = 64 * 1024 * 1024;
+        let mut task = test_task("pinned", 0);
+        task.input_data = vec![0xA5; SIZE];
+        task.input_data[SIZE - 1] = 0x5A;
+
+        let result = executor.execute_task(&mut task).unwrap();
+        assert_eq!(result.len(), SIZE);
+        assert_eq!(result, task.input_data);
+
+        // 页锁定缓冲区已归还给内存池，可供下次复用
+        let pool = executor.memory_pool.lock().unwrap();
+        assert_eq!(pool.pinned_buffers.get(&SIZE).map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn test_trim_idle_frees_cached_buffers() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        // 不同大小的任务执行后各自的缓冲区都归还到池中
+        for (i, size) in [1024, 2048, 4096].iter().enumerate() {
+            let mut task = test_task(&format!("idle_{}", i), *size);
+            executor.execute_task(&mut task).unwrap();
+        }
+        let (allocated, _) = executor.get_memory_status().unwrap();
+        assert_eq!(allocated, 1024 + 2048 + 4096);
+
+        let freed = executor.trim_idle(0).unwrap();
+        assert_eq!(freed, allocated);
+        let (allocated, _) = executor.get_memory_status().unwrap();
+        assert_eq!(allocated, 0);
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_tasks_and_frees_pool() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut tasks: Vec<MoeTask> = (0..4)
+            .map(|i| {
+                let mut task = test_task(&format!("shutdown_{}", i), 1024 * (i + 1));
+                task.stream_id = Some(i);
+                task
+            })
+            .collect();
+        executor.execute_tasks(&mut tasks).unwrap();
+
+        executor.shutdown().unwrap();
+        assert!(executor.is_shut_down());
+        assert_eq!(executor.get_memory_stats().unwrap().in_use_bytes, 0);
+        assert_eq!(executor.get_memory_status().unwrap().0, 0);
+        assert_eq!(*executor.active_tasks.lock().unwrap(), 0);
+
+        // 关闭后不再接受新任务
+        let mut late = test_task("late", 1024);
+        assert!(matches!(executor.execute_task(&mut late), Err(Error::InferenceError(_))));
+        executor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_background_idle_trimmer() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut task = test_task("idle_bg", 2048);
+        executor.execute_task(&mut task).unwrap();
+
+        executor.start_idle_trimmer(Duration::from_millis(20), 0).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        executor.stop_idle_trimmer().unwrap();
+
+        let (allocated, _) = executor.get_memory_status().unwrap();
+        assert_eq!(allocated, 0);
+    }
+
+    #[test]
+    fn test_result_cache_hits_identical_payloads() {
+        let body: Arc<[u8]> = Arc::from(vec![7u8; 1024]);
+        let header = vec![3u8, 0, 0, 0];
+        let shared_task = |task_id: &str| MoeTask {
+            input_data: header.clone(),
+            shared_input: Some(Arc::clone(&body)),
+            ..test_task(task_id, 0)
+        };
+
+        let mut cache = ResultCache::new();
+        let first = shared_task("req_a_expert_3");
+        assert!(cache.get(&first).is_none());
+        cache.insert(&first, vec![1, 2, 3]);
+
+        // 另一请求中完整输入相同的专家任务命中缓存
+        let second = shared_task("req_b_expert_3");
+        assert_eq!(cache.get(&second), Some(vec![1, 2, 3]));
+        assert_eq!(cache.hits(), 1);
+
+        // 头部不同（不同专家）不命中
+        let mut other_expert = shared_task("req_b_expert_4");
+        other_expert.input_data = vec![4u8, 0, 0, 0];
+        assert!(cache.get(&other_expert).is_none());
+
+        // 主体内容相同但不是同一份内存时不比较内容，不命中
+        let mut copied_body = shared_task("req_c_expert_3");
+        copied_body.shared_input = Some(Arc::from(body.to_vec()));
+        assert!(cache.get(&copied_body).is_none());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_memory_fraction_sizes_pool() {
+        // 模拟16GB显存的设备
+        let total_memory = 16 * 1024 * 1024 * 1024;
+        for (fraction, expected_mb) in [(0.5, 8192), (DEFAULT_MEMORY_FRACTION, 13107), (1.0, 16384)] {
+            check_memory_fraction(fraction).unwrap();
+            let pool = MemoryPool::new(pool_memory_mb(total_memory, fraction));
+            assert_eq!(pool.max_memory, expected_mb * 1024 * 1024);
+        }
+        for fraction in [0.0, -0.1, 1.5, f32::NAN] {
+            assert!(matches!(check_memory_fraction(fraction), Err(Error::ConfigError(_))));
+        }
+    }
+
+    #[test]
+    fn test_memory_pool_rejects_overflowing_size() {
+        let mut pool = MemoryPool::new(1);
+        pool.total_allocated = 1024;
+        // total_allocated + size 溢出时返回错误而不是panic
+        assert!(pool.get_buffer(usize::MAX - 10).is_err());
+        assert!(pool.get_buffer(usize::MAX).is_err());
+        assert_eq!(pool.total_allocated, 1024);
+    }
+
+    #[test]
+    fn test_memory_pool_evicts_idle_buffers_to_fit() {
+        // 分配显存需要CUDA上下文，无可用GPU时跳过
+        let _executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        const KB: usize = 1024;
+        let mut pool = MemoryPool::new(1);
+        let a = pool.get_buffer(256 * KB).unwrap();
+        let _b = pool.get_buffer(256 * KB).unwrap();
+        let c = pool.get_buffer(512 * KB).unwrap();
+        assert_eq!(pool.total_allocated, pool.max_memory);
+
+        // 已到上限，且没有空闲缓冲区可淘汰
+        assert!(pool.get_buffer(384 * KB).is_err());
+
+        // 先归还 c 再归还 a：c 最久未用，应先被淘汰
+        pool.return_buffer(c);
+        pool.return_buffer(a);
+        let d = pool.get_buffer(384 * KB).unwrap();
+        assert_eq!(d.len(), 384 * KB);
+        assert_eq!(pool.total_allocated, 256 * KB + 256 * KB + 384 * KB);
+        assert!(!pool.available_buffers.contains_key(&(512 * KB)));
+        assert_eq!(pool.idle_bytes(), 256 * KB);
+        assert_eq!(pool.return_order, VecDeque::from(vec![256 * KB]));
+
+        // 淘汰全部空闲缓冲区仍放不下时返回错误，已淘汰的内存照常释放
+        assert!(pool.get_buffer(512 * KB).is_err());
+        assert_eq!(pool.idle_bytes(), 0);
+        assert_eq!(pool.total_allocated, 256 * KB + 384 * KB);
+    }
+
+    #[test]
+    fn test_memory_stats_track_idle_buffers() {
+        // 分配显存需要CUDA上下文，无可用GPU时跳过
+        let _executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut pool = MemoryPool::new(1);
+        let a = pool.get_buffer(1024).unwrap();
+        let b = pool.get_buffer(4096).unwrap();
+        let _c = pool.get_buffer(2048).unwrap();
+        assert_eq!(pool.stats(), MemoryStats {
+            in_use_bytes: 1024 + 4096 + 2048,
+            ..MemoryStats::default()
+        });
+
+        pool.return_buffer(a);
+        pool.return_buffer(b);
+        let stats = pool.stats();
+        assert_eq!(stats.cached_idle_bytes, 1024 + 4096);
+        assert_eq!(stats.in_use_bytes, 2048);
+        assert_eq!(stats.num_cached_buffers, 2);
+        assert_eq!(stats.largest_idle_block, 4096);
+    }
+
+    #[test]
+    fn test_load_balancer_weights_by_task_size() {
+        let mut balancer = LoadBalancer::new();
+        balancer.register_gpu(DeviceId(0), 1000);
+        balancer.register_gpu(DeviceId(1), 1000);
+
+        // 大任务占满0号GPU一半负载，随后的小任务都落在1号GPU
+        let gpu = balancer.select_gpu(&[DeviceId(0), DeviceId(1)], 500).unwrap();
+        balancer.assign_task("large", gpu, 500);
+        assert_eq!(gpu, DeviceId(0));
+        for name in ["small_a", "small_b"] {
+            let gpu = balancer.select_gpu(&[DeviceId(0), DeviceId(1)], 100).unwrap();
+            balancer.assign_task(name, gpu, 100);
+            assert_eq!(gpu, DeviceId(1));
+        }
+        assert!((balancer.gpu_loads[&DeviceId(0)] - 0.5).abs() < 1e-6);
+        assert!((balancer.gpu_loads[&DeviceId(1)] - 0.2).abs() < 1e-6);
+
+        // 释放时按任务减去它计入的负载，重复释放无影响
+        balancer.release_gpu("large");
+        balancer.release_gpu("large");
+        balancer.release_gpu("small_a");
+        assert!(balancer.gpu_loads[&DeviceId(0)].abs() < 1e-6);
+        assert!((balancer.gpu_loads[&DeviceId(1)] - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_load_balancer_ties_choose_lowest_gpu_id() {
+        let mut balancer = LoadBalancer::new();
+        balancer.register_gpu(DeviceId(0), 1000);
+        balancer.register_gpu(DeviceId(1), 1000);
+
+        // 负载相同时无论传入顺序都选0号GPU
+        for available in [[DeviceId(0), DeviceId(1)], [DeviceId(1), DeviceId(0)]] {
+            assert_eq!(balancer.select_gpu(&available, 100).unwrap(), DeviceId(0));
+        }
+        balancer.assign_task("a", DeviceId(0), 100);
+        balancer.assign_task("b", DeviceId(1), 100);
+        for available in [[DeviceId(0), DeviceId(1)], [DeviceId(1), DeviceId(0)]] {
+            assert_eq!(balancer.select_gpu(&available, 100).unwrap(), DeviceId(0));
+        }
+
+        // 两块GPU交替分配，每次都按当前负载选择
+        let chosen: Vec<DeviceId> = (0..4)
+            .map(|i| {
+                let gpu = balancer.select_gpu(&[DeviceId(1), DeviceId(0)], 100).unwrap();
+                balancer.assign_task(&format!("task_{}", i), gpu, 100);
+                gpu
+            })
+            .collect();
+        assert_eq!(chosen, [0, 1, 0, 1].map(DeviceId));
+        assert!(balancer.select_gpu(&[], 100).is_err());
+    }
+
+    #[test]
+    fn test_multi_gpu_executor_spreads_tasks() {
+        // 少于两块GPU时跳过
+        if Device::num_devices().map_or(true, |count| count < 2) {
+            return;
+        }
+        let executor = match MultiGpuExecutor::new(&[DeviceId(0), DeviceId(1)]) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+
+        let mut tasks: Vec<MoeTask> = (0..4).map(|i| test_task(&format!("multi_{}", i), 64)).collect();
+        let gpu_ids = executor.dispatch(&tasks).unwrap();
+        assert_eq!(gpu_ids.iter().filter(|&&id| id == DeviceId(0)).count(), 2);
+        assert_eq!(gpu_ids.iter().filter(|&&id| id == DeviceId(1)).count(), 2);
+        let loads = executor.get_load_status().unwrap();
+        assert!(loads[&DeviceId(0)] > 0.0);
+        assert!(loads[&DeviceId(1)] > 0.0);
+
+        for (task, &gpu_id) in tasks.iter_mut().zip(&gpu_ids) {
+            let expected = task.input_data.clone();
+            assert_eq!(executor.execute_on(gpu_id, task).unwrap(), expected);
+            assert!(matches!(task.status, TaskStatus::Completed));
+        }
+        // 执行完成后负载全部释放，两块GPU都分配过显存
+        let loads = executor.get_load_status().unwrap();
+        assert!(loads.values().all(|&load| load < 1e-6));
+        assert_eq!(executor.get_memory_status(DeviceId(0)).unwrap().0, 64);
+        assert_eq!(executor.get_memory_status(DeviceId(1)).unwrap().0, 64);
+    }
+
+    #[test]
+    fn test_expert_placement_balances_memory() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 768,
+            intermediate_size: 3072,
+            num_layers: 12,
+            ..Default::default()
+        };
+        let placement = plan_expert_placement(&model_info, &[DeviceId(0), DeviceId(1)]);
+        assert_eq!(placement.len(), 8);
+        // 每个专家的 wi、wo 共 2×768×3072 个f32，即18MB
+        assert!(placement.iter().all(|mapping| mapping.memory_required == 18));
+
+        let memory_on = |gpu_id: u32| -> u64 {
+            placement.iter().filter(|mapping| mapping.gpu_id == DeviceId(gpu_id)).map(|mapping| mapping.memory_required).sum()
+        };
+        assert_eq!(memory_on(0), memory_on(1));
+        assert_eq!(memory_on(0) + memory_on(1), 8 * 18);
+        assert!(plan_expert_placement(&model_info, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_oversized_task_rejected_before_allocation() {
+        // 无可用GPU时跳过
+        let mut executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        executor.set_max_task_bytes(64);
+        assert_eq!(executor.max_task_bytes(), 64);
+
+        let mut task = test_task("oversized", 65);
+        assert!(matches!(executor.execute_task(&mut task), Err(Error::ConfigError(_))));
+        let (allocated, _) = executor.get_memory_status().unwrap();
+        assert_eq!(allocated, 0);
+        assert!(matches!(task.status, TaskStatus::Pending));
+
+        let mut task = test_task("fits", 64);
+        assert_eq!(executor.execute_task(&mut task).unwrap().len(), 64);
+    }
+
+    #[test]
+    fn test_quantized_task_round_trip() {
+        // 无可用GPU时跳过
+        let mut executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        assert!(matches!(executor.set_quantization_bits(Some(3)), Err(Error::InferenceError(_))));
+        executor.set_quantization_bits(Some(8)).unwrap();
+
+        let values: Vec<f32> = (0..64).map(|i| (i as f32 * 0.3).cos()).collect();
+        let header = TaskHeader::new(TaskKind::Expert { expert_id: 1, gate_weights: vec![1.0], column_slice: None });
+        let mut input = task_header::encode(&header);
+        input.extend_from_slice(&(values.len() as u32).to_le_bytes());
+        input.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+        let mut task = test_task("quantized", 0);
+        task.input_data = input.clone();
+
+        // 结果反量化后恢复原布局，数值误差不超过半个量化步长
+        let result = executor.execute_task(&mut task).unwrap();
+        assert_eq!(result.len(), input.len());
+        let header_len = input.len() - values.len() * 4;
+        let restored = result[header_len..].chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap()));
+        assert!(values.iter().zip(restored).all(|(v, r)| (v - r).abs() <= 0.5 / 127.0 + f32::EPSILON));
+    }
+
+    #[test]
+    fn test_size_scaled_compute_sim_grows_with_input() {
+        let sim = ComputeSim::SizeScaled { base: Duration::ZERO, per_kib: Duration::from_micros(200) };
+        assert_eq!(sim.latency(16 * 1024), Some(Duration::from_micros(3200)));
+        assert_eq!(sim.latency(64 * 1024), Some(Duration::from_micros(12800)));
+        assert_eq!(ComputeSim::Fixed(Duration::from_millis(3)).latency(64 * 1024), Some(Duration::from_millis(3)));
+
+        // 模拟延迟随输入大小线性增长：减去固定开销后与KiB数成正比
+        let base = Duration::from_micros(100);
+        let scaled = ComputeSim::SizeScaled { base, per_kib: Duration::from_micros(10) };
+        for kib in [1usize, 2, 4, 16, 64] {
+            let latency = scaled.latency(kib * 1024).unwrap();
+            assert_eq!(latency - base, Duration::from_micros(10) * kib as u32);
+        }
+        let step = scaled.latency(2048).unwrap() - scaled.latency(1024).unwrap();
+        assert_eq!(scaled.latency(9 * 1024).unwrap() - scaled.latency(8 * 1024).unwrap(), step);
+
+        let elapsed = |input_len: usize| {
+            let start = Instant::now();
+            sim.run(input_len);
+            start.elapsed()
+        };
+        let small = elapsed(16 * 1024);
+        let large = elapsed(64 * 1024);
+        assert!(small >= Duration::from_micros(3200));
+        assert!(large >= Duration::from_micros(12800));
+
+        let gemm = ComputeSim::ExpertGemm { hidden_size: 8, intermediate_size: 16 };
+        assert_eq!(gemm.latency(1024), None);
+        gemm.run(1024);
+
+        // 无可用GPU时跳过执行器部分
+        let mut executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        assert_eq!(executor.compute_sim(), ComputeSim::default());
+        executor.set_compute_sim(sim);
+        let mut small_task = test_task("sim_small", 16 * 1024);
+        let mut large_task = test_task("sim_large", 64 * 1024);
+        executor.execute_task(&mut small_task).unwrap();
+        executor.execute_task(&mut large_task).unwrap();
+        let kernel = |task_id: &str| executor.get_task_metrics(task_id).unwrap().unwrap().kernel;
+        assert!(kernel("sim_large") > kernel("sim_small"));
+    }
+
+    #[test]
+    fn test_task_timeout_fails_only_slow_task() {
+        let sim = ComputeSim::SizeScaled { base: Duration::ZERO, per_kib: Duration::from_millis(1) };
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(sim.run_until(1024, Some(deadline)));
+        assert!(!sim.run_until(64 * 1024, Some(deadline)));
+        assert!(Instant::now() < deadline + Duration::from_millis(30));
+
+        // 无可用GPU时跳过执行器部分
+        let mut executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        executor.set_compute_sim(sim);
+        executor.set_task_timeout(Some(Duration::from_millis(20)));
+        assert_eq!(executor.task_timeout(), Some(Duration::from_millis(20)));
+
+        let mut tasks = vec![test_task("fast_0", 1024), test_task("slow", 64 * 1024), test_task("fast_1", 1024)];
+        let results = executor.execute_tasks_resilient(&mut tasks, 2);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert!(matches!(results[1], Err(Error::GpuError(_))));
+        assert!(matches!(tasks[0].status, TaskStatus::Completed));
+        assert!(matches!(&tasks[1].status, TaskStatus::Failed(reason) if reason == "timeout"));
+        assert!(tasks[1].result.is_none());
+        assert!(matches!(tasks[2].status, TaskStatus::Completed));
+        // 超时任务的缓冲区和负载已释放
+        assert!(executor.get_load_status().unwrap().values().all(|load| *load == 0.0));
+        assert_eq!(executor.get_memory_stats().unwrap().in_use_bytes, 0);
+
+        // 批量执行时同样保留超时原因
+        let mut tasks = vec![test_task("batch_slow", 64 * 1024)];
+        assert!(matches!(executor.execute_tasks(&mut tasks), Err(Error::GpuError(_))));
+        assert!(matches!(&tasks[0].status, TaskStatus::Failed(reason) if reason == "timeout"));
+
+        executor.set_task_timeout(None);
+        let mut task = test_task("slow_untimed", 64 * 1024);
+        assert!(executor.execute_task(&mut task).is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_non_finite_output() {
+        let values = [1.0f32, f32::NAN, -2.5, f32::INFINITY];
+        let mut output: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        // 末尾不足一个元素的字节保持不变
+        output.push(0xff);
+
+        let mut rejected = output.clone();
+        assert!(matches!(
+            sanitize_non_finite("nan", &mut rejected, NonFinitePolicy::Error),
+            Err(Error::InferenceError(_))
+        ));
+        assert_eq!(rejected, output);
+
+        assert_eq!(sanitize_non_finite("nan", &mut output, NonFinitePolicy::Zero).unwrap(), 2);
+        let sanitized: Vec<f32> = output.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        assert_eq!(sanitized, vec![1.0, 0.0, -2.5, 0.0]);
+        assert_eq!(output.last(), Some(&0xff));
+        assert_eq!(sanitize_non_finite("nan", &mut output, NonFinitePolicy::Zero).unwrap(), 0);
+
+        // 无可用GPU时跳过执行器部分
+        let mut executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        assert_eq!(executor.output_sanitization(), None);
+        executor.sanitize_output(true);
+        assert_eq!(executor.output_sanitization(), Some(NonFinitePolicy::Zero));
+        let mut task = test_task("sanitized", 0);
+        task.input_data = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        let result = executor.execute_task(&mut task).unwrap();
+        assert!(result.chunks_exact(4).all(|c| f32::from_le_bytes(c.try_into().unwrap()).is_finite()));
+
+        executor.set_non_finite_policy(NonFinitePolicy::Error);
+        let mut task = test_task("rejected", 0);
+        task.input_data = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        assert!(matches!(executor.execute_task(&mut task), Err(Error::InferenceError(_))));
+        executor.sanitize_output(false);
+        assert_eq!(executor.output_sanitization(), None);
+    }
+
+    #[test]
+    fn test_metrics_count_tasks_and_bytes() {
+        // 无可用GPU时跳过
+        let mut executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let metrics = Arc::new(Metrics::new());
+        executor.set_metrics(Arc::clone(&metrics));
+
+        let mut tasks: Vec<MoeTask> = [100, 2048, 4096]
+            .iter()
+            .enumerate()
+            .map(|(i, size)| test_task(&format!("metrics_{}", i), *size))
+            .collect();
+        let total_bytes: usize = tasks.iter().map(|task| task.input_len()).sum();
+        executor.execute_tasks(&mut tasks).unwrap();
+
+        let snapshot = executor.metrics().snapshot();
+        assert_eq!(snapshot.task_count, tasks.len());
+        assert_eq!(snapshot.h2d_bytes, total_bytes as u64);
+        assert_eq!(snapshot.d2h_bytes, total_bytes as u64);
+        assert!(snapshot.execute_time > Duration::ZERO);
+        // 各阶段累计耗时等于逐任务耗时之和
+        let timings: Vec<TaskMetrics> = tasks
+            .iter()
+            .map(|task| executor.get_task_metrics(&task.task_id).unwrap().unwrap())
+            .collect();
+        assert_eq!(snapshot.h2d_time, timings.iter().map(|timing| timing.h2d).sum());
+        assert_eq!(snapshot.kernel_time, timings.iter().map(|timing| timing.kernel).sum());
+        assert_eq!(snapshot.d2h_time, timings.iter().map(|timing| timing.d2h).sum());
+        assert_eq!(snapshot.h2d_time + snapshot.kernel_time + snapshot.d2h_time, snapshot.execute_time);
+        assert_eq!(metrics.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_out_of_range_device_is_gpu_error() {
+        match TaskExecutor::new(DeviceId(u32::MAX)) {
+            Err(Error::GpuError(message)) => {
+                assert!(message.contains(&u32::MAX.to_string()), "{}", message);
+                assert!(message.contains("个GPU"), "{}", message);
+            }
+            // 没有CUDA驱动时初始化即失败，跳过
+            Err(Error::CudaError(_)) => {}
+            Err(e) => panic!("期望 GpuError，实际为 {}", e),
+            Ok(_) => panic!("设备 {} 不应存在", u32::MAX),
+        }
+    }
+
+    #[test]
+    fn test_cancelled_task_skips_copy_back() {
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut task = test_task("cancelled", 32);
+        task.cancel_flag = Some(Arc::new(AtomicBool::new(true)));
+        assert!(matches!(executor.execute_task(&mut task), Err(Error::InferenceError(_))));
+        assert!(matches!(&task.status, TaskStatus::Failed(reason) if reason == "cancelled"));
+        assert!(task.result.is_none());
+        // 缓冲区已归还，负载已释放
+        let (_, max_memory) = executor.get_memory_status().unwrap();
+        assert!(max_memory > 0);
+        assert!(executor.get_load_status().unwrap().values().all(|load| *load == 0.0));
+    }
+
+    #[test]
+    fn test_cancelled_task_keeps_reason_in_execute_tasks() {
+        let cancelled_tasks = || {
+            let mut tasks = vec![test_task("cancel_a", 32), test_task("cancel_b", 32)];
+            // 调度器取消已取出的任务时只置位取消标记
+            tasks[0].cancel_flag = Some(Arc::new(AtomicBool::new(true)));
+            tasks
+        };
+        let is_cancelled = |task: &MoeTask| matches!(&task.status, TaskStatus::Failed(reason) if reason == "cancelled");
+
+        let mut tasks = cancelled_tasks();
+        assert!(CpuTaskExecutor::new().execute_tasks(&mut tasks).is_err());
+        assert!(is_cancelled(&tasks[0]));
+
+        // 无可用GPU时跳过执行器部分
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
+        };
+        let mut tasks = cancelled_tasks();
+        assert!(matches!(executor.execute_tasks(&mut tasks), Err(Error::InferenceError(_))));
+        assert!(is_cancelled(&tasks[0]));
+        assert!(tasks[0].result.is_none());
+    }
+
+    #[test]
+    fn test_split_cpu_execute_merge_round_trip() {
+        use crate::config::ModelInfo;
+        use crate::task::TaskPriority;
+        use crate::task_splitter::{SplitStrategy, TaskSplitter};
+
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByBatch { batch_size: 100 }).unwrap();
+        let mut input = (64u32).to_le_bytes().to_vec();
+        input.extend((0..64).flat_map(|i| (i as f32).to_le_bytes()));
+
+        let mut tasks = splitter.split_task(&input, "cpu_round_trip", TaskPriority::Normal).unwrap();
+        assert!(tasks.len() > 1);
+        let executor: &dyn Executor = &CpuTaskExecutor::new();
+        let results = executor.execute_tasks(&mut tasks).unwrap();
+        assert_eq!(results.len(), tasks.len());
+        assert!(tasks.iter().all(|task| matches!(task.status, TaskStatus::Completed)));
+
+        // 结果乱序传入也能按批次ID还原出原始输入
+        tasks.reverse();
+        assert_eq!(splitter.merge_task_results(&tasks, None).unwrap(), input);
+    }
+
+    #[test]
+    fn test_cpu_parallel_execution_preserves_task_order() {
+        use crate::config::ModelInfo;
+        use crate::task_splitter::{SplitStrategy, TaskSplitter};
+
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 4,
+            ..Default::default()
+        };
+        let mut input = (64u32).to_le_bytes().to_vec();
+        input.extend((0..64).flat_map(|i| (i as f32).to_le_bytes()));
+        let config = SchedulerConfig { max_concurrent_tasks: 3, ..SchedulerConfig::default() };
+        let executor = CpuTaskExecutor::new();
+
+        // 8个专家任务互不依赖，并行执行
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+        let mut tasks = splitter.split_task(&input, "parallel", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 8);
+        let dependencies = splitter.get_task_dependencies(&tasks).unwrap();
+        let results = executor.execute_tasks_parallel(&mut tasks, &dependencies, &config).unwrap();
+        assert_eq!(results.len(), 8);
+        for (task, result) in tasks.iter().zip(&results) {
+            assert!(matches!(task.status, TaskStatus::Completed));
+            assert_eq!(result, &task.input_data);
+        }
+
+        // 层任务之间有依赖，串行执行，结果顺序不变
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByLayer).unwrap();
+        let mut tasks = splitter.split_task(&input, "serial", TaskPriority::Normal).unwrap();
+        let dependencies = splitter.get_task_dependencies(&tasks).unwrap();
+        let results = executor.execute_tasks_parallel(&mut tasks, &dependencies, &config).unwrap();
+        assert!(tasks.iter().zip(&results).all(|(task, result)| result == &task.input_data));
+
+        // 并发数为0的配置与调度器一样被拒绝，不会退化为rayon的默认线程数
+        let config = SchedulerConfig { max_concurrent_tasks: 0, ..SchedulerConfig::default() };
+        assert!(matches!(
+            executor.execute_tasks_parallel(&mut tasks, &dependencies, &config),
+            Err(Error::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_execute_with_retries_per_task_outcomes() {
+        use rustacuda::error::CudaError;
+        let mut tasks: Vec<MoeTask> = ["flaky", "bad_input", "always_oom", "ok"]
+            .iter()
+            .map(|task_id| test_task(task_id, 4))
+            .collect();
+
+        // 模拟执行器：flaky 前两次显存不足，bad_input 为不可重试错误，always_oom 一直显存不足
+        let mut attempts: HashMap<String, usize> = HashMap::new();
+        let results = execute_with_retries(&mut tasks, 2, Duration::from_millis(1), |task| {
+            let attempt = attempts.entry(task.task_id.clone()).or_default();
+            *attempt += 1;
+            match (task.task_id.as_str(), *attempt) {
+                ("flaky", 1..=2) | ("always_oom", _) => Err(Error::CudaError(CudaError::OutOfMemory)),
+                ("bad_input", _) => Err(Error::CudaError(CudaError::InvalidValue)),
+                _ => Ok(task.input_data.clone()),
+            }
+        });
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &tasks[0].input_data);
+        assert!(matches!(results[1], Err(Error::CudaError(CudaError::InvalidValue))));
+        assert!(matches!(results[2], Err(Error::CudaError(CudaError::OutOfMemory))));
+        assert!(results[3].is_ok());
+        assert_eq!(attempts["flaky"], 3);
+        assert_eq!(attempts["bad_input"], 1);
+        assert_eq!(attempts["always_oom"], 3);
+        assert!(matches!(tasks[1].status, TaskStatus::Failed(_)));
+        assert!(matches!(tasks[2].status, TaskStatus::Failed(_)));
+    }
+}
@@ -0,0 +1,379 @@
+// task_header.rs
+// 子任务头部编解码，DataPreparator 生成的头部与执行器、拆分校验共用同一套布局。
+use crate::error::{Error, Result};
+use crate::quantization::{self, QuantParams};
+use crate::types::{TokenMetadata, TokenRouting, WeightColumnSlice};
+
+/// 头部魔数，用于识别由本模块编码的任务数据
+pub const HEADER_MAGIC: u8 = 0x4D;
+/// 当前头部格式版本，布局变化时递增
+pub const HEADER_VERSION: u8 = 1;
+
+/// 头部种类
+const KIND_EXPERT: u8 = 1;
+const KIND_LAYER: u8 = 2;
+const KIND_LAYER_EXPERT: u8 = 3;
+
+/// 标志位：头部携带权重列范围（仅专家头部）
+const FLAG_COLUMN_SLICE: u8 = 0x01;
+/// 标志位：头部携带token元数据
+const FLAG_TOKEN_METADATA: u8 = 0x02;
+/// 标志位：负载已量化，头部携带量化参数
+const FLAG_QUANTIZED: u8 = 0x04;
+/// 标志位：头部携带按专家容量拆分的token路由
+const FLAG_TOKEN_ROUTING: u8 = 0x08;
+
+/// 层配置，随层任务下发
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerConfig {
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_experts: usize,
+}
+
+/// 头部描述的任务种类
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskKind {
+    /// 专家任务，可选地只负责部分权重列（张量并行）
+    Expert {
+        expert_id: usize,
+        gate_weights: Vec<f32>,
+        column_slice: Option<WeightColumnSlice>,
+    },
+    /// 层任务
+    Layer {
+        layer_id: usize,
+        config: LayerConfig,
+    },
+    /// 某一层中的某个专家
+    LayerExpert {
+        layer_id: usize,
+        expert_id: usize,
+        gate_weights: Vec<f32>,
+        config: LayerConfig,
+    },
+}
+
+/// 子任务头部
+///
+/// 编码布局（整数均为小端 u32，门控权重为 f32）：
+///
+/// ```text
+/// [magic: u8][version: u8][kind: u8][flags: u8]
+/// 专家:      [expert_id][num_gates][gate_weights...][col_start][col_len]（后两项仅在 FLAG_COLUMN_SLICE 时存在）
+/// 层:        [layer_id][hidden_size][intermediate_size][num_experts]
+/// 层+专家:   [layer_id][expert_id][num_gates][gate_weights...][hidden_size][intermediate_size][num_experts]
+/// [mask_len][attention_mask][pos_len][position_ids]（仅在 FLAG_TOKEN_METADATA 时存在）
+/// [num_tokens][num_kept][kept...][num_dropped][dropped...]（仅在 FLAG_TOKEN_ROUTING 时存在）
+/// [bits: u8][scale: f32]（仅在 FLAG_QUANTIZED 时存在）
+/// [payload]
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskHeader {
+    pub kind: TaskKind,
+    pub token_metadata: Option<TokenMetadata>,
+    /// 按专家容量拆分时的token路由
+    pub token_routing: Option<TokenRouting>,
+    /// 负载的量化参数，未量化时为None
+    pub quantization: Option<QuantParams>,
+}
+
+impl TaskHeader {
+    /// 创建不带token元数据的头部
+    pub fn new(kind: TaskKind) -> Self {
+        Self { kind, token_metadata: None, token_routing: None, quantization: None }
+    }
+
+    /// 专家ID，层任务为None
+    pub fn expert_id(&self) -> Option<usize> {
+        match &self.kind {
+            TaskKind::Expert { expert_id, .. } | TaskKind::LayerExpert { expert_id, .. } => Some(*expert_id),
+            TaskKind::Layer { .. } => None,
+        }
+    }
+
+    /// 层ID，专家任务为None
+    pub fn layer_id(&self) -> Option<usize> {
+        match &self.kind {
+            TaskKind::Layer { layer_id, .. } | TaskKind::LayerExpert { layer_id, .. } => Some(*layer_id),
+            TaskKind::Expert { .. } => None,
+        }
+    }
+
+    /// 门控权重，层任务为None
+    pub fn gate_weights(&self) -> Option<&[f32]> {
+        match &self.kind {
+            TaskKind::Expert { gate_weights, .. } | TaskKind::LayerExpert { gate_weights, .. } => Some(gate_weights),
+            TaskKind::Layer { .. } => None,
+        }
+    }
+}
+
+/// 不带可选字段时层任务头部的编码长度
+pub const LAYER_HEADER_LEN: usize = 4 + 4 * 4;
+
+/// 不带可选字段、门控权重有 num_gates 个时专家任务头部的编码长度
+pub fn expert_header_len(num_gates: usize) -> usize {
+    4 + 2 * 4 + num_gates * 4
+}
+
+/// 编码头部
+pub fn encode(header: &TaskHeader) -> Vec<u8> {
+    let mut flags = 0;
+    if matches!(header.kind, TaskKind::Expert { column_slice: Some(_), .. }) {
+        flags |= FLAG_COLUMN_SLICE;
+    }
+    if header.token_metadata.is_some() {
+        flags |= FLAG_TOKEN_METADATA;
+    }
+    if header.token_routing.is_some() {
+        flags |= FLAG_TOKEN_ROUTING;
+    }
+    if header.quantization.is_some() {
+        flags |= FLAG_QUANTIZED;
+    }
+    let kind = match header.kind {
+        TaskKind::Expert { .. } => KIND_EXPERT,
+        TaskKind::Layer { .. } => KIND_LAYER,
+        TaskKind::LayerExpert { .. } => KIND_LAYER_EXPERT,
+    };
+
+    let mut bytes = vec![HEADER_MAGIC, HEADER_VERSION, kind, flags];
+    let put_u32 = |bytes: &mut Vec<u8>, value: usize| bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    let put_gates = |bytes: &mut Vec<u8>, gates: &[f32]| {
+        put_u32(bytes, gates.len());
+        for gate in gates {
+            bytes.extend_from_slice(&gate.to_le_bytes());
+        }
+    };
+    let put_config = |bytes: &mut Vec<u8>, config: &LayerConfig| {
+        put_u32(bytes, config.hidden_size);
+        put_u32(bytes, config.intermediate_size);
+        put_u32(bytes, config.num_experts);
+    };
+
+    match &header.kind {
+        TaskKind::Expert { expert_id, gate_weights, column_slice } => {
+            put_u32(&mut bytes, *expert_id);
+            put_gates(&mut bytes, gate_weights);
+            if let Some(slice) = column_slice {
+                put_u32(&mut bytes, slice.start);
+                put_u32(&mut bytes, slice.len);
+            }
+        }
+        TaskKind::Layer { layer_id, config } => {
+            put_u32(&mut bytes, *layer_id);
+            put_config(&mut bytes, config);
+        }
+        TaskKind::LayerExpert { layer_id, expert_id, gate_weights, config } => {
+            put_u32(&mut bytes, *layer_id);
+            put_u32(&mut bytes, *expert_id);
+            put_gates(&mut bytes, gate_weights);
+            put_config(&mut bytes, config);
+        }
+    }
+
+    if let Some(metadata) = &header.token_metadata {
+        for section in [&metadata.attention_mask, &metadata.position_ids] {
+            put_u32(&mut bytes, section.len());
+            bytes.extend_from_slice(section);
+        }
+    }
+    if let Some(routing) = &header.token_routing {
+        put_u32(&mut bytes, routing.num_tokens);
+        for tokens in [&routing.kept, &routing.dropped] {
+            put_u32(&mut bytes, tokens.len());
+            for token in tokens {
+                put_u32(&mut bytes, *token);
+            }
+        }
+    }
+    if let Some(params) = &header.quantization {
+        bytes.push(params.bits);
+        bytes.extend_from_slice(&params.scale.to_le_bytes());
+    }
+    bytes
+}
+
+/// 解码头部，返回头部和其后的负载
+pub fn decode(bytes: &[u8]) -> Result<(TaskHeader, &[u8])> {
+    let mut reader = Reader { bytes, offset: 0 };
+    let prefix = reader.take(4, "头部前缀")?;
+    let (magic, version, kind, flags) = (prefix[0], prefix[1], prefix[2], prefix[3]);
+    if magic != HEADER_MAGIC {
+        return Err(Error::InferenceError(format!("任务头部魔数 {:#04x} 无效，期望 {:#04x}", magic, HEADER_MAGIC)));
+    }
+    if version != HEADER_VERSION {
+        return Err(Error::InferenceError(format!("不支持的任务头部版本 {}，当前版本为 {}", version, HEADER_VERSION)));
+    }
+    if flags & !(FLAG_COLUMN_SLICE | FLAG_TOKEN_METADATA | FLAG_TOKEN_ROUTING | FLAG_QUANTIZED) != 0 || (flags & FLAG_COLUMN_SLICE != 0 && kind != KIND_EXPERT) {
+        return Err(Error::InferenceError(format!("任务头部标志位 {:#04x} 无效", flags)));
+    }
+
+    let kind = match kind {
+        KIND_EXPERT => {
+            let expert_id = reader.u32("专家ID")?;
+            let gate_weights = reader.gates()?;
+            let column_slice = if flags & FLAG_COLUMN_SLICE != 0 {
+                Some(WeightColumnSlice { start: reader.u32("权重列起点")?, len: reader.u32("权重列长度")? })
+            } else {
+                None
+            };
+            TaskKind::Expert { expert_id, gate_weights, column_slice }
+        }
+        KIND_LAYER => TaskKind::Layer { layer_id: reader.u32("层ID")?, config: reader.layer_config()? },
+        KIND_LAYER_EXPERT => {
+            let layer_id = reader.u32("层ID")?;
+            let expert_id = reader.u32("专家ID")?;
+            let gate_weights = reader.gates()?;
+            TaskKind::LayerExpert { layer_id, expert_id, gate_weights, config: reader.layer_config()? }
+        }
+        other => return Err(Error::InferenceError(format!("未知的任务头部种类 {}", other))),
+    };
+
+    let token_metadata = if flags & FLAG_TOKEN_METADATA != 0 {
+        let len = reader.u32("attention_mask 长度")?;
+        let attention_mask = reader.take(len, "attention_mask")?.to_vec();
+        let len = reader.u32("position_ids 长度")?;
+        let position_ids = reader.take(len, "position_ids")?.to_vec();
+        Some(TokenMetadata { attention_mask, position_ids })
+    } else {
+        None
+    };
+
+    let token_routing = if flags & FLAG_TOKEN_ROUTING != 0 {
+        let num_tokens = reader.u32("token总数")?;
+        let kept = reader.tokens("保留的token")?;
+        let dropped = reader.tokens("丢弃的token")?;
+        Some(TokenRouting { num_tokens, kept, dropped })
+    } else {
+        None
+    };
+
+    let quantization = if flags & FLAG_QUANTIZED != 0 {
+        let bits = reader.take(1, "量化位宽")?[0];
+        quantization::check_bits(bits)?;
+        let scale = f32::from_le_bytes(reader.take(4, "量化缩放因子")?.try_into().unwrap());
+        Some(QuantParams { bits, scale })
+    } else {
+        None
+    };
+
+    Ok((TaskHeader { kind, token_metadata, token_routing, quantization }, &bytes[reader.offset..]))
+}
+
+/// 按顺序读取头部字段，越界时返回带字段名的错误
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, field: &str) -> Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.offset..self.offset.saturating_add(len)).ok_or_else(|| {
+            Error::InferenceError(format!(
+                "任务头部在 {} 处被截断：需要 {} 字节，剩余 {} 字节",
+                field, len, self.bytes.len() - self.offset
+            ))
+        })?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self, field: &str) -> Result<usize> {
+        Ok(u32::from_le_bytes(self.take(4, field)?.try_into().unwrap()) as usize)
+    }
+
+    fn gates(&mut self) -> Result<Vec<f32>> {
+        let num_gates = self.u32("门控权重个数")?;
+        let bytes = self.take(num_gates.saturating_mul(4), "门控权重")?;
+        Ok(bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap())).collect())
+    }
+
+    fn tokens(&mut self, field: &str) -> Result<Vec<usize>> {
+        let count = self.u32(field)?;
+        let bytes = self.take(count.saturating_mul(4), field)?;
+        Ok(bytes.chunks_exact(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()) as usize).collect())
+    }
+
+    fn layer_config(&mut self) -> Result<LayerConfig> {
+        Ok(LayerConfig {
+            hidden_size: self.u32("隐藏层大小")?,
+            intermediate_size: self.u32("中间层大小")?,
+            num_experts: self.u32("专家数量")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: LayerConfig = LayerConfig { hidden_size: 512, intermediate_size: 2048, num_experts: 4 };
+
+    fn round_trip(header: TaskHeader) {
+        let payload = [1u8, 2, 3, 4, 5];
+        let mut bytes = encode(&header);
+        bytes.extend_from_slice(&payload);
+        let (decoded, rest) = decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(rest, &payload[..]);
+    }
+
+    #[test]
+    fn test_expert_header_round_trip() {
+        let gate_weights = vec![0.0, 0.7, 0.0, 0.0];
+        let header = TaskHeader::new(TaskKind::Expert { expert_id: 1, gate_weights: gate_weights.clone(), column_slice: None });
+        assert_eq!(encode(&header).len(), expert_header_len(gate_weights.len()));
+        round_trip(TaskHeader::new(TaskKind::Expert { expert_id: 1, gate_weights: gate_weights.clone(), column_slice: None }));
+        round_trip(TaskHeader::new(TaskKind::Expert {
+            expert_id: 3,
+            gate_weights,
+            column_slice: Some(WeightColumnSlice { start: 512, len: 512 }),
+        }));
+    }
+
+    #[test]
+    fn test_layer_header_round_trip() {
+        assert_eq!(encode(&TaskHeader::new(TaskKind::Layer { layer_id: 5, config: CONFIG })).len(), LAYER_HEADER_LEN);
+        round_trip(TaskHeader::new(TaskKind::Layer { layer_id: 5, config: CONFIG }));
+        round_trip(TaskHeader {
+            kind: TaskKind::Layer { layer_id: 0, config: CONFIG },
+            token_metadata: Some(TokenMetadata { attention_mask: vec![1, 1, 0], position_ids: vec![0; 12] }),
+            token_routing: None,
+            quantization: Some(QuantParams { bits: 4, scale: 0.25 }),
+        });
+    }
+
+    #[test]
+    fn test_layer_expert_header_round_trip() {
+        let header = TaskHeader::new(TaskKind::LayerExpert {
+            layer_id: 2,
+            expert_id: 3,
+            gate_weights: vec![0.0, 0.0, 0.0, 1.0],
+            config: CONFIG,
+        });
+        assert_eq!(header.layer_id(), Some(2));
+        assert_eq!(header.expert_id(), Some(3));
+        round_trip(header);
+    }
+
+    #[test]
+    fn test_token_routing_round_trip() {
+        let mut header = TaskHeader::new(TaskKind::Expert { expert_id: 0, gate_weights: vec![1.0, 0.0], column_slice: None });
+        header.token_routing = Some(TokenRouting { num_tokens: 6, kept: vec![0, 2], dropped: vec![3, 5] });
+        round_trip(header);
+    }
+
+    #[test]
+    fn test_decode_rejects_foreign_or_truncated_data() {
+        let bytes = encode(&TaskHeader::new(TaskKind::Layer { layer_id: 1, config: CONFIG }));
+        // 旧格式数据：直接以u32 ID开头
+        assert!(decode(&1u32.to_le_bytes()).is_err());
+        let mut newer = bytes.clone();
+        newer[1] = HEADER_VERSION + 1;
+        assert!(decode(&newer).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&[]).is_err());
+    }
+}
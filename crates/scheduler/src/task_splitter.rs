@@ -2,17 +2,23 @@
 // 任务拆分器，负责将MOE任务按专家、层、批次等策略拆分为多个子任务。
 use crate::config::ModelInfo;
 use crate::error::{Error, Result};
-use crate::task::{MoeTask, TaskPriority, TaskStatus};
+use log::{debug, info, warn};
+use crate::task::{MoeTask, TaskId, TaskPriority, TaskStatus};
 use crate::types::*;
 use crate::data_preparator::DataPreparator;
 use crate::result_merger::ResultMerger;
+use crate::task_header::{self, TaskHeader};
+use crate::metrics::Metrics;
+#[cfg(feature = "tch")]
+use crate::model_def::switch_transformer::SwitchTransformersSparseMLP;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
 use std::path::Path;
 use std::fs::File;
 use crate::config::ModelConfigJson;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Instant;
 
 // 常量定义，避免硬编码
 const EXPERT_ID_SIZE: usize = 4;
@@ -24,10 +30,20 @@ const GATE_WEIGHT_SIZE: usize = 4;
 pub enum SplitStrategy {
     /// 按专家拆分：每个专家一个任务
     ByExpert,
+    /// 按门控权重选出 top_k 个专家，只为被选中的专家创建任务
+    ByTopKExpert { top_k: usize },
     /// 按层拆分：每个MOE层一个任务
     ByLayer,
     /// 按批次拆分：将输入分批处理
     ByBatch { batch_size: usize },
+    /// 滑动窗口拆分：每个窗口 window 字节，相邻窗口的起点相隔 stride 字节，
+    /// stride 小于 window 时相邻窗口重叠，合并时去掉重叠部分
+    ByWindow { window: usize, stride: usize },
+    /// 张量并行：每个专家沿中间层维度切成 num_shards 个连续的权重列范围，每个分片一个任务
+    ByTensorParallel { num_shards: usize },
+    /// 按路由结果拆分并限制专家容量：每个专家最多处理 ceil(capacity_factor × token数 / 专家数) 个token，
+    /// 超出容量的token被丢弃，合并时输出为0（与 Switch Transformer 的 expert_capacity 一致）
+    ByExpertCapacity { capacity_factor: f32 },
     /// 混合策略：结合多种拆分方式
     Hybrid { 
         expert_split: bool, 
@@ -44,52 +60,151 @@ impl SplitStrategy {
         match self {
             SplitStrategy::ByExpert => {
                 if model_info.num_experts == 0 {
-                    return Err(Error::InferenceError("专家数量不能为0".to_string()));
+                    return Err(Error::ConfigError("num_experts: 专家数量不能为0".to_string()));
+                }
+            }
+            SplitStrategy::ByTopKExpert { top_k } => {
+                if *top_k == 0 || *top_k > model_info.num_experts {
+                    return Err(Error::ConfigError(format!(
+                        "top_k: top_k {} 必须在 [1, {}] 范围内", top_k, model_info.num_experts
+                    )));
                 }
             }
             SplitStrategy::ByLayer => {
                 if model_info.num_layers == 0 {
-                    return Err(Error::InferenceError("层数不能为0".to_string()));
+                    return Err(Error::ConfigError("num_layers: 层数不能为0".to_string()));
+                }
+            }
+            SplitStrategy::ByExpertCapacity { capacity_factor } => {
+                if model_info.num_experts == 0 {
+                    return Err(Error::ConfigError("num_experts: 专家数量不能为0".to_string()));
+                }
+                if !(capacity_factor.is_finite() && *capacity_factor > 0.0) {
+                    return Err(Error::ConfigError(format!(
+                        "capacity_factor: 容量系数 {} 必须为正数", capacity_factor
+                    )));
                 }
             }
             SplitStrategy::ByBatch { batch_size } => {
                 if *batch_size == 0 {
-                    return Err(Error::InferenceError("批次大小不能为0".to_string()));
+                    return Err(Error::ConfigError("batch_size: 批次大小不能为0".to_string()));
                 }
                 if *batch_size > model_info.hidden_size * 4 {
-                    return Err(Error::InferenceError("批次大小过大，可能导致内存溢出".to_string()));
+                    return Err(Error::ConfigError(format!(
+                        "batch_size: 批次大小 {} 过大（上限 {}），可能导致内存溢出",
+                        batch_size, model_info.hidden_size * 4
+                    )));
+                }
+            }
+            SplitStrategy::ByWindow { window, stride } => {
+                if *stride == 0 || stride > window {
+                    return Err(Error::ConfigError(format!(
+                        "stride: 步长 {} 必须在 [1, 窗口大小 {}] 范围内", stride, window
+                    )));
+                }
+            }
+            SplitStrategy::ByTensorParallel { num_shards } => {
+                if model_info.num_experts == 0 {
+                    return Err(Error::ConfigError("num_experts: 专家数量不能为0".to_string()));
+                }
+                if *num_shards == 0 || !model_info.intermediate_size.is_multiple_of(*num_shards) {
+                    return Err(Error::ConfigError(format!(
+                        "num_shards: 分片数 {} 必须大于0且能整除中间层大小 {}",
+                        num_shards, model_info.intermediate_size
+                    )));
                 }
             }
             SplitStrategy::Hybrid { expert_split, layer_split, batch_size, expert_ratio, layer_ratio } => {
                 if !expert_split && !layer_split {
-                    return Err(Error::InferenceError("混合策略至少需要启用一种拆分方式".to_string()));
+                    return Err(Error::ConfigError("expert_split/layer_split: 混合策略至少需要启用一种拆分方式".to_string()));
                 }
                 if *batch_size == 0 {
-                    return Err(Error::InferenceError("批次大小不能为0".to_string()));
-                }
-                if *expert_ratio < 0.0 || *expert_ratio > 1.0 {
-                    return Err(Error::InferenceError("专家拆分比例必须在0.0-1.0之间".to_string()));
-                }
-                if *layer_ratio < 0.0 || *layer_ratio > 1.0 {
-                    return Err(Error::InferenceError("层拆分比例必须在0.0-1.0之间".to_string()));
+                    return Err(Error::ConfigError("batch_size: 批次大小不能为0".to_string()));
                 }
                 if *expert_split && model_info.num_experts == 0 {
-                    return Err(Error::InferenceError("专家数量不能为0".to_string()));
+                    return Err(Error::ConfigError("num_experts: 专家数量不能为0".to_string()));
                 }
                 if *layer_split && model_info.num_layers == 0 {
-                    return Err(Error::InferenceError("层数不能为0".to_string()));
+                    return Err(Error::ConfigError("num_layers: 层数不能为0".to_string()));
                 }
+                Self::validate_ratio("expert_ratio", "专家", *expert_ratio, *expert_split, model_info.num_experts)?;
+                Self::validate_ratio("layer_ratio", "层", *layer_ratio, *layer_split, model_info.num_layers)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 验证混合策略的拆分比例
+    /// 启用的拆分方式比例必须在 (0.0, 1.0] 内且按比例至少保留一个，未启用的只要求在 [0.0, 1.0] 内
+    fn validate_ratio(field: &str, name: &str, ratio: f32, enabled: bool, total: usize) -> Result<()> {
+        if !enabled {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(Error::ConfigError(format!("{}: {}拆分比例 {} 必须在0.0-1.0之间", field, name, ratio)));
             }
+            return Ok(());
+        }
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err(Error::ConfigError(format!("{}: {}拆分比例 {} 必须在 (0.0, 1.0] 范围内", field, name, ratio)));
+        }
+        if (total as f32 * ratio).round() as usize == 0 {
+            return Err(Error::ConfigError(format!(
+                "{}: {}拆分比例 {} 乘以总数 {} 四舍五入后为0", field, name, ratio, total
+            )));
         }
         Ok(())
     }
 
+    /// 不实际拆分，按算术估算 input_len 字节的输入会拆出的任务数
+    ///
+    /// 按拆分器的默认设置估算：不含解码器层、不附带token元数据、元素为f32；
+    /// ByExpertCapacity 的任务数取决于路由结果，返回上限 min(专家数, token数)。
+    /// 参数无效、拆分会失败时返回0
+    pub fn estimate_task_count(&self, model_info: &ModelInfo, input_len: usize) -> usize {
+        // 批次边界与 TaskSplitter::aligned_batch_size 一样对齐到元素宽度
+        let num_batches = |len: usize, batch_size: usize| match batch_size - batch_size % ELEMENT_SIZE {
+            0 => 0,
+            batch_size => len.div_ceil(batch_size),
+        };
+        match self {
+            SplitStrategy::ByExpert => model_info.num_experts,
+            SplitStrategy::ByTopKExpert { top_k } => (*top_k).min(model_info.num_experts),
+            SplitStrategy::ByLayer => model_info.num_layers,
+            SplitStrategy::ByBatch { batch_size } => num_batches(input_len, *batch_size),
+            SplitStrategy::ByWindow { window, stride } => num_windows(input_len, *window, *stride),
+            SplitStrategy::ByTensorParallel { num_shards } => model_info.num_experts * num_shards,
+            SplitStrategy::ByExpertCapacity { .. } => {
+                let row_size = model_info.hidden_size * ELEMENT_SIZE;
+                let num_tokens = input_len.saturating_sub(INPUT_HEADER_SIZE).checked_div(row_size).unwrap_or(0);
+                model_info.num_experts.min(num_tokens)
+            }
+            SplitStrategy::Hybrid { expert_split, layer_split, batch_size, expert_ratio, layer_ratio } => {
+                let num_experts = ratio_count(model_info.num_experts, *expert_ratio);
+                let num_layers = ratio_count(model_info.num_layers, *layer_ratio);
+                match (*expert_split, *layer_split, *batch_size > 0) {
+                    (true, true, _) => num_layers * num_experts,
+                    // 专家、层任务带上头部后再按批次拆分
+                    (true, false, true) => {
+                        num_experts * num_batches(task_header::expert_header_len(model_info.num_experts) + input_len, *batch_size)
+                    }
+                    (false, true, true) => num_layers * num_batches(task_header::LAYER_HEADER_LEN + input_len, *batch_size),
+                    (true, false, false) => num_experts,
+                    (false, true, false) => num_layers,
+                    (false, false, _) => num_batches(input_len, *batch_size),
+                }
+            }
+        }
+    }
+
     /// 获取策略描述
     pub fn description(&self) -> String {
         match self {
             SplitStrategy::ByExpert => "按专家拆分".to_string(),
+            SplitStrategy::ByTopKExpert { top_k } => format!("按Top-K专家拆分 (top_k: {})", top_k),
             SplitStrategy::ByLayer => "按层拆分".to_string(),
             SplitStrategy::ByBatch { batch_size } => format!("按批次拆分 (批次大小: {})", batch_size),
+            SplitStrategy::ByWindow { window, stride } => format!("滑动窗口拆分 (窗口: {}, 步长: {})", window, stride),
+            SplitStrategy::ByTensorParallel { num_shards } => format!("张量并行拆分 (分片数: {})", num_shards),
+            SplitStrategy::ByExpertCapacity { capacity_factor } => format!("按专家容量拆分 (容量系数: {})", capacity_factor),
             SplitStrategy::Hybrid { expert_split, layer_split, batch_size, expert_ratio, layer_ratio } => {
                 let mut parts = Vec::new();
                 if *expert_split {
@@ -105,6 +220,77 @@ impl SplitStrategy {
     }
 }
 
+/// 拆分出的专家任务与模型真实路由结果的差异
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingDiff {
+    /// 有任务但未被路由选中的专家（浪费的计算）
+    pub extra: BTreeSet<usize>,
+    /// 被路由选中但没有任务的专家
+    pub missing: BTreeSet<usize>,
+    /// 既有任务又被路由选中的专家
+    pub matched: BTreeSet<usize>,
+}
+
+/// 每个专家最多处理的token数：ceil(capacity_factor × num_tokens / num_experts)
+pub fn expert_capacity(capacity_factor: f32, num_tokens: usize, num_experts: usize) -> usize {
+    (capacity_factor as f64 * num_tokens as f64 / num_experts as f64).ceil() as usize
+}
+
+/// 混合策略按比例实际使用的数量：round(total * ratio)，total 非0时至少为1
+/// 拆分器和合并器共用，保证两边对任务数量的预期一致
+pub(crate) fn ratio_count(total: usize, ratio: f32) -> usize {
+    let count = (total as f32 * ratio).round() as usize;
+    count.clamp(total.min(1), total)
+}
+
+/// 覆盖 `total` 字节所需的滑动窗口数，最后一个窗口可能不满
+pub(crate) fn num_windows(total: usize, window: usize, stride: usize) -> usize {
+    if total <= window {
+        1
+    } else {
+        (total - window).div_ceil(stride) + 1
+    }
+}
+
+/// safetensors 文件头允许的最大字节数，超出时视为文件损坏
+const MAX_SAFETENSORS_HEADER_LEN: u64 = 100 * 1024 * 1024;
+
+/// safetensors 文件头中单个张量的描述，data_offsets 相对数据区起点
+#[derive(Debug, Deserialize)]
+struct SafetensorsEntry {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: [u64; 2],
+}
+
+/// 读取 safetensors 文件头：[header_len: u64][JSON header][data]
+/// 返回张量名到描述的映射和数据区在文件中的起始偏移
+fn read_safetensors_header(file: &mut File) -> Result<(HashMap<String, SafetensorsEntry>, u64)> {
+    let mut len_bytes = [0u8; 8];
+    file.read_exact(&mut len_bytes)
+        .map_err(|e| Error::ConfigError(format!("读取 safetensors 头部长度失败: {}", e)))?;
+    let header_len = u64::from_le_bytes(len_bytes);
+    if header_len > MAX_SAFETENSORS_HEADER_LEN {
+        return Err(Error::ConfigError(format!("safetensors 头部长度 {} 过大", header_len)));
+    }
+    let mut header = vec![0u8; header_len as usize];
+    file.read_exact(&mut header)
+        .map_err(|e| Error::ConfigError(format!("读取 safetensors 头部失败: {}", e)))?;
+
+    let mut entries: HashMap<String, serde_json::Value> = serde_json::from_slice(&header)
+        .map_err(|e| Error::ConfigError(format!("解析 safetensors 头部失败: {}", e)))?;
+    entries.remove("__metadata__");
+    let entries = entries
+        .into_iter()
+        .map(|(name, value)| {
+            let entry = serde_json::from_value(value)
+                .map_err(|e| Error::ConfigError(format!("safetensors 张量 {} 的描述无效: {}", name, e)))?;
+            Ok((name, entry))
+        })
+        .collect::<Result<_>>()?;
+    Ok((entries, 8 + header_len))
+}
+
 /// 任务拆分器，负责将MOE模型推理任务拆分为多个子任务
 /// 模型信息：用于标识模型类型、专家数量、隐藏层大小、中间层大小、层数等。
 /// 拆分策略：用于标识拆分策略，如按专家、按层、按批次、混合策略等。
@@ -119,6 +305,20 @@ pub struct TaskSplitter {
     pub data_preparator: Arc<DataPreparator>,
     /// 结果合并器
     pub result_merger: Arc<ResultMerger>,
+    /// 输入张量形状（如 [batch, seq_len, hidden]），用于计算最小输入大小
+    pub input_shape: Option<Vec<usize>>,
+    /// 输入数据布局，设置后输入大小必须与布局声明的形状和元素类型完全一致
+    pub input_layout: Option<InputLayout>,
+    /// 调用方给定的输入总字节数（含头部），设置后输入大小必须与之完全一致，优先于布局和形状
+    pub expected_input_len: Option<usize>,
+    /// 按专家拆分时是否让各子任务共享同一份输入主体
+    pub dedup_payloads: bool,
+    /// 按层拆分时是否在编码器层之后继续覆盖解码器层
+    pub include_decoder_layers: bool,
+    /// 注意力掩码和位置ID，设置后写入按专家、按层拆分的子任务头部
+    pub token_metadata: Option<TokenMetadata>,
+    /// 耗时统计，设置后记录每次拆分与合并的耗时
+    pub metrics: Option<Arc<Metrics>>,
 }
 
 /// 任务拆分器实现
@@ -136,9 +336,116 @@ impl TaskSplitter {
             strategy,
             data_preparator,
             result_merger,
+            input_shape: None,
+            input_layout: None,
+            expected_input_len: None,
+            dedup_payloads: false,
+            include_decoder_layers: false,
+            token_metadata: None,
+            metrics: None,
         })
     }
 
+    /// 设置按专家拆分时是否对输入去重
+    /// 启用后各专家任务的 input_data 只包含头部，公共输入通过 shared_input 共享同一份内存
+    pub fn set_dedup_payloads(&mut self, dedup_payloads: bool) {
+        self.dedup_payloads = dedup_payloads;
+    }
+
+    /// 设置按层拆分时是否覆盖解码器层，默认只拆分编码器层
+    /// 启用后解码器层排在编码器层之后，头部层ID按全局编号写入，任务ID使用 decoder_layer 标记
+    pub fn set_include_decoder_layers(&mut self, include_decoder_layers: bool) {
+        self.include_decoder_layers = include_decoder_layers;
+    }
+
+    /// 设置逐token的注意力掩码和位置ID
+    ///
+    /// 按专家（含 `ByTopKExpert`）和按层拆分时，元数据写入各子任务的头部，
+    /// 可用 `task_header::decode` 从任务数据中取回。
+    pub fn set_token_metadata(&mut self, metadata: TokenMetadata) {
+        self.token_metadata = Some(metadata);
+    }
+
+    /// 设置耗时统计收集器，可与执行器共用同一个收集器
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// 在头部中带上token元数据（如有），编码后追加输入数据
+    fn with_token_metadata(&self, mut header: TaskHeader, body: &[u8]) -> Vec<u8> {
+        header.token_metadata = self.token_metadata.clone();
+        DataPreparator::encode_with_payload(&header, body)
+    }
+
+    /// 按层拆分时的层数
+    fn num_split_layers(&self) -> usize {
+        if self.include_decoder_layers {
+            self.model_info.total_layers()
+        } else {
+            self.model_info.num_layers
+        }
+    }
+
+    /// 全局层编号对应的子任务ID，超出编码器层数的部分属于解码器
+    fn layer_task_id(&self, parent_task_id: &str, layer_id: usize) -> TaskId {
+        let id = TaskId::new(parent_task_id);
+        match layer_id.checked_sub(self.model_info.num_layers) {
+            Some(decoder_layer_id) => id.with_decoder_layer(decoder_layer_id),
+            None => id.with_layer(layer_id),
+        }
+    }
+
+    /// 设置输入张量形状，设置后最小输入大小按形状各维乘积计算
+    pub fn set_input_shape(&mut self, shape: Vec<usize>) {
+        self.input_shape = Some(shape);
+    }
+
+    /// 设置输入数据布局，设置后按布局精确校验输入大小，优先于 set_input_shape
+    /// 子任务输出与输入的元素类型相同，结果合并器也按该类型合并
+    pub fn set_input_layout(&mut self, layout: InputLayout) {
+        let mut result_merger = ResultMerger::new(self.model_info.clone());
+        result_merger.set_dtype(layout.dtype);
+        self.result_merger = Arc::new(result_merger);
+        self.input_layout = Some(layout);
+    }
+
+    /// 设置输入的总字节数（含头部），用于布局无法描述的输入；传入None恢复按布局或形状校验
+    pub fn set_expected_input_len(&mut self, expected_len: Option<usize>) {
+        self.expected_input_len = expected_len;
+    }
+
+    /// 计算输入数据的最小字节数：头部 + 元素个数 × 元素宽度
+    /// 给定了输入大小时即为该大小；设置了布局时为布局声明的大小；未设置形状时按单个token（hidden_size 个f32元素）计算
+    pub fn min_input_size(&self) -> usize {
+        if let Some(expected_len) = self.expected_input_len {
+            return expected_len;
+        }
+        if let Some(layout) = &self.input_layout {
+            return INPUT_HEADER_SIZE + layout.byte_len();
+        }
+        let num_elements: usize = match &self.input_shape {
+            Some(shape) => shape.iter().product(),
+            None => self.model_info.hidden_size,
+        };
+        INPUT_HEADER_SIZE + num_elements * ELEMENT_SIZE
+    }
+
+    /// 单个元素的字节数：设置了布局时按布局的 dtype，否则按f32
+    fn element_size(&self) -> usize {
+        self.input_layout.map_or(ELEMENT_SIZE, |layout| layout.dtype.size())
+    }
+
+    /// 将批次大小向下取整为元素宽度的整数倍，保证批次边界不会把一个元素切成两半
+    pub fn aligned_batch_size(&self, batch_size: usize) -> Result<usize> {
+        let element_size = self.element_size();
+        if batch_size < element_size {
+            return Err(Error::ConfigError(format!(
+                "batch_size: 批次大小 {} 小于元素宽度 {} 字节", batch_size, element_size
+            )));
+        }
+        Ok(batch_size - batch_size % element_size)
+    }
+
     /// 从模型目录自动读取 config.json 并初始化 ModelInfo
     /// 如果 config.json 不存在则返回错误
     pub fn new_from_model_dir(model_dir: &str, strategy: SplitStrategy) -> Result<Self> {
@@ -153,54 +460,421 @@ impl TaskSplitter {
         let mut contents = String::new();
         file.read_to_string(&mut contents)
             .map_err(|e| Error::ConfigError(format!("读取 config.json 失败: {}", e)))?;
-        // 解析 json
-        let config_json: ModelConfigJson = serde_json::from_str(&contents)
-            .map_err(|e| Error::ConfigError(format!("解析 config.json 失败: {}", e)))?;
+        // 解析 json，解析失败经由 From<serde_json::Error> 转为 ConfigError
+        let config_json: ModelConfigJson = serde_json::from_str(&contents)?;
         // 转换为 ModelInfo
-        let model_info = ModelInfo::from(config_json);
+        let model_info = ModelInfo::try_from(config_json)?;
         // 调用原有构造方法
         Self::new(model_info, strategy)
     }
 
     /// 拆分MOE任务
     pub fn split_task(&self, input_data: &[u8], task_id: &str, priority: TaskPriority) -> Result<Vec<MoeTask>> {
+        self.split_task_with_stream_base(input_data, task_id, priority, 0)
+    }
+
+    /// 拆分MOE任务，子任务的流ID从 `stream_id_base` 开始编号
+    ///
+    /// 执行器按流ID区分逻辑流（可对应独立的CUDA Stream），同一流上的任务会串行执行。
+    /// 并发拆分多个独立请求时，应为每个请求分配互不重叠的流ID区间（如 0、100、200...），
+    /// 避免不同请求的同号子任务（如各自的0号专家）落到同一个流上相互阻塞。
+    pub fn split_task_with_stream_base(
+        &self,
+        input_data: &[u8],
+        task_id: &str,
+        priority: TaskPriority,
+        stream_id_base: usize,
+    ) -> Result<Vec<MoeTask>> {
+        let mut tasks = self.split_task_inner(input_data, task_id, priority, None)?;
+        if stream_id_base > 0 {
+            for task in tasks.iter_mut() {
+                task.stream_id = task.stream_id.map(|id| id + stream_id_base);
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// 根据门控权重拆分MOE任务
+    ///
+    /// `ByTopKExpert` 策略只为权重最大的 top_k 个专家创建任务；`ByExpert` 和 `ByTopKExpert`
+    /// 会把各专家真实的门控权重写入任务头部，其他策略忽略门控权重。
+    pub fn split_task_with_gates(
+        &self,
+        input_data: &[u8],
+        task_id: &str,
+        priority: TaskPriority,
+        gate_weights: &GateWeights,
+    ) -> Result<Vec<MoeTask>> {
+        self.split_task_inner(input_data, task_id, priority, Some(gate_weights))
+    }
+
+    /// 按每个token的路由结果拆分，只适用于 `ByExpertCapacity` 策略
+    ///
+    /// `assignments[t]` 为第 t 个token选中的专家。每个专家按token顺序最多保留容量个token，
+    /// 任务负载只包含保留的token，头部记录保留和丢弃的token下标；没有token路由到的专家不创建任务。
+    pub fn split_task_with_assignments(
+        &self,
+        input_data: &[u8],
+        task_id: &str,
+        priority: TaskPriority,
+        assignments: &[usize],
+    ) -> Result<Vec<MoeTask>> {
+        let capacity_factor = match &self.strategy {
+            SplitStrategy::ByExpertCapacity { capacity_factor } => *capacity_factor,
+            other => {
+                return Err(Error::InferenceError(format!(
+                    "split_task_with_assignments 只适用于 ByExpertCapacity 策略，当前为 {}", other.description()
+                )))
+            }
+        };
+        let start = Instant::now();
+        self.validate_input_data(input_data)?;
+
+        let hidden_size = self.model_info.hidden_size;
+        let body = &input_data[INPUT_HEADER_SIZE..];
+        let num_elements = u32::from_le_bytes(input_data[..INPUT_HEADER_SIZE].try_into().unwrap()) as usize;
+        if body.len() != num_elements * ELEMENT_SIZE || !num_elements.is_multiple_of(hidden_size) {
+            return Err(Error::InferenceError(format!(
+                "输入元素个数 {} 与数据大小 {} 或隐藏层大小 {} 不匹配", num_elements, body.len(), hidden_size
+            )));
+        }
+        let num_tokens = num_elements / hidden_size;
+        if assignments.len() != num_tokens {
+            return Err(Error::InferenceError(format!(
+                "路由结果个数 {} 与token数 {} 不一致", assignments.len(), num_tokens
+            )));
+        }
+        let num_experts = self.model_info.num_experts;
+        if let Some(expert_id) = assignments.iter().find(|expert_id| **expert_id >= num_experts) {
+            return Err(Error::InferenceError(format!("专家ID {} 超出范围 [0, {})", expert_id, num_experts)));
+        }
+
+        let capacity = expert_capacity(capacity_factor, num_tokens, num_experts);
+        let mut routings = vec![TokenRouting { num_tokens, ..TokenRouting::default() }; num_experts];
+        for (token, &expert_id) in assignments.iter().enumerate() {
+            let routing = &mut routings[expert_id];
+            if routing.kept.len() < capacity {
+                routing.kept.push(token);
+            } else {
+                routing.dropped.push(token);
+            }
+        }
+
+        let row_size = hidden_size * ELEMENT_SIZE;
+        let mut tasks = Vec::new();
+        for (expert_id, routing) in routings.into_iter().enumerate() {
+            if routing.kept.is_empty() && routing.dropped.is_empty() {
+                continue;
+            }
+            let mut payload = ((routing.kept.len() * hidden_size) as u32).to_le_bytes().to_vec();
+            for &token in &routing.kept {
+                payload.extend_from_slice(&body[token * row_size..(token + 1) * row_size]);
+            }
+            let mut header = self.data_preparator.expert_header(expert_id, 1.0)?;
+            header.token_routing = Some(routing);
+            let mut expert_data = task_header::encode(&header);
+            expert_data.extend_from_slice(&payload);
+
+            tasks.push(MoeTask {
+                stream_id: Some(expert_id),
+                parent_task_id: Some(task_id.to_string()),
+                ..MoeTask::new(TaskId::new(task_id).with_expert(expert_id).to_string(), expert_data, priority)
+            });
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record_split(start.elapsed());
+        }
+        info!("按专家容量 {} 拆分为 {} 个任务", capacity, tasks.len());
+        Ok(tasks)
+    }
+
+    /// 惰性拆分MOE任务，逐个产出子任务而不一次性构建完整的任务列表
+    ///
+    /// 按专家、按层以及“专家+层”混合拆分时，所有子任务的 input_data 只包含各自的头部，
+    /// 公共输入通过同一个 `Arc<[u8]>` 共享；按批次拆分时每个子任务只持有自己的批次数据。
+    /// 带批次子拆分的混合策略和 `ByTopKExpert` 无法惰性生成，会退化为先完整拆分再逐个产出。
+    pub fn split_task_iter(
+        &self,
+        input_data: &[u8],
+        task_id: &str,
+        priority: TaskPriority,
+    ) -> Box<dyn Iterator<Item = Result<MoeTask>> + '_> {
+        if let Err(e) = self.validate_input_data(input_data) {
+            return Box::new(std::iter::once(Err(e)));
+        }
+        let parent_task_id = task_id.to_string();
+
+        match &self.strategy {
+            SplitStrategy::ByExpert => {
+                let shared: Arc<[u8]> = Arc::from(input_data);
+                Box::new((0..self.model_info.num_experts).map(move |expert_id| {
+                    let header = self.with_token_metadata(self.data_preparator.expert_header(expert_id, 1.0)?, &[]);
+                    let task_id = TaskId::new(&parent_task_id).with_expert(expert_id).to_string();
+                    Ok(self.shared_input_task(task_id, &parent_task_id, header, &shared, priority, expert_id))
+                }))
+            }
+            SplitStrategy::ByLayer => {
+                let shared: Arc<[u8]> = Arc::from(input_data);
+                Box::new((0..self.num_split_layers()).map(move |layer_id| {
+                    let header = self.with_token_metadata(self.data_preparator.layer_header(layer_id)?, &[]);
+                    let task_id = self.layer_task_id(&parent_task_id, layer_id).to_string();
+                    Ok(self.shared_input_task(task_id, &parent_task_id, header, &shared, priority, layer_id))
+                }))
+            }
+            SplitStrategy::ByBatch { batch_size } => {
+                let batch_size = match self.aligned_batch_size(*batch_size) {
+                    Ok(batch_size) => batch_size,
+                    Err(e) => return Box::new(std::iter::once(Err(e))),
+                };
+                let input: Arc<[u8]> = Arc::from(input_data);
+                let num_batches = input.len().div_ceil(batch_size);
+                Box::new((0..num_batches).map(move |batch_id| {
+                    let start = batch_id * batch_size;
+                    let end = std::cmp::min(start + batch_size, input.len());
+                    let mut batch_data = input[start..end].to_vec();
+                    let valid_len = batch_data.len();
+                    batch_data.resize(batch_size, 0);
+                    Ok(MoeTask {
+                        stream_id: Some(batch_id),
+                        parent_task_id: Some(parent_task_id.clone()),
+                        valid_len: Some(valid_len),
+                        ..MoeTask::new(TaskId::new(&parent_task_id).with_batch(batch_id).to_string(), batch_data, priority)
+                    })
+                }))
+            }
+            SplitStrategy::Hybrid { expert_split: true, layer_split: true, expert_ratio, layer_ratio, .. } => {
+                let shared: Arc<[u8]> = Arc::from(input_data);
+                let num_experts_to_use = ratio_count(self.model_info.num_experts, *expert_ratio);
+                let num_layers_to_use = ratio_count(self.model_info.num_layers, *layer_ratio);
+                Box::new((0..num_layers_to_use * num_experts_to_use).map(move |index| {
+                    let layer_id = index / num_experts_to_use;
+                    let expert_id = index % num_experts_to_use;
+                    let header = self.data_preparator.prepare_layer_expert_data(&[], layer_id, expert_id)?;
+                    let task_id = TaskId::new(&parent_task_id).with_layer(layer_id).with_expert(expert_id).to_string();
+                    Ok(self.shared_input_task(task_id, &parent_task_id, header, &shared, priority, index))
+                }))
+            }
+            SplitStrategy::ByTopKExpert { .. }
+            | SplitStrategy::ByWindow { .. }
+            | SplitStrategy::ByTensorParallel { .. }
+            | SplitStrategy::ByExpertCapacity { .. }
+            | SplitStrategy::Hybrid { .. } => {
+                match self.split_task_inner(input_data, task_id, priority, None) {
+                    Ok(tasks) => Box::new(tasks.into_iter().map(Ok)),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            }
+        }
+    }
+
+    /// 构造只含头部、共享公共输入的子任务
+    fn shared_input_task(
+        &self,
+        task_id: String,
+        parent_task_id: &str,
+        header: Vec<u8>,
+        shared: &Arc<[u8]>,
+        priority: TaskPriority,
+        stream_id: usize,
+    ) -> MoeTask {
+        MoeTask {
+            stream_id: Some(stream_id),
+            parent_task_id: Some(parent_task_id.to_string()),
+            shared_input: Some(Arc::clone(shared)),
+            ..MoeTask::new(task_id, header, priority)
+        }
+    }
+
+    fn split_task_inner(
+        &self,
+        input_data: &[u8],
+        task_id: &str,
+        priority: TaskPriority,
+        gate_weights: Option<&GateWeights>,
+    ) -> Result<Vec<MoeTask>> {
+        let start = Instant::now();
+        let tasks = self.split_by_strategy(input_data, task_id, priority, gate_weights)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_split(start.elapsed());
+        }
+        Ok(tasks)
+    }
+
+    /// 验证输入后按当前策略拆分
+    fn split_by_strategy(
+        &self,
+        input_data: &[u8],
+        task_id: &str,
+        priority: TaskPriority,
+        gate_weights: Option<&GateWeights>,
+    ) -> Result<Vec<MoeTask>> {
         // 验证输入数据格式
         self.validate_input_data(input_data)?;
         
         match &self.strategy {
-            SplitStrategy::ByExpert => self.split_by_expert(input_data, task_id, priority),
+            SplitStrategy::ByExpert => {
+                let expert_ids: Vec<usize> = (0..self.model_info.num_experts).collect();
+                self.split_by_experts(input_data, task_id, priority, &expert_ids, gate_weights)
+            }
+            SplitStrategy::ByTopKExpert { top_k } => {
+                let gate_weights = gate_weights.ok_or_else(|| {
+                    Error::InferenceError("ByTopKExpert 策略需要门控权重，请使用 split_task_with_gates".to_string())
+                })?;
+                if gate_weights.weights.len() != self.model_info.num_experts {
+                    return Err(Error::InferenceError(format!(
+                        "门控权重数量 {} 与专家数量 {} 不匹配",
+                        gate_weights.weights.len(), self.model_info.num_experts
+                    )));
+                }
+                let expert_ids = gate_weights.top_k_experts(*top_k);
+                self.split_by_experts(input_data, task_id, priority, &expert_ids, Some(gate_weights))
+            }
             SplitStrategy::ByLayer => self.split_by_layer(input_data, task_id, priority),
-            SplitStrategy::ByBatch { batch_size } => self.split_by_batch(input_data, task_id, priority, *batch_size),
+            SplitStrategy::ByBatch { batch_size } => self.split_by_batch(input_data, task_id, &TaskId::new(task_id), priority, *batch_size),
+            SplitStrategy::ByWindow { window, stride } => Ok(self.split_by_window(input_data, task_id, priority, *window, *stride)),
+            SplitStrategy::ByTensorParallel { num_shards } => self.split_by_tensor_parallel(input_data, task_id, priority, *num_shards),
+            SplitStrategy::ByExpertCapacity { .. } => Err(Error::InferenceError(
+                "ByExpertCapacity 策略需要每个token的路由结果，请使用 split_task_with_assignments".to_string(),
+            )),
             SplitStrategy::Hybrid { expert_split, layer_split, batch_size, expert_ratio, layer_ratio } => {
                 self.split_hybrid(input_data, task_id, priority, *expert_split, *layer_split, *batch_size, *expert_ratio, *layer_ratio)
             }
         }
     }
 
+    /// 从 safetensors 文件中按专家拆分权重，每个MoE层的每个专家一个任务（仅支持 ByExpert）
+    ///
+    /// 专家张量按 Switch Transformer 的命名 `{mlp}.experts.expert_{i}.wi.weight` 和 `.wo.weight` 查找，
+    /// 文件中每出现一个 `{mlp}` 前缀就拆出一组任务，任务ID为 `{mlp}/expert_{i}`。
+    /// 任务输入为专家头部后接 wi、wo 的原始字节，元素类型与文件中一致。
+    /// 缺少某个专家的 wi 或 wo、形状与模型配置不符或数据越界时返回 ConfigError。
+    pub fn split_weights(&self, safetensors_path: &Path) -> Result<Vec<MoeTask>> {
+        if !matches!(self.strategy, SplitStrategy::ByExpert) {
+            return Err(Error::ConfigError(format!(
+                "按权重拆分只支持按专家拆分策略，当前为 {}", self.strategy.description()
+            )));
+        }
+        let mut file = File::open(safetensors_path)
+            .map_err(|e| Error::ConfigError(format!("打开 {} 失败: {}", safetensors_path.display(), e)))?;
+        let file_len = file.metadata()?.len();
+        let (entries, data_start) = read_safetensors_header(&mut file)?;
+
+        // 专家张量名中 "experts.expert_" 之前的部分即MoE前馈层的路径
+        let mlp_prefixes: BTreeSet<&str> = entries
+            .keys()
+            .filter_map(|name| name.find("experts.expert_").map(|index| &name[..index]))
+            .collect();
+        if mlp_prefixes.is_empty() {
+            return Err(Error::ConfigError(format!(
+                "{} 中没有专家权重（experts.expert_*）", safetensors_path.display()
+            )));
+        }
+
+        let hidden_size = self.model_info.hidden_size;
+        let intermediate_size = self.model_info.intermediate_size;
+        let mut read_tensor = |name: &str, expected_shape: [usize; 2]| -> Result<Vec<u8>> {
+            let entry = entries.get(name).ok_or_else(|| {
+                Error::ConfigError(format!("{} 中缺少张量 {}", safetensors_path.display(), name))
+            })?;
+            if entry.shape != expected_shape {
+                return Err(Error::ConfigError(format!(
+                    "张量 {} 的形状 {:?} 与模型配置 {:?} 不一致", name, entry.shape, expected_shape
+                )));
+            }
+            let element_size = match entry.dtype.as_str() {
+                "F32" => DType::F32.size(),
+                "F16" => DType::F16.size(),
+                "BF16" => DType::BF16.size(),
+                other => {
+                    return Err(Error::ConfigError(format!("张量 {} 的元素类型 {} 不受支持", name, other)));
+                }
+            };
+            let [begin, end] = entry.data_offsets;
+            let expected_len = (expected_shape[0] * expected_shape[1] * element_size) as u64;
+            if end < begin || end - begin != expected_len || data_start + end > file_len {
+                return Err(Error::ConfigError(format!(
+                    "张量 {} 的数据范围 [{}, {}) 无效", name, begin, end
+                )));
+            }
+            let mut bytes = vec![0u8; expected_len as usize];
+            file.seek(SeekFrom::Start(data_start + begin))?;
+            file.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+
+        let mut tasks = Vec::new();
+        for prefix in mlp_prefixes {
+            let parent_task_id = match prefix.trim_end_matches('.') {
+                "" => safetensors_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("weights"),
+                mlp => mlp,
+            };
+            for expert_id in 0..self.model_info.num_experts {
+                let expert_path = format!("{}experts.expert_{}", prefix, expert_id);
+                let wi = read_tensor(&format!("{}.wi.weight", expert_path), [intermediate_size, hidden_size])?;
+                let wo = read_tensor(&format!("{}.wo.weight", expert_path), [hidden_size, intermediate_size])?;
+
+                let mut input_data = task_header::encode(&self.data_preparator.expert_header(expert_id, 1.0)?);
+                input_data.extend_from_slice(&wi);
+                input_data.extend_from_slice(&wo);
+                tasks.push(MoeTask {
+                    stream_id: Some(expert_id),
+                    parent_task_id: Some(parent_task_id.to_string()),
+                    ..MoeTask::new(TaskId::new(parent_task_id).with_expert(expert_id).to_string(), input_data, TaskPriority::Normal)
+                });
+            }
+        }
+
+        info!("按专家拆分权重为 {} 个任务", tasks.len());
+        Ok(tasks)
+    }
+
     /// 按专家拆分任务
     fn split_by_expert(&self, input_data: &[u8], parent_task_id: &str, priority: TaskPriority) -> Result<Vec<MoeTask>> {
+        let expert_ids: Vec<usize> = (0..self.model_info.num_experts).collect();
+        self.split_by_experts(input_data, parent_task_id, priority, &expert_ids, None)
+    }
+
+    /// 为指定的专家创建任务，流ID与专家ID一致
+    /// 提供门控权重时头部写入各专家的真实权重，否则使用独热向量
+    fn split_by_experts(
+        &self,
+        input_data: &[u8],
+        parent_task_id: &str,
+        priority: TaskPriority,
+        expert_ids: &[usize],
+        gate_weights: Option<&GateWeights>,
+    ) -> Result<Vec<MoeTask>> {
         let mut tasks = Vec::new();
+        // 启用去重时所有专家共享同一份输入主体
+        let shared_body: Option<Arc<[u8]>> = if self.dedup_payloads {
+            Some(Arc::from(input_data))
+        } else {
+            None
+        };
         
-        for expert_id in 0..self.model_info.num_experts {
-            let task_id = self.generate_task_id(parent_task_id, "expert", expert_id);
+        for &expert_id in expert_ids {
+            let task_id = TaskId::new(parent_task_id).with_expert(expert_id).to_string();
             
             // 为每个专家创建专门的任务数据
-            let expert_data = self.data_preparator.prepare_expert_data(input_data, expert_id)?;
+            let body = if shared_body.is_some() { &[][..] } else { input_data };
+            let header = match gate_weights {
+                Some(gates) => self.data_preparator.expert_header_with_gates(expert_id, gates)?,
+                None => self.data_preparator.expert_header(expert_id, 1.0)?,
+            };
+            let expert_data = self.with_token_metadata(header, body);
             
             let task = MoeTask {
-                task_id,
-                input_data: expert_data,
-                status: crate::task::TaskStatus::Pending,
-                result: None,
-                priority,
                 stream_id: Some(expert_id),
                 parent_task_id: Some(parent_task_id.to_string()),
+                shared_input: shared_body.clone(),
+                ..MoeTask::new(task_id, expert_data, priority)
             };
             
             tasks.push(task);
         }
         
-        println!("按专家拆分为 {} 个任务", tasks.len());
+        info!("按专家拆分为 {} 个任务", tasks.len());
         Ok(tasks)
     }
 
@@ -208,43 +882,42 @@ impl TaskSplitter {
     fn split_by_layer(&self, input_data: &[u8], parent_task_id: &str, priority: TaskPriority) -> Result<Vec<MoeTask>> {
         let mut tasks = Vec::new();
         
-        for layer_id in 0..self.model_info.num_layers {
-            let task_id = self.generate_task_id(parent_task_id, "layer", layer_id);
+        for layer_id in 0..self.num_split_layers() {
+            let task_id = self.layer_task_id(parent_task_id, layer_id).to_string();
             
             // 为每个层创建专门的任务数据
-            let layer_data = self.data_preparator.prepare_layer_data(input_data, layer_id)?;
+            let layer_data = self.with_token_metadata(self.data_preparator.layer_header(layer_id)?, input_data);
             
             let task = MoeTask {
-                task_id,
-                input_data: layer_data,
-                status: crate::task::TaskStatus::Pending,
-                result: None,
-                priority,
                 stream_id: Some(layer_id),
                 parent_task_id: Some(parent_task_id.to_string()),
+                ..MoeTask::new(task_id, layer_data, priority)
             };
             
             tasks.push(task);
         }
         
-        println!("按层拆分为 {} 个任务", tasks.len());
+        info!("按层拆分为 {} 个任务", tasks.len());
         Ok(tasks)
     }
 
-    /// 按批次拆分任务
-    fn split_by_batch(&self, input_data: &[u8], parent_task_id: &str, priority: TaskPriority, batch_size: usize) -> Result<Vec<MoeTask>> {
+    /// 按批次拆分任务，子任务ID在 base_id 的基础上加上批次ID
+    fn split_by_batch(&self, input_data: &[u8], parent_task_id: &str, base_id: &TaskId, priority: TaskPriority, batch_size: usize) -> Result<Vec<MoeTask>> {
         let mut tasks = Vec::new();
+        // 批次边界对齐到元素宽度
+        let batch_size = self.aligned_batch_size(batch_size)?;
         
         // 计算需要多少个批次，考虑填充
         let total_size = input_data.len();
         let num_batches = (total_size + batch_size - 1) / batch_size; // 向上取整
         
         for batch_id in 0..num_batches {
-            let task_id = self.generate_task_id(parent_task_id, "batch", batch_id);
+            let task_id = base_id.clone().with_batch(batch_id).to_string();
             
             let start = batch_id * batch_size;
             let end = std::cmp::min(start + batch_size, total_size);
             let mut batch_data = input_data[start..end].to_vec();
+            let valid_len = batch_data.len();
             
             // 如果最后一个批次不足，进行填充
             if batch_data.len() < batch_size {
@@ -253,19 +926,60 @@ impl TaskSplitter {
             }
             
             let task = MoeTask {
-                task_id,
-                input_data: batch_data,
-                status: crate::task::TaskStatus::Pending,
-                result: None,
-                priority,
                 stream_id: Some(batch_id),
                 parent_task_id: Some(parent_task_id.to_string()),
+                valid_len: Some(valid_len),
+                ..MoeTask::new(task_id, batch_data, priority)
             };
             
             tasks.push(task);
         }
         
-        println!("按批次拆分为 {} 个任务", tasks.len());
+        info!("按批次拆分为 {} 个任务", tasks.len());
+        Ok(tasks)
+    }
+
+    /// 按滑动窗口拆分任务：第 i 个窗口从 i × stride 开始，最后一个窗口不满时补0，有效长度记录在 valid_len
+    fn split_by_window(&self, input_data: &[u8], parent_task_id: &str, priority: TaskPriority, window: usize, stride: usize) -> Vec<MoeTask> {
+        let tasks: Vec<MoeTask> = (0..num_windows(input_data.len(), window, stride))
+            .map(|window_id| {
+                let start = window_id * stride;
+                let end = std::cmp::min(start + window, input_data.len());
+                let mut window_data = input_data[start..end].to_vec();
+                let valid_len = window_data.len();
+                window_data.resize(window, 0);
+                MoeTask {
+                    stream_id: Some(window_id),
+                    parent_task_id: Some(parent_task_id.to_string()),
+                    valid_len: Some(valid_len),
+                    ..MoeTask::new(TaskId::new(parent_task_id).with_batch(window_id).to_string(), window_data, priority)
+                }
+            })
+            .collect();
+
+        info!("按滑动窗口拆分为 {} 个任务", tasks.len());
+        tasks
+    }
+
+    /// 按张量并行拆分任务：每个专家的权重列均匀切成 num_shards 段，分片范围记录在任务头部
+    fn split_by_tensor_parallel(&self, input_data: &[u8], parent_task_id: &str, priority: TaskPriority, num_shards: usize) -> Result<Vec<MoeTask>> {
+        let slices = self.data_preparator.even_column_slices(num_shards)?;
+        let mut tasks = Vec::with_capacity(self.model_info.num_experts * num_shards);
+
+        for expert_id in 0..self.model_info.num_experts {
+            for (shard_id, slice) in slices.iter().enumerate() {
+                let task_id = TaskId::new(parent_task_id).with_expert(expert_id).with_shard(shard_id).to_string();
+                let shard_data = self.data_preparator.prepare_expert_data_sharded(input_data, expert_id, *slice)?;
+
+                tasks.push(MoeTask {
+                    stream_id: Some(expert_id * num_shards + shard_id),
+                    parent_task_id: Some(parent_task_id.to_string()),
+                    ..MoeTask::new(task_id, shard_data, priority)
+                });
+            }
+        }
+
+        info!("按张量并行拆分为 {} 个任务", tasks.len());
         Ok(tasks)
     }
 
@@ -285,23 +999,19 @@ impl TaskSplitter {
         
         if expert_split && layer_split {
             // 先按层拆分，再按专家拆分
-            let num_experts_to_use = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
-            let num_layers_to_use = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
+            let num_experts_to_use = ratio_count(self.model_info.num_experts, expert_ratio);
+            let num_layers_to_use = ratio_count(self.model_info.num_layers, layer_ratio);
             
             for layer_id in 0..num_layers_to_use {
                 for expert_id in 0..num_experts_to_use {
-                    let task_id = self.generate_task_id(parent_task_id, &format!("layer_{}_expert", layer_id), expert_id);
+                    let task_id = TaskId::new(parent_task_id).with_layer(layer_id).with_expert(expert_id).to_string();
                     
                     let layer_expert_data = self.data_preparator.prepare_layer_expert_data(input_data, layer_id, expert_id)?;
                     
                     let task = MoeTask {
-                        task_id,
-                        input_data: layer_expert_data,
-                        status: crate::task::TaskStatus::Pending,
-                        result: None,
-                        priority,
                         stream_id: Some(layer_id * num_experts_to_use + expert_id),
                         parent_task_id: Some(parent_task_id.to_string()),
+                        ..MoeTask::new(task_id, layer_expert_data, priority)
                     };
                     
                     tasks.push(task);
@@ -309,86 +1019,149 @@ impl TaskSplitter {
             }
         } else if expert_split && batch_size > 0 {
             // 专家拆分 + 批次拆分
-            let num_experts_to_use = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
+            let num_experts_to_use = ratio_count(self.model_info.num_experts, expert_ratio);
             let expert_tasks = self.split_by_expert(input_data, parent_task_id, priority)?;
             for expert_task in expert_tasks.iter().take(num_experts_to_use) {
-                let batch_tasks = self.split_by_batch(&expert_task.input_data, &expert_task.task_id, priority, batch_size)?;
+                let batch_tasks = self.split_by_batch(&expert_task.effective_input(), &expert_task.task_id, &expert_task.parsed_task_id()?, priority, batch_size)?;
                 tasks.extend(batch_tasks);
             }
         } else if layer_split && batch_size > 0 {
             // 层拆分 + 批次拆分
-            let num_layers_to_use = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
+            let num_layers_to_use = ratio_count(self.model_info.num_layers, layer_ratio);
             let layer_tasks = self.split_by_layer(input_data, parent_task_id, priority)?;
             for layer_task in layer_tasks.iter().take(num_layers_to_use) {
-                let batch_tasks = self.split_by_batch(&layer_task.input_data, &layer_task.task_id, priority, batch_size)?;
+                let batch_tasks = self.split_by_batch(&layer_task.input_data, &layer_task.task_id, &layer_task.parsed_task_id()?, priority, batch_size)?;
                 tasks.extend(batch_tasks);
             }
         } else if expert_split {
-            let num_experts_to_use = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
+            let num_experts_to_use = ratio_count(self.model_info.num_experts, expert_ratio);
             let expert_tasks = self.split_by_expert(input_data, parent_task_id, priority)?;
             tasks.extend(expert_tasks.into_iter().take(num_experts_to_use));
         } else if layer_split {
-            let num_layers_to_use = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
+            let num_layers_to_use = ratio_count(self.model_info.num_layers, layer_ratio);
             let layer_tasks = self.split_by_layer(input_data, parent_task_id, priority)?;
             tasks.extend(layer_tasks.into_iter().take(num_layers_to_use));
         } else {
-            return self.split_by_batch(input_data, parent_task_id, priority, batch_size);
+            return self.split_by_batch(input_data, parent_task_id, &TaskId::new(parent_task_id), priority, batch_size);
         }
         
-        println!("混合拆分为 {} 个任务", tasks.len());
+        info!("混合拆分为 {} 个任务", tasks.len());
         Ok(tasks)
     }
 
-    /// 生成任务ID
-    fn generate_task_id(&self, parent_id: &str, prefix: &str, id: usize) -> String {
-        format!("{}_{}_{}", parent_id, prefix, id)
-    }
-
     /// 验证输入数据格式
     fn validate_input_data(&self, input_data: &[u8]) -> Result<()> {
         if input_data.is_empty() {
             return Err(Error::InferenceError("输入数据为空".to_string()));
         }
+
+        if let Some(metadata) = &self.token_metadata {
+            self.validate_token_metadata(metadata)?;
+        }
+
+        if let Some(expected_len) = self.expected_input_len {
+            if expected_len < INPUT_HEADER_SIZE {
+                return Err(Error::ConfigError(format!(
+                    "expected_input_len: 输入大小 {} 小于头部的 {} 字节", expected_len, INPUT_HEADER_SIZE
+                )));
+            }
+            if input_data.len() != expected_len {
+                return Err(Error::InferenceError(format!(
+                    "输入数据大小 {} 与给定的输入大小 {} 字节不符", input_data.len(), expected_len
+                )));
+            }
+            return Ok(());
+        }
+
+        if let Some(layout) = &self.input_layout {
+            return Self::validate_input_layout(input_data, layout);
+        }
         
-        // 检查数据大小是否合理
-        let min_size = self.model_info.hidden_size * 4; // 假设每个元素4字节
+        // 检查数据大小是否合理（包含头部）
+        let min_size = self.min_input_size();
         if input_data.len() < min_size {
-            return Err(Error::InferenceError(format!(
-                "输入数据大小 {} 小于最小要求 {}", input_data.len(), min_size
+            return Err(Error::ConfigError(format!(
+                "输入数据大小 {} 小于最小要求 {} (头部 {} 字节 + {} 字节/元素)",
+                input_data.len(), min_size, INPUT_HEADER_SIZE, ELEMENT_SIZE
             )));
         }
         
         Ok(())
     }
 
+    /// 校验token元数据：位置ID与掩码的token数一致，设置了布局时还必须等于 batch × seq_len
+    fn validate_token_metadata(&self, metadata: &TokenMetadata) -> Result<()> {
+        if !metadata.position_ids.len().is_multiple_of(POSITION_ID_SIZE) {
+            return Err(Error::InferenceError(format!(
+                "位置ID字节数 {} 不是 {} 的整数倍", metadata.position_ids.len(), POSITION_ID_SIZE
+            )));
+        }
+        let mask_tokens = metadata.attention_mask.len();
+        let position_tokens = metadata.position_ids.len() / POSITION_ID_SIZE;
+        if mask_tokens > 0 && position_tokens > 0 && mask_tokens != position_tokens {
+            return Err(Error::InferenceError(format!(
+                "注意力掩码的token数 {} 与位置ID的token数 {} 不一致", mask_tokens, position_tokens
+            )));
+        }
+        if let Some(layout) = &self.input_layout {
+            let num_tokens = layout.batch * layout.seq_len;
+            for (name, tokens) in [("注意力掩码", mask_tokens), ("位置ID", position_tokens)] {
+                if tokens > 0 && tokens != num_tokens {
+                    return Err(Error::InferenceError(format!(
+                        "{}的token数 {} 与布局 [{}, {}] 的token数 {} 不符",
+                        name, tokens, layout.batch, layout.seq_len, num_tokens
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 按布局校验输入：头部记录的元素个数和数据字节数都必须与布局一致
+    fn validate_input_layout(input_data: &[u8], layout: &InputLayout) -> Result<()> {
+        let expected_size = INPUT_HEADER_SIZE + layout.byte_len();
+        if input_data.len() != expected_size {
+            return Err(Error::InferenceError(format!(
+                "输入数据大小 {} 与声明的布局不符：期望 {} 字节（头部 {} + {}x{}x{} 个 {:?} 元素 x {} 字节）",
+                input_data.len(), expected_size, INPUT_HEADER_SIZE,
+                layout.batch, layout.seq_len, layout.hidden, layout.dtype, layout.dtype.size()
+            )));
+        }
+        let header_elements = u32::from_le_bytes(input_data[..INPUT_HEADER_SIZE].try_into().unwrap()) as usize;
+        if header_elements != layout.num_elements() {
+            return Err(Error::InferenceError(format!(
+                "输入头部记录的元素个数 {} 与布局 [{}, {}, {}] 的元素个数 {} 不符",
+                header_elements, layout.batch, layout.seq_len, layout.hidden, layout.num_elements()
+            )));
+        }
+        Ok(())
+    }
+
     /// 获取任务依赖关系
+    ///
+    /// 按层拆分时第 i 层只依赖第 i-1 层：Transformer 的残差连接只跨过层内的一个子层（注意力或前馈），
+    /// 子层输入就是本层的输入，无论 LayerNorm 在残差分支内（PreNorm）还是残差相加之后（PostNorm），
+    /// 残差都不会越过层边界，不存在对第 i-2 层的依赖。
     pub fn get_task_dependencies(&self, tasks: &[MoeTask]) -> Result<HashMap<String, Vec<String>>> {
         let mut dependencies = HashMap::new();
         
         // 根据拆分策略确定依赖关系
         match &self.strategy {
-            SplitStrategy::ByExpert => {
+            SplitStrategy::ByExpert | SplitStrategy::ByTopKExpert { .. } | SplitStrategy::ByExpertCapacity { .. } => {
                 // 专家任务之间没有依赖关系，可以并行执行
                 for task in tasks {
                     dependencies.insert(task.task_id.clone(), Vec::new());
                 }
             }
             SplitStrategy::ByLayer => {
-                // 层任务有顺序依赖关系，考虑残差连接
+                // 层任务只依赖上一层
                 for (i, task) in tasks.iter().enumerate() {
-                    let mut deps = Vec::new();
-                    if i > 0 {
-                        deps.push(tasks[i-1].task_id.clone());
-                    }
-                    // 如果有残差连接，可能需要依赖更早的层
-                    if i >= 2 {
-                        deps.push(tasks[i-2].task_id.clone());
-                    }
+                    let deps = i.checked_sub(1).map(|prev| tasks[prev].task_id.clone()).into_iter().collect();
                     dependencies.insert(task.task_id.clone(), deps);
                 }
             }
-            SplitStrategy::ByBatch { .. } => {
-                // 批次任务可以并行执行
+            SplitStrategy::ByBatch { .. } | SplitStrategy::ByWindow { .. } | SplitStrategy::ByTensorParallel { .. } => {
+                // 批次任务、窗口任务、同一专家的不同分片都可以并行执行
                 for task in tasks {
                     dependencies.insert(task.task_id.clone(), Vec::new());
                 }
@@ -397,8 +1170,8 @@ impl TaskSplitter {
                 // 混合策略的依赖关系
                 if *expert_split && *layer_split {
                     // 层内专家并行，层间顺序
-                    let num_experts_to_use = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
-                    let num_layers_to_use = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
+                    let num_experts_to_use = ratio_count(self.model_info.num_experts, *expert_ratio);
+                    let num_layers_to_use = ratio_count(self.model_info.num_layers, *layer_ratio);
                     
                     for layer_id in 0..num_layers_to_use {
                         for expert_id in 0..num_experts_to_use {
@@ -406,10 +1179,10 @@ impl TaskSplitter {
                             let mut deps = Vec::new();
                             
                             // 同一层内的专家任务没有依赖
-                            // 不同层之间有依赖关系
-                            if layer_id > 0 {
+                            // 不同层之间依赖上一层的全部专家
+                            if let Some(prev_layer) = layer_id.checked_sub(1) {
                                 for prev_expert in 0..num_experts_to_use {
-                                    let prev_task_idx = (layer_id - 1) * num_experts_to_use + prev_expert;
+                                    let prev_task_idx = prev_layer * num_experts_to_use + prev_expert;
                                     if prev_task_idx < tasks.len() {
                                         deps.push(tasks[prev_task_idx].task_id.clone());
                                     }
@@ -433,63 +1206,311 @@ impl TaskSplitter {
         Ok(dependencies)
     }
 
-    /// 合并任务结果
-    pub fn merge_results(&self, results: &[Vec<u8>], gate_weights: Option<GateWeights>) -> Result<Vec<u8>> {
-        self.result_merger.merge_results(results, gate_weights, &self.strategy)
+    /// 将拆分出的专家任务与模型路由器的输出比较
+    ///
+    /// `router_logits` 的形状为 [..., num_experts]，每个token按 logits 选出 top-k 个专家
+    /// （`ByTopKExpert` 策略使用其 top_k，其他策略按 Switch Transformer 的 top-1），所有token
+    /// 选中的专家的并集即为实际激活的专家。任务对应的专家取自任务ID中的 expert 部分，不含专家的任务被忽略。
+    #[cfg(feature = "tch")]
+    pub fn compare_with_routing(&self, tasks: &[MoeTask], router_logits: &tch::Tensor) -> Result<RoutingDiff> {
+        let logits = Vec::<f32>::try_from(&router_logits.flatten(0, -1).to_kind(tch::Kind::Float))?;
+        self.compare_with_routing_logits(tasks, &logits)
     }
 
-    /// 验证拆分结果
-    pub fn verify_split_results(&self, tasks: &[MoeTask], original_input: &[u8]) -> Result<bool> {
-        // 检查任务数量是否合理
-        let expected_count = match &self.strategy {
-            SplitStrategy::ByExpert => self.model_info.num_experts,
-            SplitStrategy::ByLayer => self.model_info.num_layers,
-            SplitStrategy::ByBatch { batch_size } => {
-                (original_input.len() + batch_size - 1) / batch_size
-            }
-            SplitStrategy::Hybrid { expert_split, layer_split, expert_ratio, layer_ratio, .. } => {
-                if *expert_split && *layer_split {
-                    let num_experts = (self.model_info.num_experts as f32 * expert_ratio).round() as usize;
-                    let num_layers = (self.model_info.num_layers as f32 * layer_ratio).round() as usize;
-                    num_experts * num_layers
-                } else if *expert_split {
-                    (self.model_info.num_experts as f32 * expert_ratio).round() as usize
-                } else if *layer_split {
-                    (self.model_info.num_layers as f32 * layer_ratio).round() as usize
-                } else {
-                    0
-                }
-            }
-        };
-
-        if tasks.len() != expected_count {
-            println!("警告：任务数量 {} 与期望数量 {} 不匹配", tasks.len(), expected_count);
-            return Ok(false);
+    /// 与 compare_with_routing 相同，logits 为按token依次排列的 num_experts 个值
+    pub fn compare_with_routing_logits(&self, tasks: &[MoeTask], router_logits: &[f32]) -> Result<RoutingDiff> {
+        let num_experts = self.model_info.num_experts;
+        if num_experts == 0 || !router_logits.len().is_multiple_of(num_experts) {
+            return Err(Error::InferenceError(format!(
+                "路由 logits 个数 {} 不是专家数量 {} 的整数倍", router_logits.len(), num_experts
+            )));
         }
+        let top_k = self.routing_top_k();
+        let selected: BTreeSet<usize> = router_logits
+            .chunks_exact(num_experts)
+            .flat_map(|token_logits| GateWeights::from_logits(token_logits, top_k).top_k_experts(top_k))
+            .collect();
 
-        // 检查任务状态
+        let mut split = BTreeSet::new();
         for task in tasks {
-            if !matches!(task.status, TaskStatus::Pending) {
-                println!("警告：任务 {} 状态异常: {:?}", task.task_id, task.status);
-                return Ok(false);
+            if let Some(expert_id) = task.parsed_task_id()?.expert {
+                split.insert(expert_id);
             }
         }
 
-        // 检查输入数据完整性
-        let total_input_size: usize = tasks.iter().map(|t| t.input_data.len()).sum();
+        Ok(RoutingDiff {
+            extra: split.difference(&selected).copied().collect(),
+            missing: selected.difference(&split).copied().collect(),
+            matched: split.intersection(&selected).copied().collect(),
+        })
+    }
+
+    /// 拆分结果实际涉及的专家ID，升序且不重复
+    ///
+    /// 专家ID取自任务头部；头部无法解码的任务（例如按批次再拆分后的后续批次）改用任务ID中的 expert 部分，
+    /// 两者都没有专家的任务被忽略。top-k 或按容量拆分后可据此对齐合并用的门控权重。
+    pub fn active_expert_ids(&self, tasks: &[MoeTask]) -> Vec<usize> {
+        let expert_ids: BTreeSet<usize> = tasks
+            .iter()
+            .filter_map(|task| {
+                task_header::decode(&task.effective_input())
+                    .ok()
+                    .and_then(|(header, _)| header.expert_id())
+                    .or_else(|| task.parsed_task_id().ok().and_then(|task_id| task_id.expert))
+            })
+            .collect();
+        expert_ids.into_iter().collect()
+    }
+
+    /// 用模型路由器计算单个token输入的门控权重，可直接交给 merge_results 合并专家结果
+    ///
+    /// 路由 logits 经softmax后保留 top-k 个专家并重新归一化，top_k 的取法与 compare_with_routing 相同。
+    #[cfg(feature = "tch")]
+    pub fn compute_gate_weights(&self, input: &tch::Tensor, mlp: &SwitchTransformersSparseMLP) -> Result<GateWeights> {
+        let num_experts = self.model_info.num_experts;
+        if mlp.num_experts() != num_experts {
+            return Err(Error::InferenceError(format!(
+                "稀疏MLP的专家数量 {} 与模型配置的专家数量 {} 不一致", mlp.num_experts(), num_experts
+            )));
+        }
+        let router_logits = tch::no_grad(|| mlp.router_logits(input));
+        let logits = Vec::<f32>::try_from(&router_logits.flatten(0, -1).to_kind(tch::Kind::Float))?;
+        if logits.len() != num_experts {
+            return Err(Error::InferenceError(format!(
+                "门控权重只支持单个token的输入，实际为 {} 个token", logits.len() / num_experts.max(1)
+            )));
+        }
+        Ok(GateWeights::from_logits(&logits, self.routing_top_k()))
+    }
+
+    /// 每个token路由到的专家数：`ByTopKExpert` 策略使用其 top_k，其他策略按 Switch Transformer 的 top-1
+    fn routing_top_k(&self) -> usize {
+        match &self.strategy {
+            SplitStrategy::ByTopKExpert { top_k } => *top_k,
+            _ => 1,
+        }
+    }
+
+    /// 合并任务结果
+    pub fn merge_results(&self, results: &[Vec<u8>], gate_weights: Option<GateWeights>) -> Result<Vec<u8>> {
+        self.timed_merge(|| self.result_merger.merge_results(results, gate_weights, &self.strategy))
+    }
+
+    /// 合并已完成子任务的结果，按各任务的有效长度去除批次填充
+    pub fn merge_task_results(&self, tasks: &[MoeTask], gate_weights: Option<GateWeights>) -> Result<Vec<u8>> {
+        self.timed_merge(|| self.result_merger.merge_task_results(tasks, gate_weights, &self.strategy))
+    }
+
+    /// 执行一次合并，设置了耗时统计时记录成功合并的耗时
+    fn timed_merge(&self, merge: impl FnOnce() -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let merged = merge()?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_merge(start.elapsed());
+        }
+        Ok(merged)
+    }
+
+    /// 验证拆分结果
+    pub fn verify_split_results(&self, tasks: &[MoeTask], original_input: &[u8]) -> Result<bool> {
+        // 检查任务数量是否合理
+        // 混合策略只启用一种拆分时，每个专家/层任务还会再按批次拆分，因此只要求是分组数的整数倍
+        let (expected_count, batched) = match &self.strategy {
+            SplitStrategy::ByExpert => (self.model_info.num_experts, false),
+            SplitStrategy::ByTopKExpert { top_k } => (*top_k, false),
+            SplitStrategy::ByLayer => (self.num_split_layers(), false),
+            SplitStrategy::ByBatch { batch_size } => (original_input.len().div_ceil(self.aligned_batch_size(*batch_size)?), false),
+            SplitStrategy::ByWindow { window, stride } => (num_windows(original_input.len(), *window, *stride), false),
+            SplitStrategy::ByTensorParallel { num_shards } => (self.model_info.num_experts * num_shards, false),
+            // 按容量拆分的任务数和输入大小取决于路由结果，改为按头部中的路由检查
+            SplitStrategy::ByExpertCapacity { .. } => return self.verify_capacity_tasks(tasks, original_input),
+            SplitStrategy::Hybrid { expert_split, layer_split, expert_ratio, layer_ratio, .. } => {
+                if *expert_split && *layer_split {
+                    let num_experts = ratio_count(self.model_info.num_experts, *expert_ratio);
+                    let num_layers = ratio_count(self.model_info.num_layers, *layer_ratio);
+                    (num_experts * num_layers, false)
+                } else if *expert_split {
+                    (ratio_count(self.model_info.num_experts, *expert_ratio), true)
+                } else if *layer_split {
+                    (ratio_count(self.model_info.num_layers, *layer_ratio), true)
+                } else {
+                    (0, false)
+                }
+            }
+        };
+
+        let count_matches = if batched {
+            expected_count > 0 && !tasks.is_empty() && tasks.len().is_multiple_of(expected_count)
+        } else {
+            tasks.len() == expected_count
+        };
+        if !count_matches {
+            warn!("任务数量 {} 与期望数量 {} 不匹配", tasks.len(), expected_count);
+            return Ok(false);
+        }
+
+        // 检查任务状态
+        for task in tasks {
+            if !matches!(task.status, TaskStatus::Pending) {
+                warn!("任务 {} 状态异常: {:?}", task.task_id, task.status);
+                return Ok(false);
+            }
+        }
+
+        // 检查输入数据完整性
+        let total_input_size: usize = tasks.iter().map(|t| t.input_len()).sum();
         if total_input_size < original_input.len() {
-            println!("警告：拆分后的总输入大小 {} 小于原始输入大小 {}", total_input_size, original_input.len());
+            warn!("拆分后的总输入大小 {} 小于原始输入大小 {}", total_input_size, original_input.len());
             return Ok(false);
         }
 
-        println!("拆分结果验证通过");
+        let valid = match &self.strategy {
+            SplitStrategy::ByExpert => self.verify_id_headers(tasks, original_input, TaskHeader::expert_id),
+            SplitStrategy::ByLayer => self.verify_id_headers(tasks, original_input, TaskHeader::layer_id),
+            SplitStrategy::ByBatch { .. } => Self::verify_batches(tasks, original_input),
+            SplitStrategy::ByWindow { stride, .. } => Self::verify_windows(tasks, original_input, *stride),
+            SplitStrategy::ByTensorParallel { num_shards } => self.verify_tensor_shards(tasks, original_input, *num_shards)?,
+            SplitStrategy::ByTopKExpert { .. } | SplitStrategy::ByExpertCapacity { .. } | SplitStrategy::Hybrid { .. } => true,
+        };
+        if valid {
+            debug!("拆分结果验证通过");
+        }
+        Ok(valid)
+    }
+
+    /// 检查按容量拆分的任务：每个token恰好被一个任务保留或丢弃，保留的行与原始输入一致
+    fn verify_capacity_tasks(&self, tasks: &[MoeTask], original_input: &[u8]) -> Result<bool> {
+        let row_size = self.model_info.hidden_size * ELEMENT_SIZE;
+        let body = original_input.get(INPUT_HEADER_SIZE..).unwrap_or_default();
+        let num_tokens = body.len() / row_size;
+        let mut seen = vec![false; num_tokens];
+        for task in tasks {
+            let input = task.effective_input();
+            let (header, payload) = task_header::decode(&input)?;
+            let routing = match header.token_routing {
+                Some(routing) if routing.num_tokens == num_tokens => routing,
+                _ => {
+                    warn!("任务 {} 缺少与输入一致的token路由", task.task_id);
+                    return Ok(false);
+                }
+            };
+            let rows = payload.get(INPUT_HEADER_SIZE..).unwrap_or_default();
+            if rows.len() != routing.kept.len() * row_size {
+                warn!("任务 {} 的负载大小与保留的token数不一致", task.task_id);
+                return Ok(false);
+            }
+            for (row, &token) in rows.chunks_exact(row_size).zip(&routing.kept) {
+                if token >= num_tokens || row != &body[token * row_size..(token + 1) * row_size] {
+                    warn!("任务 {} 中token {} 的数据与原始输入不一致", task.task_id, token);
+                    return Ok(false);
+                }
+            }
+            for &token in routing.kept.iter().chain(&routing.dropped) {
+                if token >= num_tokens || std::mem::replace(&mut seen[token], true) {
+                    warn!("token {} 越界或被多个任务处理", token);
+                    return Ok(false);
+                }
+            }
+        }
+        if seen.iter().any(|seen| !seen) {
+            warn!("存在未被任何任务处理的token");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// 检查张量并行拆分的任务：每个专家的分片按顺序覆盖完整的中间层维度，且携带完整的原始输入
+    fn verify_tensor_shards(&self, tasks: &[MoeTask], original_input: &[u8], num_shards: usize) -> Result<bool> {
+        for (expert_id, expert_tasks) in tasks.chunks(num_shards).enumerate() {
+            let mut slices = Vec::with_capacity(num_shards);
+            for task in expert_tasks {
+                let input = task.effective_input();
+                let (header_id, slice, payload) = self.data_preparator.parse_expert_data_sharded(&input)?;
+                if header_id != expert_id || payload != original_input {
+                    warn!("任务 {} 的专家ID {} 或输入数据与期望不符", task.task_id, header_id);
+                    return Ok(false);
+                }
+                slices.push(slice);
+            }
+            if let Err(e) = self.data_preparator.validate_column_slices(&slices) {
+                warn!("专家 {} 的分片范围无效: {}", expert_id, e);
+                return Ok(false);
+            }
+        }
         Ok(true)
     }
+
+    /// 检查按专家/层拆分的任务：第i个任务的头部ID为i，且负载为完整的原始输入
+    fn verify_id_headers(&self, tasks: &[MoeTask], original_input: &[u8], header_id: fn(&TaskHeader) -> Option<usize>) -> bool {
+        for (id, task) in tasks.iter().enumerate() {
+            let input = task.effective_input();
+            let (header, payload) = match task_header::decode(&input) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    warn!("任务 {} 的头部无效: {}", task.task_id, e);
+                    return false;
+                }
+            };
+            if header_id(&header) != Some(id) {
+                warn!("任务 {} 的头部ID {:?} 与期望的 {} 不一致", task.task_id, header_id(&header), id);
+                return false;
+            }
+            if payload != original_input {
+                warn!("任务 {} 未携带完整的原始输入", task.task_id);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// 检查按批次拆分的任务：依次拼接后去掉末尾填充即为原始输入
+    fn verify_batches(tasks: &[MoeTask], original_input: &[u8]) -> bool {
+        let reassembled: Vec<u8> = tasks.iter().flat_map(|task| task.effective_input().into_owned()).collect();
+        let (data, padding) = reassembled.split_at(original_input.len().min(reassembled.len()));
+        if data != original_input {
+            warn!("批次拼接结果与原始输入不一致");
+            return false;
+        }
+        if padding.iter().any(|&byte| byte != 0) {
+            warn!("批次末尾填充包含非零数据");
+            return false;
+        }
+        true
+    }
+
+    /// 检查按滑动窗口拆分的任务：每个窗口的有效数据必须与原始输入中从 i × stride 开始的同长度片段一致，填充全为0
+    fn verify_windows(tasks: &[MoeTask], original_input: &[u8], stride: usize) -> bool {
+        for (window_id, task) in tasks.iter().enumerate() {
+            let start = window_id * stride;
+            let valid = task.valid_slice(&task.input_data);
+            if original_input.get(start..start + valid.len()) != Some(valid) {
+                warn!("窗口 {} 的数据与原始输入不一致", window_id);
+                return false;
+            }
+            if task.input_data[valid.len()..].iter().any(|&byte| byte != 0) {
+                warn!("窗口 {} 的填充包含非零数据", window_id);
+                return false;
+            }
+        }
+        true
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::task::LayerStack;
+    use crate::task_executor::TaskExecutor;
+
+    /// 构造单个token的输入：4字节头部 + hidden_size 个 f32
+    fn single_token_input(hidden_size: usize) -> Vec<u8> {
+        let mut input_data = Vec::new();
+        input_data.extend_from_slice(&(hidden_size as u32).to_le_bytes());
+        for i in 0..hidden_size {
+            input_data.extend_from_slice(&(i as f32).to_le_bytes());
+        }
+        input_data
+    }
 
     #[test]
     fn test_task_splitter_creation() {
@@ -499,12 +1520,153 @@ mod tests {
             hidden_size: 512,
             intermediate_size: 2048,
             num_layers: 12,
+            ..Default::default()
         };
         
         let strategy = SplitStrategy::ByExpert;
-        let splitter = TaskSplitter::new(model_info, strategy);
+        let splitter = TaskSplitter::new(model_info, strategy).unwrap();
         
-        assert_eq!(splitter.data_preparator.read().unwrap().len(), 0);
+        assert_eq!(splitter.data_preparator.model_info.num_experts, 8);
+    }
+
+    /// 记录日志的测试 logger，按线程区分记录，避免并行测试互相干扰
+    struct CaptureLogger;
+
+    static CAPTURED: std::sync::Mutex<Vec<(std::thread::ThreadId, log::Level, String)>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let entry = (std::thread::current().id(), record.level(), record.args().to_string());
+            CAPTURED.lock().unwrap().push(entry);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_split_logs_task_count_at_info() {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 8,
+            intermediate_size: 32,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        splitter.split_task(&single_token_input(8), "logged", TaskPriority::Normal).unwrap();
+
+        let thread_id = std::thread::current().id();
+        let captured = CAPTURED.lock().unwrap();
+        assert!(captured
+            .iter()
+            .any(|(id, level, message)| *id == thread_id && *level == log::Level::Info && message == "按专家拆分为 4 个任务"));
+    }
+
+    #[test]
+    fn test_metrics_record_split_and_merge() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 8,
+            intermediate_size: 32,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let mut splitter = TaskSplitter::new(model_info, SplitStrategy::ByLayer).unwrap();
+        let metrics = Arc::new(Metrics::new());
+        splitter.set_metrics(Arc::clone(&metrics));
+
+        let input = single_token_input(8);
+        let tasks = splitter.split_task(&input, "timed", TaskPriority::Normal).unwrap();
+        let results: Vec<Vec<u8>> = tasks.iter().map(|task| task.effective_input().into_owned()).collect();
+        splitter.merge_results(&results, None).unwrap();
+        assert!(splitter.split_task(&input[..3], "invalid", TaskPriority::Normal).is_err());
+
+        // 失败的拆分不计入统计
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.split_count, 1);
+        assert_eq!(snapshot.merge_count, 1);
+        assert_eq!(snapshot.task_count, 0);
+    }
+
+    #[test]
+    fn test_layer_dependencies_only_previous_layer() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 2,
+            hidden_size: 8,
+            intermediate_size: 32,
+            num_layers: 4,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByLayer).unwrap();
+        let tasks = splitter.split_task(&single_token_input(8), "residual", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 4);
+
+        // 每层只依赖上一层，没有跨两层的边
+        let dependencies = splitter.get_task_dependencies(&tasks).unwrap();
+        assert!(dependencies[&tasks[0].task_id].is_empty());
+        for i in 1..tasks.len() {
+            assert_eq!(dependencies[&tasks[i].task_id], vec![tasks[i - 1].task_id.clone()]);
+        }
+    }
+
+    #[test]
+    fn test_expert_capacity_drops_overflow_tokens() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 4,
+            intermediate_size: 16,
+            num_layers: 1,
+            ..Default::default()
+        };
+        let strategy = SplitStrategy::ByExpertCapacity { capacity_factor: 1.0 };
+        let splitter = TaskSplitter::new(model_info, strategy).unwrap();
+
+        // 8个token、4个专家，容量为2；6个token挤到专家0，其中4个被丢弃
+        let values: Vec<f32> = (0..32).map(|i| i as f32 + 1.0).collect();
+        let mut input = (values.len() as u32).to_le_bytes().to_vec();
+        input.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+        let assignments = [0, 0, 1, 0, 0, 2, 0, 0];
+        assert_eq!(expert_capacity(1.0, 8, 4), 2);
+        assert!(splitter.split_task(&input, "capacity", TaskPriority::Normal).is_err());
+
+        let mut tasks = splitter.split_task_with_assignments(&input, "capacity", TaskPriority::Normal, &assignments).unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+
+        let routings: Vec<TokenRouting> = tasks
+            .iter()
+            .map(|task| task_header::decode(&task.input_data).unwrap().0.token_routing.unwrap())
+            .collect();
+        assert_eq!(routings[0].kept, vec![0, 1]);
+        assert_eq!(routings[0].dropped, vec![3, 4, 6, 7]);
+        assert_eq!(routings.iter().map(|routing| routing.dropped.len()).sum::<usize>(), 4);
+        assert!(routings[1..].iter().all(|routing| routing.dropped.is_empty()));
+
+        // 专家原样返回输入时，合并结果中保留的token不变，被丢弃的token为0
+        for task in tasks.iter_mut() {
+            task.result = Some(task.effective_input().into_owned());
+        }
+        let merged = splitter.merge_task_results(&tasks, None).unwrap();
+        let merged: Vec<f32> = merged.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        for (token, row) in merged.chunks_exact(4).enumerate() {
+            if [3, 4, 6, 7].contains(&token) {
+                assert!(row.iter().all(|v| *v == 0.0), "token {} 应被丢弃", token);
+            } else {
+                assert_eq!(row, &values[token * 4..(token + 1) * 4]);
+            }
+        }
     }
 
     #[test]
@@ -515,6 +1677,7 @@ mod tests {
             hidden_size: 256,
             intermediate_size: 1024,
             num_layers: 6,
+            ..Default::default()
         };
         
         let preparator = DataPreparator::new(model_info);
@@ -535,6 +1698,7 @@ mod tests {
             hidden_size: 128,
             intermediate_size: 512,
             num_layers: 4,
+            ..Default::default()
         };
         
         let merger = ResultMerger::new(model_info);
@@ -555,30 +1719,22 @@ mod tests {
             top_k: 2,
         };
         
-        let merged = merger.merge_expert_results(&results, Some(gate_weights)).unwrap();
+        let merged = merger.merge_results(&results, Some(gate_weights), &SplitStrategy::ByExpert).unwrap();
         assert!(!merged.is_empty());
     }
 
     #[test]
     fn test_task_executor() {
-        let model_info = ModelInfo {
-            model_type: "switch_transformer".to_string(),
-            num_experts: 4,
-            hidden_size: 256,
-            intermediate_size: 1024,
-            num_layers: 6,
+        // 无可用GPU时跳过
+        let executor = match TaskExecutor::new(DeviceId(0)) {
+            Ok(executor) => executor,
+            Err(_) => return,
         };
         
-        let executor = TaskExecutor::new(model_info);
-        
         let mut task = MoeTask {
-            task_id: "test_expert_1".to_string(),
-            input_data: vec![1, 2, 3, 4],
-            status: crate::task::TaskStatus::Pending,
-            result: None,
-            priority: TaskPriority::Normal,
             stream_id: Some(0),
             parent_task_id: Some("parent".to_string()),
+            ..MoeTask::new("test_expert_1", vec![1, 2, 3, 4], TaskPriority::Normal)
         };
         
         let result = executor.execute_task(&mut task);
@@ -586,4 +1742,1039 @@ mod tests {
         assert!(matches!(task.status, crate::task::TaskStatus::Completed));
         assert!(task.result.is_some());
     }
+
+    #[test]
+    fn test_min_input_size_single_token() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 256,
+            intermediate_size: 1024,
+            num_layers: 6,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        assert_eq!(splitter.min_input_size(), INPUT_HEADER_SIZE + 256 * ELEMENT_SIZE);
+
+        let input_data = single_token_input(256);
+        let tasks = splitter.split_task(&input_data, "single", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 4);
+    }
+
+    #[test]
+    fn test_min_input_size_undersized() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 256,
+            intermediate_size: 1024,
+            num_layers: 6,
+            ..Default::default()
+        };
+        let mut splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+
+        // 缺少头部的输入不满足最小要求
+        let input_data = single_token_input(256);
+        let result = splitter.split_task(&input_data[INPUT_HEADER_SIZE..], "undersized", TaskPriority::Normal);
+        match result {
+            Err(Error::ConfigError(msg)) => {
+                assert!(msg.contains(&(256 * ELEMENT_SIZE).to_string()));
+                assert!(msg.contains(&(INPUT_HEADER_SIZE + 256 * ELEMENT_SIZE).to_string()));
+            }
+            other => panic!("期望 ConfigError，实际为 {:?}", other),
+        }
+
+        // 设置形状后按形状计算：2个token
+        splitter.set_input_shape(vec![1, 2, 256]);
+        assert_eq!(splitter.min_input_size(), INPUT_HEADER_SIZE + 2 * 256 * ELEMENT_SIZE);
+        assert!(splitter.split_task(&input_data, "undersized", TaskPriority::Normal).is_err());
+    }
+
+    #[test]
+    fn test_input_layout_validation_per_dtype() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 256,
+            intermediate_size: 1024,
+            num_layers: 6,
+            ..Default::default()
+        };
+        for (dtype, element_size) in [(DType::F32, 4), (DType::F16, 2), (DType::BF16, 2)] {
+            let mut splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+            let layout = InputLayout { dtype, batch: 2, seq_len: 3, hidden: 256 };
+            splitter.set_input_layout(layout);
+            assert_eq!(layout.byte_len(), 2 * 3 * 256 * element_size);
+            assert_eq!(splitter.min_input_size(), INPUT_HEADER_SIZE + layout.byte_len());
+
+            let mut input = (layout.num_elements() as u32).to_le_bytes().to_vec();
+            input.resize(INPUT_HEADER_SIZE + layout.byte_len(), 0);
+            assert_eq!(splitter.split_task(&input, "layout", TaskPriority::Normal).unwrap().len(), 4);
+
+            // 多一个元素或少一个元素都会被拒绝
+            let mut longer = input.clone();
+            longer.extend(std::iter::repeat_n(0u8, element_size));
+            for wrong in [&input[..input.len() - element_size], &longer[..]] {
+                match splitter.split_task(wrong, "layout", TaskPriority::Normal) {
+                    Err(Error::InferenceError(msg)) => {
+                        assert!(msg.contains(&input.len().to_string()), "{}", msg);
+                        assert!(msg.contains(&format!("{:?}", dtype)), "{}", msg);
+                    }
+                    other => panic!("期望 InferenceError，实际为 {:?}", other),
+                }
+            }
+
+            // 字节数正确但头部元素个数与布局不符
+            let mut wrong_header = input.clone();
+            wrong_header[..INPUT_HEADER_SIZE].copy_from_slice(&1u32.to_le_bytes());
+            assert!(matches!(
+                splitter.split_task(&wrong_header, "layout", TaskPriority::Normal),
+                Err(Error::InferenceError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_expected_input_len_overrides_default_minimum() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 256,
+            intermediate_size: 1024,
+            num_layers: 6,
+            ..Default::default()
+        };
+        // 64 个f32元素，小于默认按 hidden_size 计算的最小大小
+        let mut input = 64u32.to_le_bytes().to_vec();
+        input.extend((0..64).flat_map(|i| (i as f32).to_le_bytes()));
+        let mut splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        assert!(matches!(splitter.split_task(&input, "len", TaskPriority::Normal), Err(Error::ConfigError(_))));
+
+        splitter.set_expected_input_len(Some(input.len()));
+        assert_eq!(splitter.min_input_size(), input.len());
+        assert_eq!(splitter.split_task(&input, "len", TaskPriority::Normal).unwrap().len(), 4);
+
+        // 多一个字节或少一个字节都会被拒绝
+        let mut longer = input.clone();
+        longer.push(0);
+        for wrong in [&input[..input.len() - 1], &longer[..]] {
+            match splitter.split_task(wrong, "len", TaskPriority::Normal) {
+                Err(Error::InferenceError(msg)) => assert!(msg.contains(&input.len().to_string()), "{}", msg),
+                other => panic!("期望 InferenceError，实际为 {:?}", other.map(|tasks| tasks.len())),
+            }
+        }
+
+        // 给定的大小优先于布局；清除后恢复按布局校验
+        splitter.set_input_layout(InputLayout { dtype: DType::F32, batch: 1, seq_len: 1, hidden: 256 });
+        assert!(splitter.split_task(&input, "len", TaskPriority::Normal).is_ok());
+        splitter.set_expected_input_len(None);
+        assert!(matches!(splitter.split_task(&input, "len", TaskPriority::Normal), Err(Error::InferenceError(_))));
+    }
+
+    #[test]
+    fn test_split_task_with_stream_base() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 4,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        let input_data = single_token_input(64);
+
+        let first = splitter.split_task_with_stream_base(&input_data, "req_a", TaskPriority::Normal, 0).unwrap();
+        let second = splitter.split_task_with_stream_base(&input_data, "req_b", TaskPriority::Normal, 100).unwrap();
+
+        let first_ids: std::collections::HashSet<usize> = first.iter().filter_map(|t| t.stream_id).collect();
+        let second_ids: std::collections::HashSet<usize> = second.iter().filter_map(|t| t.stream_id).collect();
+        assert_eq!(first_ids.len(), 8);
+        assert_eq!(second_ids.len(), 8);
+        assert!(first_ids.is_disjoint(&second_ids));
+        assert_eq!(second_ids.iter().min(), Some(&100));
+    }
+
+    #[test]
+    fn test_dedup_expert_payloads() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 4,
+            ..Default::default()
+        };
+        let mut splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        splitter.set_dedup_payloads(true);
+        let input_data = single_token_input(64);
+
+        let tasks = splitter.split_task(&input_data, "dedup", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 8);
+
+        // 所有专家引用同一份主体
+        let body = tasks[0].shared_input.clone().unwrap();
+        for task in &tasks {
+            assert!(Arc::ptr_eq(task.shared_input.as_ref().unwrap(), &body));
+        }
+        assert_eq!(Arc::strong_count(&body), 9);
+
+        // 去重后的完整输入与未去重时一致
+        splitter.set_dedup_payloads(false);
+        let plain_tasks = splitter.split_task(&input_data, "dedup", TaskPriority::Normal).unwrap();
+        for (task, plain) in tasks.iter().zip(plain_tasks.iter()) {
+            assert_eq!(task.effective_input().as_ref(), plain.input_data.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_strategy_with_config_error() {
+        let model_info = ModelInfo {
+            model_type: "test".to_string(),
+            num_experts: 4,
+            hidden_size: 8,
+            intermediate_size: 32,
+            num_layers: 2,
+            ..Default::default()
+        };
+
+        match TaskSplitter::new(model_info.clone(), SplitStrategy::ByBatch { batch_size: 0 }) {
+            Err(Error::ConfigError(msg)) => assert!(msg.starts_with("batch_size")),
+            other => panic!("期望 ConfigError，实际为 {:?}", other.map(|_| ())),
+        }
+
+        let no_split = SplitStrategy::Hybrid {
+            expert_split: false,
+            layer_split: false,
+            batch_size: 4,
+            expert_ratio: 0.5,
+            layer_ratio: 0.5,
+        };
+        match TaskSplitter::new(model_info.clone(), no_split) {
+            Err(Error::ConfigError(msg)) => assert!(msg.contains("expert_split")),
+            other => panic!("期望 ConfigError，实际为 {:?}", other.map(|_| ())),
+        }
+
+        assert!(TaskSplitter::new(model_info, SplitStrategy::ByBatch { batch_size: 4 }).is_ok());
+    }
+
+    #[test]
+    fn test_new_from_model_dir_malformed_config() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("config.json"), r#"{"model_type": "switch_transformers", "num_experts": "#).unwrap();
+
+        let result = TaskSplitter::new_from_model_dir(dir.path().to_str().unwrap(), SplitStrategy::ByExpert);
+        assert!(matches!(result, Err(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_hybrid_task_count_matches_merger() {
+        let model_info = ModelInfo {
+            model_type: "test".to_string(),
+            num_experts: 4,
+            hidden_size: 4,
+            intermediate_size: 16,
+            num_layers: 3,
+            ..Default::default()
+        };
+        let input = single_token_input(model_info.hidden_size);
+        let hybrid = |expert_split, layer_split, expert_ratio, layer_ratio| SplitStrategy::Hybrid {
+            expert_split,
+            layer_split,
+            batch_size: 8,
+            expert_ratio,
+            layer_ratio,
+        };
+
+        // 专家子任务为 28 字节头部（前缀、ID、门控个数和 4 个门控权重）+ 20 字节输入，按 8 字节分为 6 个批次；
+        // 层子任务为 20 字节头部（前缀、ID 和层配置）+ 20 字节输入，分为 5 个批次
+        let cases = [
+            // (策略, 期望任务数)
+            (hybrid(true, true, 0.5, 0.67), 2 * 2),
+            (hybrid(true, false, 0.75, 1.0), 3 * 6),
+            (hybrid(false, true, 1.0, 0.34), 5),
+        ];
+        for (strategy, expected) in cases {
+            let splitter = TaskSplitter::new(model_info.clone(), strategy.clone()).unwrap();
+            let tasks = splitter.split_task(&input, "hybrid", TaskPriority::Normal).unwrap();
+            assert_eq!(tasks.len(), expected, "{}", strategy.description());
+
+            // 模拟执行：每个子任务返回相同大小的结果
+            let results: Vec<Vec<u8>> = tasks.iter().map(|_| vec![0u8; model_info.hidden_size * 4]).collect();
+            splitter.result_merger.merge_results(&results, None, &strategy).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ratio_count() {
+        assert_eq!(ratio_count(8, 0.5), 4);
+        assert_eq!(ratio_count(8, 0.0), 1);
+        assert_eq!(ratio_count(8, 1.0), 8);
+        assert_eq!(ratio_count(0, 0.5), 0);
+    }
+
+    #[test]
+    fn test_validate_rejection_paths() {
+        let model_info = ModelInfo {
+            model_type: "test".to_string(),
+            num_experts: 8,
+            hidden_size: 16,
+            intermediate_size: 64,
+            num_layers: 4,
+            ..Default::default()
+        };
+        let empty_model = ModelInfo { num_experts: 0, num_layers: 0, ..model_info.clone() };
+        let hybrid = |expert_split, layer_split, batch_size, expert_ratio, layer_ratio| SplitStrategy::Hybrid {
+            expert_split,
+            layer_split,
+            batch_size,
+            expert_ratio,
+            layer_ratio,
+        };
+        let rejects = |strategy: SplitStrategy, model_info: &ModelInfo, field: &str| {
+            match strategy.validate(model_info) {
+                Err(Error::ConfigError(msg)) => assert!(msg.starts_with(field), "{}: {}", strategy.description(), msg),
+                other => panic!("{} 应被拒绝，实际为 {:?}", strategy.description(), other),
+            }
+        };
+
+        rejects(SplitStrategy::ByExpert, &empty_model, "num_experts");
+        rejects(SplitStrategy::ByLayer, &empty_model, "num_layers");
+        rejects(SplitStrategy::ByBatch { batch_size: 0 }, &model_info, "batch_size");
+        rejects(SplitStrategy::ByBatch { batch_size: 65 }, &model_info, "batch_size");
+        rejects(hybrid(false, false, 4, 0.5, 0.5), &model_info, "expert_split/layer_split");
+        rejects(hybrid(true, true, 0, 0.5, 0.5), &model_info, "batch_size");
+        rejects(hybrid(true, false, 4, 0.5, 0.5), &empty_model, "num_experts");
+        rejects(hybrid(false, true, 4, 0.5, 0.5), &empty_model, "num_layers");
+        rejects(hybrid(true, true, 4, 0.0, 0.5), &model_info, "expert_ratio");
+        rejects(hybrid(true, true, 4, 1.5, 0.5), &model_info, "expert_ratio");
+        rejects(hybrid(true, true, 4, 0.5, -0.1), &model_info, "layer_ratio");
+        // 8 * 0.05 = 0.4，四舍五入后没有专家可用
+        rejects(hybrid(true, true, 4, 0.05, 0.5), &model_info, "expert_ratio");
+        // 未启用的拆分方式仍要求比例在 [0.0, 1.0] 内
+        rejects(hybrid(true, false, 4, 0.5, 1.5), &model_info, "layer_ratio");
+
+        assert!(hybrid(true, false, 4, 0.5, 0.0).validate(&model_info).is_ok());
+        assert!(hybrid(true, true, 4, 0.125, 1.0).validate(&model_info).is_ok());
+        assert_eq!(
+            hybrid(true, true, 4, 0.5, 1.0).description(),
+            "混合策略: 专家拆分(50.0%), 层拆分(100.0%), 批次大小: 4"
+        );
+    }
+
+    #[test]
+    fn test_verify_split_results() {
+        let model_info = ModelInfo {
+            model_type: "test".to_string(),
+            num_experts: 4,
+            hidden_size: 4,
+            intermediate_size: 16,
+            num_layers: 3,
+            ..Default::default()
+        };
+        let input = single_token_input(model_info.hidden_size);
+
+        for strategy in [SplitStrategy::ByExpert, SplitStrategy::ByLayer, SplitStrategy::ByBatch { batch_size: 8 }] {
+            let splitter = TaskSplitter::new(model_info.clone(), strategy.clone()).unwrap();
+            let tasks = splitter.split_task(&input, "verify", TaskPriority::Normal).unwrap();
+            assert!(splitter.verify_split_results(&tasks, &input).unwrap(), "{}", strategy.description());
+
+            // 交换前两个任务，ID头部或批次顺序不再匹配
+            let mut swapped = tasks.clone();
+            swapped.swap(0, 1);
+            assert!(!splitter.verify_split_results(&swapped, &input).unwrap(), "{}", strategy.description());
+
+            // 缺少一个任务
+            assert!(!splitter.verify_split_results(&tasks[1..], &input).unwrap(), "{}", strategy.description());
+        }
+
+        // 篡改专家任务携带的输入
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+        let mut tasks = splitter.split_task(&input, "verify", TaskPriority::Normal).unwrap();
+        *tasks[2].input_data.last_mut().unwrap() ^= 0xff;
+        assert!(!splitter.verify_split_results(&tasks, &input).unwrap());
+
+        // 批次填充中混入非零数据
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByBatch { batch_size: 12 }).unwrap();
+        let mut tasks = splitter.split_task(&input, "verify", TaskPriority::Normal).unwrap();
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+        *tasks.last_mut().unwrap().input_data.last_mut().unwrap() = 1;
+        assert!(!splitter.verify_split_results(&tasks, &input).unwrap());
+    }
+
+    #[test]
+    fn test_split_top_k_experts() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 4,
+            intermediate_size: 16,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByTopKExpert { top_k: 2 }).unwrap();
+        let input = single_token_input(model_info.hidden_size);
+        let gate_weights = GateWeights {
+            weights: vec![0.05, 0.1, 0.02, 0.3, 0.05, 0.08, 0.35, 0.05],
+            top_k: 2,
+        };
+
+        let tasks = splitter.split_task_with_gates(&input, "topk", TaskPriority::Normal, &gate_weights).unwrap();
+        let stream_ids: Vec<Option<usize>> = tasks.iter().map(|task| task.stream_id).collect();
+        assert_eq!(stream_ids, vec![Some(3), Some(6)]);
+        let mut reversed = tasks.clone();
+        reversed.reverse();
+        assert_eq!(splitter.active_expert_ids(&reversed), vec![3, 6]);
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+        // 头部写入的是被选中专家的真实门控权重
+        for task in &tasks {
+            let expert_id = task.stream_id.unwrap();
+            let (header, _) = task_header::decode(&task.input_data).unwrap();
+            assert_eq!(header.expert_id(), Some(expert_id));
+            assert_eq!(header.gate_weights().unwrap()[expert_id], gate_weights.weights[expert_id]);
+        }
+
+        // 没有门控权重时无法选择专家
+        assert!(splitter.split_task(&input, "topk", TaskPriority::Normal).is_err());
+        assert!(TaskSplitter::new(model_info, SplitStrategy::ByTopKExpert { top_k: 9 }).is_err());
+    }
+
+    #[test]
+    fn test_batch_merge_removes_padding() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByBatch { batch_size: 256 }).unwrap();
+        // 249 个 f32 元素加 4 字节头部，共 1000 字节
+        let mut input = 249u32.to_le_bytes().to_vec();
+        input.extend((0..249).flat_map(|i| (i as f32).to_le_bytes()));
+        assert_eq!(input.len(), 1000);
+
+        let mut tasks = splitter.split_task(&input, "padded", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 4);
+        assert!(tasks.iter().all(|task| task.input_data.len() == 256));
+        assert_eq!(tasks[3].valid_len, Some(1000 - 3 * 256));
+        for task in tasks.iter_mut() {
+            task.result = Some(task.input_data.clone());
+        }
+
+        let merged = splitter.merge_task_results(&tasks, None).unwrap();
+        assert_eq!(merged.len(), 1000);
+        assert_eq!(merged, input);
+
+        let lazy: Vec<MoeTask> = splitter
+            .split_task_iter(&input, "padded", TaskPriority::Normal)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(lazy.last().unwrap().valid_len, Some(1000 - 3 * 256));
+    }
+
+    #[test]
+    fn test_batch_boundaries_align_to_element_size() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let mut input = 64u32.to_le_bytes().to_vec();
+        input.extend((0..64).flat_map(|i| (i as f32).to_le_bytes()));
+
+        // 批次大小10不是f32宽度的整数倍，按8字节拆分
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByBatch { batch_size: 10 }).unwrap();
+        let mut tasks = splitter.split_task(&input, "aligned", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), input.len().div_ceil(8));
+        let mut offset = 0;
+        for task in &tasks {
+            assert_eq!(offset % ELEMENT_SIZE, 0, "批次起点 {} 切开了一个f32", offset);
+            assert_eq!(task.input_data.len(), 8);
+            offset += task.valid_len.unwrap();
+        }
+        assert_eq!(offset, input.len());
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+        for task in tasks.iter_mut() {
+            task.result = Some(task.input_data.clone());
+        }
+        assert_eq!(splitter.merge_task_results(&tasks, None).unwrap(), input);
+
+        // 半精度布局下按2字节对齐
+        let mut half = TaskSplitter::new(model_info.clone(), SplitStrategy::ByBatch { batch_size: 10 }).unwrap();
+        half.set_input_layout(InputLayout { dtype: DType::F16, batch: 1, seq_len: 1, hidden: 64 });
+        assert_eq!(half.aligned_batch_size(10).unwrap(), 10);
+
+        // 批次大小小于一个元素时报错
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByBatch { batch_size: 3 }).unwrap();
+        match splitter.split_task(&input, "aligned", TaskPriority::Normal) {
+            Err(Error::ConfigError(msg)) => assert!(msg.starts_with("batch_size"), "{}", msg),
+            other => panic!("期望 ConfigError，实际为 {:?}", other.map(|tasks| tasks.len())),
+        }
+    }
+
+    #[test]
+    fn test_estimate_task_count_matches_split() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 6,
+            ..Default::default()
+        };
+        // 3 个token
+        let mut input = ((3 * 64) as u32).to_le_bytes().to_vec();
+        input.extend((0..3 * 64).flat_map(|i| (i as f32).to_le_bytes()));
+        let hybrid = |expert_split, layer_split, batch_size| SplitStrategy::Hybrid {
+            expert_split,
+            layer_split,
+            batch_size,
+            expert_ratio: 0.3,
+            layer_ratio: 0.6,
+        };
+        let strategies = [
+            SplitStrategy::ByExpert,
+            SplitStrategy::ByLayer,
+            SplitStrategy::ByBatch { batch_size: 250 },
+            SplitStrategy::ByWindow { window: 256, stride: 100 },
+            SplitStrategy::ByTensorParallel { num_shards: 4 },
+            hybrid(true, true, 128),
+            hybrid(true, false, 250),
+            hybrid(false, true, 250),
+        ];
+        for strategy in strategies {
+            let splitter = TaskSplitter::new(model_info.clone(), strategy.clone()).unwrap();
+            let tasks = splitter.split_task(&input, "estimate", TaskPriority::Normal).unwrap();
+            assert_eq!(strategy.estimate_task_count(&model_info, input.len()), tasks.len(), "{}", strategy.description());
+        }
+
+        let strategy = SplitStrategy::ByTopKExpert { top_k: 2 };
+        let splitter = TaskSplitter::new(model_info.clone(), strategy.clone()).unwrap();
+        let gates = GateWeights::from_logits(&[0.1, 2.0, 0.3, 1.5, 0.0, 0.2, 0.4, 0.5], 2);
+        let tasks = splitter.split_task_with_gates(&input, "estimate", TaskPriority::Normal, &gates).unwrap();
+        assert_eq!(strategy.estimate_task_count(&model_info, input.len()), tasks.len());
+
+        // 按专家容量拆分的估算是上限，每个token路由到不同专家时正好取到
+        let strategy = SplitStrategy::ByExpertCapacity { capacity_factor: 1.0 };
+        let splitter = TaskSplitter::new(model_info.clone(), strategy.clone()).unwrap();
+        let tasks = splitter.split_task_with_assignments(&input, "estimate", TaskPriority::Normal, &[0, 5, 2]).unwrap();
+        assert_eq!(strategy.estimate_task_count(&model_info, input.len()), tasks.len());
+        let tasks = splitter.split_task_with_assignments(&input, "estimate", TaskPriority::Normal, &[1, 1, 1]).unwrap();
+        assert!(strategy.estimate_task_count(&model_info, input.len()) >= tasks.len());
+
+        assert_eq!(SplitStrategy::ByBatch { batch_size: 3 }.estimate_task_count(&model_info, input.len()), 0);
+    }
+
+    #[test]
+    fn test_split_by_overlapping_windows() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let input: Vec<u8> = (0..512).map(|i| (i % 251) as u8).collect();
+        let mut splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByWindow { window: 256, stride: 128 }).unwrap();
+        splitter.set_expected_input_len(Some(input.len()));
+
+        // 窗口起点为 0、128、256，最后一个窗口正好覆盖到输入末尾
+        let mut tasks = splitter.split_task(&input, "window", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert!(tasks.iter().all(|task| task.input_data.len() == 256));
+        assert_eq!(tasks[1].input_data[..], input[128..384]);
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+        assert!(splitter.get_task_dependencies(&tasks).unwrap().values().all(Vec::is_empty));
+
+        for task in tasks.iter_mut() {
+            task.result = Some(task.input_data.clone());
+        }
+        let results: Vec<Vec<u8>> = tasks.iter().map(|task| task.input_data.clone()).collect();
+        assert_eq!(splitter.merge_results(&results, None).unwrap(), input);
+        tasks.reverse();
+        assert_eq!(splitter.merge_task_results(&tasks, None).unwrap(), input);
+
+        // 输入长度不是步长的整数倍时最后一个窗口补0，合并时去掉填充
+        let short = &input[..500];
+        splitter.set_expected_input_len(Some(short.len()));
+        let mut tasks = splitter.split_task(short, "window", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[2].valid_len, Some(500 - 256));
+        for task in tasks.iter_mut() {
+            task.result = Some(task.input_data.clone());
+        }
+        assert_eq!(splitter.merge_task_results(&tasks, None).unwrap(), short);
+
+        for (window, stride) in [(256, 0), (128, 256)] {
+            match TaskSplitter::new(model_info.clone(), SplitStrategy::ByWindow { window, stride }) {
+                Err(Error::ConfigError(msg)) => assert!(msg.starts_with("stride"), "{}", msg),
+                other => panic!("期望 ConfigError，实际为 {:?}", other.map(|splitter| splitter.strategy.description())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_merge_out_of_order_completion() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 8,
+            hidden_size: 64,
+            intermediate_size: 256,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByBatch { batch_size: 64 }).unwrap();
+        let mut input = 249u32.to_le_bytes().to_vec();
+        input.extend((0..249).flat_map(|i| (i as f32).to_le_bytes()));
+
+        let mut tasks = splitter.split_task(&input, "shuffled", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 16);
+        for task in tasks.iter_mut() {
+            task.result = Some(task.input_data.clone());
+        }
+        // 模拟并发执行时的乱序完成，带填充的最后一个批次不在末尾
+        let mut shuffled = tasks.clone();
+        shuffled.reverse();
+        shuffled.swap(3, 9);
+        assert_eq!(shuffled[0].valid_len, Some(1000 - 15 * 64));
+
+        assert_eq!(splitter.merge_task_results(&shuffled, None).unwrap(), input);
+
+        let indexed: Vec<(usize, Vec<u8>)> = shuffled
+            .iter()
+            .map(|task| (task.parsed_task_id().unwrap().batch.unwrap(), task.valid_slice(task.result.as_ref().unwrap()).to_vec()))
+            .collect();
+        assert_eq!(splitter.result_merger.merge_indexed_batch_results(&indexed).unwrap(), input);
+
+        // 缺少或重复批次时报错
+        let missing: Vec<(usize, Vec<u8>)> = indexed.iter().filter(|(batch_id, _)| *batch_id != 5).cloned().collect();
+        assert!(splitter.result_merger.merge_indexed_batch_results(&missing).is_err());
+        let mut duplicated = indexed.clone();
+        duplicated[0].0 = duplicated[1].0;
+        assert!(splitter.result_merger.merge_indexed_batch_results(&duplicated).is_err());
+    }
+
+    #[test]
+    fn test_task_id_round_trip_for_every_strategy() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 16,
+            intermediate_size: 64,
+            num_layers: 4,
+            ..Default::default()
+        };
+        let hybrid = |expert_split, layer_split, batch_size| SplitStrategy::Hybrid {
+            expert_split,
+            layer_split,
+            batch_size,
+            expert_ratio: 1.0,
+            layer_ratio: 1.0,
+        };
+        let strategies = vec![
+            SplitStrategy::ByExpert,
+            SplitStrategy::ByTopKExpert { top_k: 2 },
+            SplitStrategy::ByLayer,
+            SplitStrategy::ByBatch { batch_size: 16 },
+            SplitStrategy::ByTensorParallel { num_shards: 4 },
+            hybrid(true, true, 16),
+            hybrid(true, false, 32),
+            hybrid(false, true, 32),
+        ];
+        let input = single_token_input(model_info.hidden_size);
+        let gate_weights = GateWeights { weights: vec![0.1, 0.4, 0.2, 0.3], top_k: 2 };
+
+        for strategy in strategies {
+            let splitter = TaskSplitter::new(model_info.clone(), strategy.clone()).unwrap();
+            // 父任务ID本身包含 "_layer_"、"/" 等容易混淆的片段
+            let tasks = splitter
+                .split_task_with_gates(&input, "req/3_layer_1", TaskPriority::Normal, &gate_weights)
+                .unwrap();
+            let mut seen = std::collections::HashSet::new();
+            for task in &tasks {
+                let id = task.parsed_task_id().unwrap();
+                assert_eq!(id.to_string(), task.task_id, "{:?}", strategy);
+                assert_eq!(id.to_string().parse::<TaskId>().unwrap(), id, "{:?}", strategy);
+                assert_eq!(id.parent, "req/3_layer_1");
+                assert!(seen.insert(id), "{:?} 生成了重复的任务ID", strategy);
+            }
+        }
+
+        let splitter = TaskSplitter::new(model_info, hybrid(true, true, 16)).unwrap();
+        let tasks = splitter.split_task(&input, "req", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks[5].parsed_task_id().unwrap(), TaskId::new("req").with_layer(1).with_expert(1));
+    }
+
+    #[test]
+    fn test_split_by_layer_encoder_decoder_stacks() {
+        // 12个编码器层、6个解码器层的非对称配置
+        let model_info = ModelInfo {
+            model_type: "switch_transformers".to_string(),
+            num_experts: 8,
+            hidden_size: 16,
+            intermediate_size: 64,
+            num_layers: 12,
+            num_decoder_layers: Some(6),
+            ..Default::default()
+        };
+        let input = single_token_input(model_info.hidden_size);
+        let mut splitter = TaskSplitter::new(model_info, SplitStrategy::ByLayer).unwrap();
+
+        // 默认只拆分编码器层
+        let tasks = splitter.split_task(&input, "req", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 12);
+        assert!(tasks.iter().all(|task| task.parsed_task_id().unwrap().layer_stack() == Some(LayerStack::Encoder)));
+
+        splitter.set_include_decoder_layers(true);
+        let tasks = splitter.split_task(&input, "req", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 18);
+        let stacks: Vec<LayerStack> = tasks
+            .iter()
+            .map(|task| task.parsed_task_id().unwrap().layer_stack().unwrap())
+            .collect();
+        assert!(stacks[..12].iter().all(|stack| *stack == LayerStack::Encoder));
+        assert!(stacks[12..].iter().all(|stack| *stack == LayerStack::Decoder));
+        assert_eq!(tasks[11].parsed_task_id().unwrap(), TaskId::new("req").with_layer(11));
+        assert_eq!(tasks[12].parsed_task_id().unwrap(), TaskId::new("req").with_decoder_layer(0));
+        assert_eq!(tasks[17].task_id, "req/decoder_layer_5");
+        // 头部层ID按全局编号写入，惰性拆分与完整拆分一致
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+        let lazy: Vec<MoeTask> = splitter
+            .split_task_iter(&input, "req", TaskPriority::Normal)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(lazy.len(), 18);
+        for (lazy_task, task) in lazy.iter().zip(&tasks) {
+            assert_eq!(lazy_task.task_id, task.task_id);
+            assert_eq!(lazy_task.effective_input(), task.effective_input());
+        }
+    }
+
+    #[test]
+    fn test_token_metadata_in_split_tasks() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 8,
+            intermediate_size: 32,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let layout = InputLayout { dtype: DType::F32, batch: 1, seq_len: 3, hidden: 8 };
+        let mut input = (layout.num_elements() as u32).to_le_bytes().to_vec();
+        input.extend(vec![0u8; layout.byte_len()]);
+        let metadata = TokenMetadata {
+            attention_mask: vec![1, 1, 0],
+            position_ids: [0u32, 1, 2].iter().flat_map(|id| id.to_le_bytes()).collect(),
+        };
+
+        let mut splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        splitter.set_input_layout(layout);
+        splitter.set_token_metadata(metadata.clone());
+        let tasks = splitter.split_task(&input, "req", TaskPriority::Normal).unwrap();
+        for task in &tasks {
+            let (header, payload) = task_header::decode(&task.input_data).unwrap();
+            assert_eq!(header.token_metadata.as_ref(), Some(&metadata));
+            assert_eq!(payload, &input[..]);
+        }
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+
+        // 掩码的token数与布局不符
+        splitter.set_token_metadata(TokenMetadata { attention_mask: vec![1, 1], ..metadata });
+        assert!(splitter.split_task(&input, "req", TaskPriority::Normal).is_err());
+    }
+
+    #[test]
+    fn test_compare_with_routing_logits() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 4,
+            hidden_size: 8,
+            intermediate_size: 32,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let input = single_token_input(model_info.hidden_size);
+        // 3个token分别路由到专家 2、0、2
+        let logits = [
+            0.1, 0.2, 3.0, -1.0,
+            2.5, 0.0, 1.0, 0.3,
+            -0.5, 0.4, 0.9, 0.8,
+        ];
+
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+        let tasks = splitter.split_task(&input, "req", TaskPriority::Normal).unwrap();
+        let diff = splitter.compare_with_routing_logits(&tasks, &logits).unwrap();
+        assert_eq!(diff.matched, BTreeSet::from([0, 2]));
+        assert_eq!(diff.extra, BTreeSet::from([1, 3]));
+        assert!(diff.missing.is_empty());
+
+        // top-2 时第3个token还选中专家3；只为专家0、1拆分任务时专家2、3缺失
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByTopKExpert { top_k: 2 }).unwrap();
+        let gates = GateWeights { weights: vec![0.6, 0.3, 0.05, 0.05], top_k: 2 };
+        let tasks = splitter.split_task_with_gates(&input, "req", TaskPriority::Normal, &gates).unwrap();
+        let diff = splitter.compare_with_routing_logits(&tasks, &logits).unwrap();
+        assert_eq!(diff.matched, BTreeSet::from([0, 1]));
+        assert_eq!(diff.missing, BTreeSet::from([2, 3]));
+        assert!(diff.extra.is_empty());
+
+        assert!(splitter.compare_with_routing_logits(&tasks, &logits[..5]).is_err());
+    }
+
+    #[cfg(feature = "tch")]
+    #[test]
+    fn test_compute_gate_weights_from_router() {
+        use tch::{nn, Device, Kind, Tensor};
+
+        tch::manual_seed(3);
+        let model_info = ModelInfo {
+            model_type: "switch_transformers".to_string(),
+            num_experts: 2,
+            hidden_size: 8,
+            intermediate_size: 16,
+            num_layers: 1,
+            ..Default::default()
+        };
+        let vs = nn::VarStore::new(Device::Cpu);
+        let mlp = SwitchTransformersSparseMLP::new(vs.root(), &model_info);
+        let xs = Tensor::randn([1, 8], (Kind::Float, Device::Cpu));
+
+        for (strategy, top_k) in [(SplitStrategy::ByExpert, 1), (SplitStrategy::ByTopKExpert { top_k: 2 }, 2)] {
+            let splitter = TaskSplitter::new(model_info.clone(), strategy).unwrap();
+            let gate_weights = splitter.compute_gate_weights(&xs, &mlp).unwrap();
+            assert_eq!(gate_weights.weights.len(), 2);
+            assert_eq!(gate_weights.top_k, top_k);
+            assert_eq!(gate_weights.weights.iter().filter(|&&w| w > 0.0).count(), top_k);
+            assert!((gate_weights.weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+            // 权重可直接用于合并专家结果
+            let results = vec![
+                [1.0f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(),
+                [3.0f32, 4.0].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(),
+            ];
+            assert!(splitter.merge_results(&results, Some(gate_weights)).is_ok());
+        }
+
+        // 多个token的输入无法给出单一的门控权重
+        let splitter = TaskSplitter::new(model_info, SplitStrategy::ByExpert).unwrap();
+        let batch = Tensor::randn([3, 8], (Kind::Float, Device::Cpu));
+        assert!(matches!(splitter.compute_gate_weights(&batch, &mlp), Err(Error::InferenceError(_))));
+    }
+
+    #[test]
+    fn test_split_by_tensor_parallel_shard_boundaries() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 2,
+            hidden_size: 64,
+            intermediate_size: 2048,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByTensorParallel { num_shards: 4 }).unwrap();
+        let input = single_token_input(model_info.hidden_size);
+        let tasks = splitter.split_task(&input, "tp", TaskPriority::Normal).unwrap();
+        assert_eq!(tasks.len(), 8);
+
+        for (index, task) in tasks.iter().enumerate() {
+            let (expert_id, slice, payload) =
+                splitter.data_preparator.parse_expert_data_sharded(&task.input_data).unwrap();
+            assert_eq!(expert_id, index / 4);
+            assert_eq!(slice, WeightColumnSlice { start: (index % 4) * 512, len: 512 });
+            assert_eq!(payload, &input[..]);
+            assert_eq!(task.parsed_task_id().unwrap(), TaskId::new("tp").with_expert(index / 4).with_shard(index % 4));
+        }
+        assert!(splitter.verify_split_results(&tasks, &input).unwrap());
+
+        // 分片数必须整除中间层大小
+        assert!(matches!(
+            TaskSplitter::new(model_info, SplitStrategy::ByTensorParallel { num_shards: 3 }),
+            Err(Error::ConfigError(msg)) if msg.starts_with("num_shards:")
+        ));
+    }
+
+    #[test]
+    fn test_split_task_iter_bounded_allocations() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 128,
+            hidden_size: 1024,
+            intermediate_size: 4096,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+        let input = single_token_input(model_info.hidden_size);
+
+        let (count, iter_peak) = alloc_counter::measure_peak(|| {
+            let mut count = 0;
+            let mut shared: Option<Arc<[u8]>> = None;
+            for task in splitter.split_task_iter(&input, "lazy", TaskPriority::Normal) {
+                let task = task.unwrap();
+                let body = task.shared_input.clone().unwrap();
+                // 所有子任务共享同一份输入主体
+                if let Some(shared) = &shared {
+                    assert!(Arc::ptr_eq(shared, &body));
+                }
+                shared = Some(body);
+                assert_eq!(task.stream_id, Some(count));
+                count += 1;
+            }
+            count
+        });
+        assert_eq!(count, 128);
+
+        let (_, eager_peak) = alloc_counter::measure_peak(|| {
+            splitter.split_task(&input, "eager", TaskPriority::Normal).unwrap().len()
+        });
+
+        // 惰性拆分的峰值只包含一份公共输入和单个子任务，不随专家数量增长
+        let single_task_bytes = 4 + model_info.num_experts * 4 + 128;
+        assert!(
+            iter_peak < input.len() + 4 * single_task_bytes,
+            "惰性拆分峰值 {} 字节过大",
+            iter_peak
+        );
+        assert!(eager_peak > 16 * iter_peak, "完整拆分峰值 {} / 惰性拆分峰值 {}", eager_peak, iter_peak);
+    }
+
+    /// 按 safetensors 格式写出f32张量：[header_len: u64][JSON header][data]
+    fn write_safetensors(path: &Path, tensors: &[(String, Vec<usize>, Vec<f32>)]) {
+        let mut header = serde_json::Map::new();
+        header.insert("__metadata__".to_string(), serde_json::json!({ "format": "pt" }));
+        let mut data = Vec::new();
+        for (name, shape, values) in tensors {
+            let begin = data.len();
+            data.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+            header.insert(
+                name.clone(),
+                serde_json::json!({ "dtype": "F32", "shape": shape, "data_offsets": [begin, data.len()] }),
+            );
+        }
+        let header = serde_json::to_vec(&header).unwrap();
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&data);
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_split_weights_from_safetensors() {
+        let model_info = ModelInfo {
+            model_type: "switch_transformer".to_string(),
+            num_experts: 2,
+            hidden_size: 2,
+            intermediate_size: 3,
+            num_layers: 2,
+            ..Default::default()
+        };
+        let splitter = TaskSplitter::new(model_info.clone(), SplitStrategy::ByExpert).unwrap();
+        let mlp = "encoder.block.1.layer.1.mlp";
+        let expert_tensors = |expert_id: usize| {
+            let base = expert_id as f32 * 100.0;
+            [
+                (format!("{}.experts.expert_{}.wi.weight", mlp, expert_id), vec![3, 2], (0..6).map(|i| base + i as f32).collect()),
+                (format!("{}.experts.expert_{}.wo.weight", mlp, expert_id), vec![2, 3], (0..6).map(|i| base + 50.0 + i as f32).collect()),
+            ]
+        };
+        let mut tensors: Vec<(String, Vec<usize>, Vec<f32>)> = (0..2).flat_map(expert_tensors).collect();
+        tensors.push((format!("{}.router.classifier.weight", mlp), vec![2, 2], vec![1.0; 4]));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        write_safetensors(&path, &tensors);
+
+        let tasks = splitter.split_weights(&path).unwrap();
+        assert_eq!(tasks.len(), 2);
+        for (expert_id, task) in tasks.iter().enumerate() {
+            assert_eq!(task.task_id, format!("{}/expert_{}", mlp, expert_id));
+            let (header, payload) = task_header::decode(&task.input_data).unwrap();
+            assert_eq!(header.expert_id(), Some(expert_id));
+            // 负载为该专家的 wi 后接 wo
+            let expected: Vec<u8> = expert_tensors(expert_id)
+                .iter()
+                .flat_map(|(_, _, values)| values.iter().flat_map(|value| value.to_le_bytes()))
+                .collect();
+            assert_eq!(payload, &expected[..]);
+        }
+
+        // 缺少专家1的 wo 时报出张量名
+        let missing: Vec<_> = tensors.iter().filter(|(name, _, _)| !name.contains("expert_1.wo")).cloned().collect();
+        write_safetensors(&path, &missing);
+        match splitter.split_weights(&path) {
+            Err(Error::ConfigError(msg)) => assert!(msg.contains("experts.expert_1.wo.weight"), "{}", msg),
+            other => panic!("期望 ConfigError，实际为 {:?}", other.map(|tasks| tasks.len())),
+        }
+
+        // 形状与模型配置不符
+        let mut transposed = tensors.clone();
+        transposed[0].1 = vec![2, 3];
+        write_safetensors(&path, &transposed);
+        assert!(matches!(splitter.split_weights(&path), Err(Error::ConfigError(_))));
+
+        // 只支持按专家拆分
+        let by_layer = TaskSplitter::new(model_info, SplitStrategy::ByLayer).unwrap();
+        assert!(matches!(by_layer.split_weights(&path), Err(Error::ConfigError(_))));
+    }
+
+    /// 统计当前线程堆内存峰值的测试分配器
+    mod alloc_counter {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        struct CountingAllocator;
+
+        thread_local! {
+            static TRACKING: Cell<bool> = const { Cell::new(false) };
+            static LIVE: Cell<isize> = const { Cell::new(0) };
+            static PEAK: Cell<isize> = const { Cell::new(0) };
+        }
+
+        fn record(delta: isize) {
+            let _ = TRACKING.try_with(|tracking| {
+                if tracking.get() {
+                    let live = LIVE.with(|live| {
+                        live.set(live.get() + delta);
+                        live.get()
+                    });
+                    PEAK.with(|peak| peak.set(peak.get().max(live)));
+                }
+            });
+        }
+
+        unsafe impl GlobalAlloc for CountingAllocator {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                record(layout.size() as isize);
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                record(-(layout.size() as isize));
+                System.dealloc(ptr, layout)
+            }
+
+            unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+                record(new_size as isize - layout.size() as isize);
+                System.realloc(ptr, layout, new_size)
+            }
+        }
+
+        #[global_allocator]
+        static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+        /// 执行 `f` 并返回其结果和执行期间当前线程新增堆内存的峰值（字节）
+        pub fn measure_peak<T>(f: impl FnOnce() -> T) -> (T, usize) {
+            LIVE.with(|live| live.set(0));
+            PEAK.with(|peak| peak.set(0));
+            TRACKING.with(|tracking| tracking.set(true));
+            let result = f();
+            TRACKING.with(|tracking| tracking.set(false));
+            (result, PEAK.with(|peak| peak.get()).max(0) as usize)
+        }
+    }
 }
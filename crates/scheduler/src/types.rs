@@ -1,12 +1,58 @@
 // types.rs
 // 定义通用类型，如专家到GPU的映射、门控权重、常量等辅助类型。
+use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// GPU设备编号，与CUDA设备序号一致
+///
+/// 配置文件中按整数读写，反序列化时拒绝负数和超出 u32 范围的值。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "i64", into = "u32")]
+pub struct DeviceId(pub u32);
+
+impl DeviceId {
+    /// 作为下标使用的设备序号
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl From<u32> for DeviceId {
+    fn from(id: u32) -> Self {
+        DeviceId(id)
+    }
+}
+
+impl From<DeviceId> for u32 {
+    fn from(id: DeviceId) -> Self {
+        id.0
+    }
+}
+
+impl TryFrom<i64> for DeviceId {
+    type Error = Error;
+
+    fn try_from(id: i64) -> Result<Self> {
+        u32::try_from(id)
+            .map(DeviceId)
+            .map_err(|_| Error::ConfigError(format!("gpu_ids: GPU编号 {} 无效，必须是非负整数", id)))
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 /// 专家到GPU的映射信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExpertGpuMapping {
     pub expert_id: usize,
-    pub gpu_id: i32,
+    pub gpu_id: DeviceId,
     pub memory_required: u64, // MB
 }
 
@@ -17,7 +63,225 @@ pub struct GateWeights {
     pub top_k: usize,
 }
 
+impl GateWeights {
+    /// 由路由器输出的原始 logits 构造门控权重
+    ///
+    /// 先做softmax，再只保留概率最大的 top_k 个专家（概率相同时优先较小的专家ID）并重新归一化，
+    /// 其余专家的权重为0；top_k 超过专家数时保留全部专家。
+    pub fn from_logits(logits: &[f32], top_k: usize) -> GateWeights {
+        let top_k = top_k.min(logits.len());
+        let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = logits.iter().map(|logit| (logit - max_logit).exp()).collect();
+        let total: f32 = exps.iter().sum();
+        let softmax = GateWeights { weights: exps.iter().map(|e| e / total).collect(), top_k };
+
+        let kept = softmax.top_k_experts(top_k);
+        let kept_total: f32 = kept.iter().map(|&expert_id| softmax.weights[expert_id]).sum();
+        let mut weights = vec![0.0; logits.len()];
+        for expert_id in kept {
+            weights[expert_id] = softmax.weights[expert_id] / kept_total;
+        }
+        GateWeights { weights, top_k }
+    }
+
+    /// 权重最大的 k 个专家ID，按专家ID升序返回；权重相同时优先较小的ID
+    pub fn top_k_experts(&self, k: usize) -> Vec<usize> {
+        let mut expert_ids: Vec<usize> = (0..self.weights.len()).collect();
+        expert_ids.sort_by(|&a, &b| self.weights[b].total_cmp(&self.weights[a]).then(a.cmp(&b)));
+        expert_ids.truncate(k);
+        expert_ids.sort_unstable();
+        expert_ids
+    }
+
+    /// 保存到文件：先写 top_k（u32 小端），再依次写各专家权重（f32 小端）
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let top_k = u32::try_from(self.top_k)
+            .map_err(|_| Error::ConfigError(format!("top_k: {} 超出 u32 范围", self.top_k)))?;
+        let mut bytes = Vec::with_capacity(4 + self.weights.len() * ELEMENT_SIZE);
+        bytes.extend_from_slice(&top_k.to_le_bytes());
+        bytes.extend(self.weights.iter().flat_map(|weight| weight.to_le_bytes()));
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// 从 save 写出的文件读取门控权重，文件格式不对时返回 ConfigError
+    pub fn load(path: &Path) -> Result<GateWeights> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 4 || !(bytes.len() - 4).is_multiple_of(ELEMENT_SIZE) {
+            return Err(Error::ConfigError(format!(
+                "门控权重文件 {} 大小 {} 不是 4 字节 top_k 加整数个 f32 权重",
+                path.display(),
+                bytes.len()
+            )));
+        }
+        let top_k = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        let weights: Vec<f32> = bytes[4..]
+            .chunks_exact(ELEMENT_SIZE)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        if top_k > weights.len() {
+            return Err(Error::ConfigError(format!(
+                "门控权重文件 {} 的 top_k {} 超过权重个数 {}",
+                path.display(),
+                top_k,
+                weights.len()
+            )));
+        }
+        Ok(GateWeights { weights, top_k })
+    }
+}
+
+/// 专家权重矩阵的列切片 [start, start + len)，用于按中间层维度分片的专家
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightColumnSlice {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl WeightColumnSlice {
+    /// 切片结束列（不包含）
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+/// 输入张量的元素类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DType {
+    F32,
+    F16,
+    BF16,
+}
+
+impl DType {
+    /// 单个元素的字节数
+    pub fn size(&self) -> usize {
+        match self {
+            DType::F32 => 4,
+            DType::F16 | DType::BF16 => 2,
+        }
+    }
+}
+
+/// 输入张量的布局描述：[batch, seq_len, hidden]，元素类型为 dtype
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputLayout {
+    pub dtype: DType,
+    pub batch: usize,
+    pub seq_len: usize,
+    pub hidden: usize,
+}
+
+impl InputLayout {
+    /// 元素个数
+    pub fn num_elements(&self) -> usize {
+        self.batch * self.seq_len * self.hidden
+    }
+
+    /// 张量数据的字节数（不含头部）
+    pub fn byte_len(&self) -> usize {
+        self.num_elements() * self.dtype.size()
+    }
+}
+
+/// 逐token的元数据：注意力掩码和位置ID，随子任务一起下发，避免对填充token做无效计算
+///
+/// 编码在子任务头部的末尾（见 `task_header`），布局为
+/// `[mask_len: u32][attention_mask][pos_len: u32][position_ids]`，长度均为字节数。
+/// 约定掩码每个token一个字节（0为填充），位置ID每个token一个 u32。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub attention_mask: Vec<u8>,
+    pub position_ids: Vec<u8>,
+}
+
+impl TokenMetadata {
+    /// 序列化后的字节数
+    pub fn encoded_len(&self) -> usize {
+        2 * METADATA_LEN_SIZE + self.attention_mask.len() + self.position_ids.len()
+    }
+}
+
+/// 按专家容量拆分时单个专家任务的token路由，下标均为token在原始输入中的位置
+///
+/// `kept` 按顺序对应任务负载中的各行，`dropped` 为路由到该专家但超出容量被丢弃的token，
+/// 合并时这些位置填0。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenRouting {
+    /// 原始输入的token总数
+    pub num_tokens: usize,
+    pub kept: Vec<usize>,
+    pub dropped: Vec<usize>,
+}
+
 // 常量定义，避免硬编码
 pub const EXPERT_ID_SIZE: usize = 4;
 pub const LAYER_ID_SIZE: usize = 4;
-pub const GATE_WEIGHT_SIZE: usize = 4; 
\ No newline at end of file
+pub const GATE_WEIGHT_SIZE: usize = 4;
+/// 输入数据头部大小（u32 表示的输入元素个数）
+pub const INPUT_HEADER_SIZE: usize = 4;
+/// 输入元素的字节宽度（f32）
+pub const ELEMENT_SIZE: usize = 4;
+/// 元数据各段长度前缀的字节宽度（u32）
+pub const METADATA_LEN_SIZE: usize = 4;
+/// 每个token的位置ID字节宽度（u32）
+pub const POSITION_ID_SIZE: usize = 4;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_gate_weights_from_logits_top1_is_argmax() {
+        let gates = GateWeights::from_logits(&[0.5, 2.0, -1.0, 1.9], 1);
+        assert_eq!(gates.top_k, 1);
+        assert_eq!(gates.weights, vec![0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_gate_weights_from_logits_all_experts_is_softmax() {
+        let logits = [1.0f32, 2.0, 3.0];
+        let total: f32 = logits.iter().map(|l| l.exp()).sum();
+        let expected: Vec<f32> = logits.iter().map(|l| l.exp() / total).collect();
+        assert_close(&GateWeights::from_logits(&logits, 3).weights, &expected);
+        // top_k 超过专家数时同样保留全部专家
+        let gates = GateWeights::from_logits(&logits, 8);
+        assert_eq!(gates.top_k, 3);
+        assert_close(&gates.weights, &expected);
+        // 数值较大的 logits 不会溢出
+        assert_close(&GateWeights::from_logits(&[1000.0, 1000.0], 2).weights, &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_gate_weights_save_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gates.bin");
+        let gates = GateWeights { weights: (0..16).map(|i| i as f32 / 120.0).collect(), top_k: 4 };
+        gates.save(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4 + 16 * 4);
+
+        let loaded = GateWeights::load(&path).unwrap();
+        assert_eq!(loaded.top_k, 4);
+        assert_eq!(loaded.weights, gates.weights);
+
+        // 截断的文件和 top_k 超过权重个数的文件都被拒绝
+        std::fs::write(&path, [4, 0, 0, 0, 0, 0]).unwrap();
+        assert!(matches!(GateWeights::load(&path), Err(Error::ConfigError(_))));
+        GateWeights { weights: vec![1.0], top_k: 2 }.save(&path).unwrap();
+        assert!(matches!(GateWeights::load(&path), Err(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_gate_weights_from_logits_ties_prefer_lower_id() {
+        let gates = GateWeights::from_logits(&[1.0, 3.0, 3.0, 3.0], 2);
+        assert_close(&gates.weights, &[0.0, 0.5, 0.5, 0.0]);
+        assert_eq!(gates.top_k_experts(2), vec![1, 2]);
+    }
+}
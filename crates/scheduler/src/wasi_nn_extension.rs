@@ -0,0 +1,369 @@
+// wasi_nn_extension.rs
+// WASI-NN 扩展，定义面向MOE推理的统一配置，并与调度器内部的 ModelInfo/SchedulerConfig 互相转换。
+// MoeAdapter 是 WASI-NN 后端需要实现的接口，CudaMoeAdapter 通过拆分器、执行器和合并器实现它。
+use crate::config::{ModelInfo, SchedulerConfig};
+use crate::error::{Error, Result};
+use log::info;
+use crate::pipeline::{Pipeline, PipelineConfig, PipelineDevice};
+use crate::quantization;
+use crate::task_executor::{Executor, TaskExecutor, DEFAULT_MEMORY_FRACTION};
+use crate::task_splitter::SplitStrategy;
+use crate::types::DeviceId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// MOE推理配置，一份配置即可描述模型、路由、设备和量化方式
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MoeConfig {
+    /// 模型目录路径
+    pub model_path: String,
+    /// 模型类型
+    pub model_type: String,
+    /// 隐藏层大小
+    pub hidden_size: usize,
+    /// 中间层大小
+    pub intermediate_size: usize,
+    /// 层数
+    pub num_layers: usize,
+    /// 专家数量
+    pub num_experts: usize,
+    /// 每个token路由到的专家数
+    pub top_k: usize,
+    /// 可用GPU设备ID列表
+    pub device_ids: Vec<DeviceId>,
+    /// 最大并发任务数
+    pub max_concurrent_tasks: usize,
+    /// 批处理大小
+    pub batch_size: usize,
+    /// 是否启用量化
+    pub use_quantization: bool,
+    /// 量化位宽
+    pub quantization_bits: u8,
+}
+
+impl MoeConfig {
+    /// 由模型信息和调度器配置构建 MoeConfig，不启用量化
+    pub fn from_parts(
+        model_path: String,
+        model_info: &ModelInfo,
+        scheduler_config: &SchedulerConfig,
+        top_k: usize,
+    ) -> Result<Self> {
+        let config = Self {
+            model_path,
+            model_type: model_info.model_type.clone(),
+            hidden_size: model_info.hidden_size,
+            intermediate_size: model_info.intermediate_size,
+            num_layers: model_info.num_layers,
+            num_experts: model_info.num_experts,
+            top_k,
+            device_ids: scheduler_config.gpu_ids.clone(),
+            max_concurrent_tasks: scheduler_config.max_concurrent_tasks,
+            batch_size: scheduler_config.default_batch_size,
+            use_quantization: false,
+            quantization_bits: 8,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 验证配置的一致性
+    pub fn validate(&self) -> Result<()> {
+        if self.num_experts == 0 {
+            return Err(Error::ConfigError("专家数量不能为0".to_string()));
+        }
+        if self.top_k == 0 || self.top_k > self.num_experts {
+            return Err(Error::ConfigError(format!(
+                "top_k {} 必须在 [1, {}] 范围内", self.top_k, self.num_experts
+            )));
+        }
+        if self.device_ids.is_empty() {
+            return Err(Error::ConfigError("设备列表不能为空".to_string()));
+        }
+        if self.use_quantization && !quantization::SUPPORTED_BITS.contains(&self.quantization_bits) {
+            return Err(Error::ConfigError(format!(
+                "不支持的量化位宽 {}，支持的位宽为 {:?}", self.quantization_bits, quantization::SUPPORTED_BITS
+            )));
+        }
+        Ok(())
+    }
+
+    /// 执行器使用的量化位宽，未启用量化时为None
+    pub fn quantization(&self) -> Option<u8> {
+        self.use_quantization.then_some(self.quantization_bits)
+    }
+
+    /// 转换为拆分器使用的模型信息
+    pub fn to_model_info(&self) -> ModelInfo {
+        ModelInfo {
+            model_type: self.model_type.clone(),
+            num_experts: self.num_experts,
+            hidden_size: self.hidden_size,
+            intermediate_size: self.intermediate_size,
+            num_layers: self.num_layers,
+            ..Default::default()
+        }
+    }
+
+    /// 转换为调度器配置
+    pub fn to_scheduler_config(&self) -> SchedulerConfig {
+        SchedulerConfig {
+            max_concurrent_tasks: self.max_concurrent_tasks,
+            default_batch_size: self.batch_size,
+            gpu_ids: self.device_ids.clone(),
+        }
+    }
+}
+
+/// WASI-NN 的MOE推理后端接口，模型以 `load_model` 返回的ID引用
+pub trait MoeAdapter {
+    /// 按配置加载模型，返回模型ID；同一模型目录重复加载时返回已有的ID
+    fn load_model(&mut self, config: &MoeConfig) -> Result<u32>;
+
+    /// 对输入执行一次MOE推理，返回输出数据
+    fn compute(&self, model_id: u32, input: &[u8]) -> Result<Vec<u8>>;
+
+    /// 释放模型
+    fn release_model(&mut self, model_id: u32) -> Result<()>;
+
+    /// 查询模型目录对应的模型ID，未加载时返回None
+    fn get_model_id(&self, model_path: &str) -> Option<u32>;
+}
+
+/// 已加载的模型
+struct LoadedModel {
+    model_path: String,
+    pipeline: Pipeline,
+}
+
+/// 基于 TaskExecutor 的 MoeAdapter 实现：`compute` 拆分输入、执行子任务并合并结果
+pub struct CudaMoeAdapter {
+    executor: Arc<dyn Executor>,
+    // 执行器所在的设备，写入各模型的流水线配置
+    device: PipelineDevice,
+    strategy: SplitStrategy,
+    models: HashMap<u32, LoadedModel>,
+    next_model_id: u32,
+}
+
+impl CudaMoeAdapter {
+    /// 在指定GPU上创建适配器
+    ///
+    /// CUDA执行器不能跨线程共享，这里的 Arc 只用于在已加载的模型之间共享同一个执行器。
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn new(device_id: DeviceId) -> Result<Self> {
+        let executor: Arc<dyn Executor> = Arc::new(TaskExecutor::new(device_id)?);
+        Ok(Self { device: PipelineDevice::Cuda(device_id), ..Self::with_executor(executor) })
+    }
+
+    /// 按配置在第一个设备上创建适配器，启用量化时执行器按配置的位宽量化传输
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn from_config(config: &MoeConfig) -> Result<Self> {
+        config.validate()?;
+        let mut executor = TaskExecutor::new(config.device_ids[0])?;
+        executor.set_quantization_bits(config.quantization())?;
+        let executor: Arc<dyn Executor> = Arc::new(executor);
+        Ok(Self { device: PipelineDevice::Cuda(config.device_ids[0]), ..Self::with_executor(executor) })
+    }
+
+    /// 使用给定的主机执行后端创建适配器，例如没有GPU时使用 CpuTaskExecutor
+    pub fn with_executor(executor: Arc<dyn Executor>) -> Self {
+        Self {
+            executor,
+            device: PipelineDevice::Cpu,
+            strategy: SplitStrategy::ByExpert,
+            models: HashMap::new(),
+            next_model_id: 0,
+        }
+    }
+
+    /// 设置之后加载的模型使用的拆分策略，默认按专家拆分
+    pub fn set_strategy(&mut self, strategy: SplitStrategy) {
+        self.strategy = strategy;
+    }
+
+    /// 已加载模型的数量
+    pub fn num_models(&self) -> usize {
+        self.models.len()
+    }
+}
+
+impl MoeAdapter for CudaMoeAdapter {
+    fn load_model(&mut self, config: &MoeConfig) -> Result<u32> {
+        config.validate()?;
+        if let Some(model_id) = self.get_model_id(&config.model_path) {
+            return Ok(model_id);
+        }
+
+        let pipeline_config = PipelineConfig {
+            model_dir: config.model_path.clone(),
+            strategy: self.strategy.clone(),
+            device: self.device,
+            memory_fraction: DEFAULT_MEMORY_FRACTION,
+            max_concurrent_tasks: config.max_concurrent_tasks,
+        };
+        let pipeline = Pipeline::with_executor(pipeline_config, Arc::clone(&self.executor))?;
+        let model_info = pipeline.model_info();
+        if model_info.num_experts != config.num_experts || model_info.hidden_size != config.hidden_size {
+            return Err(Error::ConfigError(format!(
+                "模型 {} 的专家数 {} / 隐藏层大小 {} 与配置的 {} / {} 不一致",
+                config.model_path, model_info.num_experts, model_info.hidden_size,
+                config.num_experts, config.hidden_size
+            )));
+        }
+
+        let model_id = self.next_model_id;
+        self.next_model_id += 1;
+        self.models.insert(model_id, LoadedModel {
+            model_path: config.model_path.clone(),
+            pipeline,
+        });
+        info!("模型 {} 加载完成，模型ID: {}", config.model_path, model_id);
+        Ok(model_id)
+    }
+
+    fn compute(&self, model_id: u32, input: &[u8]) -> Result<Vec<u8>> {
+        let model = self.models.get(&model_id)
+            .ok_or_else(|| Error::InferenceError(format!("模型ID {} 未加载", model_id)))?;
+        model.pipeline.infer(input)
+    }
+
+    fn release_model(&mut self, model_id: u32) -> Result<()> {
+        let model = self.models.remove(&model_id)
+            .ok_or_else(|| Error::InferenceError(format!("模型ID {} 未加载", model_id)))?;
+        info!("模型 {} 已释放", model.model_path);
+        Ok(())
+    }
+
+    fn get_model_id(&self, model_path: &str) -> Option<u32> {
+        self.models
+            .iter()
+            .find(|(_, model)| model.model_path == model_path)
+            .map(|(model_id, _)| *model_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::TaskPriority;
+    use crate::task_executor::CpuTaskExecutor;
+    use crate::task_splitter::TaskSplitter;
+
+    fn test_config() -> MoeConfig {
+        MoeConfig {
+            model_path: "downloads/google/switch-base-8".to_string(),
+            model_type: "switch_transformers".to_string(),
+            hidden_size: 768,
+            intermediate_size: 3072,
+            num_layers: 12,
+            num_experts: 8,
+            top_k: 1,
+            device_ids: vec![DeviceId(0), DeviceId(1)],
+            max_concurrent_tasks: 4,
+            batch_size: 2,
+            use_quantization: false,
+            quantization_bits: 8,
+        }
+    }
+
+    #[test]
+    fn test_moe_config_round_trip() {
+        let config = test_config();
+        let model_info = config.to_model_info();
+        let scheduler_config = config.to_scheduler_config();
+        assert_eq!(model_info.num_experts, 8);
+        assert_eq!(model_info.hidden_size, 768);
+        assert_eq!(scheduler_config.gpu_ids, vec![DeviceId(0), DeviceId(1)]);
+
+        let rebuilt = MoeConfig::from_parts(
+            config.model_path.clone(),
+            &model_info,
+            &scheduler_config,
+            config.top_k,
+        ).unwrap();
+        assert_eq!(rebuilt, config);
+    }
+
+    #[test]
+    fn test_moe_config_rejects_top_k_above_num_experts() {
+        let mut config = test_config();
+        config.top_k = 9;
+        assert!(matches!(config.validate(), Err(Error::ConfigError(_))));
+
+        let result = MoeConfig::from_parts(
+            config.model_path.clone(),
+            &config.to_model_info(),
+            &config.to_scheduler_config(),
+            9,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_moe_config_quantization_bits() {
+        let mut config = test_config();
+        assert_eq!(config.quantization(), None);
+        config.use_quantization = true;
+        config.quantization_bits = 4;
+        assert_eq!(config.quantization(), Some(4));
+        config.quantization_bits = 3;
+        assert!(matches!(config.validate(), Err(Error::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_scheduler_config_shared_with_scheduler() {
+        // MoeConfig 转换出的配置与调度器使用的是同一个 SchedulerConfig 类型
+        let scheduler_config: crate::config::SchedulerConfig = test_config().to_scheduler_config();
+        let scheduler = crate::scheduler::TaskScheduler::new(scheduler_config.clone());
+        assert_eq!(scheduler.config.gpu_ids, scheduler_config.gpu_ids);
+        assert_eq!(scheduler.config.max_concurrent_tasks, 4);
+    }
+
+    #[test]
+    fn test_cuda_moe_adapter_compute_with_cpu_executor() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_json = r#"{
+            "model_type": "switch_transformers",
+            "num_experts": 4,
+            "d_model": 16,
+            "d_ff": 64,
+            "num_layers": 2
+        }"#;
+        std::fs::write(dir.path().join("config.json"), config_json).unwrap();
+
+        let mut config = test_config();
+        config.model_path = dir.path().to_str().unwrap().to_string();
+        config.hidden_size = 16;
+        config.intermediate_size = 64;
+        config.num_layers = 2;
+        config.num_experts = 4;
+
+        let mut adapter = CudaMoeAdapter::with_executor(Arc::new(CpuTaskExecutor::new()));
+        let model_id = adapter.load_model(&config).unwrap();
+        assert_eq!(adapter.load_model(&config).unwrap(), model_id);
+        assert_eq!(adapter.get_model_id(&config.model_path), Some(model_id));
+
+        let mut input = (16u32).to_le_bytes().to_vec();
+        input.extend((0..16).flat_map(|i| (i as f32 * 0.25).to_le_bytes()));
+
+        // CPU执行器原样返回子任务输入，期望值即为直接合并拆分结果
+        let splitter = TaskSplitter::new(config.to_model_info(), SplitStrategy::ByExpert).unwrap();
+        let tasks = splitter.split_task(&input, "expected", TaskPriority::Normal).unwrap();
+        let inputs: Vec<Vec<u8>> = tasks.iter().map(|task| task.effective_input().into_owned()).collect();
+        let expected = splitter.merge_results(&inputs, None).unwrap();
+
+        let output = adapter.compute(model_id, &input).unwrap();
+        assert!(!output.is_empty());
+        assert_eq!(output, expected);
+
+        adapter.release_model(model_id).unwrap();
+        assert_eq!(adapter.get_model_id(&config.model_path), None);
+        assert!(matches!(adapter.compute(model_id, &input), Err(Error::InferenceError(_))));
+
+        // 配置与 config.json 不一致时拒绝加载
+        config.num_experts = 8;
+        assert!(matches!(adapter.load_model(&config), Err(Error::ConfigError(_))));
+    }
+}